@@ -1,6 +1,10 @@
 use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
 use librsync::whole::{delta as whole_delta, patch as whole_patch, signature as whole_signature};
-use libsync3::{apply_delta, generate_delta, generate_signatures};
+use libsync3::{
+    DeltaCommand, apply_delta, apply_delta_at, apply_encoded, generate_delta,
+    generate_delta_parallel, generate_signatures, generate_signatures_parallel,
+    generate_signatures_with_block_size, write_delta, write_delta_with_checksums,
+};
 use std::io::Cursor;
 
 fn generate_test_data(size: usize) -> (Vec<u8>, Vec<u8>) {
@@ -95,6 +99,14 @@ fn benchmark_delta_generation(c: &mut Criterion) {
             );
         });
 
+        group.bench_with_input(BenchmarkId::new("xxhash3_parallel", size), &size, |b, _| {
+            b.iter_batched(
+                || (signatures.clone(), modified.clone()),
+                |(sigs, data)| generate_delta_parallel(&sigs, &data, 0).unwrap(),
+                criterion::BatchSize::LargeInput,
+            );
+        });
+
         group.bench_with_input(BenchmarkId::new("librsync", size), &size, |b, _| {
             b.iter_batched(
                 || (sig.clone(), modified.clone()),
@@ -212,12 +224,186 @@ fn benchmark_end_to_end(c: &mut Criterion) {
     group.finish();
 }
 
+/// Copy-heavy delta reading the base blocks in reverse order, so every copy needs a seek.
+fn benchmark_positioned_apply(c: &mut Criterion) {
+    const BLOCK: usize = 4096;
+    let size = 8 * 1024 * 1024;
+    let (original, _) = generate_test_data(size);
+    let path = std::env::temp_dir().join(format!("libsync3-bench-{}", std::process::id()));
+    std::fs::write(&path, &original).unwrap();
+    let delta: Vec<DeltaCommand> = (0..size / BLOCK)
+        .rev()
+        .map(|block| DeltaCommand::Copy {
+            offset: (block * BLOCK) as u64,
+            length: BLOCK,
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("positioned_apply");
+    group.bench_function("seek_read", |b| {
+        b.iter(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            let mut result = Vec::with_capacity(size);
+            apply_delta(file, &delta, &mut result).unwrap();
+            result
+        });
+    });
+    group.bench_function("read_at", |b| {
+        b.iter(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            let mut result = Vec::with_capacity(size);
+            apply_delta_at(&file, &delta, &mut result).unwrap();
+            result
+        });
+    });
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// One byte changed every 200, leaving tens of thousands of short copies of nearby blocks.
+fn benchmark_fragmented_apply(c: &mut Criterion) {
+    let size = 8 * 1024 * 1024;
+    let (original, _) = generate_test_data(size);
+    let mut modified = original.clone();
+    for chunk in modified.chunks_mut(200) {
+        chunk[0] = chunk[0].wrapping_add(1);
+    }
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let delta = generate_delta(&signatures, &modified[..]).unwrap();
+    let path = std::env::temp_dir().join(format!("libsync3-bench-frag-{}", std::process::id()));
+    std::fs::write(&path, &original).unwrap();
+
+    let mut group = c.benchmark_group("fragmented_apply");
+    group.bench_function("seek_read", |b| {
+        b.iter(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            let mut result = Vec::with_capacity(size);
+            apply_delta(file, &delta, &mut result).unwrap();
+            result
+        });
+    });
+    group.bench_function("read_at", |b| {
+        b.iter(|| {
+            let file = std::fs::File::open(&path).unwrap();
+            let mut result = Vec::with_capacity(size);
+            apply_delta_at(&file, &delta, &mut result).unwrap();
+            result
+        });
+    });
+    group.finish();
+    std::fs::remove_file(&path).unwrap();
+}
+
+/// 95% similar inputs: the scan is dominated by strong hash confirmations of matching blocks.
+fn benchmark_parallel_delta(c: &mut Criterion) {
+    let size = 100 * 1024 * 1024;
+    let (original, _) = generate_test_data(size);
+    let mut modified = original.clone();
+    for chunk in modified.chunks_mut(4096).step_by(20) {
+        chunk[0] = chunk[0].wrapping_add(1);
+    }
+    let signatures = generate_signatures(&original[..]).unwrap();
+
+    let mut group = c.benchmark_group("parallel_delta");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| generate_delta(&signatures, &modified[..]).unwrap());
+    });
+    for threads in [2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("parallel", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| generate_delta_parallel(&signatures, &modified, threads).unwrap());
+            },
+        );
+    }
+    group.finish();
+}
+
+fn benchmark_parallel_signatures(c: &mut Criterion) {
+    let size = 100 * 1024 * 1024;
+    let (original, _) = generate_test_data(size);
+
+    let mut group = c.benchmark_group("parallel_signatures");
+    group.sample_size(10);
+    group.bench_function("sequential", |b| {
+        b.iter(|| generate_signatures(&original[..]).unwrap());
+    });
+    for threads in [2, 4, 8] {
+        group.bench_with_input(
+            BenchmarkId::new("parallel", threads),
+            &threads,
+            |b, &threads| {
+                b.iter(|| generate_signatures_parallel(&original, 4096, threads).unwrap());
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Unrelated inputs: every byte of the new data is a weak checksum lookup that misses.
+fn benchmark_unmatched_delta(c: &mut Criterion) {
+    let size = 16 * 1024 * 1024;
+    let (original, _) = generate_test_data(size);
+    let unrelated: Vec<u8> = original.iter().rev().copied().collect();
+    let signatures = generate_signatures(&original[..]).unwrap();
+
+    let mut group = c.benchmark_group("unmatched_delta");
+    group.sample_size(10);
+    group.bench_function("xxhash3", |b| {
+        b.iter(|| generate_delta(&signatures, &unrelated[..]).unwrap());
+    });
+    group.finish();
+}
+
+/// Cost of the CRC-32C of checked data frames, on a delta that is all literals, next to
+/// generating that delta.
+fn benchmark_checked_data(c: &mut Criterion) {
+    let size = 16 * 1024 * 1024;
+    let (original, _) = generate_test_data(size);
+    let unrelated: Vec<u8> = original.iter().rev().copied().collect();
+    let signatures = generate_signatures(&original[..]).unwrap();
+    let delta = generate_delta(&signatures, &unrelated[..]).unwrap();
+    let mut plain = Vec::new();
+    write_delta(&delta, &mut plain).unwrap();
+    let mut checked = Vec::new();
+    write_delta_with_checksums(&delta, &mut checked).unwrap();
+
+    let mut group = c.benchmark_group("checked_data");
+    group.sample_size(10);
+    group.bench_function("generate", |b| {
+        b.iter(|| generate_delta(&signatures, &unrelated[..]).unwrap());
+    });
+    group.bench_function("encode", |b| {
+        b.iter(|| write_delta(&delta, Vec::with_capacity(plain.len())).unwrap());
+    });
+    group.bench_function("encode_checked", |b| {
+        b.iter(|| {
+            write_delta_with_checksums(&delta, Vec::with_capacity(checked.len())).unwrap();
+        });
+    });
+    group.bench_function("apply", |b| {
+        b.iter(|| apply_encoded(Cursor::new(&original), &plain[..], Vec::new()).unwrap());
+    });
+    group.bench_function("apply_checked", |b| {
+        b.iter(|| apply_encoded(Cursor::new(&original), &checked[..], Vec::new()).unwrap());
+    });
+    group.finish();
+}
+
 criterion_group!(
     benches,
     benchmark_signature_generation,
     benchmark_delta_generation,
     benchmark_patch_application,
     benchmark_end_to_end,
+    benchmark_positioned_apply,
+    benchmark_fragmented_apply,
+    benchmark_parallel_delta,
+    benchmark_parallel_signatures,
+    benchmark_unmatched_delta,
+    benchmark_checked_data,
 );
 
 criterion_main!(benches);
@@ -0,0 +1,135 @@
+use std::io::{Read, Seek, SeekFrom};
+
+/// `Read + Seek` adapter that only issues sector-aligned reads to the inner reader.
+///
+/// Raw block devices reject reads that do not start at, and span a whole number of, sectors.
+/// This adapter serves arbitrary reads and seeks from an internal bounce buffer filled with
+/// aligned reads, so it can be used as the base of `apply_delta` or fed to signature
+/// generation. The size of the device is found with `SeekFrom::End(0)`, which block devices
+/// support even though their metadata reports a length of zero.
+///
+/// Opening the device with `O_DIRECT` additionally requires an aligned memory buffer, which
+/// this adapter does not provide.
+pub struct SectorAlignedReader<R> {
+    inner: R,
+    sector_size: usize,
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    buffer_len: usize,
+    pos: u64,
+    len: Option<u64>,
+}
+
+impl<R: Read + Seek> SectorAlignedReader<R> {
+    /// Wrap `inner`, reading `sectors_per_read` sectors of `sector_size` bytes at a time.
+    ///
+    /// # Panics
+    /// Panics if `sector_size` or `sectors_per_read` is zero.
+    #[must_use]
+    pub fn new(inner: R, sector_size: usize, sectors_per_read: usize) -> Self {
+        assert!(sector_size > 0, "sector size must be greater than zero");
+        assert!(
+            sectors_per_read > 0,
+            "sectors per read must be greater than zero"
+        );
+        Self {
+            inner,
+            sector_size,
+            buffer: vec![0u8; sector_size * sectors_per_read],
+            buffer_start: 0,
+            buffer_len: 0,
+            pos: 0,
+            len: None,
+        }
+    }
+
+    /// Size of the underlying device or file.
+    ///
+    /// # Errors
+    /// Returns an error if seeking the inner reader fails.
+    pub fn len(&mut self) -> std::io::Result<u64> {
+        if let Some(len) = self.len {
+            return Ok(len);
+        }
+        let len = self.inner.seek(SeekFrom::End(0))?;
+        // Force the next read to seek back to an aligned offset.
+        self.buffer_len = 0;
+        self.len = Some(len);
+        Ok(len)
+    }
+
+    /// Whether the underlying device or file is empty.
+    ///
+    /// # Errors
+    /// Returns an error if seeking the inner reader fails.
+    pub fn is_empty(&mut self) -> std::io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    #[must_use]
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    fn fill_buffer(&mut self) -> std::io::Result<()> {
+        let sector_size = self.sector_size as u64;
+        let aligned_start = self.pos / sector_size * sector_size;
+        self.inner.seek(SeekFrom::Start(aligned_start))?;
+        self.buffer_start = aligned_start;
+        self.buffer_len = 0;
+
+        // Keep reading only while the next read stays aligned; a short read means the end of
+        // the device was reached.
+        while self.buffer_len < self.buffer.len() {
+            match self.inner.read(&mut self.buffer[self.buffer_len..]) {
+                Ok(0) => break,
+                Ok(n) => {
+                    self.buffer_len += n;
+                    if !self.buffer_len.is_multiple_of(self.sector_size) {
+                        break;
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SectorAlignedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let buffer_end = self.buffer_start + self.buffer_len as u64;
+        if self.pos < self.buffer_start || self.pos >= buffer_end {
+            self.fill_buffer()?;
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = (self.pos - self.buffer_start) as usize;
+        if offset >= self.buffer_len {
+            return Ok(0);
+        }
+        let available = &self.buffer[offset..self.buffer_len];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SectorAlignedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.len()?.checked_add_signed(delta),
+        };
+        self.pos = new_pos.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
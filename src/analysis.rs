@@ -0,0 +1,151 @@
+//! Slow, exhaustive analysis of how much reuse a delta achieves, for tuning block sizes.
+//!
+//! Everything here works on in-memory inputs and is quadratic in the worst case: use it on
+//! samples, not on production-sized files.
+
+use crate::DeltaCommand;
+use std::collections::HashMap;
+
+/// Shortest run of identical bytes counted as reusable.
+pub const MIN_MATCH: usize = 8;
+
+/// Maximum number of base positions tried when extending a match.
+const MAX_CANDIDATES: usize = 64;
+
+/// How a delta's reuse of the base compares to the best byte-granular reuse.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AnalysisReport {
+    /// Size of the new data.
+    pub new_len: u64,
+    /// Bytes of the new data found in the base by a greedy byte-granular search.
+    pub reusable_bytes: u64,
+    /// Bytes the delta copies from the base.
+    pub matched_bytes: u64,
+    /// Bytes the delta sends as data. Bytes copied from the output itself count as neither
+    /// matched nor literal.
+    pub literal_bytes: u64,
+    /// Reusable bytes in runs shorter than a block, which block matching cannot find.
+    pub missed_below_block_size: u64,
+    /// Reusable bytes in long enough runs that do not cover whole, aligned base blocks.
+    pub missed_misalignment: u64,
+}
+
+impl AnalysisReport {
+    /// Reusable bytes the delta did not copy.
+    #[inline]
+    #[must_use]
+    pub fn missed_bytes(&self) -> u64 {
+        self.reusable_bytes.saturating_sub(self.matched_bytes)
+    }
+
+    /// Fraction of the reusable bytes the delta copied: 1.0 for a matcher as good as the
+    /// byte-granular search, and also when nothing is reusable.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn efficiency(&self) -> f64 {
+        if self.reusable_bytes == 0 {
+            return 1.0;
+        }
+        (self.matched_bytes.min(self.reusable_bytes) as f64) / self.reusable_bytes as f64
+    }
+}
+
+/// Bytes of the base run `old_start..old_start + len` that block matching can copy.
+fn coverable(old_start: usize, len: usize, old_len: usize, block_size: usize) -> usize {
+    let end = old_start + len;
+    let first = old_start.div_ceil(block_size) * block_size;
+    let mut last = end / block_size * block_size;
+    if end == old_len && last < end && first <= last {
+        last = end;
+    }
+    last.saturating_sub(first)
+}
+
+/// Fraction of the `new_len` bytes of the new data that `delta` copies from the base, 1.0 for
+/// empty new data.
+///
+/// Unlike [`analyze`], this needs neither input and is cheap enough to track on every delta,
+/// but it cannot tell a weak matcher from new data that shares little with the base.
+#[must_use]
+#[allow(clippy::cast_precision_loss)]
+pub fn delta_efficiency(delta: &[DeltaCommand], new_len: u64) -> f64 {
+    if new_len == 0 {
+        return 1.0;
+    }
+    let copied = delta
+        .iter()
+        .filter_map(|command| match command {
+            DeltaCommand::Copy { length, .. } => Some(*length as u64),
+            _ => None,
+        })
+        .fold(0, u64::saturating_add);
+    copied as f64 / new_len as f64
+}
+
+/// Compare `delta` from `old` to `new` against the best reuse found byte by byte.
+///
+/// `block_size` is the block size of the signatures the delta was generated from.
+#[must_use]
+pub fn analyze(
+    old: &[u8],
+    new: &[u8],
+    delta: &[DeltaCommand],
+    block_size: usize,
+) -> AnalysisReport {
+    let block_size = block_size.max(1);
+    let mut report = AnalysisReport {
+        new_len: new.len() as u64,
+        ..AnalysisReport::default()
+    };
+    for command in delta {
+        let len = command.output_len() as u64;
+        match command {
+            DeltaCommand::Copy { .. } => {
+                report.matched_bytes = report.matched_bytes.saturating_add(len);
+            }
+            DeltaCommand::Data(_) => {
+                report.literal_bytes = report.literal_bytes.saturating_add(len);
+            }
+            DeltaCommand::SelfCopy { .. } => {}
+        }
+    }
+
+    let mut grams: HashMap<&[u8], Vec<usize>> = HashMap::new();
+    for (position, gram) in old.windows(MIN_MATCH).enumerate() {
+        let positions = grams.entry(gram).or_default();
+        if positions.len() < MAX_CANDIDATES {
+            positions.push(position);
+        }
+    }
+
+    let mut i = 0;
+    while i + MIN_MATCH <= new.len() {
+        let best = grams
+            .get(&new[i..i + MIN_MATCH])
+            .into_iter()
+            .flatten()
+            .map(|&start| {
+                let len = old[start..]
+                    .iter()
+                    .zip(&new[i..])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                (len, start)
+            })
+            .max_by_key(|&(len, start)| (len, coverable(start, len, old.len(), block_size)));
+
+        let Some((len, start)) = best else {
+            i += 1;
+            continue;
+        };
+        report.reusable_bytes += len as u64;
+        if len < block_size {
+            report.missed_below_block_size += len as u64;
+        } else {
+            report.missed_misalignment +=
+                (len - coverable(start, len, old.len(), block_size)) as u64;
+        }
+        i += len;
+    }
+    report
+}
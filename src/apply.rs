@@ -0,0 +1,387 @@
+use crate::output::OpWriter;
+use crate::spans::{OpSpan, Spans, copy_out_of_bounds, self_copy_source};
+use crate::{APPLY_BUF_SIZE, DeltaCommand, ReadAt, SELF_COPY_WINDOW, try_alloc_buffer};
+use std::borrow::Borrow;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::iter::Peekable;
+use std::ops::Range;
+
+/// Largest run of unused base bytes read to serve two nearby copies with a single read.
+const MAX_READ_GAP: u64 = 4096;
+
+/// Hooks called by [`apply_commands`] as it writes the output.
+pub(crate) trait ApplyObserver {
+    /// Bytes of the command of `span` were written at `output_range`. `source` is the range
+    /// they were read from: in the base for a copy, in the output for a self-referential copy.
+    fn applied(&mut self, span: &OpSpan, output_range: Range<u64>, source: Option<Range<u64>>);
+}
+
+impl ApplyObserver for () {
+    #[inline]
+    fn applied(&mut self, _: &OpSpan, _: Range<u64>, _: Option<Range<u64>>) {}
+}
+
+/// Something [`apply_spans`] wrote, kept to read the output back for self-referential copies.
+enum Written<C> {
+    /// A copy or data command, whose bytes are read again from the base or its data.
+    Command(C),
+    /// Bytes written by a self-referential copy.
+    Copied(Vec<u8>),
+}
+
+/// What the last [`SELF_COPY_WINDOW`] bytes of the output were made of.
+///
+/// Copies and data are kept as the commands themselves and only read again when a
+/// self-referential copy needs them, so deltas without any cost no copy of their output.
+struct History<C> {
+    entries: VecDeque<(OpSpan, Written<C>)>,
+}
+
+impl<C: Borrow<DeltaCommand>> History<C> {
+    /// Record what was written at `span`, forgetting what is now out of the window.
+    fn push(&mut self, span: OpSpan, written: Written<C>) {
+        let keep_from = span
+            .output_range
+            .end
+            .saturating_sub(SELF_COPY_WINDOW as u64);
+        self.entries.push_back((span, written));
+        while self
+            .entries
+            .front()
+            .is_some_and(|(span, _)| span.output_range.end <= keep_from)
+        {
+            self.entries.pop_front();
+        }
+    }
+
+    /// Fill `buf` with the output at `offset`, which must be within the window.
+    fn read<B: ReadAt + ?Sized>(
+        &self,
+        base: &B,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> std::io::Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            let pos = offset + filled as u64;
+            let index = self
+                .entries
+                .partition_point(|(span, _)| span.output_range.end <= pos);
+            let (span, written) = &self.entries[index];
+            #[allow(clippy::cast_possible_truncation)]
+            let (skip, len) = (
+                (pos - span.output_range.start) as usize,
+                (span.output_range.end - pos).min((buf.len() - filled) as u64) as usize,
+            );
+            let dest = &mut buf[filled..filled + len];
+            match written {
+                Written::Copied(bytes) => dest.copy_from_slice(&bytes[skip..skip + len]),
+                Written::Command(command) => match (command.borrow(), &span.basis_range) {
+                    (DeltaCommand::Data(data), _) => {
+                        dest.copy_from_slice(&data[skip..skip + len]);
+                    }
+                    (_, Some(basis_range)) => base
+                        .read_exact_at(dest, basis_range.start + skip as u64)
+                        .map_err(|e| out_of_bounds(e, span))?,
+                    (_, None) => unreachable!("self-referential copies are kept as bytes"),
+                },
+            }
+            filled += len;
+        }
+        Ok(())
+    }
+}
+
+/// Output of [`apply_spans`], dropping the bytes an earlier apply already wrote.
+struct Output<'a, W> {
+    writer: &'a mut W,
+    /// Output offset of the next byte.
+    pos: u64,
+    /// Bytes before this offset are not written again.
+    resume_at: u64,
+}
+
+impl<W: Write> Output<'_, W> {
+    fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        #[allow(clippy::cast_possible_truncation)]
+        let skip = self
+            .resume_at
+            .saturating_sub(self.pos)
+            .min(bytes.len() as u64) as usize;
+        self.pos += bytes.len() as u64;
+        self.writer.write_all(&bytes[skip..])
+    }
+}
+
+/// Turn a base ending early while reading the copy of `span` into a [`CopyOutOfBounds`].
+///
+/// [`CopyOutOfBounds`]: crate::CopyOutOfBounds
+fn out_of_bounds(e: std::io::Error, span: &OpSpan) -> std::io::Error {
+    match &span.basis_range {
+        Some(basis_range) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            copy_out_of_bounds(span.op_index, basis_range.clone())
+        }
+        _ => e,
+    }
+}
+
+/// Read the copy of `span` from `base` and write it, one buffer at a time.
+fn copy_at<B: ReadAt + ?Sized, W: Write, O: ApplyObserver>(
+    base: &B,
+    output: &mut Output<'_, W>,
+    buffer: &mut [u8],
+    span: &OpSpan,
+    observer: &mut O,
+) -> std::io::Result<()> {
+    let Some(basis_range) = &span.basis_range else {
+        return Ok(());
+    };
+    let mut offset = basis_range.start;
+    while offset < basis_range.end {
+        #[allow(clippy::cast_possible_truncation)]
+        let len = (basis_range.end - offset).min(buffer.len() as u64) as usize;
+        base.read_exact_at(&mut buffer[..len], offset)
+            .map_err(|e| out_of_bounds(e, span))?;
+        let output_start = output.pos;
+        output.write_all(&buffer[..len])?;
+        observer.applied(
+            span,
+            output_start..output.pos,
+            Some(offset..offset + len as u64),
+        );
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+/// Copy `source` from the output to the end of the output, one buffer at a time.
+///
+/// `source` may run into the bytes the copy itself writes, which are added to the history
+/// before being read back.
+fn copy_from_history<B: ReadAt + ?Sized, W: Write, C: Borrow<DeltaCommand>, O: ApplyObserver>(
+    base: &B,
+    output: &mut Output<'_, W>,
+    history: &mut History<C>,
+    buffer: &mut [u8],
+    span: &OpSpan,
+    source: &Range<u64>,
+    observer: &mut O,
+) -> std::io::Result<()> {
+    if span.output_range.start - source.start > SELF_COPY_WINDOW as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "command {} copies output at offset {}, more than {SELF_COPY_WINDOW} bytes before offset {}",
+                span.op_index, source.start, span.output_range.start
+            ),
+        ));
+    }
+    let mut from = source.start;
+    while from < source.end {
+        #[allow(clippy::cast_possible_truncation)]
+        let len = (source.end - from)
+            .min(output.pos - from)
+            .min(buffer.len() as u64) as usize;
+        history.read(base, from, &mut buffer[..len])?;
+        let output_start = output.pos;
+        output.write_all(&buffer[..len])?;
+        observer.applied(
+            span,
+            output_start..output.pos,
+            Some(from..from + len as u64),
+        );
+        let piece = OpSpan {
+            output_range: output_start..output.pos,
+            ..span.clone()
+        };
+        history.push(piece, Written::Copied(buffer[..len].to_vec()));
+        from += len as u64;
+    }
+    Ok(())
+}
+
+/// Read into `buf` from `offset` until it is full or the base ends, returning how many bytes
+/// were read.
+fn read_up_to_at<B: ReadAt + ?Sized>(
+    base: &B,
+    buf: &mut [u8],
+    offset: u64,
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match base.read_at(&mut buf[filled..], offset + filled as u64) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+/// Add to `batch` the commands following a copy of `first` whose copies can be served by the
+/// same read of at most `buffer_len` bytes, returning where that read ends.
+fn extend_batch<I, C>(
+    spans: &mut Peekable<I>,
+    first: &Range<u64>,
+    buffer_len: u64,
+    batch: &mut Vec<(OpSpan, C)>,
+) -> u64
+where
+    I: Iterator<Item = std::io::Result<(OpSpan, C)>>,
+    C: Borrow<DeltaCommand>,
+{
+    let mut window_end = first.end;
+    let mut data_len = 0;
+    while let Some(Ok(item)) = spans.next_if(|item| {
+        let Ok((next, command)) = item else {
+            return false;
+        };
+        let Some(range) = &next.basis_range else {
+            let DeltaCommand::Data(data) = command.borrow() else {
+                return false;
+            };
+            data_len += data.len();
+            return data_len <= APPLY_BUF_SIZE;
+        };
+        let fits = range.start >= window_end
+            && range.start - window_end <= MAX_READ_GAP
+            && range.end - first.start <= buffer_len;
+        if fits {
+            window_end = range.end;
+        }
+        fits
+    }) {
+        batch.push(item);
+    }
+    window_end
+}
+
+/// Apply `delta` to `base`, writing the output to `writer` from offset `already_written` on.
+///
+/// Same as [`apply_spans`], for a delta held as commands.
+pub(crate) fn apply_commands<B, W, I, O>(
+    base: &B,
+    delta: I,
+    writer: &mut W,
+    already_written: u64,
+    observer: &mut O,
+) -> std::io::Result<()>
+where
+    B: ReadAt + ?Sized,
+    W: OpWriter,
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+    O: ApplyObserver,
+{
+    apply_spans(
+        base,
+        Spans::new(delta.into_iter()),
+        writer,
+        already_written,
+        observer,
+    )
+}
+
+/// Apply the commands of `spans` to `base`, writing the output to `writer` from offset
+/// `already_written` on.
+///
+/// This is the loop behind every streaming apply function. Copies reading nearby base ranges
+/// in increasing order are served by a single read covering all of them. The commands behind
+/// the last [`SELF_COPY_WINDOW`] bytes of output are kept to resolve self-referential copies,
+/// which read their source again from the base or the data of those commands. When resuming,
+/// the commands before `already_written` are only recorded, except self-referential copies,
+/// which are applied again without writing their output for later ones to read. `observer`
+/// is told about every piece of output once it is written.
+///
+/// `spans` must tile the output in order, but may split a command into several items.
+pub(crate) fn apply_spans<B, W, I, C, O>(
+    base: &B,
+    spans: I,
+    writer: &mut W,
+    already_written: u64,
+    observer: &mut O,
+) -> std::io::Result<()>
+where
+    B: ReadAt + ?Sized,
+    W: OpWriter,
+    I: Iterator<Item = std::io::Result<(OpSpan, C)>>,
+    C: Borrow<DeltaCommand>,
+    O: ApplyObserver,
+{
+    let mut buffer = try_alloc_buffer(APPLY_BUF_SIZE)?;
+    let buffer_len = buffer.len() as u64;
+    let mut output = Output {
+        writer,
+        pos: 0,
+        resume_at: already_written,
+    };
+    let mut history = History {
+        entries: VecDeque::new(),
+    };
+    let mut spans = spans.peekable();
+    let mut batch = Vec::new();
+
+    while let Some(item) = spans.next() {
+        let (span, command) = item?;
+        if let Some(source) = self_copy_source(&span, command.borrow())? {
+            output.writer.start_op(span.op_index);
+            copy_from_history(
+                base,
+                &mut output,
+                &mut history,
+                &mut buffer,
+                &span,
+                &source,
+                observer,
+            )?;
+            continue;
+        }
+        if span.output_range.end <= already_written {
+            output.pos = span.output_range.end;
+            history.push(span, Written::Command(command));
+            continue;
+        }
+        output.writer.start_op(span.op_index);
+        let Some(first) = span.basis_range.clone() else {
+            if let DeltaCommand::Data(data) = command.borrow() {
+                output.write_all(data)?;
+            }
+            observer.applied(&span, span.output_range.clone(), None);
+            history.push(span, Written::Command(command));
+            continue;
+        };
+        if first.end - first.start > buffer_len {
+            copy_at(base, &mut output, &mut buffer, &span, observer)?;
+            history.push(span, Written::Command(command));
+            continue;
+        }
+
+        batch.push((span, command));
+        let window_end = extend_batch(&mut spans, &first, buffer_len, &mut batch);
+        #[allow(clippy::cast_possible_truncation)]
+        let window = &mut buffer[..(window_end - first.start) as usize];
+        // A base ending within the read fails the first copy reaching past it, after the
+        // output of the commands before it is written.
+        let available = first.start + read_up_to_at(base, window, first.start)? as u64;
+        for (span, command) in batch.drain(..) {
+            output.writer.start_op(span.op_index);
+            #[allow(clippy::cast_possible_truncation)]
+            output.write_all(match (&span.basis_range, command.borrow()) {
+                (Some(range), _) if !range.is_empty() && range.end > available => {
+                    return Err(copy_out_of_bounds(span.op_index, range.clone()));
+                }
+                (Some(range), _) => {
+                    &window
+                        [(range.start - first.start) as usize..(range.end - first.start) as usize]
+                }
+                (None, DeltaCommand::Data(data)) => data,
+                (None, _) => &[],
+            })?;
+            observer.applied(&span, span.output_range.clone(), span.basis_range.clone());
+            history.push(span, Written::Command(command));
+        }
+    }
+    Ok(())
+}
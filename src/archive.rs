@@ -0,0 +1,337 @@
+//! Self-contained sync archives: a file followed by its own signatures.
+//!
+//! A recipient holding an older version of an archive can produce signatures for it without
+//! rehashing the payload, and the sender of a newer version can delta against them without a
+//! separate signature file. An archive is laid out as:
+//!
+//! | Section    | Contents                                                          |
+//! |------------|-------------------------------------------------------------------|
+//! | payload    | the data, unchanged                                               |
+//! | signatures | the signatures of the payload, encoded as described below         |
+//! | footer     | payload length, signatures length, checksum, version, magic `LS3A` |
+//!
+//! From version 3, the signatures section holds the signatures exactly as written by
+//! [`Signatures::write_to`], in the `LS3S` layout of [`crate::encoding`]. Versions 1 and 2
+//! used a layout of their own: the block size, the source size, a flags byte (bit 0: an
+//! Adler-32 of the whole payload follows, bit 1: every block carries a CRC-32) and then the
+//! block index, weak hash and strong hash of every block. Integers are LEB128 varints,
+//! hashes are fixed-width little-endian.
+//!
+//! The footer always ends with its version byte and the magic bytes, so it is found by reading
+//! the last bytes of the file. Version 1 holds the two lengths as little-endian `u64`s; version
+//! 2 adds the xxh3-128 of the signatures section after them, and version 3 has the same
+//! footer as version 2. All three are read, only version 3 is written.
+
+use crate::encoding::{read_varint, to_usize};
+use crate::{
+    DEFAULT_BLOCK_SIZE, FinalChunkMode, MAX_BLOCK_BUFFER_SIZE, SignatureStrong, Signatures,
+    generate_signatures_impl, xxh3_128,
+};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+pub const ARCHIVE_MAGIC: [u8; 4] = *b"LS3A";
+/// Footer version written by [`write_with_signature`].
+pub const ARCHIVE_VERSION: u8 = 3;
+/// Footer versions read by [`read_signature`] and [`payload_reader`].
+pub const ARCHIVE_READ_VERSIONS: [u8; 3] = [1, 2, 3];
+
+const FLAG_FILE_ADLER: u8 = 0x01;
+const FLAG_CRC32: u8 = 0x02;
+/// Version byte and magic, present at the end of every footer version.
+const TRAILER_LEN: usize = 1 + ARCHIVE_MAGIC.len();
+
+/// How the signatures embedded in an archive are generated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ArchiveOptions {
+    pub block_size: usize,
+    /// Store a CRC-32 of every block, see `generate_signatures_with_crc32`.
+    pub crc32: bool,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+            crc32: false,
+        }
+    }
+}
+
+fn invalid(msg: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reader copying everything it reads to `writer`.
+struct Tee<R, W> {
+    reader: R,
+    writer: W,
+    len: u64,
+}
+
+impl<R: Read, W: Write> Read for Tee<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.writer.write_all(&buf[..n])?;
+        self.len += n as u64;
+        Ok(n)
+    }
+}
+
+fn read_array<const N: usize, R: Read>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Decode the signatures section of a version 1 or 2 archive.
+fn decode_legacy_signatures(mut section: &[u8]) -> std::io::Result<Signatures> {
+    let reader = &mut section;
+    let mut signatures = Signatures::new(to_usize(read_varint(reader)?)?);
+    signatures.source_size = read_varint(reader)?;
+    let [flags] = read_array(reader)?;
+    if flags & !(FLAG_FILE_ADLER | FLAG_CRC32) != 0 {
+        return Err(invalid(format!("unknown signature flags {flags:#04x}")));
+    }
+    if flags & FLAG_FILE_ADLER != 0 {
+        signatures.file_adler = Some(u32::from_le_bytes(read_array(reader)?));
+    }
+    let count = read_varint(reader)?;
+    for _ in 0..count {
+        let block_index = to_usize(read_varint(reader)?)?;
+        let weak = u32::from_le_bytes(read_array(reader)?);
+        let strong = u128::from_le_bytes(read_array(reader)?);
+        let crc32 = if flags & FLAG_CRC32 == 0 {
+            None
+        } else {
+            Some(u32::from_le_bytes(read_array(reader)?))
+        };
+        signatures.insert(
+            weak,
+            SignatureStrong {
+                strong,
+                block_index,
+                crc32,
+            },
+        );
+    }
+    if !section.is_empty() {
+        return Err(invalid(format!(
+            "{} unexpected bytes after the signatures",
+            section.len()
+        )));
+    }
+    Ok(signatures)
+}
+
+/// Write `data` to `out` followed by its signatures and the archive footer.
+///
+/// The data is hashed as it is copied, so it is read only once. Returns the signatures that
+/// were embedded.
+///
+/// # Errors
+/// Returns an error if the block size is zero, or if reading `data` or writing `out` fails.
+pub fn write_with_signature<R: Read, W: Write>(
+    data: R,
+    out: W,
+    opts: ArchiveOptions,
+) -> std::io::Result<Signatures> {
+    if opts.block_size == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "block size must be greater than zero",
+        ));
+    }
+
+    let mut tee = Tee {
+        reader: data,
+        writer: BufWriter::new(out),
+        len: 0,
+    };
+    let signatures = generate_signatures_impl(
+        &mut tee,
+        opts.block_size,
+        MAX_BLOCK_BUFFER_SIZE,
+        opts.crc32,
+        FinalChunkMode::Exact,
+    )?;
+    let Tee {
+        mut writer, len, ..
+    } = tee;
+
+    let mut section = Vec::new();
+    signatures.write_to(&mut section)?;
+    writer.write_all(&section)?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&(section.len() as u64).to_le_bytes())?;
+    writer.write_all(&xxh3_128(&section).to_le_bytes())?;
+    writer.write_all(&[ARCHIVE_VERSION])?;
+    writer.write_all(&ARCHIVE_MAGIC)?;
+    writer.flush()?;
+    Ok(signatures)
+}
+
+/// Location of the sections of an archive, read from its footer.
+struct Footer {
+    version: u8,
+    payload_len: u64,
+    signatures_len: u64,
+    checksum: Option<u128>,
+}
+
+fn read_footer<R: Read + Seek>(reader: &mut R) -> std::io::Result<Footer> {
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    let not_an_archive = || invalid("not a sync archive: footer not found");
+    if file_len < TRAILER_LEN as u64 {
+        return Err(not_an_archive());
+    }
+    reader.seek(SeekFrom::Start(file_len - TRAILER_LEN as u64))?;
+    let [version, magic @ ..] = read_array::<TRAILER_LEN, _>(reader)?;
+    if magic != ARCHIVE_MAGIC {
+        return Err(not_an_archive());
+    }
+
+    let fields_len: usize = match version {
+        1 => 16,
+        2 | 3 => 32,
+        _ => return Err(invalid(format!("unsupported archive version {version}"))),
+    };
+    let footer_len = (fields_len + TRAILER_LEN) as u64;
+    if file_len < footer_len {
+        return Err(invalid("archive footer is truncated"));
+    }
+    reader.seek(SeekFrom::Start(file_len - footer_len))?;
+    let payload_len = u64::from_le_bytes(read_array(reader)?);
+    let signatures_len = u64::from_le_bytes(read_array(reader)?);
+    let checksum = if version >= 2 {
+        Some(u128::from_le_bytes(read_array(reader)?))
+    } else {
+        None
+    };
+
+    if payload_len
+        .checked_add(signatures_len)
+        .and_then(|len| len.checked_add(footer_len))
+        != Some(file_len)
+    {
+        return Err(invalid(format!(
+            "archive footer describes {payload_len} payload bytes and {signatures_len} signature bytes, which do not fit in {file_len} bytes"
+        )));
+    }
+    Ok(Footer {
+        version,
+        payload_len,
+        signatures_len,
+        checksum,
+    })
+}
+
+/// Read the signatures embedded in the archive at `path`.
+///
+/// Only the footer and the signatures section are read, however large the payload.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, is not an archive, uses an unknown footer
+/// version, or if its signatures are corrupt.
+pub fn read_signature<P: AsRef<Path>>(path: P) -> std::io::Result<Signatures> {
+    let mut file = File::open(path)?;
+    let footer = read_footer(&mut file)?;
+    file.seek(SeekFrom::Start(footer.payload_len))?;
+    let mut section = Vec::new();
+    (&mut file)
+        .take(footer.signatures_len)
+        .read_to_end(&mut section)?;
+    if let Some(checksum) = footer.checksum
+        && xxh3_128(&section) != checksum
+    {
+        return Err(invalid("archive signatures do not match their checksum"));
+    }
+    if footer.version < 3 {
+        return decode_legacy_signatures(&section);
+    }
+    let mut reader = &section[..];
+    let signatures = Signatures::read_from(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(invalid(format!(
+            "{} unexpected bytes after the signatures",
+            reader.len()
+        )));
+    }
+    Ok(signatures)
+}
+
+/// Payload of an archive, without the signatures and footer that follow it.
+///
+/// Reads and seeks behave as if the payload were the whole file, so it can be hashed or used
+/// as the base of `apply_delta` directly.
+#[derive(Debug)]
+pub struct PayloadReader {
+    file: File,
+    len: u64,
+    pos: u64,
+}
+
+impl PayloadReader {
+    /// Length of the payload in bytes.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Read for PayloadReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.pos);
+        let want = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        if want == 0 {
+            return Ok(0);
+        }
+        let n = self.file.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for PayloadReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let new_pos = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        let new_pos = new_pos.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        self.pos = self.file.seek(SeekFrom::Start(new_pos))?;
+        Ok(self.pos)
+    }
+}
+
+/// Open the payload of the archive at `path`.
+///
+/// # Errors
+/// Returns an error if the file cannot be read, is not an archive or uses an unknown footer
+/// version.
+pub fn payload_reader<P: AsRef<Path>>(path: P) -> std::io::Result<PayloadReader> {
+    let mut file = File::open(path)?;
+    let footer = read_footer(&mut file)?;
+    file.rewind()?;
+    Ok(PayloadReader {
+        file,
+        len: footer.payload_len,
+        pos: 0,
+    })
+}
@@ -0,0 +1,104 @@
+//! Delta generation against signatures read in place from rkyv bytes.
+//!
+//! [`ArchivedSignatures`] are matched exactly like the [`Signatures`](crate::Signatures) they
+//! were archived from, without deserializing them first. The crate enables the
+//! `pointer_width_64` feature of rkyv, so block sizes and indices are archived as 64-bit
+//! integers.
+
+use crate::rolling::RollingChecksum;
+use crate::{
+    ArchivedFinalChunkMode, ArchivedSignatureStrong, ArchivedSignatures, BlockEntry, DeltaBuilder,
+    DeltaCommand, FinalChunkMode, SignatureWeak, find_block_except, padded_tail, unmatchable_tail,
+};
+use std::io::Read;
+
+impl BlockEntry for ArchivedSignatureStrong {
+    #[inline]
+    fn strong(&self) -> u128 {
+        self.strong.to_native()
+    }
+
+    #[inline]
+    #[allow(clippy::cast_possible_truncation)]
+    fn block_index(&self) -> usize {
+        self.block_index.to_native() as usize
+    }
+
+    #[inline]
+    fn crc32(&self) -> Option<u32> {
+        self.crc32.as_ref().map(|crc| crc.to_native())
+    }
+}
+
+impl ArchivedSignatures {
+    #[inline]
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn block_size(&self) -> usize {
+        self.block_size.to_native() as usize
+    }
+
+    /// Number of bytes of the source covered by these signatures.
+    #[inline]
+    #[must_use]
+    pub fn source_size(&self) -> u64 {
+        self.source_size.to_native()
+    }
+
+    /// How the last block was hashed when shorter than the block size.
+    #[inline]
+    #[must_use]
+    pub fn final_chunk_mode(&self) -> FinalChunkMode {
+        match self.final_chunk_mode {
+            ArchivedFinalChunkMode::Exact => FinalChunkMode::Exact,
+            ArchivedFinalChunkMode::PadZero => FinalChunkMode::PadZero,
+        }
+    }
+
+    /// Same as [`Signatures::from`](crate::Signatures::from).
+    #[must_use]
+    pub fn from(&self, data: &[u8]) -> Option<usize> {
+        let block_size = self.block_size();
+        if self.final_chunk_mode().pads(data.len(), block_size) {
+            let mut padded = data.to_vec();
+            padded.resize(block_size, 0);
+            return self.find_within(RollingChecksum::compute(&padded), &padded, data.len());
+        }
+        self.find(RollingChecksum::compute(data), data)
+    }
+
+    /// Index of the block with weak hash `weak` and the content of `block`.
+    #[inline]
+    pub(crate) fn find(&self, weak: SignatureWeak, block: &[u8]) -> Option<usize> {
+        self.find_within(weak, block, block.len())
+    }
+
+    fn find_within(&self, weak: SignatureWeak, block: &[u8], needed: usize) -> Option<usize> {
+        let entries = self
+            .weak_to_strong
+            .get(&rkyv::Archived::<SignatureWeak>::from_native(weak))?;
+        let padded_tail = padded_tail(
+            self.final_chunk_mode(),
+            self.block_size(),
+            self.source_size(),
+        );
+        find_block_except(entries, block, unmatchable_tail(padded_tail, needed))
+    }
+}
+
+/// Same as [`generate_delta`](crate::generate_delta), matching against signatures still in
+/// their archived form.
+///
+/// # Errors
+/// Returns an error if reading from the reader fails or if the block buffer cannot be allocated.
+pub fn generate_delta_archived<R: Read>(
+    old_signatures: &ArchivedSignatures,
+    reader: R,
+) -> std::io::Result<Vec<DeltaCommand>> {
+    let mut result = Vec::new();
+    DeltaBuilder::from_archived(old_signatures)?.generate(reader, |command| {
+        result.push(command);
+        Ok(())
+    })?;
+    Ok(result)
+}
@@ -0,0 +1,355 @@
+#[cfg(feature = "rkyv")]
+use crate::ArchivedSignatures;
+use crate::encoding::{encoded_command_size, encoded_data_size};
+use crate::rolling::RollingChecksum;
+use crate::{
+    AllocationFailed, DeltaCommand, SignatureWeak, Signatures, emit_copy_for_block_idx,
+    flush_last_copy, flush_pending_data, read_exact_or_eof, try_alloc_buffer,
+};
+use std::io::Read;
+
+/// Largest `Data` command a [`DeltaBuilder`] emits unless told otherwise with
+/// [`DeltaBuilder::with_max_data_len`], which also bounds the unmatched data it holds back.
+pub const DEFAULT_MAX_DATA_LEN: usize = 1024 * 1024;
+
+/// Incremental delta generation, for new data that arrives in pieces.
+///
+/// Feeding the whole new file through [`DeltaBuilder::push`] and then calling
+/// [`DeltaBuilder::finish`] produces the same commands as `generate_delta`, however the data
+/// is split. At most two blocks of new data are buffered, plus up to [`DEFAULT_MAX_DATA_LEN`]
+/// bytes of data not matched yet.
+pub struct DeltaBuilder<'a> {
+    signatures: Lookup<'a>,
+    block_size: usize,
+    window: Vec<u8>,
+    window_start: usize,
+    window_len: usize,
+    rolling: RollingChecksum,
+    rolling_valid: bool,
+    last_copy: Option<(u64, usize)>,
+    pending_data: Vec<u8>,
+    max_data_len: usize,
+    /// Encoded size of the `Data` commands counted so far, when only counting.
+    data_size: Option<usize>,
+}
+
+impl<'a> DeltaBuilder<'a> {
+    /// Start a delta against `signatures`.
+    ///
+    /// # Errors
+    /// Returns an error if the window of two blocks cannot be allocated.
+    pub fn new(signatures: &'a Signatures) -> std::io::Result<Self> {
+        Self::with_lookup(Lookup::Native(signatures), signatures.block_size())
+    }
+
+    /// Start a delta against signatures still in their archived form.
+    ///
+    /// # Errors
+    /// Returns an error if the window of two blocks cannot be allocated.
+    #[cfg(feature = "rkyv")]
+    pub fn from_archived(signatures: &'a ArchivedSignatures) -> std::io::Result<Self> {
+        Self::with_lookup(Lookup::Archived(signatures), signatures.block_size())
+    }
+
+    fn with_lookup(signatures: Lookup<'a>, block_size: usize) -> std::io::Result<Self> {
+        let window_size = block_size.checked_mul(2).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::OutOfMemory,
+                AllocationFailed {
+                    requested: usize::MAX,
+                },
+            )
+        })?;
+
+        Ok(Self {
+            signatures,
+            block_size,
+            window: try_alloc_buffer(window_size)?,
+            window_start: 0,
+            window_len: 0,
+            rolling: RollingChecksum::new(),
+            rolling_valid: false,
+            last_copy: None,
+            pending_data: Vec::new(),
+            max_data_len: DEFAULT_MAX_DATA_LEN,
+            data_size: None,
+        })
+    }
+
+    /// Split unmatched data into `Data` commands of at most `max_data_len` bytes, which also
+    /// bounds the unmatched data held back at any time. Zero is treated as one.
+    #[must_use]
+    pub fn with_max_data_len(mut self, max_data_len: usize) -> Self {
+        self.max_data_len = max_data_len.max(1);
+        self
+    }
+
+    /// Feed the next piece of new data and return the commands that are now final.
+    ///
+    /// Unmatched data and a copy that may still be extended are held back until a later push
+    /// or [`DeltaBuilder::finish`].
+    pub fn push(&mut self, mut data: &[u8]) -> Vec<DeltaCommand> {
+        let mut commands = Vec::new();
+        let mut cb = |command| {
+            commands.push(command);
+            Ok(())
+        };
+        if self.block_size == 0 {
+            // The callback never fails.
+            let _ = self.push_unmatched(data, &mut cb);
+            return commands;
+        }
+
+        while !data.is_empty() {
+            self.compact();
+            let n = data.len().min(self.window.len() - self.window_len);
+            self.window[self.window_len..self.window_len + n].copy_from_slice(&data[..n]);
+            self.window_len += n;
+            data = &data[n..];
+            // The callback never fails.
+            let _ = self.process(&mut cb);
+        }
+        commands
+    }
+
+    /// Flush everything held back and return the remaining commands.
+    #[must_use]
+    pub fn finish(mut self) -> Vec<DeltaCommand> {
+        let mut commands = Vec::new();
+        // The callback never fails.
+        let _ = self.finish_with_cb(&mut |command| {
+            commands.push(command);
+            Ok(())
+        });
+        commands
+    }
+
+    /// Run the whole of `reader` through the builder, reading directly into the window, and
+    /// pass every command to `cb` as soon as it is final.
+    ///
+    /// Memory use stays bounded however little of the new data matches the base, as unmatched
+    /// data is passed on in pieces of at most [`DEFAULT_MAX_DATA_LEN`] bytes, or the size set
+    /// with [`DeltaBuilder::with_max_data_len`].
+    ///
+    /// # Errors
+    /// Returns an error if reading from the reader fails or if the callback returns an error.
+    pub fn generate<R: Read, F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+        mut self,
+        reader: R,
+        mut cb: F,
+    ) -> std::io::Result<()> {
+        self.run(reader, &mut cb)
+    }
+
+    /// Encoded size of the delta [`DeltaBuilder::generate`] produces from `reader`, header
+    /// excluded, counted without building the payloads of `Data` commands.
+    pub(crate) fn encoded_size<R: Read>(mut self, reader: R) -> std::io::Result<usize> {
+        let mut size = 0;
+        self.data_size = Some(0);
+        self.run(reader, &mut |command| {
+            size += encoded_command_size(&command);
+            Ok(())
+        })?;
+        Ok(size + self.data_size.unwrap_or_default())
+    }
+
+    fn run<R: Read, F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+        &mut self,
+        mut reader: R,
+        cb: &mut F,
+    ) -> std::io::Result<()> {
+        if self.block_size == 0 {
+            let mut buffer = [0u8; 8192];
+            loop {
+                let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                self.push_unmatched(&buffer[..bytes_read], cb)?;
+            }
+        } else {
+            loop {
+                self.compact();
+                let bytes_read =
+                    read_exact_or_eof(&mut reader, &mut self.window[self.window_len..])?;
+                if bytes_read == 0 {
+                    break;
+                }
+                self.window_len += bytes_read;
+                self.process(cb)?;
+            }
+        }
+        self.finish_with_cb(cb)
+    }
+
+    fn compact(&mut self) {
+        if self.window_start > 0 {
+            self.window
+                .copy_within(self.window_start..self.window_len, 0);
+            self.window_len -= self.window_start;
+            self.window_start = 0;
+        }
+    }
+
+    /// Match every full block available in the window.
+    fn process<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+        &mut self,
+        cb: &mut F,
+    ) -> std::io::Result<()> {
+        let block_size = self.block_size;
+        while self.window_len - self.window_start >= block_size {
+            let block = &self.window[self.window_start..self.window_start + block_size];
+            if !self.rolling_valid {
+                self.rolling.reset();
+                self.rolling.update(block);
+                self.rolling_valid = true;
+            }
+
+            if let Some(block_idx) = self.signatures.find(self.rolling.value(), block) {
+                self.flush_data(cb)?;
+                emit_copy_for_block_idx(
+                    &mut self.last_copy,
+                    &mut self.pending_data,
+                    block_idx,
+                    block_size,
+                    block_size,
+                    cb,
+                )?;
+                self.window_start += block_size;
+                self.rolling_valid = false;
+                continue;
+            }
+
+            let old_byte = self.window[self.window_start];
+            self.pending_data.push(old_byte);
+            self.window_start += 1;
+            if self.pending_data.len() >= self.max_data_len {
+                self.flush_data(cb)?;
+            }
+
+            if self.window_len - self.window_start >= block_size {
+                let new_byte = self.window[self.window_start + block_size - 1];
+                self.rolling.roll(old_byte, new_byte, block_size);
+            } else {
+                self.rolling_valid = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue data that cannot be matched, flushing it whenever `max_data_len` is reached.
+    fn push_unmatched<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+        &mut self,
+        mut data: &[u8],
+        cb: &mut F,
+    ) -> std::io::Result<()> {
+        if self.data_size.is_none() {
+            return push_unmatched(
+                &mut self.last_copy,
+                &mut self.pending_data,
+                data,
+                self.max_data_len,
+                cb,
+            );
+        }
+        while !data.is_empty() {
+            let n = data.len().min(self.max_data_len - self.pending_data.len());
+            self.pending_data.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.pending_data.len() >= self.max_data_len {
+                self.flush_data(cb)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Pass on the data held back, or only count its encoded size and keep the buffer when
+    /// counting.
+    fn flush_data<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+        &mut self,
+        cb: &mut F,
+    ) -> std::io::Result<()> {
+        let Some(data_size) = self.data_size.as_mut() else {
+            return flush_pending_data(&mut self.last_copy, &mut self.pending_data, cb);
+        };
+        if !self.pending_data.is_empty() {
+            flush_last_copy(&mut self.last_copy, cb)?;
+            *data_size += encoded_data_size(self.pending_data.len());
+            self.pending_data.clear();
+        }
+        Ok(())
+    }
+
+    fn finish_with_cb<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+        &mut self,
+        cb: &mut F,
+    ) -> std::io::Result<()> {
+        let remaining = &self.window[self.window_start..self.window_len];
+        if !remaining.is_empty() {
+            if let Some(block_idx) = self.signatures.from(remaining) {
+                let length = remaining.len();
+                self.flush_data(cb)?;
+                emit_copy_for_block_idx(
+                    &mut self.last_copy,
+                    &mut self.pending_data,
+                    block_idx,
+                    self.block_size,
+                    length,
+                    cb,
+                )?;
+            } else {
+                let window = std::mem::take(&mut self.window);
+                self.push_unmatched(&window[self.window_start..self.window_len], cb)?;
+            }
+        }
+
+        self.flush_data(cb)?;
+        flush_last_copy(&mut self.last_copy, cb)
+    }
+}
+
+/// Queue `data`, which cannot be matched, flushing it whenever `max_data_len` is reached.
+pub(crate) fn push_unmatched<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
+    last_copy: &mut Option<(u64, usize)>,
+    pending_data: &mut Vec<u8>,
+    mut data: &[u8],
+    max_data_len: usize,
+    cb: &mut F,
+) -> std::io::Result<()> {
+    while !data.is_empty() {
+        let n = data.len().min(max_data_len - pending_data.len());
+        pending_data.extend_from_slice(&data[..n]);
+        data = &data[n..];
+        if pending_data.len() >= max_data_len {
+            flush_pending_data(last_copy, pending_data, cb)?;
+        }
+    }
+    Ok(())
+}
+
+/// Signatures a [`DeltaBuilder`] matches against.
+#[derive(Clone, Copy)]
+enum Lookup<'a> {
+    Native(&'a Signatures),
+    #[cfg(feature = "rkyv")]
+    Archived(&'a ArchivedSignatures),
+}
+
+impl Lookup<'_> {
+    #[inline]
+    fn find(self, weak: SignatureWeak, block: &[u8]) -> Option<usize> {
+        match self {
+            Self::Native(signatures) => signatures.find(weak, block),
+            #[cfg(feature = "rkyv")]
+            Self::Archived(signatures) => signatures.find(weak, block),
+        }
+    }
+
+    fn from(self, data: &[u8]) -> Option<usize> {
+        match self {
+            Self::Native(signatures) => signatures.from(data),
+            #[cfg(feature = "rkyv")]
+            Self::Archived(signatures) => signatures.from(data),
+        }
+    }
+}
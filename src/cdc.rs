@@ -0,0 +1,190 @@
+//! Content-defined chunking, for judging chunking parameters on a dataset.
+//!
+//! Chunks end where a gear hash of the last 64 bytes matches a mask, so an edit only moves the
+//! boundaries next to it: the chunks after it are found again at their new offsets.
+//! [`ChunkStrategy::Cdc`](crate::ChunkStrategy::Cdc) signs and matches data with these chunks;
+//! [`boundary_stability`] measures how well a set of parameters would do before committing to
+//! them.
+
+use crate::{read_exact_or_eof, xxh3_128};
+use std::collections::HashSet;
+use std::io::Read;
+
+const fn splitmix64(state: u64) -> u64 {
+    let mut z = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < table.len() {
+        table[i] = splitmix64(i as u64);
+        i += 1;
+    }
+    table
+};
+
+const READ_BUF_SIZE: usize = 64 * 1024;
+/// Most bytes reserved for a chunk up front. Chunks longer than this grow as they are read, so
+/// a huge `max_size` only costs memory for data that is actually there.
+const MAX_CHUNK_RESERVE: usize = 1024 * 1024;
+
+/// Chunking parameters: a chunk ends at the first position past `min_size` where
+/// `hash & mask == 0`, or at `max_size`, whichever comes first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CdcParams {
+    pub min_size: usize,
+    /// Average distance between hash boundaries is `mask + 1` for a mask of low bits.
+    pub mask: u64,
+    pub max_size: usize,
+}
+
+impl CdcParams {
+    /// Parameters cutting chunks of `min_size` to `max_size` bytes, about `avg_size` bytes on
+    /// average. The distance between hash boundaries past the minimum is rounded up to a power
+    /// of two to make the mask.
+    #[must_use]
+    pub fn new(min_size: usize, avg_size: usize, max_size: usize) -> Self {
+        let spacing = avg_size.saturating_sub(min_size).max(1).next_power_of_two();
+        Self {
+            min_size,
+            mask: spacing as u64 - 1,
+            max_size,
+        }
+    }
+}
+
+impl Default for CdcParams {
+    /// Chunks of 2 KiB to 64 KiB, 8 KiB apart on average past the minimum.
+    fn default() -> Self {
+        Self {
+            min_size: 2 * 1024,
+            mask: (1 << 13) - 1,
+            max_size: 64 * 1024,
+        }
+    }
+}
+
+/// Split `reader` into chunks and call `f` with each of them.
+///
+/// # Errors
+/// Returns an error if `max_size` is zero or smaller than `min_size`, if reading fails, or if
+/// `f` fails.
+pub fn for_each_chunk<R: Read, F: FnMut(&[u8]) -> std::io::Result<()>>(
+    mut reader: R,
+    params: CdcParams,
+    mut f: F,
+) -> std::io::Result<()> {
+    if params.max_size == 0 || params.max_size < params.min_size {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "chunk sizes {}..={} are empty",
+                params.min_size, params.max_size
+            ),
+        ));
+    }
+
+    let mut chunk = Vec::with_capacity(params.max_size.min(MAX_CHUNK_RESERVE));
+    let mut hash: u64 = 0;
+    let mut buffer = vec![0u8; READ_BUF_SIZE];
+    loop {
+        let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        for &byte in &buffer[..bytes_read] {
+            chunk.push(byte);
+            hash = (hash << 1).wrapping_add(GEAR[usize::from(byte)]);
+            if (chunk.len() >= params.min_size && hash & params.mask == 0)
+                || chunk.len() == params.max_size
+            {
+                f(&chunk)?;
+                chunk.clear();
+                hash = 0;
+            }
+        }
+    }
+    if !chunk.is_empty() {
+        f(&chunk)?;
+    }
+    Ok(())
+}
+
+/// Offsets where the chunks of `reader` end, the last one being its length.
+///
+/// # Errors
+/// Returns an error if the parameters are invalid or if reading fails.
+pub fn chunk_boundaries<R: Read>(reader: R, params: CdcParams) -> std::io::Result<Vec<u64>> {
+    let mut boundaries = Vec::new();
+    let mut offset = 0;
+    for_each_chunk(reader, params, |chunk| {
+        offset += chunk.len() as u64;
+        boundaries.push(offset);
+        Ok(())
+    })?;
+    Ok(boundaries)
+}
+
+/// How the chunks of an edited stream compare to those of the original.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StabilityReport {
+    pub old_chunks: u64,
+    pub new_chunks: u64,
+    pub new_len: u64,
+    /// Boundaries of the new stream ending a chunk that also is a chunk of the old stream.
+    pub surviving_boundaries: u64,
+    /// Bytes of the new stream in those chunks.
+    pub surviving_bytes: u64,
+    /// Number of new chunks by size: entry `i` counts sizes in `2^i..2^(i + 1)`.
+    pub size_histogram: Vec<u64>,
+}
+
+impl StabilityReport {
+    /// Average size of the chunks of the new stream.
+    #[inline]
+    #[must_use]
+    pub fn average_chunk_size(&self) -> u64 {
+        self.new_len.checked_div(self.new_chunks).unwrap_or(0)
+    }
+}
+
+/// Chunk `old` and `new` with the same parameters and report how many chunks of `new` are
+/// unchanged chunks of `old`.
+///
+/// Both streams are read once; only a hash per old chunk is kept.
+///
+/// # Errors
+/// Returns an error if the parameters are invalid or if reading either stream fails.
+pub fn boundary_stability<Ro: Read, Rn: Read>(
+    old: Ro,
+    new: Rn,
+    params: CdcParams,
+) -> std::io::Result<StabilityReport> {
+    let mut report = StabilityReport::default();
+    let mut old_chunks = HashSet::new();
+    for_each_chunk(old, params, |chunk| {
+        old_chunks.insert(xxh3_128(chunk));
+        report.old_chunks += 1;
+        Ok(())
+    })?;
+
+    for_each_chunk(new, params, |chunk| {
+        report.new_chunks += 1;
+        report.new_len += chunk.len() as u64;
+        if old_chunks.contains(&xxh3_128(chunk)) {
+            report.surviving_boundaries += 1;
+            report.surviving_bytes += chunk.len() as u64;
+        }
+        let bucket = chunk.len().ilog2() as usize;
+        if report.size_histogram.len() <= bucket {
+            report.size_histogram.resize(bucket + 1, 0);
+        }
+        report.size_histogram[bucket] += 1;
+        Ok(())
+    })?;
+    Ok(report)
+}
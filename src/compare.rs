@@ -0,0 +1,37 @@
+use crate::read_exact_or_eof;
+use std::io::Read;
+
+const COMPARE_BUF_SIZE: usize = 64 * 1024;
+
+/// Offset of the first byte where `a` and `b` differ, or `None` if they are identical.
+///
+/// Both readers are read in lockstep, one buffer at a time, so inputs of any size are compared
+/// in constant memory. When one input is a prefix of the other, the offset is the length of the
+/// shorter one, where it ends.
+///
+/// # Errors
+/// Returns an error if reading either input fails.
+pub fn first_difference<R1: Read, R2: Read>(mut a: R1, mut b: R2) -> std::io::Result<Option<u64>> {
+    let mut buf_a = vec![0u8; COMPARE_BUF_SIZE];
+    let mut buf_b = vec![0u8; COMPARE_BUF_SIZE];
+    let mut offset: u64 = 0;
+    loop {
+        let len_a = read_exact_or_eof(&mut a, &mut buf_a)?;
+        let len_b = read_exact_or_eof(&mut b, &mut buf_b)?;
+        let common = len_a.min(len_b);
+        if let Some(pos) = buf_a[..common]
+            .iter()
+            .zip(&buf_b[..common])
+            .position(|(x, y)| x != y)
+        {
+            return Ok(Some(offset + pos as u64));
+        }
+        if len_a != len_b {
+            return Ok(Some(offset + common as u64));
+        }
+        if len_a == 0 {
+            return Ok(None);
+        }
+        offset += common as u64;
+    }
+}
@@ -0,0 +1,91 @@
+//! Loading of JSON signatures and deltas written by earlier versions of the crate.
+//!
+//! Blobs kept in long-term storage must stay readable after the types change. Every field
+//! added since the first release is listed here with the value it takes when missing:
+//!
+//! | Field                     | Default when missing                                   |
+//! |---------------------------|--------------------------------------------------------|
+//! | `Signatures::source_size` | `(highest block index + 1) * block_size`               |
+//! | `SignatureStrong::crc32`  | `None`: blocks are checked on the strong hash only     |
+//! | `Signatures::file_adler`  | `None`: [`Signatures::quick_differs`] skips the Adler  |
+//! | `Signatures::generation`  | `0`, with no block generations and no dirty blocks     |
+//! | `Signatures::final_chunk_mode` | `Exact`: the last block was hashed as-is          |
+//!
+//! Old signatures did not record the length of the last block, so the default assumes it
+//! was full. Matching is unaffected; only [`Signatures::chunk_offsets`] and related
+//! accessors may report up to `block_size - 1` extra bytes at the end.
+//!
+//! `DeltaCommand` has kept the variants of the first release, with ranged copies. `SelfCopy`
+//! was added next to them, so every old delta still reads unchanged.
+//!
+//! The committed fixtures in `tests/fixtures/legacy` hold one blob per historical shape.
+//! Changing a serialized type requires adding a fixture of the new shape there.
+
+use crate::{DeltaCommand, FinalChunkMode, SignatureStrong, SignatureWeak, Signatures};
+use std::collections::HashMap;
+
+#[derive(serde::Deserialize)]
+struct LegacySignatures {
+    block_size: usize,
+    source_size: Option<u64>,
+    weak_to_strong: HashMap<SignatureWeak, Vec<SignatureStrong>>,
+    file_adler: Option<u32>,
+    #[serde(default)]
+    generation: u32,
+    #[serde(default)]
+    generations: Vec<u32>,
+    #[serde(default)]
+    dirty: Vec<usize>,
+    #[serde(default)]
+    final_chunk_mode: FinalChunkMode,
+}
+
+impl Signatures {
+    /// Deserialize signatures from JSON written by any version of the crate.
+    ///
+    /// See the [`compat`](crate::compat) module for how missing fields are filled in.
+    ///
+    /// # Errors
+    /// Returns an error of kind [`std::io::ErrorKind::InvalidData`] if `json` is not a valid
+    /// serialized `Signatures`.
+    pub fn from_legacy_json(json: &str) -> std::io::Result<Self> {
+        let legacy: LegacySignatures = serde_json::from_str(json)?;
+        if legacy.block_size == 0 && !legacy.weak_to_strong.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "signatures with blocks must have a non-zero block size",
+            ));
+        }
+
+        let source_size = legacy.source_size.unwrap_or_else(|| {
+            let blocks = legacy
+                .weak_to_strong
+                .values()
+                .flatten()
+                .map(|entry| entry.block_index as u64 + 1)
+                .max()
+                .unwrap_or(0);
+            blocks * legacy.block_size as u64
+        });
+
+        Ok(Self {
+            block_size: legacy.block_size,
+            source_size,
+            weak_to_strong: legacy.weak_to_strong.into_iter().collect(),
+            file_adler: legacy.file_adler,
+            generation: legacy.generation,
+            generations: legacy.generations,
+            dirty: legacy.dirty,
+            final_chunk_mode: legacy.final_chunk_mode,
+        })
+    }
+}
+
+/// Deserialize a delta from JSON written by any version of the crate.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::InvalidData`] if `json` is not a valid
+/// serialized list of `DeltaCommand`.
+pub fn delta_from_legacy_json(json: &str) -> std::io::Result<Vec<DeltaCommand>> {
+    Ok(serde_json::from_str(json)?)
+}
@@ -0,0 +1,78 @@
+use crate::splice::push_merged;
+use crate::{DeltaCommand, Signatures, generate_delta};
+
+/// Relative costs used to decide whether a match is worth emitting as a copy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CostModel {
+    /// Fixed cost of every `Copy` and `SelfCopy` command.
+    pub copy_cost: u64,
+    /// Cost of every byte sent as `Data`.
+    pub insert_byte_cost: u64,
+    /// Extra cost of a `Copy` that does not continue where the previous `Copy` ended.
+    pub seek_cost: u64,
+}
+
+impl CostModel {
+    /// Estimated cost of applying `delta`.
+    #[must_use]
+    pub fn cost(&self, delta: &[DeltaCommand]) -> u64 {
+        let mut base_pos = 0;
+        let mut total = 0;
+        for command in delta {
+            match command {
+                DeltaCommand::Data(data) => total += data.len() as u64 * self.insert_byte_cost,
+                DeltaCommand::Copy { offset, length } => {
+                    total += self.copy_cost;
+                    if *offset != base_pos {
+                        total += self.seek_cost;
+                    }
+                    base_pos = offset + *length as u64;
+                }
+                DeltaCommand::SelfCopy { .. } => total += self.copy_cost,
+            }
+        }
+        total
+    }
+}
+
+/// Generate a delta that minimizes the estimated cost under `cost_model` rather than its size.
+///
+/// The regular matcher runs first, then every copy whose estimated cost (including the seek it
+/// would cause) exceeds sending its bytes as data is turned into data, greedily from the start.
+///
+/// # Errors
+/// Returns an error if generating the underlying delta fails.
+pub fn generate_delta_with_cost(
+    old_signatures: &Signatures,
+    new_data: &[u8],
+    cost_model: &CostModel,
+) -> std::io::Result<Vec<DeltaCommand>> {
+    let delta = generate_delta(old_signatures, new_data)?;
+
+    let mut result: Vec<DeltaCommand> = Vec::with_capacity(delta.len());
+    let mut output_pos = 0;
+    let mut base_pos = 0;
+    for command in delta {
+        let start = output_pos;
+        output_pos += command.output_len();
+
+        let command = match command {
+            DeltaCommand::Copy { offset, length } => {
+                let mut copy_cost = cost_model.copy_cost;
+                if offset != base_pos {
+                    copy_cost += cost_model.seek_cost;
+                }
+                if length as u64 * cost_model.insert_byte_cost < copy_cost {
+                    DeltaCommand::Data(new_data[start..output_pos].to_vec())
+                } else {
+                    base_pos = offset + length as u64;
+                    DeltaCommand::Copy { offset, length }
+                }
+            }
+            command => command,
+        };
+
+        push_merged(&mut result, command);
+    }
+    Ok(result)
+}
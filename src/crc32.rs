@@ -0,0 +1,120 @@
+//! CRC-32 (IEEE 802.3, reflected, polynomial `0xEDB88320`), as used by zlib and gzip, and
+//! CRC-32C (Castagnoli, reflected, polynomial `0x82F63B78`), as used by iSCSI and ext4.
+
+const POLY: u32 = 0xEDB8_8320;
+const CASTAGNOLI_POLY: u32 = 0x82F6_3B78;
+
+/// Tables for slicing by 8: `tables[k][i]` is the CRC of byte `i` followed by `k` zero bytes.
+const fn tables(poly: u32) -> [[u32; 256]; 8] {
+    let mut tables = [[0u32; 256]; 8];
+    let mut i: u32 = 0;
+    while i < 256 {
+        let mut crc = i;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 0 {
+                crc >> 1
+            } else {
+                (crc >> 1) ^ poly
+            };
+            bit += 1;
+        }
+        tables[0][i as usize] = crc;
+        i += 1;
+    }
+    let mut k = 1;
+    while k < 8 {
+        let mut i = 0;
+        while i < 256 {
+            let prev = tables[k - 1][i];
+            tables[k][i] = (prev >> 8) ^ tables[0][(prev & 0xFF) as usize];
+            i += 1;
+        }
+        k += 1;
+    }
+    tables
+}
+
+const TABLES: [[u32; 256]; 8] = tables(POLY);
+const CASTAGNOLI_TABLES: [[u32; 256]; 8] = tables(CASTAGNOLI_POLY);
+
+#[inline]
+fn update(tables: &[[u32; 256]; 8], mut crc: u32, data: &[u8]) -> u32 {
+    let byte = |word: u32, shift: u32| ((word >> shift) & 0xFF) as usize;
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let low = crc ^ u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let high = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+        crc = tables[7][byte(low, 0)]
+            ^ tables[6][byte(low, 8)]
+            ^ tables[5][byte(low, 16)]
+            ^ tables[4][byte(low, 24)]
+            ^ tables[3][byte(high, 0)]
+            ^ tables[2][byte(high, 8)]
+            ^ tables[1][byte(high, 16)]
+            ^ tables[0][byte(high, 24)];
+    }
+    for &value in chunks.remainder() {
+        crc = tables[0][byte(crc ^ u32::from(value), 0)] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Incremental CRC-32 hasher.
+pub(crate) struct Crc32(u32);
+
+impl Crc32 {
+    #[inline]
+    pub(crate) fn new() -> Self {
+        Self(!0)
+    }
+
+    #[inline]
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        self.0 = update(&TABLES, self.0, data);
+    }
+
+    #[inline]
+    pub(crate) fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+#[inline]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+#[inline]
+pub(crate) fn crc32c(data: &[u8]) -> u32 {
+    !update(&CASTAGNOLI_TABLES, !0, data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(b""), 0);
+
+        // Lengths around the 8-byte slices agree with feeding one byte at a time.
+        let data: Vec<u8> = (0..=255).collect();
+        for len in [7, 8, 9, 17, 256] {
+            let mut crc = Crc32::new();
+            data[..len].iter().for_each(|byte| crc.update(&[*byte]));
+            assert_eq!(crc.finish(), crc32(&data[..len]));
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::rolling;
+use crate::{SignatureStrong, SignatureWeak, Signatures};
+use std::num::NonZeroUsize;
+
+/// Base of the polynomial combining the strong hashes of fine blocks into a coarse one.
+const STRONG_BASE: u128 = 0x9E37_79B9_7F4A_7C15_F39C_C060_5CED_C835;
+
+/// `STRONG_BASE` to the power `exp`, modulo 2^128.
+fn base_pow(mut exp: u64) -> u128 {
+    let mut result: u128 = 1;
+    let mut base = STRONG_BASE;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.wrapping_mul(base);
+        }
+        base = base.wrapping_mul(base);
+        exp >>= 1;
+    }
+    result
+}
+
+impl Signatures {
+    /// Hashes of every block by index, `None` for blocks left out of the signatures.
+    fn blocks_by_index(&self) -> Vec<Option<(SignatureWeak, &SignatureStrong)>> {
+        let mut blocks = vec![None; self.chunk_offsets().count()];
+        for (weak, entries) in &self.weak_to_strong {
+            for entry in entries {
+                if let Some(slot) = blocks.get_mut(entry.block_index) {
+                    *slot = Some((*weak, entry));
+                }
+            }
+        }
+        blocks
+    }
+
+    /// Signatures with blocks `factor` times larger, derived from these without the source.
+    ///
+    /// Coarse block `i` covers blocks `i * factor..(i + 1) * factor`, the last one whatever
+    /// remains, and `source_size` is unchanged. Its hashes are derived from those of the blocks
+    /// it covers, in order:
+    ///
+    /// - the weak hash is the Adler-32 of the covered bytes, combined from the weak hashes and
+    ///   lengths of the blocks as [`rolling::combine`] does;
+    /// - the strong hash is the sum modulo 2^128 of `strong * B^(offset - start)` over the
+    ///   blocks, where `offset` is where the block starts in the source, `start` where the
+    ///   coarse block starts, and `B` is `0x9E3779B97F4A7C15F39CC0605CEDC835`.
+    ///
+    /// The strong hash is not a hash of the covered bytes, so downsampled signatures cannot
+    /// match new data: compare them with [`Signatures::changed_blocks`] against signatures
+    /// downsampled the same way. The derivation composes, `downsample(a)` then
+    /// `downsample(b)` giving the same hashes as `downsample(a * b)`, and a factor of one
+    /// keeps every hash. Coarse blocks covering a block missing from these signatures are left
+    /// out, and CRC-32s, generations and dirty blocks are dropped.
+    #[must_use]
+    pub fn downsample(&self, factor: NonZeroUsize) -> Self {
+        let factor = factor.get();
+        let block_size = self.block_size as u64;
+        let step = base_pow(block_size);
+        let mut coarse = Self::new(self.block_size.saturating_mul(factor));
+        coarse.source_size = self.source_size;
+        coarse.file_adler = self.file_adler;
+        coarse.final_chunk_mode = self.final_chunk_mode;
+
+        let lengths: Vec<usize> = self.chunk_offsets().map(|(_, _, length)| length).collect();
+        for (coarse_index, group) in self.blocks_by_index().chunks(factor).enumerate() {
+            let Some(group) = group.iter().copied().collect::<Option<Vec<_>>>() else {
+                continue;
+            };
+            let first_index = coarse_index * factor;
+            let mut weak = group[0].0;
+            let mut strong: u128 = 0;
+            let mut weight: u128 = 1;
+            for (i, (block_weak, entry)) in group.iter().enumerate() {
+                if i > 0 {
+                    weak = rolling::combine(weak, *block_weak, lengths[first_index + i] as u64);
+                }
+                strong = strong.wrapping_add(entry.strong.wrapping_mul(weight));
+                weight = weight.wrapping_mul(step);
+            }
+            coarse.insert(
+                weak,
+                SignatureStrong {
+                    strong,
+                    block_index: coarse_index,
+                    crc32: None,
+                },
+            );
+        }
+        coarse
+    }
+
+    /// Indices of the blocks whose hashes differ from those of `other`, in order.
+    ///
+    /// Both signatures must have the same block size, for example after downsampling them by
+    /// the same factor. Blocks present in only one of them count as changed, including every
+    /// block past the end of the shorter source. Returns every block of both when the block
+    /// sizes differ.
+    #[must_use]
+    pub fn changed_blocks(&self, other: &Self) -> Vec<usize> {
+        let ours = self.blocks_by_index();
+        let theirs = other.blocks_by_index();
+        let count = ours.len().max(theirs.len());
+        if self.block_size != other.block_size {
+            return (0..count).collect();
+        }
+        let hashes = |block: Option<&Option<(SignatureWeak, &SignatureStrong)>>| {
+            block
+                .copied()
+                .flatten()
+                .map(|(weak, entry)| (weak, entry.strong))
+        };
+        (0..count)
+            .filter(|&index| {
+                let (ours, theirs) = (hashes(ours.get(index)), hashes(theirs.get(index)));
+                ours.is_none() || ours != theirs
+            })
+            .collect()
+    }
+}
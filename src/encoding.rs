@@ -0,0 +1,929 @@
+//! Compact binary encoding of deltas.
+//!
+//! An encoded delta starts with the magic bytes `LS3D` and a version byte, followed by one
+//! frame per command:
+//!
+//! | Opcode | Fields                          | Command               |
+//! |--------|---------------------------------|-----------------------|
+//! | `0x00` | offset (varint), length (varint) | `DeltaCommand::Copy` |
+//! | `0x01` | length (varint), `length` bytes  | `DeltaCommand::Data` |
+//! | `0x02` | output offset (varint), length (varint) | `DeltaCommand::SelfCopy` |
+//! | `0x03` | length (varint), `length` bytes, CRC-32C | `DeltaCommand::Data` |
+//!
+//! Opcodes `0x04` to `0xFF` are reserved and rejected by [`read_delta`]. Version 1 predates
+//! `0x02` and version 2 predates `0x03`, which are rejected in deltas of those versions.
+//!
+//! A `0x03` frame carries the CRC-32C of its payload, little-endian, and at most
+//! [`MAX_CHECKED_DATA_LEN`] bytes, so the decoder can check the payload before writing any of
+//! it. Only [`write_delta_with_checksums`] writes them; copies are left to the checks of the
+//! base.
+//!
+//! Varints are unsigned LEB128: seven bits per byte, least significant group first, with the
+//! high bit set on every byte but the last. Every multi-byte value is written byte by byte
+//! this way, so the encoding does not depend on the platform's endianness or word size. For
+//! example `Copy { offset: 300, length: 1 }` is `00 AC 02 01`.
+//!
+//! These bytes are pinned by tests; changing them requires a new [`DELTA_VERSION`].
+//!
+//! Signatures have their own layout, written by [`Signatures::write_to`]: the magic bytes
+//! `LS3S`, a version byte, a flags byte, the block size and source size as varints, the
+//! Adler-32 of the whole source if known, the re-signing state if any, and the block count as
+//! a varint. Then every block in index order: the gap since the previous block index as a
+//! varint, zero for consecutive blocks, the weak hash, the strong hash and the CRC-32 if
+//! recorded. Fixed-width hashes are little-endian.
+//!
+//! The re-signing state of [`Signatures::resign_dirty`] is the generation, the number of block
+//! generations followed by each of them, and the number of dirty blocks followed by the gaps
+//! between their indices, all varints.
+
+use crate::apply::apply_spans;
+use crate::output::with_apply_writer;
+use crate::spans::output_overflow;
+use crate::splice::push_merged;
+use crate::{
+    APPLY_BUF_SIZE, DeltaBuilder, DeltaCommand, FinalChunkMode, OpKind, OpSpan, SeekReadAdapter,
+    SignatureStrong, Signatures, crc32, generate_delta_with_cb,
+};
+use std::borrow::Borrow;
+use std::io::{BufWriter, Read, Seek, Write};
+use twox_hash::XxHash3_128;
+
+pub const DELTA_MAGIC: [u8; 4] = *b"LS3D";
+pub const DELTA_VERSION: u8 = 2;
+/// Delta version written by [`write_delta_with_checksums`], the first with checked data frames.
+pub const CHECKED_DELTA_VERSION: u8 = 3;
+/// Delta versions [`read_delta`] accepts.
+pub const DELTA_READ_VERSIONS: [u8; 3] = [1, 2, 3];
+pub const DELTA_HEADER_LEN: usize = DELTA_MAGIC.len() + 1;
+pub const SIGNATURE_MAGIC: [u8; 4] = *b"LS3S";
+pub const SIGNATURE_VERSION: u8 = 1;
+/// Every block carries its CRC-32.
+const SIGNATURE_FLAG_CRC32: u8 = 0x01;
+/// The last block was hashed as [`FinalChunkMode::PadZero`].
+const SIGNATURE_FLAG_PAD_ZERO: u8 = 0x02;
+/// The Adler-32 of the whole source follows the sizes.
+const SIGNATURE_FLAG_FILE_ADLER: u8 = 0x04;
+/// The re-signing state follows the Adler-32 of the whole source.
+const SIGNATURE_FLAG_RESIGN: u8 = 0x08;
+/// Longest possible varint, for a `u64`.
+pub const MAX_VARINT_LEN: usize = 10;
+/// Largest encoded size of a copy frame.
+pub const MAX_COPY_FRAME_LEN: usize = 1 + 2 * MAX_VARINT_LEN;
+/// Largest number of bytes a data frame adds on top of its payload.
+pub const MAX_DATA_FRAME_OVERHEAD: usize = 1 + MAX_VARINT_LEN;
+
+/// Opcode of a `DeltaCommand::Copy` frame.
+pub const OP_COPY: u8 = 0x00;
+/// Opcode of a `DeltaCommand::Data` frame.
+pub const OP_DATA: u8 = 0x01;
+/// Opcode of a `DeltaCommand::SelfCopy` frame.
+pub const OP_SELF_COPY: u8 = 0x02;
+/// Opcode of a `DeltaCommand::Data` frame followed by the CRC-32C of its payload.
+pub const OP_CHECKED_DATA: u8 = 0x03;
+/// Largest payload of a checked data frame.
+pub const MAX_CHECKED_DATA_LEN: usize = APPLY_BUF_SIZE;
+
+/// Error payload returned when the payload of a checked data frame does not match its
+/// CRC-32C, which means the delta was corrupted after it was encoded.
+///
+/// It is wrapped in an [`std::io::Error`] of kind [`std::io::ErrorKind::InvalidData`] and can
+/// be recovered with `error.get_ref().and_then(|e| e.downcast_ref::<DataChecksumMismatch>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataChecksumMismatch {
+    pub op_index: usize,
+    /// CRC-32C recorded in the frame.
+    pub expected: u32,
+    /// CRC-32C of the payload as read.
+    pub actual: u32,
+}
+
+impl std::fmt::Display for DataChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "command {} has data with CRC-32C {:08x}, expected {:08x}",
+            self.op_index, self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for DataChecksumMismatch {}
+
+#[inline]
+fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub(crate) fn write_varint<W: Write>(writer: &mut W, mut value: u64) -> std::io::Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        #[allow(clippy::cast_possible_truncation)]
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&buf[..len])
+}
+
+pub(crate) fn read_varint<R: Read>(reader: &mut R) -> std::io::Result<u64> {
+    let mut value: u64 = 0;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        value |= u64::from(byte[0] & 0x7F) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "varint is too long",
+    ))
+}
+
+pub(crate) fn to_usize(value: u64) -> std::io::Result<usize> {
+    usize::try_from(value).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("length {value} does not fit in usize"),
+        )
+    })
+}
+
+/// Number of bytes `command` takes once encoded.
+#[must_use]
+pub fn encoded_command_size(command: &DeltaCommand) -> usize {
+    match command {
+        DeltaCommand::Copy { offset, length }
+        | DeltaCommand::SelfCopy {
+            output_offset: offset,
+            length,
+        } => 1 + varint_len(*offset) + varint_len(*length as u64),
+        DeltaCommand::Data(data) => encoded_data_size(data.len()),
+    }
+}
+
+/// Number of bytes a `Data` command of `len` bytes takes once encoded.
+pub(crate) fn encoded_data_size(len: usize) -> usize {
+    1 + varint_len(len as u64) + len
+}
+
+/// Number of bytes `delta` takes once encoded, header included.
+#[must_use]
+pub fn encoded_delta_size<I>(delta: I) -> usize
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    DELTA_HEADER_LEN
+        + delta
+            .into_iter()
+            .map(|command| encoded_command_size(command.borrow()))
+            .sum::<usize>()
+}
+
+/// Upper bound on the encoded size of any delta [`crate::generate_delta`] can produce for a
+/// new file of `new_len` bytes matched against signatures with the given `block_size`.
+///
+/// Every copy except one at the very end covers at least `block_size` bytes, and data frames
+/// alternate with copies, so the bound assumes one copy per block plus a data frame around each
+/// of them. The actual size is usually far smaller when most of the file is copied; for a file
+/// with no match at all it exceeds the actual size by at most
+/// `MAX_COPY_FRAME_LEN + MAX_DATA_FRAME_OVERHEAD` per block.
+#[must_use]
+pub fn encoded_delta_size_upper_bound(new_len: u64, block_size: usize) -> u64 {
+    let copies = new_len / block_size.max(1) as u64 + 1;
+    DELTA_HEADER_LEN as u64
+        + new_len
+        + copies * MAX_COPY_FRAME_LEN as u64
+        + (copies + 1) * MAX_DATA_FRAME_OVERHEAD as u64
+}
+
+pub(crate) fn write_command<W: Write>(
+    writer: &mut W,
+    command: &DeltaCommand,
+) -> std::io::Result<()> {
+    match command {
+        DeltaCommand::Copy { offset, length } => {
+            writer.write_all(&[OP_COPY])?;
+            write_varint(writer, *offset)?;
+            write_varint(writer, *length as u64)
+        }
+        DeltaCommand::Data(data) => {
+            writer.write_all(&[OP_DATA])?;
+            write_varint(writer, data.len() as u64)?;
+            writer.write_all(data)
+        }
+        DeltaCommand::SelfCopy {
+            output_offset,
+            length,
+        } => {
+            writer.write_all(&[OP_SELF_COPY])?;
+            write_varint(writer, *output_offset)?;
+            write_varint(writer, *length as u64)
+        }
+    }
+}
+
+/// Read the opcode of the next frame of a delta of `version`, or `None` at the end of the
+/// stream.
+fn read_opcode<R: Read>(reader: &mut R, version: u8) -> std::io::Result<Option<u8>> {
+    let mut opcode = [0u8];
+    if crate::read_exact_or_eof(reader, &mut opcode)? == 0 {
+        return Ok(None);
+    }
+    match opcode[0] {
+        OP_COPY | OP_DATA => Ok(Some(opcode[0])),
+        OP_SELF_COPY if version >= 2 => Ok(Some(opcode[0])),
+        OP_CHECKED_DATA if version >= 3 => Ok(Some(opcode[0])),
+        opcode => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unknown opcode {opcode:#04x} for delta version {version}"),
+        )),
+    }
+}
+
+/// Read command `op_index` of a delta of `version`, or `None` at the end of the stream.
+pub(crate) fn read_command<R: Read>(
+    reader: &mut R,
+    version: u8,
+    op_index: usize,
+) -> std::io::Result<Option<DeltaCommand>> {
+    let Some(opcode) = read_opcode(reader, version)? else {
+        return Ok(None);
+    };
+    let command = match opcode {
+        OP_COPY => {
+            let (offset, length) = read_copy_range(reader)?;
+            DeltaCommand::Copy { offset, length }
+        }
+        OP_DATA => {
+            let len = read_varint(reader)?;
+            let mut data = Vec::new();
+            reader.take(len).read_to_end(&mut data)?;
+            if data.len() as u64 != len {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+            DeltaCommand::Data(data)
+        }
+        OP_CHECKED_DATA => DeltaCommand::Data(read_checked_data(reader, op_index)?),
+        // `OP_SELF_COPY`, the only other opcode `read_opcode` returns.
+        _ => {
+            let (output_offset, length) = read_copy_range(reader)?;
+            DeltaCommand::SelfCopy {
+                output_offset,
+                length,
+            }
+        }
+    };
+    Ok(Some(command))
+}
+
+/// Read the payload of checked data frame `op_index` and check it against its CRC-32C.
+fn read_checked_data<R: Read>(reader: &mut R, op_index: usize) -> std::io::Result<Vec<u8>> {
+    let len = read_varint(reader)?;
+    if len > MAX_CHECKED_DATA_LEN as u64 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "command {op_index} has a checked data frame of {len} bytes, more than {MAX_CHECKED_DATA_LEN}"
+            ),
+        ));
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    let mut data = vec![0u8; len as usize];
+    reader.read_exact(&mut data)?;
+    let expected = u32::from_le_bytes(read_array(reader)?);
+    let actual = crc32::crc32c(&data);
+    if actual != expected {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            DataChecksumMismatch {
+                op_index,
+                expected,
+                actual,
+            },
+        ));
+    }
+    Ok(data)
+}
+
+fn write_checked_data<W: Write>(writer: &mut W, data: &[u8]) -> std::io::Result<()> {
+    writer.write_all(&[OP_CHECKED_DATA])?;
+    write_varint(writer, data.len() as u64)?;
+    writer.write_all(data)?;
+    writer.write_all(&crc32::crc32c(data).to_le_bytes())
+}
+
+/// Read the offset and length of a copy frame, checking that the range they cover ends
+/// before `u64::MAX`.
+fn read_copy_range<R: Read>(reader: &mut R) -> std::io::Result<(u64, usize)> {
+    let offset = read_varint(reader)?;
+    let length = read_varint(reader)?;
+    if offset.checked_add(length).is_none() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("copy of {length} bytes at offset {offset} overflows"),
+        ));
+    }
+    Ok((offset, to_usize(length)?))
+}
+
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writer.write_all(&DELTA_MAGIC)?;
+    writer.write_all(&[DELTA_VERSION])
+}
+
+/// Read the header of an encoded delta, returning its version.
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> std::io::Result<u8> {
+    let mut header = [0u8; DELTA_HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if header[..DELTA_MAGIC.len()] != DELTA_MAGIC {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "not an encoded delta",
+        ));
+    }
+    let version = header[DELTA_MAGIC.len()];
+    if !DELTA_READ_VERSIONS.contains(&version) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unsupported delta version {version}"),
+        ));
+    }
+    Ok(version)
+}
+
+/// Encode a delta into `writer`.
+///
+/// # Errors
+/// Returns an error if writing to the writer fails.
+pub fn write_delta<W: Write, I>(delta: I, writer: W) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut writer = BufWriter::new(writer);
+    write_header(&mut writer)?;
+    for command in delta {
+        write_command(&mut writer, command.borrow())?;
+    }
+    writer.flush()
+}
+
+/// Encode a delta into `writer` as [`CHECKED_DELTA_VERSION`], with the CRC-32C of its payload
+/// in every data frame.
+///
+/// `Data` commands longer than [`MAX_CHECKED_DATA_LEN`] are split over several frames, so they
+/// read back as several commands producing the same output.
+///
+/// # Errors
+/// Returns an error if writing to the writer fails.
+pub fn write_delta_with_checksums<W: Write, I>(delta: I, writer: W) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&DELTA_MAGIC)?;
+    writer.write_all(&[CHECKED_DELTA_VERSION])?;
+    for command in delta {
+        match command.borrow() {
+            DeltaCommand::Data(data) if data.is_empty() => write_checked_data(&mut writer, data)?,
+            DeltaCommand::Data(data) => {
+                for chunk in data.chunks(MAX_CHECKED_DATA_LEN) {
+                    write_checked_data(&mut writer, chunk)?;
+                }
+            }
+            command => write_command(&mut writer, command)?,
+        }
+    }
+    writer.flush()
+}
+
+/// Decode a delta written by [`write_delta`] or [`write_delta_with_checksums`].
+///
+/// # Errors
+/// Returns an error if reading fails or if the data is not a valid encoded delta, with a
+/// [`DataChecksumMismatch`] payload if a checked data frame does not match its CRC-32C.
+pub fn read_delta<R: Read>(mut reader: R) -> std::io::Result<Vec<DeltaCommand>> {
+    let version = read_header(&mut reader)?;
+    let mut delta = Vec::new();
+    while let Some(command) = read_command(&mut reader, version, delta.len())? {
+        delta.push(command);
+    }
+    Ok(delta)
+}
+
+fn invalid_signatures(msg: impl Into<String>) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.into())
+}
+
+/// Block index stored as a gap after `next_index`.
+fn read_block_index<R: Read>(reader: &mut R, next_index: usize) -> std::io::Result<usize> {
+    to_usize(read_varint(reader)?)?
+        .checked_add(next_index)
+        .ok_or_else(|| invalid_signatures("block index overflows usize"))
+}
+
+/// Index the gap of the block after `block_index` counts from.
+fn next_block_index(block_index: usize) -> std::io::Result<usize> {
+    block_index
+        .checked_add(1)
+        .ok_or_else(|| invalid_signatures("block index overflows usize"))
+}
+
+fn read_generation<R: Read>(reader: &mut R) -> std::io::Result<u32> {
+    let generation = read_varint(reader)?;
+    u32::try_from(generation)
+        .map_err(|_| invalid_signatures(format!("generation {generation} does not fit in u32")))
+}
+
+fn read_array<R: Read, const N: usize>(reader: &mut R) -> std::io::Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Number of bytes [`Signatures::write_to`] writes for the signatures of a whole source of
+/// `source_size` bytes, generated with the given `block_size`, with or without CRC-32s.
+///
+/// Exact for signatures fresh from `generate_signatures` and its variants: every block is
+/// present and the Adler-32 of the whole source is known. Signatures with blocks left out or
+/// re-signed with [`Signatures::resign_dirty`] take a few more bytes.
+#[must_use]
+pub fn encoded_signature_size(source_size: u64, block_size: usize, crc32: bool) -> u64 {
+    let blocks = source_size.div_ceil(block_size.max(1) as u64);
+    let block_len = 1 + 4 + 16 + if crc32 { 4 } else { 0 };
+    (SIGNATURE_MAGIC.len() + 2) as u64
+        + varint_len(block_size as u64) as u64
+        + varint_len(source_size) as u64
+        + 4
+        + varint_len(blocks) as u64
+        + blocks * block_len
+}
+
+impl Signatures {
+    /// Encode the signatures into `writer` in the compact binary layout described in the
+    /// [module documentation](self).
+    ///
+    /// Block indices are stored as gaps, so a block costs one byte on top of its hashes unless
+    /// blocks were left out. CRC-32s are only written when every block has one: they are a
+    /// prefilter, and dropping them does not change which blocks match. The generations and
+    /// dirty blocks of [`Signatures::resign_dirty`] are written only once it has been used.
+    ///
+    /// # Errors
+    /// Returns an error of kind [`std::io::ErrorKind::InvalidInput`] if two blocks have the
+    /// same index, which only signatures assembled by hand can have, or if writing to the
+    /// writer fails.
+    pub fn write_to<W: Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut blocks: Vec<_> = self
+            .weak_to_strong
+            .iter()
+            .flat_map(|(weak, entries)| entries.iter().map(move |entry| (*weak, entry)))
+            .collect();
+        blocks.sort_unstable_by_key(|(_, entry)| entry.block_index);
+        if let Some(pair) = blocks
+            .windows(2)
+            .find(|pair| pair[0].1.block_index == pair[1].1.block_index)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("block {} has several hashes", pair[0].1.block_index),
+            ));
+        }
+        let crc32 = !blocks.is_empty() && blocks.iter().all(|(_, entry)| entry.crc32.is_some());
+        let resign = self.generation != 0 || !self.generations.is_empty() || !self.dirty.is_empty();
+
+        let mut flags = 0;
+        if crc32 {
+            flags |= SIGNATURE_FLAG_CRC32;
+        }
+        if self.final_chunk_mode == FinalChunkMode::PadZero {
+            flags |= SIGNATURE_FLAG_PAD_ZERO;
+        }
+        if self.file_adler.is_some() {
+            flags |= SIGNATURE_FLAG_FILE_ADLER;
+        }
+        if resign {
+            flags |= SIGNATURE_FLAG_RESIGN;
+        }
+
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(&SIGNATURE_MAGIC)?;
+        writer.write_all(&[SIGNATURE_VERSION, flags])?;
+        write_varint(&mut writer, self.block_size as u64)?;
+        write_varint(&mut writer, self.source_size)?;
+        if let Some(file_adler) = self.file_adler {
+            writer.write_all(&file_adler.to_le_bytes())?;
+        }
+        if resign {
+            write_varint(&mut writer, u64::from(self.generation))?;
+            write_varint(&mut writer, self.generations.len() as u64)?;
+            for &generation in &self.generations {
+                write_varint(&mut writer, u64::from(generation))?;
+            }
+            write_varint(&mut writer, self.dirty.len() as u64)?;
+            let mut next_dirty = 0;
+            for &block_index in &self.dirty {
+                write_varint(&mut writer, (block_index - next_dirty) as u64)?;
+                next_dirty = block_index.saturating_add(1);
+            }
+        }
+        write_varint(&mut writer, blocks.len() as u64)?;
+        let mut next_index = 0;
+        for (weak, entry) in blocks {
+            write_varint(&mut writer, (entry.block_index - next_index) as u64)?;
+            next_index = entry.block_index.saturating_add(1);
+            writer.write_all(&weak.to_le_bytes())?;
+            writer.write_all(&entry.strong.to_le_bytes())?;
+            if let (true, Some(crc)) = (crc32, entry.crc32) {
+                writer.write_all(&crc.to_le_bytes())?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Decode signatures written by [`Signatures::write_to`].
+    ///
+    /// # Errors
+    /// Returns an error if reading fails, including on truncated input, or if the data is not
+    /// valid encoded signatures, e.g. with a zero block size, or with generations or dirty
+    /// blocks that do not fit the number of blocks of the source.
+    pub fn read_from<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let [magic @ .., version, flags] = read_array::<R, 6>(&mut reader)?;
+        if magic != SIGNATURE_MAGIC {
+            return Err(invalid_signatures("not encoded signatures"));
+        }
+        if version != SIGNATURE_VERSION {
+            return Err(invalid_signatures(format!(
+                "unsupported signature version {version}"
+            )));
+        }
+        if flags
+            & !(SIGNATURE_FLAG_CRC32
+                | SIGNATURE_FLAG_PAD_ZERO
+                | SIGNATURE_FLAG_FILE_ADLER
+                | SIGNATURE_FLAG_RESIGN)
+            != 0
+        {
+            return Err(invalid_signatures(format!(
+                "unknown signature flags {flags:#04x}"
+            )));
+        }
+
+        let mut signatures = Self::new(to_usize(read_varint(&mut reader)?)?);
+        if signatures.block_size == 0 {
+            return Err(invalid_signatures("block size must be greater than zero"));
+        }
+        signatures.source_size = read_varint(&mut reader)?;
+        let block_count = signatures
+            .source_size
+            .div_ceil(signatures.block_size as u64);
+        if flags & SIGNATURE_FLAG_PAD_ZERO != 0 {
+            signatures.final_chunk_mode = FinalChunkMode::PadZero;
+        }
+        if flags & SIGNATURE_FLAG_FILE_ADLER != 0 {
+            signatures.file_adler = Some(u32::from_le_bytes(read_array(&mut reader)?));
+        }
+        if flags & SIGNATURE_FLAG_RESIGN != 0 {
+            signatures.generation = read_generation(&mut reader)?;
+            let generations = read_varint(&mut reader)?;
+            if generations != 0 && generations != block_count {
+                return Err(invalid_signatures(format!(
+                    "{generations} block generations for {block_count} blocks"
+                )));
+            }
+            for _ in 0..generations {
+                signatures.generations.push(read_generation(&mut reader)?);
+            }
+            let mut next_dirty = 0;
+            for _ in 0..read_varint(&mut reader)? {
+                let block_index = read_block_index(&mut reader, next_dirty)?;
+                if block_index as u64 >= block_count {
+                    return Err(invalid_signatures(format!(
+                        "dirty block {block_index} is past the {block_count} blocks of the source"
+                    )));
+                }
+                signatures.dirty.push(block_index);
+                next_dirty = next_block_index(block_index)?;
+            }
+        }
+        let count = read_varint(&mut reader)?;
+        let mut next_index = 0usize;
+        for _ in 0..count {
+            let block_index = read_block_index(&mut reader, next_index)?;
+            next_index = next_block_index(block_index)?;
+            let weak = u32::from_le_bytes(read_array(&mut reader)?);
+            let strong = u128::from_le_bytes(read_array(&mut reader)?);
+            let crc32 = if flags & SIGNATURE_FLAG_CRC32 == 0 {
+                None
+            } else {
+                Some(u32::from_le_bytes(read_array(&mut reader)?))
+            };
+            signatures.insert(
+                weak,
+                SignatureStrong {
+                    strong,
+                    block_index,
+                    crc32,
+                },
+            );
+        }
+        Ok(signatures)
+    }
+}
+
+/// Commands of an encoded delta with their spans, decoded as they are read.
+///
+/// Data frames are split into commands of at most `APPLY_BUF_SIZE` bytes sharing the index of
+/// their frame, so memory use does not grow with the size of a frame. Checked data frames are
+/// read and checked whole before they come out as a single command.
+struct EncodedSpans<R> {
+    reader: R,
+    version: u8,
+    /// Whether data frames without a CRC-32C are rejected.
+    require_checksums: bool,
+    op_index: usize,
+    output_pos: u64,
+    /// Bytes of the current data frame left to read.
+    data_left: u64,
+}
+
+impl<R: Read> EncodedSpans<R> {
+    fn next_span(&mut self) -> std::io::Result<Option<(OpSpan, DeltaCommand)>> {
+        if self.data_left == 0 {
+            let Some(opcode) = read_opcode(&mut self.reader, self.version)? else {
+                return Ok(None);
+            };
+            if opcode == OP_DATA && self.require_checksums {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("command {} is a data frame without CRC-32C", self.op_index),
+                ));
+            } else if opcode == OP_DATA {
+                self.data_left = read_varint(&mut self.reader)?;
+                if self.data_left == 0 {
+                    return self
+                        .span(OpKind::Data, DeltaCommand::Data(Vec::new()))
+                        .map(Some);
+                }
+            } else if opcode == OP_CHECKED_DATA {
+                let data = read_checked_data(&mut self.reader, self.op_index)?;
+                return self.span(OpKind::Data, DeltaCommand::Data(data)).map(Some);
+            } else {
+                let (offset, length) = read_copy_range(&mut self.reader)?;
+                return if opcode == OP_COPY {
+                    self.span(OpKind::Copy, DeltaCommand::Copy { offset, length })
+                } else {
+                    self.span(
+                        OpKind::SelfCopy,
+                        DeltaCommand::SelfCopy {
+                            output_offset: offset,
+                            length,
+                        },
+                    )
+                }
+                .map(Some);
+            }
+        }
+
+        #[allow(clippy::cast_possible_truncation)]
+        let len = self.data_left.min(APPLY_BUF_SIZE as u64) as usize;
+        let mut data = Vec::with_capacity(len);
+        (&mut self.reader).take(len as u64).read_to_end(&mut data)?;
+        if data.len() < len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        self.data_left -= len as u64;
+        self.span(OpKind::Data, DeltaCommand::Data(data)).map(Some)
+    }
+
+    /// Span of `command`, the next one of the output, moving on to the next frame unless it
+    /// is a piece of a data frame with more to come.
+    fn span(
+        &mut self,
+        kind: OpKind,
+        command: DeltaCommand,
+    ) -> std::io::Result<(OpSpan, DeltaCommand)> {
+        let start = self.output_pos;
+        self.output_pos = start
+            .checked_add(command.output_len() as u64)
+            .ok_or_else(|| output_overflow(self.op_index))?;
+        let basis_range = match command {
+            DeltaCommand::Copy { offset, length } => Some(offset..offset + length as u64),
+            _ => None,
+        };
+        let span = OpSpan {
+            op_index: self.op_index,
+            kind,
+            output_range: start..self.output_pos,
+            basis_range,
+        };
+        if self.data_left == 0 {
+            self.op_index += 1;
+        }
+        Ok((span, command))
+    }
+}
+
+impl<R: Read> Iterator for EncodedSpans<R> {
+    type Item = std::io::Result<(OpSpan, DeltaCommand)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_span().transpose()
+    }
+}
+
+/// Apply the encoded delta read from `delta_reader` to the base, without decoding it first.
+///
+/// Frames are applied as they are read: copies are read from the base as by `apply_delta`,
+/// and data payloads are streamed from `delta_reader` to the output. Memory use does not grow
+/// with the size of the delta or of any of its data frames. Paired with
+/// [`delta_bounded_memory`], neither side ever holds a whole delta. The framing is the one
+/// described in the [module documentation](self).
+///
+/// Checked data frames are checked against their CRC-32C before any of their payload is
+/// written.
+///
+/// # Errors
+/// Returns an error if the delta is not a valid encoded delta or is truncated, with a
+/// [`DataChecksumMismatch`] payload if a checked data frame does not match its CRC-32C, if a
+/// copy reaches past the end of the base, or if IO operations fail.
+pub fn apply_encoded<R: Read + Seek, D: Read, W: Write>(
+    base_reader: R,
+    delta_reader: D,
+    target_writer: W,
+) -> std::io::Result<()> {
+    apply_encoded_frames(base_reader, delta_reader, target_writer, false)
+}
+
+/// Same as [`apply_encoded`], rejecting data frames without a CRC-32C, as only
+/// [`write_delta_with_checksums`] writes them.
+///
+/// Everything before the first data frame without a CRC-32C is written.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::InvalidData`] if a data frame has no CRC-32C,
+/// or any error of [`apply_encoded`].
+pub fn apply_encoded_with_checksums<R: Read + Seek, D: Read, W: Write>(
+    base_reader: R,
+    delta_reader: D,
+    target_writer: W,
+) -> std::io::Result<()> {
+    apply_encoded_frames(base_reader, delta_reader, target_writer, true)
+}
+
+fn apply_encoded_frames<R: Read + Seek, D: Read, W: Write>(
+    base_reader: R,
+    mut delta_reader: D,
+    target_writer: W,
+    require_checksums: bool,
+) -> std::io::Result<()> {
+    let version = read_header(&mut delta_reader)?;
+    let spans = EncodedSpans {
+        reader: delta_reader,
+        version,
+        require_checksums,
+        op_index: 0,
+        output_pos: 0,
+        data_left: 0,
+    };
+    let base = SeekReadAdapter::new(base_reader);
+    with_apply_writer(target_writer, 0, |writer| {
+        apply_spans(&base, spans, writer, 0, &mut ())
+    })
+}
+
+/// Generate the delta between `old_signatures` and `reader` and encode it into `writer`,
+/// holding at most `max_mem` bytes of new data at any time.
+///
+/// Two blocks of new data are buffered for matching, and unmatched data is written out as
+/// soon as the rest of the budget is used up, so long unmatched runs come out as several
+/// consecutive `Data` commands. Commands are written to `writer` as they are found, without
+/// buffering: wrap it in a `BufWriter` if needed, its buffer not being part of the budget.
+///
+/// # Errors
+/// Returns an error if `max_mem` does not exceed two blocks, or if reading or writing fails.
+pub fn delta_bounded_memory<R: Read, W: Write>(
+    reader: R,
+    old_signatures: &Signatures,
+    mut writer: W,
+    max_mem: usize,
+) -> std::io::Result<()> {
+    let window = old_signatures.block_size().saturating_mul(2);
+    if max_mem <= window {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!(
+                "memory budget of {max_mem} bytes leaves nothing for data beyond the {window} bytes of matching window"
+            ),
+        ));
+    }
+
+    write_header(&mut writer)?;
+    DeltaBuilder::new(old_signatures)?
+        .with_max_data_len(max_mem - window)
+        .generate(reader, |command| write_command(&mut writer, &command))?;
+    writer.flush()
+}
+
+/// Compute the encoded size of the delta between `old_signatures` and `reader` without keeping
+/// the delta in memory.
+///
+/// # Errors
+/// Returns an error if reading from the reader fails.
+pub fn estimate_delta_size<R: Read>(
+    old_signatures: &Signatures,
+    reader: R,
+) -> std::io::Result<usize> {
+    Ok(DELTA_HEADER_LEN + DeltaBuilder::new(old_signatures)?.encoded_size(reader)?)
+}
+
+/// Whether to send a delta or the new data itself, as decided by [`delta_or_full`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DeltaOutcome {
+    /// The encoded delta is smaller than the new data.
+    Delta(Vec<DeltaCommand>),
+    /// The encoded delta would be at least as large as the new data, which should be sent as
+    /// is.
+    SendFull,
+}
+
+/// Generate the delta between `old_signatures` and `reader`, or [`DeltaOutcome::SendFull`]
+/// when its encoded size, header included, is not smaller than the new data.
+///
+/// Data sharing nothing with the base comes out as literals plus framing, always larger than
+/// the data itself, so it is sent in full.
+///
+/// # Errors
+/// Returns an error if reading from the reader fails.
+pub fn delta_or_full<R: Read>(
+    old_signatures: &Signatures,
+    reader: R,
+) -> std::io::Result<DeltaOutcome> {
+    let mut delta = Vec::new();
+    let mut new_len = 0;
+    generate_delta_with_cb(old_signatures, reader, |command| {
+        new_len += command.output_len();
+        delta.push(command);
+        Ok(())
+    })?;
+    Ok(if encoded_delta_size(&delta) < new_len {
+        DeltaOutcome::Delta(delta)
+    } else {
+        DeltaOutcome::SendFull
+    })
+}
+
+/// Stable 128-bit hash of the content of `delta`, for content-addressed storage.
+///
+/// The hash is the xxh3-128 of the encoded [`crate::optimize_delta`] form of `delta`, header
+/// included, so it does not depend on how commands are split. Deltas that reconstruct the same
+/// output with different copies or data hash differently.
+#[must_use]
+pub fn delta_content_hash<I>(delta: I) -> u128
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    struct HashWriter(XxHash3_128);
+
+    impl Write for HashWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.write(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut hasher = HashWriter(XxHash3_128::new());
+    // Writing to the hasher never fails.
+    let _ = write_header(&mut hasher);
+    let mut pending = Vec::with_capacity(2);
+    for command in delta {
+        push_merged(&mut pending, command.borrow().clone());
+        if pending.len() == 2 {
+            let _ = write_command(&mut hasher, &pending.remove(0));
+        }
+    }
+    if let Some(command) = pending.pop() {
+        let _ = write_command(&mut hasher, &command);
+    }
+    hasher.0.finish_128()
+}
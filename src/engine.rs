@@ -0,0 +1,217 @@
+use crate::{
+    DEFAULT_BLOCK_SIZE, DeltaCommand, ReadAt, Signatures, TextSignatures, apply_delta,
+    apply_delta_at, generate_delta, generate_delta_hierarchical,
+    generate_signatures_with_block_size, generate_text_delta, generate_text_signatures, read_delta,
+    write_delta,
+};
+use std::io::{Read, Seek, Write};
+
+/// One way of signing a base, diffing new data against it and applying the result.
+///
+/// Every engine produces plain [`DeltaCommand`]s, so deltas are stored and encoded the same
+/// way whichever engine made them; only the signature type differs. Code written against this
+/// trait can switch engines without other changes.
+pub trait SyncEngine {
+    /// What the side holding the base sends to the side holding the new data.
+    type Signature;
+
+    /// Sign the base read from `base`.
+    ///
+    /// # Errors
+    /// Returns an error if reading the base fails or the engine parameters are invalid.
+    fn signature<R: Read>(&self, base: R) -> std::io::Result<Self::Signature>;
+
+    /// Diff the new data read from `new` against `signature`.
+    ///
+    /// # Errors
+    /// Returns an error if reading the new data fails.
+    fn delta<R: Read>(
+        &self,
+        signature: &Self::Signature,
+        new: R,
+    ) -> std::io::Result<Vec<DeltaCommand>>;
+
+    /// Rebuild the new data from the base and a delta made by this engine.
+    ///
+    /// # Errors
+    /// Returns an error if the delta does not fit the base or if IO operations fail.
+    fn apply<R: Read + Seek, W: Write>(
+        &self,
+        base: R,
+        delta: &[DeltaCommand],
+        target: W,
+    ) -> std::io::Result<()> {
+        apply_delta(base, delta, target)
+    }
+}
+
+/// Object-safe counterpart of [`SyncEngine`], exchanging signatures and deltas as bytes.
+///
+/// Signatures are encoded with [`Signatures::write_to`] and deltas with `write_delta`, so one
+/// storage schema holds the output of every engine, and the engine can be picked at runtime
+/// as a `Box<dyn DynEngine>`. Implemented by the engines signing fixed-size blocks:
+/// [`RollingEngine`], and [`HierarchicalEngine`], whose signature is the encoding of every
+/// level in turn. Text signatures have no binary encoding.
+pub trait DynEngine {
+    /// Sign the base read from `base`, returning the encoded signature.
+    ///
+    /// # Errors
+    /// Returns an error if reading the base fails or the engine parameters are invalid.
+    fn encoded_signature(&self, base: &mut dyn Read) -> std::io::Result<Vec<u8>>;
+
+    /// Diff the new data read from `new` against an encoded signature, returning the encoded
+    /// delta.
+    ///
+    /// # Errors
+    /// Returns an error if the signature is not one this engine encodes, or if reading the new
+    /// data fails.
+    fn encoded_delta(&self, signature: &[u8], new: &mut dyn Read) -> std::io::Result<Vec<u8>>;
+
+    /// Rebuild the new data from the base and an encoded delta.
+    ///
+    /// # Errors
+    /// Returns an error if the delta is not a valid encoded delta, if it does not fit the base
+    /// or if IO operations fail.
+    fn apply_encoded(
+        &self,
+        base: &dyn ReadAt,
+        delta: &[u8],
+        target: &mut dyn Write,
+    ) -> std::io::Result<()> {
+        apply_delta_at(base, read_delta(delta)?, target)
+    }
+}
+
+fn encode_delta(delta: &[DeltaCommand]) -> std::io::Result<Vec<u8>> {
+    let mut encoded = Vec::new();
+    write_delta(delta, &mut encoded)?;
+    Ok(encoded)
+}
+
+/// Fixed-size blocks found at any offset with a rolling checksum: `generate_delta`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RollingEngine {
+    pub block_size: usize,
+}
+
+impl Default for RollingEngine {
+    fn default() -> Self {
+        Self {
+            block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+impl SyncEngine for RollingEngine {
+    type Signature = Signatures;
+
+    fn signature<R: Read>(&self, base: R) -> std::io::Result<Signatures> {
+        generate_signatures_with_block_size(base, self.block_size)
+    }
+
+    fn delta<R: Read>(&self, signature: &Signatures, new: R) -> std::io::Result<Vec<DeltaCommand>> {
+        generate_delta(signature, new)
+    }
+}
+
+impl DynEngine for RollingEngine {
+    fn encoded_signature(&self, base: &mut dyn Read) -> std::io::Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        SyncEngine::signature(self, base)?.write_to(&mut encoded)?;
+        Ok(encoded)
+    }
+
+    fn encoded_delta(&self, mut signature: &[u8], new: &mut dyn Read) -> std::io::Result<Vec<u8>> {
+        let signatures = Signatures::read_from(&mut signature)?;
+        encode_delta(&SyncEngine::delta(self, &signatures, new)?)
+    }
+}
+
+/// Several block sizes matched at once, largest first: `generate_delta_hierarchical`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HierarchicalEngine {
+    pub block_sizes: Vec<usize>,
+}
+
+impl Default for HierarchicalEngine {
+    /// The default block size and 16 times it.
+    fn default() -> Self {
+        Self {
+            block_sizes: vec![DEFAULT_BLOCK_SIZE * 16, DEFAULT_BLOCK_SIZE],
+        }
+    }
+}
+
+impl SyncEngine for HierarchicalEngine {
+    type Signature = Vec<Signatures>;
+
+    fn signature<R: Read>(&self, mut base: R) -> std::io::Result<Vec<Signatures>> {
+        let mut data = Vec::new();
+        base.read_to_end(&mut data)?;
+        self.block_sizes
+            .iter()
+            .map(|&block_size| generate_signatures_with_block_size(&data[..], block_size))
+            .collect()
+    }
+
+    fn delta<R: Read>(
+        &self,
+        signature: &Vec<Signatures>,
+        mut new: R,
+    ) -> std::io::Result<Vec<DeltaCommand>> {
+        let mut data = Vec::new();
+        new.read_to_end(&mut data)?;
+        let signatures: Vec<&Signatures> = signature.iter().collect();
+        generate_delta_hierarchical(&signatures, &data)
+    }
+}
+
+impl DynEngine for HierarchicalEngine {
+    fn encoded_signature(&self, base: &mut dyn Read) -> std::io::Result<Vec<u8>> {
+        let mut encoded = Vec::new();
+        for signatures in SyncEngine::signature(self, base)? {
+            signatures.write_to(&mut encoded)?;
+        }
+        Ok(encoded)
+    }
+
+    fn encoded_delta(&self, mut signature: &[u8], new: &mut dyn Read) -> std::io::Result<Vec<u8>> {
+        let mut levels = Vec::new();
+        while !signature.is_empty() {
+            levels.push(Signatures::read_from(&mut signature)?);
+        }
+        encode_delta(&SyncEngine::delta(self, &levels, new)?)
+    }
+}
+
+/// Variable-length blocks that never split a UTF-8 character: `generate_text_delta`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextEngine {
+    pub target_block_size: usize,
+}
+
+impl Default for TextEngine {
+    fn default() -> Self {
+        Self {
+            target_block_size: DEFAULT_BLOCK_SIZE,
+        }
+    }
+}
+
+impl SyncEngine for TextEngine {
+    type Signature = TextSignatures;
+
+    fn signature<R: Read>(&self, base: R) -> std::io::Result<TextSignatures> {
+        generate_text_signatures(base, self.target_block_size)
+    }
+
+    fn delta<R: Read>(
+        &self,
+        signature: &TextSignatures,
+        mut new: R,
+    ) -> std::io::Result<Vec<DeltaCommand>> {
+        let mut data = Vec::new();
+        new.read_to_end(&mut data)?;
+        Ok(generate_text_delta(signature, &data))
+    }
+}
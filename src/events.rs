@@ -0,0 +1,86 @@
+use crate::apply::{ApplyObserver, apply_commands};
+use crate::output::with_apply_writer;
+use crate::{DeltaCommand, OpKind, OpSpan, SeekReadAdapter};
+use std::borrow::Borrow;
+use std::io::{Read, Seek, Write};
+use std::ops::Range;
+
+/// Progress of [`apply_delta_with_events`], with the exact bytes each step produced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApplyEvent {
+    /// Bytes of a `Copy` command were read from `basis_range` and written to `output_range`.
+    /// Long copies are reported in several pieces.
+    CopyApplied {
+        op_index: usize,
+        basis_range: Range<u64>,
+        output_range: Range<u64>,
+    },
+    /// Bytes of a `SelfCopy` command were read back from `source_range` of the output and
+    /// written to `output_range`. Long copies are reported in several pieces.
+    SelfCopyApplied {
+        op_index: usize,
+        source_range: Range<u64>,
+        output_range: Range<u64>,
+    },
+    /// A `Data` command was written to `output_range`.
+    DataApplied {
+        op_index: usize,
+        output_range: Range<u64>,
+    },
+    /// Everything reported so far reached the target writer.
+    Flushed,
+}
+
+/// Forwards the pieces of output written by the apply loop as events.
+struct EventObserver<F>(F);
+
+impl<F: FnMut(ApplyEvent)> ApplyObserver for EventObserver<F> {
+    fn applied(&mut self, span: &OpSpan, output_range: Range<u64>, source: Option<Range<u64>>) {
+        let op_index = span.op_index;
+        (self.0)(match (span.kind, source) {
+            (OpKind::SelfCopy, Some(source_range)) => ApplyEvent::SelfCopyApplied {
+                op_index,
+                source_range,
+                output_range,
+            },
+            (_, Some(basis_range)) => ApplyEvent::CopyApplied {
+                op_index,
+                basis_range,
+                output_range,
+            },
+            (_, None) => ApplyEvent::DataApplied {
+                op_index,
+                output_range,
+            },
+        });
+    }
+}
+
+/// Same as `apply_delta`, but calls `on_event` after each command, or each piece of a long
+/// copy, is written.
+///
+/// The ranges of the events tile the output in order, without gaps or overlaps, and match
+/// the spans returned by `delta_spans`. Output is buffered, so written bytes are only known to
+/// have reached `target_writer` at the final [`ApplyEvent::Flushed`].
+///
+/// # Errors
+/// Returns an error if a copy reaches past the end of the base or if IO operations fail.
+pub fn apply_delta_with_events<R: Read + Seek, W: Write, I, F>(
+    base_reader: R,
+    delta: I,
+    target_writer: W,
+    on_event: F,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+    F: FnMut(ApplyEvent),
+{
+    let base = SeekReadAdapter::new(base_reader);
+    let mut observer = EventObserver(on_event);
+    with_apply_writer(target_writer, 0, |writer| {
+        apply_commands(&base, delta, writer, 0, &mut observer)
+    })?;
+    (observer.0)(ApplyEvent::Flushed);
+    Ok(())
+}
@@ -0,0 +1,280 @@
+use crate::{FinalChunkMode, SignatureStrong, SignatureWeak, Signatures};
+use std::io::{BufRead, Write};
+
+/// Line-oriented formats for exchanging block hashes with external tooling.
+///
+/// The first record describes the signatures as `block_size, source_size, final_chunk_mode,
+/// file_adler`, and every following record one block as `index, offset, length, weak, strong,
+/// crc32`. Hashes are written as lowercase hex and the final chunk mode as `exact` or
+/// `pad_zero`. The Adler-32 of the source and the CRC-32 of a block are optional.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values, with a header line before the signature record and another
+    /// before the block records. Missing optional values are left empty.
+    Csv,
+    /// One JSON object per line, without the members of missing optional values.
+    NdJson,
+}
+
+const SIGNATURE_FIELDS: [&str; 4] = [
+    "block_size",
+    "source_size",
+    "final_chunk_mode",
+    "file_adler",
+];
+const BLOCK_FIELDS: [&str; 6] = ["index", "offset", "length", "weak", "strong", "crc32"];
+const OPTIONAL_FIELDS: [&str; 2] = ["file_adler", "crc32"];
+const CSV_SIGNATURE_HEADER: &str = "block_size,source_size,final_chunk_mode,file_adler";
+const CSV_BLOCK_HEADER: &str = "index,offset,length,weak,strong,crc32";
+
+struct Record {
+    index: usize,
+    offset: u64,
+    length: usize,
+    weak: SignatureWeak,
+    strong: u128,
+    crc32: Option<u32>,
+}
+
+fn invalid(line: usize, msg: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("line {line}: {msg}"),
+    )
+}
+
+fn invalid_block(msg: impl std::fmt::Display) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn parse_field<T: std::str::FromStr>(line: usize, name: &str, value: &str) -> std::io::Result<T> {
+    value
+        .parse()
+        .map_err(|_| invalid(line, format!("invalid {name} {value:?}")))
+}
+
+fn parse_hex(line: usize, name: &str, value: &str) -> std::io::Result<u128> {
+    u128::from_str_radix(value, 16).map_err(|_| invalid(line, format!("invalid {name} {value:?}")))
+}
+
+fn parse_hex32(line: usize, name: &str, value: &str) -> std::io::Result<u32> {
+    parse_hex(line, name, value).and_then(|hash| {
+        u32::try_from(hash).map_err(|_| invalid(line, format!("{name} does not fit in 32 bits")))
+    })
+}
+
+/// An optional 32-bit hash, `None` when left empty.
+fn parse_optional_hex32(line: usize, name: &str, value: &str) -> std::io::Result<Option<u32>> {
+    (!value.is_empty())
+        .then(|| parse_hex32(line, name, value))
+        .transpose()
+}
+
+/// An optional 32-bit hash as hex, empty when missing.
+fn hex32(hash: Option<u32>) -> String {
+    hash.map(|hash| format!("{hash:08x}")).unwrap_or_default()
+}
+
+/// JSON member of an optional value, to append to an object, or nothing when it is empty.
+fn json_member(name: &str, value: &str) -> String {
+    if value.is_empty() {
+        String::new()
+    } else {
+        format!(r#","{name}":"{value}""#)
+    }
+}
+
+/// Split a record into the values of the fields `names`, in that order.
+fn parse_fields<'a, const N: usize>(
+    line: usize,
+    text: &'a str,
+    format: ExportFormat,
+    names: [&str; N],
+) -> std::io::Result<[&'a str; N]> {
+    let mut fields = [""; N];
+    let mut count = 0;
+    match format {
+        ExportFormat::Csv => {
+            for value in text.split(',') {
+                if let Some(slot) = fields.get_mut(count) {
+                    *slot = value.trim();
+                }
+                count += 1;
+            }
+        }
+        ExportFormat::NdJson => {
+            let body = text
+                .strip_prefix('{')
+                .and_then(|text| text.strip_suffix('}'))
+                .ok_or_else(|| invalid(line, "expected a JSON object"))?;
+            for pair in body.split(',') {
+                let (key, value) = pair
+                    .split_once(':')
+                    .ok_or_else(|| invalid(line, format!("invalid member {pair:?}")))?;
+                let position = names
+                    .iter()
+                    .position(|name| key.trim().trim_matches('"') == *name)
+                    .ok_or_else(|| invalid(line, format!("unknown member {key}")))?;
+                fields[position] = value.trim().trim_matches('"');
+            }
+            count = N;
+        }
+    }
+    let missing = names
+        .iter()
+        .zip(&fields)
+        .any(|(name, field)| field.is_empty() && !OPTIONAL_FIELDS.contains(name));
+    if count != N || missing {
+        return Err(invalid(line, format!("expected {}", names.join(", "))));
+    }
+    Ok(fields)
+}
+
+/// Signatures without any block, as described by the first record.
+fn parse_signatures(line: usize, text: &str, format: ExportFormat) -> std::io::Result<Signatures> {
+    let fields = parse_fields(line, text, format, SIGNATURE_FIELDS)?;
+    let mut signatures = Signatures::new(parse_field(line, "block size", fields[0])?);
+    signatures.source_size = parse_field(line, "source size", fields[1])?;
+    signatures.final_chunk_mode = match fields[2] {
+        "exact" => FinalChunkMode::Exact,
+        "pad_zero" => FinalChunkMode::PadZero,
+        mode => return Err(invalid(line, format!("invalid final chunk mode {mode:?}"))),
+    };
+    signatures.file_adler = parse_optional_hex32(line, "file Adler-32", fields[3])?;
+    if signatures.block_size == 0 && signatures.source_size > 0 {
+        return Err(invalid(line, "block size must be greater than zero"));
+    }
+    Ok(signatures)
+}
+
+impl Record {
+    fn parse(line: usize, text: &str, format: ExportFormat) -> std::io::Result<Self> {
+        let fields = parse_fields(line, text, format, BLOCK_FIELDS)?;
+        Ok(Self {
+            index: parse_field(line, "index", fields[0])?,
+            offset: parse_field(line, "offset", fields[1])?,
+            length: parse_field(line, "length", fields[2])?,
+            weak: parse_hex32(line, "weak hash", fields[3])?,
+            strong: parse_hex(line, "strong hash", fields[4])?,
+            crc32: parse_optional_hex32(line, "CRC-32", fields[5])?,
+        })
+    }
+}
+
+impl Signatures {
+    /// Write the signature record, then one record per block, in block order.
+    ///
+    /// # Errors
+    /// Returns an error if writing to the writer fails.
+    pub fn export<W: Write>(&self, mut writer: W, format: ExportFormat) -> std::io::Result<()> {
+        let mut blocks: Vec<_> = self
+            .weak_to_strong
+            .iter()
+            .flat_map(|(weak, entries)| entries.iter().map(move |entry| (*weak, entry)))
+            .collect();
+        blocks.sort_unstable_by_key(|(_, entry)| entry.block_index);
+
+        let (block_size, source_size) = (self.block_size, self.source_size);
+        let mode = match self.final_chunk_mode {
+            FinalChunkMode::Exact => "exact",
+            FinalChunkMode::PadZero => "pad_zero",
+        };
+        let file_adler = hex32(self.file_adler);
+        match format {
+            ExportFormat::Csv => {
+                writeln!(writer, "{CSV_SIGNATURE_HEADER}")?;
+                writeln!(writer, "{block_size},{source_size},{mode},{file_adler}")?;
+                writeln!(writer, "{CSV_BLOCK_HEADER}")?;
+            }
+            ExportFormat::NdJson => writeln!(
+                writer,
+                r#"{{"block_size":{block_size},"source_size":{source_size},"final_chunk_mode":"{mode}"{}}}"#,
+                json_member("file_adler", &file_adler)
+            )?,
+        }
+
+        for (weak, entry) in blocks {
+            let offset = entry.block_index as u64 * self.block_size as u64;
+            #[allow(clippy::cast_possible_truncation)]
+            let length = self
+                .source_size
+                .saturating_sub(offset)
+                .min(self.block_size as u64) as usize;
+            let (index, strong, crc32) = (entry.block_index, entry.strong, hex32(entry.crc32));
+            match format {
+                ExportFormat::Csv => writeln!(
+                    writer,
+                    "{index},{offset},{length},{weak:08x},{strong:032x},{crc32}"
+                )?,
+                ExportFormat::NdJson => writeln!(
+                    writer,
+                    r#"{{"index":{index},"offset":{offset},"length":{length},"weak":"{weak:08x}","strong":"{strong:032x}"{}}}"#,
+                    json_member("crc32", &crc32)
+                )?,
+            }
+        }
+        writer.flush()
+    }
+
+    /// Read back signatures written by [`Signatures::export`].
+    ///
+    /// Blank lines, surrounding whitespace, CRLF line endings and a leading byte order mark are
+    /// tolerated. Blocks may be missing at the end of the source, as they are from
+    /// [`crate::generate_signatures_excluding_tail`].
+    ///
+    /// # Errors
+    /// Returns an error if reading fails, if the signature record is missing, if a record is
+    /// malformed, if indices are not consecutive from zero, or if offsets and lengths disagree
+    /// with the block size and source size of the signature record.
+    pub fn import<R: BufRead>(reader: R, format: ExportFormat) -> std::io::Result<Self> {
+        let mut signatures: Option<Self> = None;
+        let mut blocks: u64 = 0;
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let text = line.trim_start_matches('\u{feff}').trim();
+            if text.is_empty()
+                || (format == ExportFormat::Csv
+                    && (text == CSV_SIGNATURE_HEADER || text == CSV_BLOCK_HEADER))
+            {
+                continue;
+            }
+            let Some(signatures) = signatures.as_mut() else {
+                signatures = Some(parse_signatures(number + 1, text, format)?);
+                continue;
+            };
+
+            let record = Record::parse(number + 1, text, format)?;
+            let (block_size, source_size) = (signatures.block_size as u64, signatures.source_size);
+            let expected_offset = blocks * block_size;
+            if record.index as u64 != blocks {
+                return Err(invalid_block(format!(
+                    "expected block index {blocks}, got {}",
+                    record.index
+                )));
+            }
+            if expected_offset >= source_size {
+                return Err(invalid_block(format!(
+                    "block {blocks} starts past the source size {source_size}"
+                )));
+            }
+            if record.offset != expected_offset
+                || record.length as u64 != block_size.min(source_size - expected_offset)
+            {
+                return Err(invalid_block(format!(
+                    "block {blocks} does not match block size {block_size} and source size {source_size}"
+                )));
+            }
+            signatures.insert(
+                record.weak,
+                SignatureStrong {
+                    strong: record.strong,
+                    block_index: record.index,
+                    crc32: record.crc32,
+                },
+            );
+            blocks += 1;
+        }
+
+        signatures.ok_or_else(|| invalid_block("no signature record found"))
+    }
+}
@@ -0,0 +1,229 @@
+//! Writing files atomically through temporary files, and cleaning up after crashes.
+//!
+//! Output goes to a temporary file next to the destination, named
+//! `.{file name}.{unique id}.libsync3-tmp`, which is synced and renamed over the destination
+//! once complete. A process killed before that leaves the temporary file behind; call
+//! [`recover_temp_files`] at startup to remove such files or pick up where they stopped.
+
+use crate::{DeltaCommand, apply_delta_at};
+use std::borrow::Borrow;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Suffix of every temporary file created by [`TempGuard`].
+pub const TEMP_SUFFIX: &str = ".libsync3-tmp";
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+fn invalid_target(target: &Path) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        format!("{} does not name a file", target.display()),
+    )
+}
+
+/// Split `target` into its directory, `.` for a bare file name, and file name.
+fn split_target(target: &Path) -> std::io::Result<(PathBuf, OsString)> {
+    let name = target
+        .file_name()
+        .ok_or_else(|| invalid_target(target))?
+        .to_owned();
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Ok((dir, name))
+}
+
+/// Temporary file that replaces `target` on [`TempGuard::commit`], and is removed if dropped
+/// before that.
+#[derive(Debug)]
+pub struct TempGuard {
+    file: File,
+    path: PathBuf,
+    target: PathBuf,
+    committed: bool,
+}
+
+impl TempGuard {
+    /// Create a new, empty temporary file in the directory of `target`.
+    ///
+    /// # Errors
+    /// Returns an error if `target` has no file name or if the file cannot be created.
+    pub fn new<P: AsRef<Path>>(target: P) -> std::io::Result<Self> {
+        let target = target.as_ref();
+        let (dir, name) = split_target(target)?;
+        loop {
+            let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+            let mut temp_name = OsString::from(".");
+            temp_name.push(&name);
+            temp_name.push(format!(".{}-{id}{TEMP_SUFFIX}", std::process::id()));
+            let path = dir.join(temp_name);
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(Self {
+                        file,
+                        path,
+                        target: target.to_path_buf(),
+                        committed: false,
+                    });
+                }
+                // Left behind by an earlier process with the same id.
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Take over a temporary file left behind by an interrupted process, to finish it.
+    ///
+    /// Writes are appended after the bytes already in the file.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be opened.
+    pub fn adopt(orphan: &OrphanedTemp) -> std::io::Result<Self> {
+        let file = OpenOptions::new().append(true).open(&orphan.path)?;
+        Ok(Self {
+            file,
+            path: orphan.path.clone(),
+            target: orphan.target.clone(),
+            committed: false,
+        })
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// The temporary file, to write the output to.
+    #[inline]
+    pub fn file(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    /// Sync the temporary file and rename it over the target.
+    ///
+    /// # Errors
+    /// Returns an error if syncing or renaming fails; the temporary file is then removed.
+    pub fn commit(mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()?;
+        std::fs::rename(&self.path, &self.target)?;
+        self.committed = true;
+        // Persist the rename itself. Directories cannot be opened as files everywhere.
+        #[cfg(unix)]
+        if let Some(dir) = self.target.parent()
+            && !dir.as_os_str().is_empty()
+        {
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for TempGuard {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Apply `delta` to the file at `base`, replacing the file at `target` atomically.
+///
+/// `target` is left untouched unless the whole output was written and synced. It may be the
+/// same file as `base`.
+///
+/// # Errors
+/// Returns an error if the base cannot be read, if the delta does not fit it, or if the
+/// output cannot be written or committed.
+pub fn apply_delta_to_path<Pb, Pt, I>(base: Pb, delta: I, target: Pt) -> std::io::Result<()>
+where
+    Pb: AsRef<Path>,
+    Pt: AsRef<Path>,
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let base = File::open(base)?;
+    let mut guard = TempGuard::new(target)?;
+    apply_delta_at(&base, delta, guard.file())?;
+    guard.commit()
+}
+
+/// What [`recover_temp_files`] does with the temporary files it finds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    /// Delete them.
+    Remove,
+    /// Leave them for the caller to finish with [`TempGuard::adopt`], e.g. by resuming the
+    /// apply with `apply_delta_resume` from [`OrphanedTemp::len`] bytes.
+    Adopt,
+}
+
+/// Temporary file found by [`recover_temp_files`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OrphanedTemp {
+    pub path: PathBuf,
+    /// File the temporary file was meant to replace.
+    pub target: PathBuf,
+    /// Bytes written before the process stopped.
+    pub len: u64,
+}
+
+/// Name of the target of a temporary file named by [`TempGuard::new`].
+fn parse_temp_name(name: &str) -> Option<&str> {
+    let stem = name.strip_prefix('.')?.strip_suffix(TEMP_SUFFIX)?;
+    let (target, id) = stem.rsplit_once('.')?;
+    let (pid, counter) = id.split_once('-')?;
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    (!target.is_empty() && is_number(pid) && is_number(counter)).then_some(target)
+}
+
+/// Find the temporary files left in `dir` by [`TempGuard`]s that were never committed nor
+/// dropped, and remove or keep them according to `policy`.
+///
+/// Only files named like a [`TempGuard`]'s are touched. Call this at startup, before any
+/// apply into `dir` is running: in-progress temporary files look the same as orphaned ones.
+///
+/// # Errors
+/// Returns an error if `dir` cannot be listed or a temporary file cannot be removed.
+pub fn recover_temp_files<P: AsRef<Path>>(
+    dir: P,
+    policy: RecoveryPolicy,
+) -> std::io::Result<Vec<OrphanedTemp>> {
+    let dir = dir.as_ref();
+    let mut orphans = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(target_name) = name.to_str().and_then(parse_temp_name) else {
+            continue;
+        };
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+
+        let orphan = OrphanedTemp {
+            path: entry.path(),
+            target: dir.join(target_name),
+            len: entry.metadata()?.len(),
+        };
+        if policy == RecoveryPolicy::Remove {
+            std::fs::remove_file(&orphan.path)?;
+        }
+        orphans.push(orphan);
+    }
+    orphans.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(orphans)
+}
@@ -0,0 +1,188 @@
+use crate::rolling::RollingChecksum;
+use crate::splice::push_merged;
+use crate::{DeltaCommand, Signatures};
+
+type Lookup<'a> = Box<dyn Fn(u32, &[u8]) -> Option<u64> + 'a>;
+
+/// One block size of a multi-scale search, with the rolling checksum of the window at the
+/// current position.
+pub(crate) struct Scale<'a> {
+    block_size: usize,
+    /// Base offset of the block with this weak hash and content, if any.
+    lookup: Lookup<'a>,
+    rolling: RollingChecksum,
+    valid: bool,
+}
+
+impl<'a> Scale<'a> {
+    pub(crate) fn new(block_size: usize, lookup: impl Fn(u32, &[u8]) -> Option<u64> + 'a) -> Self {
+        Self {
+            block_size,
+            lookup: Box::new(lookup),
+            rolling: RollingChecksum::new(),
+            valid: false,
+        }
+    }
+
+    /// Offset and length of the base block matching `new_data` at `pos`, if any.
+    fn find(&mut self, new_data: &[u8], pos: usize) -> Option<(u64, usize)> {
+        let Some(block) = new_data.get(pos..pos + self.block_size) else {
+            self.valid = false;
+            return None;
+        };
+        if !self.valid {
+            self.rolling.reset();
+            self.rolling.update(block);
+            self.valid = true;
+        }
+        let offset = (self.lookup)(self.rolling.value(), block)?;
+        Some((offset, self.block_size))
+    }
+}
+
+/// Result of [`match_scales`]: the commands so far, the unmatched data not pushed yet and the
+/// position where matching stopped, less than the smallest block size from the end.
+pub(crate) struct Matched {
+    pub(crate) delta: Vec<DeltaCommand>,
+    pub(crate) pending_data: Vec<u8>,
+    pub(crate) pos: usize,
+}
+
+/// Copy the largest matching block at every position of `new_data`, trying `scales` from the
+/// largest block size down.
+pub(crate) fn match_scales(mut scales: Vec<Scale>, new_data: &[u8]) -> Matched {
+    scales.retain(|scale| scale.block_size > 0);
+    scales.sort_by_key(|scale| std::cmp::Reverse(scale.block_size));
+    let min_block_size = scales.last().map_or(usize::MAX, |scale| scale.block_size);
+
+    let mut delta = Vec::new();
+    let mut pending_data = Vec::new();
+    let mut pos = 0;
+    while new_data.len().saturating_sub(pos) >= min_block_size {
+        let matched = scales
+            .iter_mut()
+            .find_map(|scale| scale.find(new_data, pos));
+        if let Some((offset, length)) = matched {
+            push_merged(
+                &mut delta,
+                DeltaCommand::Data(std::mem::take(&mut pending_data)),
+            );
+            push_merged(&mut delta, DeltaCommand::Copy { offset, length });
+            pos += length;
+            for scale in &mut scales {
+                scale.valid = false;
+            }
+            continue;
+        }
+
+        let old_byte = new_data[pos];
+        pending_data.push(old_byte);
+        pos += 1;
+        for scale in &mut scales {
+            if scale.valid && pos + scale.block_size <= new_data.len() {
+                let new_byte = new_data[pos + scale.block_size - 1];
+                scale.rolling.roll(old_byte, new_byte, scale.block_size);
+            } else {
+                scale.valid = false;
+            }
+        }
+    }
+
+    Matched {
+        delta,
+        pending_data,
+        pos,
+    }
+}
+
+fn validate(signatures: &[&Signatures]) -> std::io::Result<()> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+    let first = signatures
+        .first()
+        .ok_or_else(|| invalid("no signatures to match against".to_string()))?;
+    if let Some(other) = signatures
+        .iter()
+        .find(|other| other.source_size() != first.source_size())
+    {
+        return Err(invalid(format!(
+            "signatures cover {} and {} bytes, not the same base",
+            first.source_size(),
+            other.source_size()
+        )));
+    }
+    if signatures
+        .iter()
+        .any(|signatures| signatures.block_size() == 0)
+    {
+        return Err(invalid("block size must be greater than zero".to_string()));
+    }
+    Ok(())
+}
+
+/// Encoded size allowed for a `generate_delta_hierarchical` delta by the quality tests, with
+/// the finest scale as the block size. Never worse than a single-scale delta at that size.
+#[cfg(feature = "test-util")]
+pub(crate) const DELTA_SIZE_ENVELOPE: crate::test_util::DeltaSizeEnvelope =
+    crate::test_util::DeltaSizeEnvelope {
+        per_new_byte: 3,
+        blocks_per_edit: 2,
+        bytes_per_edit: 64,
+        fixed: 64,
+    };
+
+/// Generate a delta against several signatures of the same base, at different block sizes.
+///
+/// At every position the largest block that matches is copied, falling back to smaller block
+/// sizes, and to data when none matches. Coarse signatures keep long unchanged regions cheap to
+/// find while fine ones still catch short matches between scattered edits. Copies reference
+/// the base directly, so the result applies with `apply_delta` like any other delta.
+///
+/// # Errors
+/// Returns an error if `signatures` is empty, if one has a zero block size, or if they were not
+/// generated from bases of the same size.
+pub fn generate_delta_hierarchical(
+    signatures: &[&Signatures],
+    new_data: &[u8],
+) -> std::io::Result<Vec<DeltaCommand>> {
+    validate(signatures)?;
+
+    let scales = signatures
+        .iter()
+        .map(|signatures| {
+            let block_size = signatures.block_size();
+            Scale::new(block_size, move |weak, block| {
+                let block_idx = signatures.find(weak, block)?;
+                Some((block_idx * block_size) as u64)
+            })
+        })
+        .collect();
+    let Matched {
+        mut delta,
+        mut pending_data,
+        pos,
+    } = match_scales(scales, new_data);
+
+    // The last block of the base may be shorter than the block size.
+    let remaining = &new_data[pos..];
+    let tail_match = signatures.iter().find_map(|signatures| {
+        let block_idx = signatures.from(remaining)?;
+        Some((block_idx * signatures.block_size()) as u64)
+    });
+    match tail_match {
+        Some(offset) if !remaining.is_empty() => {
+            push_merged(&mut delta, DeltaCommand::Data(pending_data));
+            push_merged(
+                &mut delta,
+                DeltaCommand::Copy {
+                    offset,
+                    length: remaining.len(),
+                },
+            );
+        }
+        _ => {
+            pending_data.extend_from_slice(remaining);
+            push_merged(&mut delta, DeltaCommand::Data(pending_data));
+        }
+    }
+    Ok(delta)
+}
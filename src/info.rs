@@ -0,0 +1,68 @@
+use crate::archive::{ARCHIVE_READ_VERSIONS, ARCHIVE_VERSION};
+use crate::encoding::{DELTA_READ_VERSIONS, DELTA_VERSION, SIGNATURE_VERSION};
+use crate::{Capabilities, DEFAULT_BLOCK_SIZE};
+
+/// Cargo features of the crate, with whether each one is enabled in this build.
+const FEATURES: [(&str, bool); 5] = [
+    ("serde", cfg!(feature = "serde")),
+    ("rkyv", cfg!(feature = "rkyv")),
+    ("compat", cfg!(feature = "compat")),
+    ("test-util", cfg!(feature = "test-util")),
+    ("verify-simd", cfg!(feature = "verify-simd")),
+];
+
+/// What this build of the library supports, for tools that pick formats at runtime.
+///
+/// Built by [`library_info`] from the constants the encoders use and from the features enabled
+/// at compile time, so it always describes the code it ships with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LibraryInfo {
+    /// Version of the crate.
+    pub version: &'static str,
+    /// Cargo features enabled in this build.
+    pub features: Vec<&'static str>,
+    /// Encoded delta versions accepted by `read_delta`.
+    pub delta_versions_read: Vec<u8>,
+    /// Encoded delta version produced by `write_delta`.
+    pub delta_version_written: u8,
+    /// Encoded signature versions accepted by `Signatures::read_from`.
+    pub signature_versions_read: Vec<u8>,
+    /// Encoded signature version produced by `Signatures::write_to`.
+    pub signature_version_written: u8,
+    /// Archive footer versions accepted by `archive::read_signature`.
+    pub archive_versions_read: Vec<u8>,
+    /// Archive footer version produced by `archive::write_with_signature`.
+    pub archive_version_written: u8,
+    /// Weak hash of every block, rolled over the new data.
+    pub weak_hash: &'static str,
+    /// Strong hash confirming every weak hash match.
+    pub strong_hash: &'static str,
+    /// Block size of `generate_signatures`.
+    pub default_block_size: usize,
+    /// Parameters offered to `negotiate` by default.
+    pub capabilities: Capabilities,
+}
+
+/// Describe what this build of the library supports.
+#[must_use]
+pub fn library_info() -> LibraryInfo {
+    LibraryInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        features: FEATURES
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| *name)
+            .collect(),
+        delta_versions_read: DELTA_READ_VERSIONS.to_vec(),
+        delta_version_written: DELTA_VERSION,
+        signature_versions_read: vec![SIGNATURE_VERSION],
+        signature_version_written: SIGNATURE_VERSION,
+        archive_versions_read: ARCHIVE_READ_VERSIONS.to_vec(),
+        archive_version_written: ARCHIVE_VERSION,
+        weak_hash: "adler32",
+        strong_hash: "xxh3-128",
+        default_block_size: DEFAULT_BLOCK_SIZE,
+        capabilities: Capabilities::default(),
+    }
+}
@@ -1,9 +1,98 @@
+mod aligned;
+pub mod analysis;
+mod apply;
+pub mod archive;
+#[cfg(feature = "rkyv")]
+mod archived;
+mod builder;
+pub mod cdc;
+mod compare;
+#[cfg(feature = "compat")]
+pub mod compat;
+mod cost;
+mod crc32;
+mod downsample;
+pub mod encoding;
+mod engine;
+mod events;
+mod export;
+pub mod fs;
+mod hierarchical;
+mod info;
+mod matcher;
+mod negotiate;
+mod output;
+mod parallel;
+mod profile;
+mod profiled;
+mod read_at;
+mod redact;
+mod resign;
 pub mod rolling;
+mod spans;
+mod splice;
+mod strategy;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod text;
+pub mod vcdiff;
+mod verify;
+mod watchdog;
 
+pub use aligned::SectorAlignedReader;
+#[cfg(feature = "rkyv")]
+pub use archived::generate_delta_archived;
+pub use builder::{DEFAULT_MAX_DATA_LEN, DeltaBuilder};
+pub use compare::first_difference;
+#[cfg(feature = "compat")]
+pub use compat::delta_from_legacy_json;
+pub use cost::{CostModel, generate_delta_with_cost};
+pub use encoding::{
+    DataChecksumMismatch, DeltaOutcome, apply_encoded, apply_encoded_with_checksums,
+    delta_bounded_memory, delta_content_hash, delta_or_full, estimate_delta_size, read_delta,
+    write_delta, write_delta_with_checksums,
+};
+pub use engine::{DynEngine, HierarchicalEngine, RollingEngine, SyncEngine, TextEngine};
+pub use events::{ApplyEvent, apply_delta_with_events};
+pub use export::ExportFormat;
+pub use hierarchical::generate_delta_hierarchical;
+pub use info::{LibraryInfo, library_info};
+pub use matcher::Matcher;
+pub use negotiate::{AgreedParams, Capabilities, negotiate};
+pub use output::ApplyWriteFailed;
+pub use parallel::{
+    apply_parallel_to_slice, generate_delta_parallel, generate_signatures_parallel,
+};
+pub use profile::{
+    ChunkSizeProfile, SyncOptions, SyncPreset, generate_signatures_auto,
+    generate_signatures_for_path, suggest_block_size,
+};
+pub use profiled::{ApplyProfile, apply_profiled};
+pub use read_at::{ReadAt, ScatterBase, SeekReadAdapter};
+pub use redact::{Redacted, RedactionPolicy};
+pub use spans::{ApplyPlan, CopyOutOfBounds, OpKind, OpSpan, delta_spans, plan_apply};
+pub use splice::{invert_delta, optimize_delta, postmatch_delta, splice_deltas};
+pub use strategy::{
+    ChunkSignatures, ChunkStrategy, generate_delta_with_strategy, generate_signatures_with_strategy,
+};
+pub use text::{TextSignatures, generate_text_delta, generate_text_signatures};
+pub use vcdiff::write_vcdiff;
+pub use verify::{
+    BasisFingerprint, BasisMismatch, OutputDigest, OutputMismatch, apply_delta_checked,
+    apply_verified, generate_delta_with_digest,
+};
+pub use watchdog::{ApplyStalled, apply_delta_with_watchdog};
+
+use apply::apply_commands;
+use output::with_apply_writer;
+use read_at::{FetchBase, ForwardBase};
 use rolling::RollingChecksum;
+use spans::{Spans, copy_out_of_bounds, copy_within_output, self_copy_source};
 use std::borrow::Borrow;
 use std::collections::HashMap;
-use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::hash::{BuildHasherDefault, Hasher};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::Range;
 use twox_hash::XxHash3_128;
 
 /// Reads exactly `buf.len()` bytes or until EOF, returning the number of bytes read.
@@ -20,6 +109,46 @@ fn read_exact_or_eof<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result
     Ok(total)
 }
 
+/// Largest block buffered whole while generating signatures; larger blocks are hashed
+/// incrementally.
+pub const MAX_BLOCK_BUFFER_SIZE: usize = 256 * 1024 * 1024;
+
+/// Largest distance from a self-referential copy back to the output it reads, in bytes.
+///
+/// Apply functions writing to a stream keep the commands behind this much of the output they
+/// wrote, to resolve [`DeltaCommand::SelfCopy`] without reading the target back, and
+/// [`Matcher::generate_delta_with_self_copies`] never reaches further.
+pub const SELF_COPY_WINDOW: usize = 8 * 1024 * 1024;
+
+/// Error payload returned when a buffer cannot be allocated.
+///
+/// It is wrapped in an [`std::io::Error`] of kind [`std::io::ErrorKind::OutOfMemory`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllocationFailed {
+    pub requested: usize,
+}
+
+impl std::fmt::Display for AllocationFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to allocate a buffer of {} bytes", self.requested)
+    }
+}
+
+impl std::error::Error for AllocationFailed {}
+
+/// Allocates a zeroed buffer, reporting failure instead of aborting.
+fn try_alloc_buffer(len: usize) -> std::io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(len).map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::OutOfMemory,
+            AllocationFailed { requested: len },
+        )
+    })?;
+    buffer.resize(len, 0);
+    Ok(buffer)
+}
+
 #[inline]
 #[must_use]
 pub fn xxh3_128(chunk: &[u8]) -> u128 {
@@ -28,18 +157,133 @@ pub fn xxh3_128(chunk: &[u8]) -> u128 {
 
 #[derive(Clone, Debug, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+)]
 pub struct SignatureStrong {
     pub strong: u128,
     pub block_index: usize,
+    /// CRC-32 of the block, checked before the strong hash when present.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub crc32: Option<u32>,
 }
 
 pub type SignatureWeak = u32;
 
+/// Hasher for maps keyed by [`SignatureWeak`].
+///
+/// Weak checksums are already well spread, so instead of running them through `SipHash` they are
+/// only multiplied by a 64-bit odd constant and folded, which is enough for the table to use
+/// both their high and low bits. Lookups happen once per byte of unmatched data, where
+/// `SipHash` showed up in profiles. Collisions only cost speed: the strong hash is always
+/// compared before a block is matched.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct WeakHasher(u64);
+
+impl WeakHasher {
+    const MULTIPLIER: u64 = 0x9E37_79B9_7F4A_7C15;
+}
+
+impl Hasher for WeakHasher {
+    #[inline]
+    fn write_u32(&mut self, weak: u32) {
+        self.0 = (self.0 ^ u64::from(weak)).wrapping_mul(Self::MULTIPLIER);
+    }
+
+    #[inline]
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(Self::MULTIPLIER);
+        }
+    }
+
+    #[inline]
+    fn finish(&self) -> u64 {
+        self.0 ^ (self.0 >> 32)
+    }
+}
+
+/// Map keyed by weak checksum, hashed with [`WeakHasher`].
+pub(crate) type WeakMap<V> = HashMap<SignatureWeak, V, BuildHasherDefault<WeakHasher>>;
+
+/// How the last block of a source is hashed when it is shorter than the block size.
+///
+/// Deltas generated against the signatures honor the same mode. librsync hashes the last block
+/// as-is, like [`FinalChunkMode::Exact`]; [`FinalChunkMode::PadZero`] is for interoperating
+/// with implementations that pad it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+)]
+pub enum FinalChunkMode {
+    /// Hash only the bytes of the last block.
+    #[default]
+    Exact,
+    /// Hash the last block followed by zeros up to the block size.
+    PadZero,
+}
+
+impl FinalChunkMode {
+    #[inline]
+    #[must_use]
+    pub fn is_exact(&self) -> bool {
+        *self == Self::Exact
+    }
+
+    /// Whether a block of `len` bytes is padded before hashing.
+    #[inline]
+    fn pads(self, len: usize, block_size: usize) -> bool {
+        self == Self::PadZero && len > 0 && len < block_size
+    }
+}
+
 #[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+)]
 pub struct Signatures {
     block_size: usize,
-    weak_to_strong: HashMap<SignatureWeak, Vec<SignatureStrong>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    source_size: u64,
+    weak_to_strong: WeakMap<Vec<SignatureStrong>>,
+    /// Adler-32 of the whole source, when it was read in full to generate the signatures.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    file_adler: Option<u32>,
+    /// Generation of the last [`Signatures::resign_dirty`], zero until the first one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "resign::is_zero")
+    )]
+    generation: u32,
+    /// Generation each block was last hashed in, by block index. Empty until the first
+    /// [`Signatures::resign_dirty`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    generations: Vec<u32>,
+    /// Sorted indices of the blocks marked by [`Signatures::mark_dirty`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Vec::is_empty")
+    )]
+    dirty: Vec<usize>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "FinalChunkMode::is_exact")
+    )]
+    final_chunk_mode: FinalChunkMode,
 }
 
 impl Signatures {
@@ -47,10 +291,68 @@ impl Signatures {
     pub fn new(block_size: usize) -> Self {
         Self {
             block_size,
-            weak_to_strong: HashMap::new(),
+            source_size: 0,
+            weak_to_strong: WeakMap::default(),
+            file_adler: None,
+            generation: 0,
+            generations: Vec::new(),
+            dirty: Vec::new(),
+            final_chunk_mode: FinalChunkMode::Exact,
         }
     }
 
+    /// Build signatures from block hashes that were computed elsewhere.
+    ///
+    /// `hashes` yields the weak and strong hash of every block of the source, in order. Every
+    /// block must be exactly `block_size` bytes long except the last one, which may be shorter.
+    /// The caller is responsible for cutting the source at those same boundaries: hashes of
+    /// differently aligned blocks are accepted but will never produce a match.
+    ///
+    /// # Errors
+    /// Returns an error if `block_size` is zero or if the number of hashes does not match the
+    /// number of blocks needed to cover `source_size` bytes.
+    pub fn from_precomputed<I>(
+        block_size: usize,
+        source_size: u64,
+        hashes: I,
+    ) -> std::io::Result<Self>
+    where
+        I: IntoIterator<Item = (SignatureWeak, u128)>,
+    {
+        if block_size == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "block size must be greater than zero",
+            ));
+        }
+
+        let expected = source_size.div_ceil(block_size as u64);
+        let mut signatures = Self::new(block_size);
+        signatures.source_size = source_size;
+        let mut count: u64 = 0;
+        for (block_index, (weak, strong)) in hashes.into_iter().enumerate() {
+            signatures.insert(
+                weak,
+                SignatureStrong {
+                    strong,
+                    block_index,
+                    crc32: None,
+                },
+            );
+            count += 1;
+        }
+
+        if count != expected {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "expected {expected} block hashes for {source_size} bytes with block size {block_size}, got {count}"
+                ),
+            ));
+        }
+        Ok(signatures)
+    }
+
     #[inline]
     pub fn extend(&mut self, new_mapping: HashMap<SignatureWeak, Vec<SignatureStrong>>) {
         self.weak_to_strong.extend(new_mapping);
@@ -69,11 +371,37 @@ impl Signatures {
 
     #[must_use]
     pub fn from(&self, data: &[u8]) -> Option<usize> {
-        let weak = RollingChecksum::compute(data);
-        self.weak_to_strong.get(&weak).and_then(|entries| {
-            let strong = xxh3_128(data);
-            find_strong_hash(entries, strong)
-        })
+        if self.final_chunk_mode.pads(data.len(), self.block_size) {
+            let mut padded = data.to_vec();
+            padded.resize(self.block_size, 0);
+            return self.find_within(RollingChecksum::compute(&padded), &padded, data.len());
+        }
+        self.find(RollingChecksum::compute(data), data)
+    }
+
+    /// Index of the block with weak hash `weak` and the content of `block`.
+    #[inline]
+    pub(crate) fn find(&self, weak: SignatureWeak, block: &[u8]) -> Option<usize> {
+        self.find_within(weak, block, block.len())
+    }
+
+    /// Same as [`Signatures::find`], for a block of which only the first `needed` bytes are
+    /// copied. A zero-padded last block only matches if it holds that many bytes.
+    fn find_within(&self, weak: SignatureWeak, block: &[u8], needed: usize) -> Option<usize> {
+        let entries = self.weak_to_strong.get(&weak)?;
+        find_block_except(entries, block, unmatchable_tail(self.padded_tail(), needed))
+    }
+
+    /// Index and length of the last block, if it was hashed with zero padding.
+    pub(crate) fn padded_tail(&self) -> Option<(usize, usize)> {
+        padded_tail(self.final_chunk_mode, self.block_size, self.source_size)
+    }
+
+    /// How the last block was hashed when shorter than the block size.
+    #[inline]
+    #[must_use]
+    pub fn final_chunk_mode(&self) -> FinalChunkMode {
+        self.final_chunk_mode
     }
 
     #[inline]
@@ -82,6 +410,219 @@ impl Signatures {
         self.block_size
     }
 
+    /// Number of bytes of the source covered by these signatures.
+    #[inline]
+    #[must_use]
+    pub fn source_size(&self) -> u64 {
+        self.source_size
+    }
+
+    /// Number of blocks that are exactly `block_size` long.
+    #[inline]
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn full_chunk_count(&self) -> usize {
+        if self.block_size == 0 {
+            return 0;
+        }
+        (self.source_size / self.block_size as u64) as usize
+    }
+
+    /// Whether the last block is shorter than `block_size`.
+    #[inline]
+    #[must_use]
+    pub fn has_partial_tail(&self) -> bool {
+        self.block_size != 0 && !self.source_size.is_multiple_of(self.block_size as u64)
+    }
+
+    /// Iterate over the `(block_index, offset, length)` of every block of the source.
+    ///
+    /// All blocks are `block_size` long except the last one, which covers whatever remains of
+    /// `source_size`.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn chunk_offsets(&self) -> impl Iterator<Item = (usize, u64, usize)> + '_ {
+        let block_size = self.block_size as u64;
+        let count = if block_size == 0 {
+            0
+        } else {
+            self.source_size.div_ceil(block_size)
+        };
+        (0..count).map(move |index| {
+            let offset = index * block_size;
+            let length = (self.source_size - offset).min(block_size) as usize;
+            (index as usize, offset, length)
+        })
+    }
+
+    /// Split into `shards` signatures covering consecutive ranges of block indices.
+    ///
+    /// Blocks keep their absolute indices, so every shard can be matched on its own and the
+    /// shards recombined with [`Signatures::merge`]. Shards are as even as possible; trailing
+    /// shards are empty when there are fewer blocks than shards. Zero shards is treated as one.
+    #[must_use]
+    pub fn split(&self, shards: usize) -> Vec<Self> {
+        let shards = shards.max(1);
+        let per_shard = self.len().div_ceil(shards).max(1);
+        let mut parts: Vec<Self> = (0..shards)
+            .map(|_| Self {
+                block_size: self.block_size,
+                source_size: self.source_size,
+                weak_to_strong: WeakMap::default(),
+                file_adler: self.file_adler,
+                generation: self.generation,
+                generations: self.generations.clone(),
+                dirty: self.dirty.clone(),
+                final_chunk_mode: self.final_chunk_mode,
+            })
+            .collect();
+
+        for (weak, entries) in &self.weak_to_strong {
+            for entry in entries {
+                let shard = (entry.block_index / per_shard).min(shards - 1);
+                parts[shard].insert(*weak, entry.clone());
+            }
+        }
+        parts
+    }
+
+    /// Recombine signatures produced by [`Signatures::split`].
+    ///
+    /// # Errors
+    /// Returns an error if `parts` is empty, if the parts disagree on block size, source size or
+    /// final chunk mode, or if their block indices are not contiguous from zero.
+    pub fn merge(parts: &[Self]) -> std::io::Result<Self> {
+        let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+        let first = parts
+            .first()
+            .ok_or_else(|| invalid("no signatures to merge".to_string()))?;
+        if let Some(part) = parts.iter().find(|part| {
+            part.block_size != first.block_size
+                || part.source_size != first.source_size
+                || part.final_chunk_mode != first.final_chunk_mode
+        }) {
+            return Err(invalid(format!(
+                "cannot merge signatures with block size {} over {} bytes ({:?} final chunk) into block size {} over {} bytes ({:?} final chunk)",
+                part.block_size,
+                part.source_size,
+                part.final_chunk_mode,
+                first.block_size,
+                first.source_size,
+                first.final_chunk_mode
+            )));
+        }
+
+        let mut ranges: Vec<(usize, usize)> = parts
+            .iter()
+            .filter_map(|part| {
+                let indices = part
+                    .weak_to_strong
+                    .values()
+                    .flatten()
+                    .map(|entry| entry.block_index);
+                Some((indices.clone().min()?, indices.max()?))
+            })
+            .collect();
+        ranges.sort_unstable();
+        let mut next = 0;
+        for (start, end) in ranges {
+            if start != next {
+                return Err(invalid(format!(
+                    "expected a shard starting at block {next}, found one starting at block {start}"
+                )));
+            }
+            next = end + 1;
+        }
+
+        let mut merged = Self::new(first.block_size);
+        merged.source_size = first.source_size;
+        merged.file_adler = first.file_adler;
+        merged.generation = first.generation;
+        merged.generations.clone_from(&first.generations);
+        merged.dirty.clone_from(&first.dirty);
+        merged.final_chunk_mode = first.final_chunk_mode;
+        for part in parts {
+            for (weak, entries) in &part.weak_to_strong {
+                merged
+                    .weak_to_strong
+                    .entry(*weak)
+                    .or_default()
+                    .extend(entries.iter().cloned());
+            }
+        }
+        if merged.len() != next {
+            return Err(invalid(format!(
+                "shards hold {} blocks for block indices 0..{next}",
+                merged.len()
+            )));
+        }
+        Ok(merged)
+    }
+
+    /// Keep only the blocks that overlap `byte_range` of the source.
+    ///
+    /// Blocks keep their original indices, so deltas generated against the result still
+    /// reference absolute offsets and apply to the full source unchanged, while only ever
+    /// reading the kept blocks. The matchable region is `byte_range` widened to block boundaries.
+    #[must_use]
+    pub fn restrict_to_range(&self, byte_range: Range<u64>) -> Self {
+        if byte_range.is_empty() {
+            return self.retain_blocks(|_| false);
+        }
+        let block_size = self.block_size as u64;
+        self.retain_blocks(|block_index| {
+            let start = block_index as u64 * block_size;
+            start < byte_range.end && start + block_size > byte_range.start
+        })
+    }
+
+    fn retain_blocks(&self, keep: impl Fn(usize) -> bool) -> Self {
+        let weak_to_strong = self
+            .weak_to_strong
+            .iter()
+            .filter_map(|(weak, entries)| {
+                let kept: Vec<_> = entries
+                    .iter()
+                    .filter(|entry| keep(entry.block_index))
+                    .cloned()
+                    .collect();
+                (!kept.is_empty()).then_some((*weak, kept))
+            })
+            .collect();
+
+        Self {
+            block_size: self.block_size,
+            source_size: self.source_size,
+            weak_to_strong,
+            file_adler: self.file_adler,
+            generation: self.generation,
+            generations: self.generations.clone(),
+            dirty: self.dirty.clone(),
+            final_chunk_mode: self.final_chunk_mode,
+        }
+    }
+
+    /// Adler-32 of the whole source, if the signatures were generated from it.
+    #[inline]
+    #[must_use]
+    pub fn file_adler(&self) -> Option<u32> {
+        self.file_adler
+    }
+
+    /// Cheap check telling apart the sources of two signatures without comparing blocks.
+    ///
+    /// Compares the number of blocks, the covered length and, when both have one, the
+    /// Adler-32 of the whole source. `true` means the sources differ; `false` only means they
+    /// may be equal, and a delta is needed to be sure.
+    #[must_use]
+    pub fn quick_differs(&self, other: &Self) -> bool {
+        self.len() != other.len()
+            || self.source_size != other.source_size
+            || matches!(
+                (self.file_adler, other.file_adler),
+                (Some(ours), Some(theirs)) if ours != theirs
+            )
+    }
+
     #[inline]
     #[must_use]
     pub fn len(&self) -> usize {
@@ -95,11 +636,82 @@ impl Signatures {
     }
 }
 
+/// Index and length of the last block of a source, if it was hashed with zero padding.
+#[allow(clippy::cast_possible_truncation)]
+fn padded_tail(
+    final_chunk_mode: FinalChunkMode,
+    block_size: usize,
+    source_size: u64,
+) -> Option<(usize, usize)> {
+    if final_chunk_mode != FinalChunkMode::PadZero || block_size == 0 {
+        return None;
+    }
+    let tail_len = (source_size % block_size as u64) as usize;
+    (tail_len > 0).then(|| ((source_size / block_size as u64) as usize, tail_len))
+}
+
+/// Index of the zero-padded last block when it holds fewer than the `needed` bytes a match
+/// would copy.
+fn unmatchable_tail(padded_tail: Option<(usize, usize)>, needed: usize) -> Option<usize> {
+    padded_tail
+        .filter(|&(_, tail_len)| needed > tail_len)
+        .map(|(tail_index, _)| tail_index)
+}
+
+/// Hashes of a block as [`find_block`] compares them, from [`SignatureStrong`] or its archived
+/// form.
+pub(crate) trait BlockEntry {
+    fn strong(&self) -> u128;
+    fn block_index(&self) -> usize;
+    fn crc32(&self) -> Option<u32>;
+}
+
+impl BlockEntry for SignatureStrong {
+    #[inline]
+    fn strong(&self) -> u128 {
+        self.strong
+    }
+
+    #[inline]
+    fn block_index(&self) -> usize {
+        self.block_index
+    }
+
+    #[inline]
+    fn crc32(&self) -> Option<u32> {
+        self.crc32
+    }
+}
+
+/// Find the block among `entries`, which share the weak hash of `block`.
+///
+/// Entries carrying a CRC-32 are rejected on it first, and the strong hash is only computed
+/// once some entry is left to compare.
 #[inline]
-fn find_strong_hash(entries: &[SignatureStrong], strong_hash: u128) -> Option<usize> {
+fn find_block<E: BlockEntry>(entries: &[E], block: &[u8]) -> Option<usize> {
+    find_block_except(entries, block, None)
+}
+
+/// Same as [`find_block`], never matching the block at index `excluded`.
+fn find_block_except<E: BlockEntry>(
+    entries: &[E],
+    block: &[u8],
+    excluded: Option<usize>,
+) -> Option<usize> {
+    let crc = entries
+        .iter()
+        .any(|entry| entry.crc32().is_some())
+        .then(|| crc32::crc32(block));
+    let mut strong = None;
     for entry in entries {
-        if entry.strong == strong_hash {
-            return Some(entry.block_index);
+        if Some(entry.block_index()) == excluded
+            || (entry.crc32().is_some() && entry.crc32() != crc)
+        {
+            continue;
+        }
+        let strong = *strong.get_or_insert_with(|| xxh3_128(block));
+        if entry.strong() == strong {
+            return Some(entry.block_index());
         }
     }
     None
@@ -150,17 +762,6 @@ fn push_or_merge_copy<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
     Ok(())
 }
 
-#[inline]
-fn reset_rolling(
-    rolling: &mut RollingChecksum,
-    window: &[u8],
-    window_start: usize,
-    block_size: usize,
-) {
-    rolling.reset();
-    rolling.update(&window[window_start..window_start + block_size]);
-}
-
 #[inline]
 fn emit_copy_for_block_idx<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
     last_copy: &mut Option<(u64, usize)>,
@@ -175,14 +776,86 @@ fn emit_copy_for_block_idx<F: FnMut(DeltaCommand) -> std::io::Result<()>>(
     push_or_merge_copy(last_copy, new_offset, length, cb)
 }
 
-#[derive(Debug)]
+/// One step of a delta.
+///
+/// The `Debug` and `Display` output never shows the bytes of `Data` beyond what the global
+/// [`RedactionPolicy`] allows.
+///
+/// New kinds of commands may be added, along with a new encoded delta version, so matches
+/// outside this crate need a wildcard arm.
+#[derive(Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
 pub enum DeltaCommand {
     Data(Vec<u8>),
-    Copy { offset: u64, length: usize },
+    Copy {
+        offset: u64,
+        length: usize,
+    },
+    /// Copy of `length` bytes of the output itself, starting at `output_offset`.
+    ///
+    /// `output_offset` must be before the start of the command. Bytes are copied in order, so
+    /// the source may run into the output of the command itself, repeating the bytes from
+    /// `output_offset` to the start of the command as LZ77 does. Apply functions writing into
+    /// a buffer, such as [`apply_to_slice`], read the source back from it. The others read it
+    /// again from the base or the delta, and fail on copies reaching further back than
+    /// [`SELF_COPY_WINDOW`] bytes.
+    SelfCopy {
+        output_offset: u64,
+        length: usize,
+    },
+}
+
+impl DeltaCommand {
+    /// Number of bytes this command contributes to the reconstructed output.
+    #[inline]
+    #[must_use]
+    pub fn output_len(&self) -> usize {
+        match self {
+            DeltaCommand::Data(data) => data.len(),
+            DeltaCommand::Copy { length, .. } | DeltaCommand::SelfCopy { length, .. } => *length,
+        }
+    }
 }
 
-const DEFAULT_BLOCK_SIZE: usize = 4096;
+impl std::fmt::Debug for DeltaCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeltaCommand::Data(data) => f.debug_tuple("Data").field(&Redacted::new(data)).finish(),
+            DeltaCommand::Copy { offset, length } => f
+                .debug_struct("Copy")
+                .field("offset", offset)
+                .field("length", length)
+                .finish(),
+            DeltaCommand::SelfCopy {
+                output_offset,
+                length,
+            } => f
+                .debug_struct("SelfCopy")
+                .field("output_offset", output_offset)
+                .field("length", length)
+                .finish(),
+        }
+    }
+}
+
+impl std::fmt::Display for DeltaCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeltaCommand::Data(data) => write!(f, "data {}", Redacted::new(data)),
+            DeltaCommand::Copy { offset, length } => {
+                write!(f, "copy {length} bytes from offset {offset}")
+            }
+            DeltaCommand::SelfCopy {
+                output_offset,
+                length,
+            } => write!(f, "copy {length} bytes from output offset {output_offset}"),
+        }
+    }
+}
+
+pub(crate) const DEFAULT_BLOCK_SIZE: usize = 4096;
+const APPLY_BUF_SIZE: usize = 64 * 1024;
 
 /// Generate signatures from a reader.
 ///
@@ -192,41 +865,222 @@ pub fn generate_signatures<R: Read>(reader: R) -> std::io::Result<Signatures> {
     generate_signatures_with_block_size(reader, DEFAULT_BLOCK_SIZE)
 }
 
+/// Generate signatures from a reader using a block size of `1 << shift` bytes.
+///
+/// Power-of-two block sizes line up with filesystem blocks and pages, and keep the delta
+/// window buffer (two blocks) a power of two as well.
+///
+/// # Errors
+/// Returns an error if `shift` does not fit in `usize` or if reading from the reader fails.
+pub fn generate_signatures_pow2<R: Read>(reader: R, shift: u8) -> std::io::Result<Signatures> {
+    let block_size = 1usize.checked_shl(u32::from(shift)).ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("block size shift {shift} overflows usize"),
+        )
+    })?;
+    generate_signatures_with_block_size(reader, block_size)
+}
+
 /// Generate signatures from a reader.
 ///
 /// # Errors
-/// Returns an error if reading from the reader fails.
+/// Returns an error if the block size is zero, if reading from the reader fails or if the block
+/// buffer cannot be allocated.
 pub fn generate_signatures_with_block_size<R: Read>(
+    reader: R,
+    block_size: usize,
+) -> std::io::Result<Signatures> {
+    generate_signatures_with_buffer_limit(reader, block_size, MAX_BLOCK_BUFFER_SIZE)
+}
+
+/// Same as `generate_signatures_with_block_size`, but never buffers more than `buffer_limit`
+/// bytes at once.
+///
+/// Blocks larger than `buffer_limit` are hashed incrementally while being read in pieces of
+/// `buffer_limit` bytes, producing the same hashes as if they were buffered whole.
+///
+/// # Errors
+/// Returns an error if the block size is zero, if reading from the reader fails or if the buffer
+/// cannot be allocated.
+pub fn generate_signatures_with_buffer_limit<R: Read>(
+    reader: R,
+    block_size: usize,
+    buffer_limit: usize,
+) -> std::io::Result<Signatures> {
+    generate_signatures_impl(
+        reader,
+        block_size,
+        buffer_limit,
+        false,
+        FinalChunkMode::Exact,
+    )
+}
+
+/// Same as `generate_signatures_with_block_size`, but also records the CRC-32 of every block.
+///
+/// Delta generation then checks the CRC-32 of a candidate block before computing its strong
+/// hash, which rejects weak hash collisions without hashing the block twice. This mostly helps
+/// when the new file shares little with the base, where collisions dominate.
+///
+/// # Errors
+/// Returns an error if the block size is zero, if reading from the reader fails or if the block
+/// buffer cannot be allocated.
+pub fn generate_signatures_with_crc32<R: Read>(
+    reader: R,
+    block_size: usize,
+) -> std::io::Result<Signatures> {
+    generate_signatures_impl(
+        reader,
+        block_size,
+        MAX_BLOCK_BUFFER_SIZE,
+        true,
+        FinalChunkMode::Exact,
+    )
+}
+
+/// Same as `generate_signatures_with_block_size`, hashing a last block shorter than
+/// `block_size` as `final_chunk_mode` says.
+///
+/// # Errors
+/// Returns an error if the block size is zero, if reading from the reader fails or if the block
+/// buffer cannot be allocated.
+pub fn generate_signatures_with_final_chunk_mode<R: Read>(
+    reader: R,
+    block_size: usize,
+    final_chunk_mode: FinalChunkMode,
+) -> std::io::Result<Signatures> {
+    generate_signatures_impl(
+        reader,
+        block_size,
+        MAX_BLOCK_BUFFER_SIZE,
+        false,
+        final_chunk_mode,
+    )
+}
+
+fn generate_signatures_impl<R: Read>(
     mut reader: R,
     block_size: usize,
+    buffer_limit: usize,
+    with_crc32: bool,
+    final_chunk_mode: FinalChunkMode,
 ) -> std::io::Result<Signatures> {
+    if block_size == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "block size must be greater than zero",
+        ));
+    }
     let mut signatures = Signatures::new(block_size);
-    let mut buffer = vec![0u8; block_size];
+    signatures.final_chunk_mode = final_chunk_mode;
+    let mut buffer = try_alloc_buffer(block_size.min(buffer_limit.max(1)))?;
     let mut rolling = RollingChecksum::new();
+    let mut file_rolling = RollingChecksum::new();
 
     for block_index in 0.. {
         rolling.reset();
-        let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+        let mut crc = crc32::Crc32::new();
+        let (bytes_read, strong) = if buffer.len() == block_size {
+            let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+            file_rolling.update(&buffer[..bytes_read]);
+            let chunk = if final_chunk_mode.pads(bytes_read, block_size) {
+                buffer[bytes_read..].fill(0);
+                &buffer[..]
+            } else {
+                &buffer[..bytes_read]
+            };
+            rolling.update(chunk);
+            if with_crc32 {
+                crc.update(chunk);
+            }
+            (bytes_read, xxh3_128(chunk))
+        } else {
+            let mut hasher = XxHash3_128::new();
+            let mut bytes_read = 0;
+            while bytes_read < block_size {
+                let want = (block_size - bytes_read).min(buffer.len());
+                let n = read_exact_or_eof(&mut reader, &mut buffer[..want])?;
+                rolling.update(&buffer[..n]);
+                file_rolling.update(&buffer[..n]);
+                hasher.write(&buffer[..n]);
+                if with_crc32 {
+                    crc.update(&buffer[..n]);
+                }
+                bytes_read += n;
+                if n < want {
+                    break;
+                }
+            }
+            if final_chunk_mode.pads(bytes_read, block_size) {
+                buffer.fill(0);
+                let mut padding = block_size - bytes_read;
+                while padding > 0 {
+                    let n = padding.min(buffer.len());
+                    rolling.update(&buffer[..n]);
+                    hasher.write(&buffer[..n]);
+                    if with_crc32 {
+                        crc.update(&buffer[..n]);
+                    }
+                    padding -= n;
+                }
+            }
+            (bytes_read, hasher.finish_128())
+        };
         if bytes_read == 0 {
             break;
         }
+        signatures.source_size += bytes_read as u64;
 
-        let chunk = &buffer[..bytes_read];
-        rolling.update(chunk);
-        let weak = rolling.value();
-        let strong = xxh3_128(chunk);
         signatures.insert(
-            weak,
+            rolling.value(),
             SignatureStrong {
                 strong,
                 block_index,
+                crc32: with_crc32.then(|| crc.finish()),
             },
         );
     }
 
+    signatures.file_adler = Some(file_rolling.value());
     Ok(signatures)
 }
 
+/// Same as `generate_signatures_with_block_size`, but never matches the last `tail_bytes` of
+/// the source.
+///
+/// Every block overlapping the tail is left out, so a volatile trailer (e.g. a timestamp
+/// footer) is always sent as data instead of being copied from the base. The matchable region
+/// is the source minus the tail, rounded down to a block boundary.
+///
+/// # Errors
+/// Returns an error if the block size is zero, if reading from the reader fails or if the block
+/// buffer cannot be allocated.
+pub fn generate_signatures_excluding_tail<R: Read>(
+    reader: R,
+    block_size: usize,
+    tail_bytes: u64,
+) -> std::io::Result<Signatures> {
+    let signatures = generate_signatures_with_block_size(reader, block_size)?;
+    let source_size = signatures.source_size;
+    let matchable = source_size.saturating_sub(tail_bytes);
+    let block_size = block_size as u64;
+    Ok(signatures.retain_blocks(|block_index| {
+        let end = ((block_index as u64 + 1) * block_size).min(source_size);
+        end <= matchable
+    }))
+}
+
+/// Encoded size allowed for a `generate_delta` delta by the quality tests. An edit costs the
+/// block it lands in and the partial block before the next aligned match.
+#[cfg(feature = "test-util")]
+pub(crate) const DELTA_SIZE_ENVELOPE: test_util::DeltaSizeEnvelope = test_util::DeltaSizeEnvelope {
+    per_new_byte: 3,
+    blocks_per_edit: 2,
+    bytes_per_edit: 64,
+    fixed: 64,
+};
+
 /// Generate delta from signatures and a reader containing new data.
 /// Uses a rolling checksum to efficiently find matching blocks at any offset.
 /// Reads data in chunks to avoid loading the entire input into memory.
@@ -245,126 +1099,270 @@ pub fn generate_delta<R: Read>(
     Ok(result)
 }
 
+/// Generate a delta for new data known to be the base shifted by `alignment` bytes.
+///
+/// The first `alignment` bytes are sent as data, and the rest is matched block by block at
+/// block boundaries only, skipping the rolling search. Use `generate_delta` when the shift is
+/// not known.
+///
+/// # Errors
+/// Returns an error if reading from the reader fails or if the block buffer cannot be allocated.
+pub fn generate_delta_with_alignment<R: Read>(
+    old_signatures: &Signatures,
+    mut reader: R,
+    alignment: usize,
+) -> std::io::Result<Vec<DeltaCommand>> {
+    let mut result = Vec::new();
+    let mut prefix = Vec::new();
+    (&mut reader)
+        .take(alignment as u64)
+        .read_to_end(&mut prefix)?;
+    splice::push_merged(&mut result, DeltaCommand::Data(prefix));
+
+    let block_size = old_signatures.block_size().max(1);
+    let mut buffer = try_alloc_buffer(block_size)?;
+    loop {
+        let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        let block = &buffer[..bytes_read];
+        let command = match old_signatures.from(block) {
+            Some(block_idx) => DeltaCommand::Copy {
+                offset: (block_idx * block_size) as u64,
+                length: bytes_read,
+            },
+            None => DeltaCommand::Data(block.to_vec()),
+        };
+        splice::push_merged(&mut result, command);
+    }
+    Ok(result)
+}
+
 /// Same as `generate_delta`, but allows for custom callback when a new delta is located.
 ///
 /// # Errors
 /// Returns an error if the callback returns an error or if reading from the reader fails.
 pub fn generate_delta_with_cb<R: Read, F: FnMut(DeltaCommand) -> std::io::Result<()>>(
     old_signatures: &Signatures,
-    mut reader: R,
-    mut cb: F,
+    reader: R,
+    cb: F,
 ) -> std::io::Result<()> {
-    let block_size = old_signatures.block_size();
-    let buffer_size = block_size * 2;
-
-    let mut last_copy: Option<(u64, usize)> = None;
-    let mut pending_data: Vec<u8> = Vec::new();
-
-    let mut window = vec![0u8; buffer_size];
-    let mut window_start = 0;
-    let mut window_len;
-
-    let initial_read = read_exact_or_eof(&mut reader, &mut window[..block_size])?;
-    if initial_read == 0 {
-        return Ok(());
-    }
-    window_len = initial_read;
+    DeltaBuilder::new(old_signatures)?.generate(reader, cb)
+}
 
-    if initial_read < block_size {
-        if let Some(block_idx) = old_signatures.from(&window[..initial_read]) {
-            cb(DeltaCommand::Copy {
-                offset: (block_idx * block_size) as u64,
-                length: initial_read,
-            })?;
-            return Ok(());
-        }
-        cb(DeltaCommand::Data(window[..initial_read].to_vec()))?;
-        return Ok(());
-    }
+/// Apply `delta` to the base read from `base_reader`, writing the output to `target_writer`.
+///
+/// The base is read through a [`SeekReadAdapter`], as [`apply_delta_at`] would, so nearby
+/// copies are served by a single read and the base is only sought when a copy does not start
+/// where the previous read ended.
+///
+/// # Errors
+/// Returns an error if the delta contains invalid copy commands (out of bounds or overflow) or if IO operations fail.
+///
+/// Errors from `target_writer` stop the apply immediately and carry an [`ApplyWriteFailed`]
+/// with the failing command and the number of output bytes committed so far.
+pub fn apply_delta<R: Read + Seek, W: Write, I>(
+    base_reader: R,
+    delta: I,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    apply_delta_resume(base_reader, delta, target_writer, 0)
+}
 
-    let mut rolling = RollingChecksum::new();
-    rolling.update(&window[..block_size]);
+/// Same as `apply_delta`, but skips the first `already_written` bytes of the output.
+///
+/// This allows continuing an interrupted apply: commands that end before `already_written`
+/// are not written again, the command straddling it is emitted only from that point on, and
+/// everything after is applied as usual. `target_writer` is expected to be positioned right
+/// after the bytes that were already written. Only self-referential copies before
+/// `already_written` are applied again, without writing their output, so that later ones can
+/// read it back; nothing else is read from the base.
+///
+/// # Errors
+/// Returns an error if the delta contains invalid copy commands (out of bounds or overflow) or if IO operations fail.
+pub fn apply_delta_resume<R: Read + Seek, W: Write, I>(
+    base_reader: R,
+    delta: I,
+    target_writer: W,
+    already_written: u64,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let base = SeekReadAdapter::new(base_reader);
+    with_apply_writer(target_writer, already_written, |writer| {
+        apply_commands(&base, delta, writer, already_written, &mut ())
+    })
+}
 
-    loop {
-        while window_len - window_start >= block_size {
-            let weak = rolling.value();
-
-            if let Some(entries) = old_signatures.weak(weak) {
-                let strong = xxh3_128(&window[window_start..window_start + block_size]);
-
-                if let Some(block_idx) = find_strong_hash(entries, strong) {
-                    emit_copy_for_block_idx(
-                        &mut last_copy,
-                        &mut pending_data,
-                        block_idx,
-                        block_size,
-                        block_size,
-                        &mut cb,
-                    )?;
-
-                    window_start += block_size;
-
-                    if window_len - window_start >= block_size {
-                        reset_rolling(&mut rolling, &window, window_start, block_size);
-                    }
-                    continue;
-                }
-            }
+/// Same as `apply_delta`, but reads base bytes through `fetch(offset, length)` instead of a
+/// seekable reader.
+///
+/// `fetch` is called for every read of the base, which covers one copy or several nearby
+/// ones as with [`apply_delta_at`], and must return exactly `length` bytes.
+///
+/// # Errors
+/// Returns an error if `fetch` fails or returns the wrong number of bytes, or if writing fails.
+pub fn apply_delta_with_fetch<F, W: Write, I>(
+    fetch: F,
+    delta: I,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    F: Fn(u64, usize) -> std::io::Result<Vec<u8>>,
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let base = FetchBase(fetch);
+    with_apply_writer(target_writer, 0, |writer| {
+        apply_commands(&base, delta, writer, 0, &mut ())
+    })
+}
 
-            let old_byte = window[window_start];
-            pending_data.push(old_byte);
-            window_start += 1;
+/// Same as `apply_delta`, but reads base bytes with positioned reads.
+///
+/// Every copy is served by [`ReadAt::read_exact_at`] calls on `base` instead of a seek and a
+/// read, so the base is never repositioned and can be shared with other readers. Sources that
+/// only implement `Read + Seek` can be wrapped in a [`SeekReadAdapter`].
+///
+/// Copies reading nearby base ranges in increasing order, as left by scattered edits, are
+/// served by a single read covering all of them. Fragmented deltas then cost one read per
+/// 64 KiB of base instead of one per command.
+///
+/// # Errors
+/// Returns an error if a copy reaches past the end of the base, or if reading or writing fails.
+pub fn apply_delta_at<R: ReadAt + ?Sized, W: Write, I>(
+    base: &R,
+    delta: I,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    with_apply_writer(target_writer, 0, |writer| {
+        apply_commands(base, delta, writer, 0, &mut ())
+    })
+}
 
-            if window_len - window_start >= block_size {
-                rolling.roll(old_byte, window[window_start + block_size - 1], block_size);
-            }
-        }
+/// Check that `delta` applies cleanly against the base, without producing any output.
+///
+/// Every copy is read from the base and discarded, so a base that is too short or fails to
+/// read is caught exactly as `apply_delta` would hit it, at the cost of the reads only. Returns
+/// the size of the output the apply would produce.
+///
+/// # Errors
+/// Returns an error if a copy reaches past the end of the base, if a self-referential copy
+/// starts at or after its own output, or if reading the base fails.
+pub fn apply_dry_run<R: Read + Seek, I>(mut base_reader: R, delta: I) -> std::io::Result<u64>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut current_pos: u64 = 0;
+    let mut output_len: u64 = 0;
 
-        if window_start > 0 {
-            let remaining = window_len - window_start;
-            window.copy_within(window_start..window_len, 0);
-            window_len = remaining;
-            window_start = 0;
+    for item in Spans::new(delta.into_iter()) {
+        let (span, command) = item?;
+        output_len = span.output_range.end;
+        self_copy_source(&span, command.borrow())?;
+        let Some(basis_range) = span.basis_range else {
+            continue;
+        };
+        if basis_range.start != current_pos {
+            base_reader.seek(SeekFrom::Start(basis_range.start))?;
         }
-
-        let bytes_read = read_exact_or_eof(&mut reader, &mut window[window_len..buffer_size])?;
-        if bytes_read == 0 {
-            break;
+        let len = basis_range.end - basis_range.start;
+        let read = std::io::copy(&mut (&mut base_reader).take(len), &mut std::io::sink())?;
+        if read != len {
+            return Err(copy_out_of_bounds(span.op_index, basis_range.clone()));
         }
+        current_pos = basis_range.end;
+    }
+    Ok(output_len)
+}
 
-        let old_window_len = window_len;
-        window_len += bytes_read;
+/// Same as `apply_delta`, but writes the output into `out` instead of a writer.
+///
+/// Copies are read from the base straight into their place in `out`, so the output can live in
+/// a preallocated or memory-mapped region without an intermediate buffer. Size `out` with
+/// [`ApplyPlan::final_size`]; bytes past the output are left untouched. Self-referential copies
+/// are copied within `out`, from the output written before them. Returns the number of bytes
+/// written.
+///
+/// # Errors
+/// Returns an error if the output does not fit in `out`, in which case nothing past the end of
+/// the last command that fits is written, if a copy reaches past the end of the base, if a
+/// self-referential copy starts at or after its own output, or if reading the base fails.
+pub fn apply_to_slice<R: Read + Seek, I>(
+    mut base_reader: R,
+    delta: I,
+    out: &mut [u8],
+) -> std::io::Result<usize>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut current_pos: u64 = 0;
+    let mut written = 0;
+    let out_len = out.len();
 
-        if old_window_len < block_size && window_len >= block_size {
-            reset_rolling(&mut rolling, &window, window_start, block_size);
+    for item in Spans::new(delta.into_iter()) {
+        let (span, command) = item?;
+        let end = usize::try_from(span.output_range.end)
+            .ok()
+            .filter(|&end| end <= out_len)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    format!(
+                        "command {} writes {:?} past the end of the {}-byte output",
+                        span.op_index, span.output_range, out_len
+                    ),
+                )
+            })?;
+        if let Some(source) = self_copy_source(&span, command.borrow())? {
+            copy_within_output(out, &source, span.output_range.start);
+            written = end;
+            continue;
         }
-    }
-
-    let remaining = &window[window_start..window_len];
-    if !remaining.is_empty() {
-        if let Some(block_idx) = old_signatures.from(remaining) {
-            emit_copy_for_block_idx(
-                &mut last_copy,
-                &mut pending_data,
-                block_idx,
-                block_size,
-                remaining.len(),
-                &mut cb,
-            )?;
-        } else {
-            pending_data.extend_from_slice(remaining);
+        let target = &mut out[written..end];
+        if let Some(basis_range) = span.basis_range {
+            if basis_range.start != current_pos {
+                base_reader.seek(SeekFrom::Start(basis_range.start))?;
+            }
+            base_reader.read_exact(target).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    copy_out_of_bounds(span.op_index, basis_range.clone())
+                } else {
+                    e
+                }
+            })?;
+            current_pos = basis_range.end;
+        } else if let DeltaCommand::Data(data) = command.borrow() {
+            target.copy_from_slice(data);
         }
+        written = end;
     }
-
-    flush_pending_data(&mut last_copy, &mut pending_data, &mut cb)?;
-    flush_last_copy(&mut last_copy, &mut cb)?;
-
-    Ok(())
+    Ok(written)
 }
 
+/// Same as `apply_delta`, for a base that can only be read once from start to end, such as a
+/// streaming decompressor.
+///
+/// The whole base is read into memory first. When copies never read backwards,
+/// `apply_delta_forward_only` avoids that.
+///
 /// # Errors
-/// Returns an error if the delta contains invalid copy commands (out of bounds or overflow) or if IO operations fail.
-pub fn apply_delta<R: Read + Seek, W: Write, I>(
+/// Returns an error if reading the base fails, if a copy reaches past its end, or if writing
+/// fails.
+pub fn apply_delta_from_stream<R: Read, W: Write, I>(
     mut base_reader: R,
     delta: I,
     target_writer: W,
@@ -373,27 +1371,52 @@ where
     I: IntoIterator,
     I::Item: Borrow<DeltaCommand>,
 {
-    const BUF_SIZE: usize = 64 * 1024;
-    let mut writer = BufWriter::with_capacity(BUF_SIZE, target_writer);
-    let mut current_pos: u64 = 0;
-
-    for command in delta {
-        match command.borrow() {
-            DeltaCommand::Data(data) => {
-                writer.write_all(data)?;
-            }
-            DeltaCommand::Copy { offset, length } => {
-                let start = *offset;
+    let mut base = Vec::new();
+    base_reader.read_to_end(&mut base)?;
+    apply_delta_at(&base[..], delta, target_writer)
+}
 
-                if start != current_pos {
-                    base_reader.seek(SeekFrom::Start(start))?;
-                }
+/// Same as `apply_delta`, for a base split across several buffers, such as those filled by a
+/// vectored read.
+///
+/// Copies are read through a [`ScatterBase`] over `base`, so they may span buffer boundaries
+/// and the buffers are never concatenated.
+///
+/// # Errors
+/// Returns an error if a copy reaches past the end of the base or if writing fails.
+pub fn apply_scatter<B: AsRef<[u8]>, W: Write, I>(
+    base: &[B],
+    delta: I,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    apply_delta_at(&ScatterBase::new(base), delta, target_writer)
+}
 
-                let len = *length as u64;
-                std::io::copy(&mut (&mut base_reader).take(len), &mut writer)?;
-                current_pos = start + len;
-            }
-        }
-    }
-    writer.flush()
+/// Same as `apply_delta`, but streams the base forward instead of seeking.
+///
+/// Works for deltas whose copies never read before the end of the previous copy, which is the
+/// case for appends and small edits. Gaps between copies are read and discarded. Since the
+/// base cannot be read again, self-referential copies may only read bytes of `Data` commands
+/// or of other self-referential copies.
+///
+/// # Errors
+/// Returns an error if a copy reads backwards, if the base ends before a copy is satisfied,
+/// or if IO operations fail.
+pub fn apply_delta_forward_only<R: Read, W: Write, I>(
+    base_reader: R,
+    delta: I,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let base = ForwardBase::new(base_reader);
+    with_apply_writer(target_writer, 0, |writer| {
+        apply_commands(&base, delta, writer, 0, &mut ())
+    })
 }
@@ -0,0 +1,263 @@
+use crate::builder::push_unmatched;
+use crate::rolling::RollingChecksum;
+use crate::splice::push_merged;
+use crate::{
+    DEFAULT_MAX_DATA_LEN, DeltaCommand, SELF_COPY_WINDOW, Signatures, WeakMap,
+    emit_copy_for_block_idx, flush_last_copy, flush_pending_data, xxh3_128,
+};
+use std::collections::HashMap;
+
+/// Index over signatures for matching in two modes: aligned and rolling.
+///
+/// Next to the weak hashes of the signatures, the matcher keeps a map from strong hash to
+/// block. While new data keeps matching block after block, each block is looked up by its
+/// strong hash alone, without maintaining a rolling checksum. After a miss, the search rolls
+/// byte by byte until a block matches again, then goes back to aligned lookups from there.
+/// Build it once to generate several deltas against the same signatures.
+///
+/// A matcher is never modified after [`Matcher::new`]: every method takes `&self` and keeps
+/// its scratch state on the stack of the call, so one matcher can be shared by any number of
+/// threads.
+pub struct Matcher<'a> {
+    signatures: &'a Signatures,
+    by_strong: HashMap<u128, usize>,
+}
+
+impl<'a> Matcher<'a> {
+    #[must_use]
+    pub fn new(signatures: &'a Signatures) -> Self {
+        let mut by_strong = HashMap::with_capacity(signatures.len());
+        // A zero-padded last block would copy past the end of the base when matched whole.
+        let padded_tail = signatures.padded_tail().map(|(tail_index, _)| tail_index);
+        for entry in signatures.weak_to_strong.values().flatten() {
+            if Some(entry.block_index) == padded_tail {
+                continue;
+            }
+            by_strong
+                .entry(entry.strong)
+                .and_modify(|block_idx: &mut usize| {
+                    *block_idx = (*block_idx).min(entry.block_index);
+                })
+                .or_insert(entry.block_index);
+        }
+        Self {
+            signatures,
+            by_strong,
+        }
+    }
+
+    /// Index of the block with the same content as `block`, found by strong hash only.
+    #[inline]
+    #[must_use]
+    pub fn aligned(&self, block: &[u8]) -> Option<usize> {
+        self.by_strong.get(&xxh3_128(block)).copied()
+    }
+
+    /// Generate the delta of `new_data`, switching between aligned and rolling matching.
+    ///
+    /// The result applies with `apply_delta` like any other delta. It can differ from the one
+    /// of `generate_delta` where blocks repeat in the base, but copies the same data, and splits
+    /// unmatched data into `Data` commands of at most [`DEFAULT_MAX_DATA_LEN`] bytes the same
+    /// way.
+    #[must_use]
+    pub fn generate_delta(&self, new_data: &[u8]) -> Vec<DeltaCommand> {
+        let block_size = self.signatures.block_size();
+        let mut delta = Vec::new();
+        let mut cb = |command| {
+            delta.push(command);
+            Ok(())
+        };
+        let mut last_copy = None;
+        let mut pending_data = Vec::new();
+        let mut rolling = RollingChecksum::new();
+        let mut rolling_valid = false;
+        let mut aligned = true;
+        let mut pos = 0;
+
+        while block_size > 0 && new_data.len() - pos >= block_size {
+            let block = &new_data[pos..pos + block_size];
+            let matched = if aligned {
+                self.aligned(block)
+            } else {
+                if !rolling_valid {
+                    rolling.reset();
+                    rolling.update(block);
+                    rolling_valid = true;
+                }
+                self.signatures.find(rolling.value(), block)
+            };
+
+            if let Some(block_idx) = matched {
+                // The callback never fails.
+                let _ = emit_copy_for_block_idx(
+                    &mut last_copy,
+                    &mut pending_data,
+                    block_idx,
+                    block_size,
+                    block_size,
+                    &mut cb,
+                );
+                pos += block_size;
+                aligned = true;
+                rolling_valid = false;
+                continue;
+            }
+
+            let _ = push_unmatched(
+                &mut last_copy,
+                &mut pending_data,
+                &new_data[pos..=pos],
+                DEFAULT_MAX_DATA_LEN,
+                &mut cb,
+            );
+            pos += 1;
+            if aligned {
+                aligned = false;
+            } else if new_data.len() - pos >= block_size {
+                rolling.roll(
+                    new_data[pos - 1],
+                    new_data[pos + block_size - 1],
+                    block_size,
+                );
+            } else {
+                rolling_valid = false;
+            }
+        }
+
+        let remaining = &new_data[pos..];
+        if !remaining.is_empty() {
+            match self.signatures.from(remaining) {
+                Some(block_idx) if block_size > 0 => {
+                    let _ = emit_copy_for_block_idx(
+                        &mut last_copy,
+                        &mut pending_data,
+                        block_idx,
+                        block_size,
+                        remaining.len(),
+                        &mut cb,
+                    );
+                }
+                _ => {
+                    let _ = push_unmatched(
+                        &mut last_copy,
+                        &mut pending_data,
+                        remaining,
+                        DEFAULT_MAX_DATA_LEN,
+                        &mut cb,
+                    );
+                }
+            }
+        }
+        let _ = flush_pending_data(&mut last_copy, &mut pending_data, &mut cb);
+        let _ = flush_last_copy(&mut last_copy, &mut cb);
+        delta
+    }
+
+    /// Same as [`Matcher::generate_delta`], but also copies blocks that repeat within
+    /// `new_data`.
+    ///
+    /// Every block-aligned block of `new_data` is indexed once the scan has passed it, and a
+    /// block matching nothing in the base is looked up among those starting at most
+    /// [`SELF_COPY_WINDOW`] bytes before it, as an LZ compressor would. Such matches become
+    /// [`DeltaCommand::SelfCopy`] commands, which every apply function resolves. Matches in
+    /// the base are preferred. Unmatched data is split as by [`Matcher::generate_delta`].
+    #[must_use]
+    pub fn generate_delta_with_self_copies(&self, new_data: &[u8]) -> Vec<DeltaCommand> {
+        let block_size = self.signatures.block_size();
+        let mut delta = Vec::new();
+        let mut seen: WeakMap<Vec<usize>> = WeakMap::default();
+        let mut indexed = 0;
+        let mut pending_start = 0;
+        let mut rolling = RollingChecksum::new();
+        let mut rolling_valid = false;
+        let mut pos = 0;
+
+        while block_size > 0 && new_data.len() - pos >= block_size {
+            // Only blocks that end before `pos` are indexed, so a match is always written already.
+            while indexed + block_size <= pos {
+                let mut checksum = RollingChecksum::new();
+                checksum.update(&new_data[indexed..indexed + block_size]);
+                seen.entry(checksum.value()).or_default().push(indexed);
+                indexed += block_size;
+            }
+
+            let block = &new_data[pos..pos + block_size];
+            if !rolling_valid {
+                rolling.reset();
+                rolling.update(block);
+                rolling_valid = true;
+            }
+            let weak = rolling.value();
+            let matched = if let Some(block_idx) = self.signatures.find(weak, block) {
+                Some(DeltaCommand::Copy {
+                    offset: (block_idx * block_size) as u64,
+                    length: block_size,
+                })
+            } else {
+                // Continuing the previous self-copy lets the two merge.
+                let continued = match delta.last() {
+                    Some(DeltaCommand::SelfCopy {
+                        output_offset,
+                        length,
+                    }) if pending_start == pos => usize::try_from(*output_offset)
+                        .ok()
+                        .map(|offset| offset + length),
+                    _ => None,
+                };
+                continued
+                    .into_iter()
+                    .chain(seen.get(&weak).into_iter().flatten().copied())
+                    .find(|&start| {
+                        start + block_size <= pos
+                            && pos - start <= SELF_COPY_WINDOW
+                            && &new_data[start..start + block_size] == block
+                    })
+                    .map(|start| DeltaCommand::SelfCopy {
+                        output_offset: start as u64,
+                        length: block_size,
+                    })
+            };
+
+            if let Some(command) = matched {
+                push_data(&mut delta, &new_data[pending_start..pos]);
+                push_merged(&mut delta, command);
+                pos += block_size;
+                pending_start = pos;
+                rolling_valid = false;
+                continue;
+            }
+
+            pos += 1;
+            if new_data.len() - pos >= block_size {
+                rolling.roll(
+                    new_data[pos - 1],
+                    new_data[pos + block_size - 1],
+                    block_size,
+                );
+            }
+        }
+
+        let remaining = &new_data[pos..];
+        match self.signatures.from(remaining) {
+            Some(block_idx) if block_size > 0 && !remaining.is_empty() => {
+                push_data(&mut delta, &new_data[pending_start..pos]);
+                push_merged(
+                    &mut delta,
+                    DeltaCommand::Copy {
+                        offset: (block_idx * block_size) as u64,
+                        length: remaining.len(),
+                    },
+                );
+            }
+            _ => push_data(&mut delta, &new_data[pending_start..]),
+        }
+        delta
+    }
+}
+
+/// Push unmatched `data` as `Data` commands of at most [`DEFAULT_MAX_DATA_LEN`] bytes.
+fn push_data(delta: &mut Vec<DeltaCommand>, data: &[u8]) {
+    for chunk in data.chunks(DEFAULT_MAX_DATA_LEN) {
+        delta.push(DeltaCommand::Data(chunk.to_vec()));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::encoding::DELTA_VERSION;
+
+/// Parameters one peer is able to sync with, exchanged before any signature is sent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Capabilities {
+    /// Block sizes this peer can generate or match signatures with.
+    pub block_sizes: Vec<usize>,
+    /// Encoded delta versions this peer can read and write.
+    pub delta_versions: Vec<u8>,
+    /// Whether this peer can produce and check per-block CRC-32s.
+    pub crc32: bool,
+}
+
+impl Default for Capabilities {
+    /// Every power of two block size from 512 bytes to 64 KiB, the current delta version and
+    /// CRC-32 support.
+    fn default() -> Self {
+        Self {
+            block_sizes: (9..=16).map(|shift| 1 << shift).collect(),
+            delta_versions: vec![DELTA_VERSION],
+            crc32: true,
+        }
+    }
+}
+
+/// Parameters both peers agreed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AgreedParams {
+    pub block_size: usize,
+    pub delta_version: u8,
+    pub crc32: bool,
+}
+
+fn highest_common<T: Copy + Ord>(ours: &[T], theirs: &[T]) -> Option<T> {
+    ours.iter()
+        .filter(|value| theirs.contains(value))
+        .max()
+        .copied()
+}
+
+/// Pick the parameters to sync with from the capabilities of both peers.
+///
+/// The result does not depend on which side is `ours`: the largest common block size is
+/// chosen, as it keeps the signature smallest, along with the highest common delta version.
+/// CRC-32s are used only when both peers support them.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::Unsupported`] naming both sides' values if
+/// the peers share no block size or no delta version.
+pub fn negotiate(ours: &Capabilities, theirs: &Capabilities) -> std::io::Result<AgreedParams> {
+    let unsupported = |what: &str, ours: &dyn std::fmt::Debug, theirs: &dyn std::fmt::Debug| {
+        std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("no common {what}: ours are {ours:?}, theirs are {theirs:?}"),
+        )
+    };
+
+    let block_size = highest_common(&ours.block_sizes, &theirs.block_sizes)
+        .filter(|&block_size| block_size > 0)
+        .ok_or_else(|| unsupported("block size", &ours.block_sizes, &theirs.block_sizes))?;
+    let delta_version =
+        highest_common(&ours.delta_versions, &theirs.delta_versions).ok_or_else(|| {
+            unsupported(
+                "delta version",
+                &ours.delta_versions,
+                &theirs.delta_versions,
+            )
+        })?;
+
+    Ok(AgreedParams {
+        block_size,
+        delta_version,
+        crc32: ours.crc32 && theirs.crc32,
+    })
+}
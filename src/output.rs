@@ -0,0 +1,137 @@
+use std::io::{BufWriter, IoSlice, Write};
+
+/// Error payload returned when writing the reconstructed output fails.
+///
+/// It is wrapped in an [`std::io::Error`] of the same kind as the underlying write error and can
+/// be recovered with `error.get_ref().and_then(|e| e.downcast_ref::<ApplyWriteFailed>())`.
+///
+/// `output_offset` is the number of output bytes the writer accepted before failing. Apply
+/// functions never write anything after a failure, so it is also the committed length to pass
+/// to `apply_delta_resume` once the problem is fixed.
+#[derive(Debug)]
+pub struct ApplyWriteFailed {
+    /// Index of the command being applied when the failure surfaced. Output is buffered, so
+    /// the bytes that failed may belong to an earlier command.
+    pub op_index: usize,
+    pub output_offset: u64,
+    pub source: std::io::Error,
+}
+
+impl std::fmt::Display for ApplyWriteFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "writing output failed at offset {} while applying command {}: {}",
+            self.output_offset, self.op_index, self.source
+        )
+    }
+}
+
+impl std::error::Error for ApplyWriteFailed {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+pub(crate) fn write_failed(
+    op_index: usize,
+    output_offset: u64,
+    source: std::io::Error,
+) -> std::io::Error {
+    std::io::Error::new(
+        source.kind(),
+        ApplyWriteFailed {
+            op_index,
+            output_offset,
+            source,
+        },
+    )
+}
+
+/// Writer counting the bytes accepted by the target and adding context to its errors.
+pub(crate) struct TrackedWriter<W> {
+    inner: W,
+    pub(crate) written: u64,
+    pub(crate) op_index: usize,
+}
+
+impl<W: Write> TrackedWriter<W> {
+    pub(crate) fn new(inner: W, written: u64) -> Self {
+        Self {
+            inner,
+            written,
+            op_index: 0,
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> W {
+        self.inner
+    }
+
+    fn wrap(&self, e: std::io::Error) -> std::io::Error {
+        if e.kind() == std::io::ErrorKind::Interrupted {
+            e
+        } else {
+            write_failed(self.op_index, self.written, e)
+        }
+    }
+}
+
+impl<W: Write> Write for TrackedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf).map_err(|e| self.wrap(e))?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        let n = self.inner.write_vectored(bufs).map_err(|e| self.wrap(e))?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush().map_err(|e| self.wrap(e))
+    }
+}
+
+/// Writer told which command the bytes it is given belong to, to report failures against it.
+pub(crate) trait OpWriter: Write {
+    fn start_op(&mut self, op_index: usize);
+}
+
+impl<W: Write> OpWriter for TrackedWriter<W> {
+    #[inline]
+    fn start_op(&mut self, op_index: usize) {
+        self.op_index = op_index;
+    }
+}
+
+impl<W: OpWriter> OpWriter for BufWriter<W> {
+    #[inline]
+    fn start_op(&mut self, op_index: usize) {
+        self.get_mut().start_op(op_index);
+    }
+}
+
+pub(crate) type ApplyWriter<W> = BufWriter<TrackedWriter<W>>;
+
+/// Run `f` against a buffered, tracked writer, then flush it.
+///
+/// On failure the buffered bytes are discarded instead of being flushed on drop, so the target
+/// holds exactly the committed length reported by the error.
+pub(crate) fn with_apply_writer<W: Write>(
+    target_writer: W,
+    already_written: u64,
+    f: impl FnOnce(&mut ApplyWriter<W>) -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let mut writer = BufWriter::with_capacity(
+        crate::APPLY_BUF_SIZE,
+        TrackedWriter::new(target_writer, already_written),
+    );
+    let result = f(&mut writer).and_then(|()| writer.flush());
+    if result.is_err() {
+        drop(writer.into_parts());
+    }
+    result
+}
@@ -0,0 +1,443 @@
+use crate::builder::push_unmatched;
+use crate::rolling::RollingChecksum;
+use crate::spans::{OpSpan, Spans, copy_out_of_bounds, copy_within_output, self_copy_source};
+use crate::{
+    DEFAULT_MAX_DATA_LEN, DeltaCommand, SignatureStrong, SignatureWeak, Signatures,
+    emit_copy_for_block_idx, flush_last_copy, flush_pending_data, xxh3_128,
+};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+/// Positions below which splitting the scan across threads costs more than it saves.
+const MIN_POSITIONS_PER_THREAD: usize = 64 * 1024;
+/// Positions every thread walks in one round of `generate_delta_parallel`.
+const ROUND_POSITIONS_PER_THREAD: usize = 256 * 1024;
+/// Source bytes below which hashing blocks on several threads costs more than it saves.
+const MIN_SOURCE_PER_THREAD: usize = 1024 * 1024;
+/// Output bytes below which splitting an apply across threads costs more than it saves.
+const MIN_OUTPUT_PER_THREAD: usize = 1024 * 1024;
+
+fn resolve_threads(threads: usize, work: usize, min_per_thread: usize) -> std::io::Result<usize> {
+    let threads = if threads == 0 {
+        std::thread::available_parallelism()?.get()
+    } else {
+        threads
+    };
+    Ok(threads.min(work.div_ceil(min_per_thread)).max(1))
+}
+
+/// Weak and strong hashes of every block of `blocks`, whose first block is `first_index`.
+fn hash_blocks(
+    blocks: &[u8],
+    block_size: usize,
+    first_index: usize,
+) -> Vec<(SignatureWeak, SignatureStrong)> {
+    blocks
+        .chunks(block_size)
+        .zip(first_index..)
+        .map(|(block, block_index)| {
+            (
+                RollingChecksum::compute(block),
+                SignatureStrong {
+                    strong: xxh3_128(block),
+                    block_index,
+                    crc32: None,
+                },
+            )
+        })
+        .collect()
+}
+
+/// Same as `generate_signatures_with_block_size` over in-memory data, hashing blocks on
+/// `threads` threads.
+///
+/// Every thread hashes a contiguous run of blocks and the hashes are inserted in block order,
+/// so the result is identical to the sequential one. The data can be a memory-mapped file,
+/// which is then read by every thread at once without copying. Zero threads uses the
+/// available parallelism.
+///
+/// # Errors
+/// Returns an error if the block size is zero or if the worker threads cannot be spawned.
+pub fn generate_signatures_parallel(
+    data: &[u8],
+    block_size: usize,
+    threads: usize,
+) -> std::io::Result<Signatures> {
+    if block_size == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "block size must be greater than zero",
+        ));
+    }
+    let mut signatures = Signatures::new(block_size);
+    signatures.file_adler = Some(RollingChecksum::compute(data));
+    signatures.source_size = data.len() as u64;
+    let blocks = data.len().div_ceil(block_size);
+    let threads = resolve_threads(threads, data.len(), MIN_SOURCE_PER_THREAD)?;
+
+    let hashes: Vec<(SignatureWeak, SignatureStrong)> = if threads == 1 {
+        hash_blocks(data, block_size, 0)
+    } else {
+        let blocks_per_thread = blocks.div_ceil(threads);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = data
+                .chunks(blocks_per_thread * block_size)
+                .zip((0..).step_by(blocks_per_thread))
+                .map(|(run, first_index)| {
+                    std::thread::Builder::new()
+                        .spawn_scoped(scope, move || hash_blocks(run, block_size, first_index))
+                })
+                .collect::<std::io::Result<_>>()?;
+            Ok::<_, std::io::Error>(
+                workers
+                    .into_iter()
+                    .flat_map(|worker| {
+                        worker
+                            .join()
+                            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+                    })
+                    .collect(),
+            )
+        })?
+    };
+    for (weak, strong) in hashes {
+        signatures.insert(weak, strong);
+    }
+    Ok(signatures)
+}
+
+/// Greedy walk over `positions` as `generate_delta` makes it: positions are checked in order
+/// until one matches a block, then the walk resumes right after that block. Returns the
+/// matches as `(position, block_index)` pairs, stopping after `max_matches`.
+fn scan(
+    signatures: &Signatures,
+    new_data: &[u8],
+    positions: Range<usize>,
+    max_matches: usize,
+) -> Vec<(usize, usize)> {
+    let block_size = signatures.block_size();
+    let mut matches = Vec::new();
+    let mut rolling = RollingChecksum::new();
+    let mut pos = positions.start;
+    let mut synced = false;
+    while pos < positions.end && matches.len() < max_matches {
+        if !synced {
+            rolling = RollingChecksum::new();
+            rolling.update(&new_data[pos..pos + block_size]);
+            synced = true;
+        }
+        if let Some(block_idx) = signatures.find(rolling.value(), &new_data[pos..pos + block_size])
+        {
+            matches.push((pos, block_idx));
+            pos += block_size;
+            synced = false;
+            continue;
+        }
+        if pos + 1 < positions.end {
+            rolling.roll(new_data[pos], new_data[pos + block_size], block_size);
+        }
+        pos += 1;
+    }
+    matches
+}
+
+/// A run of positions, with the matches of the greedy walk over it.
+type Walk = (Range<usize>, Vec<(usize, usize)>);
+
+/// Matches `generate_delta` finds from `pos` on, among `positions`, given the greedy walks
+/// `slices` made over consecutive runs of them.
+///
+/// A walk that started where `generate_delta` reaches its run agrees with it. Otherwise the
+/// positions the walk skipped inside a matched block are checked inline, until both agree
+/// again, which takes at most one block.
+fn reconcile(
+    signatures: &Signatures,
+    new_data: &[u8],
+    mut pos: usize,
+    slices: Vec<Walk>,
+    matches: &mut Vec<(usize, usize)>,
+) -> usize {
+    let block_size = signatures.block_size();
+    for (slice, walk) in slices {
+        while pos < slice.end {
+            let next = walk.partition_point(|&(at, _)| at < pos);
+            let scanned_from = match next {
+                0 => slice.start,
+                next => walk[next - 1].0 + block_size,
+            };
+            let found = if pos >= scanned_from {
+                walk.get(next).copied()
+            } else {
+                scan(signatures, new_data, pos..scanned_from.min(slice.end), 1)
+                    .first()
+                    .copied()
+            };
+            match found {
+                Some((at, block_idx)) => {
+                    matches.push((at, block_idx));
+                    pos = at + block_size;
+                }
+                None if pos >= scanned_from => pos = slice.end,
+                None => pos = scanned_from.min(slice.end),
+            }
+        }
+    }
+    pos
+}
+
+/// Greedy walks over `round`, split into one run per thread.
+fn walk_round(
+    signatures: &Signatures,
+    new_data: &[u8],
+    round: Range<usize>,
+    threads: usize,
+) -> std::io::Result<Vec<Walk>> {
+    if threads == 1 {
+        let walk = scan(signatures, new_data, round.clone(), usize::MAX);
+        return Ok(vec![(round, walk)]);
+    }
+    let per_thread = round.len().div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = round
+            .clone()
+            .step_by(per_thread)
+            .map(|start| {
+                let slice = start..(start + per_thread).min(round.end);
+                std::thread::Builder::new().spawn_scoped(scope, move || {
+                    let walk = scan(signatures, new_data, slice.clone(), usize::MAX);
+                    (slice, walk)
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        Ok(workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect())
+    })
+}
+
+/// Same as `generate_delta` over in-memory data, confirming candidate blocks on `threads`
+/// threads.
+///
+/// The input is scanned in rounds of up to `threads` runs of positions, each walked by its own
+/// thread as `generate_delta` would from the start of the run. The walks are then reconciled
+/// in order, confirming inline the few positions a walk skipped where `generate_delta` does
+/// not, so the result is identical and memory use is bounded by the size of a round. Zero
+/// threads uses the available parallelism.
+///
+/// # Errors
+/// Returns an error if the worker threads cannot be spawned.
+pub fn generate_delta_parallel(
+    signatures: &Signatures,
+    new_data: &[u8],
+    threads: usize,
+) -> std::io::Result<Vec<DeltaCommand>> {
+    let block_size = signatures.block_size();
+    if block_size == 0 {
+        return Ok(if new_data.is_empty() {
+            Vec::new()
+        } else {
+            vec![DeltaCommand::Data(new_data.to_vec())]
+        });
+    }
+    let positions = (new_data.len() + 1).saturating_sub(block_size);
+    let threads = resolve_threads(threads, positions, MIN_POSITIONS_PER_THREAD)?;
+
+    let mut delta = Vec::new();
+    let mut cb = |command| {
+        delta.push(command);
+        Ok(())
+    };
+    let mut last_copy = None;
+    let mut pending_data = Vec::new();
+    let mut matches = Vec::new();
+    let mut pos = 0;
+    while pos < positions {
+        let round = pos..positions.min(pos + threads * ROUND_POSITIONS_PER_THREAD);
+        let slices = walk_round(signatures, new_data, round, threads)?;
+        let round_end = reconcile(signatures, new_data, pos, slices, &mut matches);
+        for (at, block_idx) in matches.drain(..) {
+            push_unmatched(
+                &mut last_copy,
+                &mut pending_data,
+                &new_data[pos..at],
+                DEFAULT_MAX_DATA_LEN,
+                &mut cb,
+            )?;
+            emit_copy_for_block_idx(
+                &mut last_copy,
+                &mut pending_data,
+                block_idx,
+                block_size,
+                block_size,
+                &mut cb,
+            )?;
+            pos = at + block_size;
+        }
+        let round_end = round_end.min(positions);
+        if pos < round_end {
+            push_unmatched(
+                &mut last_copy,
+                &mut pending_data,
+                &new_data[pos..round_end],
+                DEFAULT_MAX_DATA_LEN,
+                &mut cb,
+            )?;
+            pos = round_end;
+        }
+    }
+
+    let remaining = &new_data[pos..];
+    if !remaining.is_empty() {
+        if let Some(block_idx) = signatures.from(remaining) {
+            emit_copy_for_block_idx(
+                &mut last_copy,
+                &mut pending_data,
+                block_idx,
+                block_size,
+                remaining.len(),
+                &mut cb,
+            )?;
+        } else {
+            push_unmatched(
+                &mut last_copy,
+                &mut pending_data,
+                remaining,
+                DEFAULT_MAX_DATA_LEN,
+                &mut cb,
+            )?;
+        }
+    }
+    flush_pending_data(&mut last_copy, &mut pending_data, &mut cb)?;
+    flush_last_copy(&mut last_copy, &mut cb)?;
+    Ok(delta)
+}
+
+/// Reconstruct the part of the output starting at `start` that fits in `out`, except for
+/// self-referential copies.
+#[allow(clippy::cast_possible_truncation)]
+fn apply_range<R: Read + Seek>(
+    base: &mut R,
+    spans: &[(OpSpan, &DeltaCommand)],
+    start: u64,
+    out: &mut [u8],
+) -> std::io::Result<()> {
+    let end = start + out.len() as u64;
+    let first = spans.partition_point(|(span, _)| span.output_range.end <= start);
+    let mut current_pos = None;
+    for (span, command) in &spans[first..] {
+        if span.output_range.start >= end {
+            break;
+        }
+        let lo = span.output_range.start.max(start);
+        let hi = span.output_range.end.min(end);
+        let target = &mut out[(lo - start) as usize..(hi - start) as usize];
+        let skip = lo - span.output_range.start;
+
+        if let Some(basis_range) = &span.basis_range {
+            let from = basis_range.start + skip;
+            if current_pos != Some(from) {
+                base.seek(SeekFrom::Start(from))?;
+            }
+            base.read_exact(target).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::UnexpectedEof {
+                    copy_out_of_bounds(span.op_index, basis_range.clone())
+                } else {
+                    e
+                }
+            })?;
+            current_pos = Some(from + target.len() as u64);
+        } else if let DeltaCommand::Data(data) = command {
+            let skip = skip as usize;
+            target.copy_from_slice(&data[skip..skip + target.len()]);
+        }
+    }
+    Ok(())
+}
+
+/// Fill the targets of self-referential copies, in order, once everything else is written.
+fn resolve_self_copies(out: &mut [u8], self_copies: &[(Range<u64>, u64)]) {
+    for (source, start) in self_copies {
+        copy_within_output(out, source, *start);
+    }
+}
+
+/// Same as `apply_to_slice`, but reconstructs disjoint ranges of `out` on `threads` threads.
+///
+/// The output is cut into one contiguous range per thread, splitting commands where needed,
+/// and every thread reads the copies of its range through its own base handle from
+/// `open_base`. Self-referential copies are resolved afterwards, in order, on the calling
+/// thread. This pays off for copy-dominated deltas over a base that serves concurrent reads
+/// well. Zero threads uses the available parallelism. Returns the number of bytes written.
+///
+/// # Errors
+/// Returns an error if the output does not fit in `out`, in which case nothing is written, if
+/// `open_base` fails, if a copy reaches past the end of the base, if a self-referential copy
+/// starts at or after its own output, if reading the base fails or if the worker threads
+/// cannot be spawned.
+pub fn apply_parallel_to_slice<R, F>(
+    open_base: F,
+    delta: &[DeltaCommand],
+    out: &mut [u8],
+    threads: usize,
+) -> std::io::Result<usize>
+where
+    R: Read + Seek,
+    F: Fn() -> std::io::Result<R> + Sync,
+{
+    let spans = Spans::new(delta.iter()).collect::<std::io::Result<Vec<_>>>()?;
+    let final_size = spans.last().map_or(0, |(span, _)| span.output_range.end);
+    let out_len = out.len();
+    let out = usize::try_from(final_size)
+        .ok()
+        .and_then(|len| out.get_mut(..len))
+        .ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::WriteZero,
+                format!(
+                    "delta writes {final_size} bytes, past the end of the {out_len}-byte output"
+                ),
+            )
+        })?;
+    let self_copies = spans
+        .iter()
+        .filter_map(|(span, command)| {
+            self_copy_source(span, command)
+                .transpose()
+                .map(|source| source.map(|source| (source, span.output_range.start)))
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let total = out.len();
+    let threads = resolve_threads(threads, total, MIN_OUTPUT_PER_THREAD)?;
+
+    if threads == 1 {
+        apply_range(&mut open_base()?, &spans, 0, out)?;
+        resolve_self_copies(out, &self_copies);
+        return Ok(total);
+    }
+    let per_thread = total.div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = out
+            .chunks_mut(per_thread)
+            .enumerate()
+            .map(|(i, range)| {
+                let (spans, open_base) = (&spans, &open_base);
+                let start = (i * per_thread) as u64;
+                std::thread::Builder::new().spawn_scoped(scope, move || {
+                    apply_range(&mut open_base()?, spans, start, range)
+                })
+            })
+            .collect::<std::io::Result<_>>()?;
+        workers.into_iter().try_for_each(|worker| {
+            worker
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    })?;
+    resolve_self_copies(out, &self_copies);
+    Ok(total)
+}
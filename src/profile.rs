@@ -0,0 +1,179 @@
+use crate::{
+    FinalChunkMode, MAX_BLOCK_BUFFER_SIZE, Signatures, generate_signatures_impl,
+    generate_signatures_with_block_size,
+};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Rule for picking a block size from the size of the file being signed.
+///
+/// The block size is chosen so that the signature stays within `target_signature_bytes`,
+/// rounded up to a power of two and clamped to `min_block_size..=max_block_size`. Larger
+/// files therefore never get smaller blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkSizeProfile {
+    /// Signature size to aim for, in bytes.
+    pub target_signature_bytes: u64,
+    pub min_block_size: usize,
+    pub max_block_size: usize,
+    /// Bytes every block adds to the signature.
+    pub bytes_per_block: u64,
+}
+
+impl Default for ChunkSizeProfile {
+    /// 1 MiB signatures with blocks of 512 bytes to 64 KiB, counting the 4 byte weak and
+    /// 16 byte strong hash of every block.
+    fn default() -> Self {
+        Self {
+            target_signature_bytes: 1 << 20,
+            min_block_size: 512,
+            max_block_size: 64 * 1024,
+            bytes_per_block: 20,
+        }
+    }
+}
+
+impl ChunkSizeProfile {
+    /// Block size to use for a file of `file_size` bytes.
+    #[must_use]
+    pub fn suggest(&self, file_size: u64) -> usize {
+        let min = self.min_block_size.max(1);
+        let max = self.max_block_size.max(min);
+        let ideal = (u128::from(file_size) * u128::from(self.bytes_per_block))
+            .div_ceil(u128::from(self.target_signature_bytes.max(1)));
+        let ideal = usize::try_from(ideal).unwrap_or(usize::MAX);
+        ideal
+            .checked_next_power_of_two()
+            .unwrap_or(usize::MAX)
+            .clamp(min, max)
+    }
+}
+
+/// Block size suggested by the default [`ChunkSizeProfile`].
+#[must_use]
+pub fn suggest_block_size(file_size: u64) -> usize {
+    ChunkSizeProfile::default().suggest(file_size)
+}
+
+/// Generate signatures with a block size picked by `profile` for the length of `reader`.
+///
+/// The length is measured from the current position to the end, and signing starts from the
+/// current position.
+///
+/// # Errors
+/// Returns an error if seeking or reading fails or if the block buffer cannot be allocated.
+pub fn generate_signatures_auto<R: Read + Seek>(
+    mut reader: R,
+    profile: &ChunkSizeProfile,
+) -> std::io::Result<Signatures> {
+    let start = reader.stream_position()?;
+    let end = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(start))?;
+    generate_signatures_with_block_size(reader, profile.suggest(end.saturating_sub(start)))
+}
+
+/// Generate signatures of the file at `path` with a block size picked by `profile`.
+///
+/// # Errors
+/// Returns an error if the file cannot be opened or read or if the block buffer cannot be
+/// allocated.
+pub fn generate_signatures_for_path<P: AsRef<Path>>(
+    path: P,
+    profile: &ChunkSizeProfile,
+) -> std::io::Result<Signatures> {
+    let file = std::fs::File::open(path)?;
+    let block_size = profile.suggest(file.metadata()?.len());
+    generate_signatures_with_block_size(std::io::BufReader::new(file), block_size)
+}
+
+/// Everything that decides how a base is signed.
+///
+/// Start from a [`SyncPreset`] and override fields with struct update syntax:
+/// `SyncOptions { crc32: true, ..SyncOptions::from_preset(SyncPreset::VmImage) }`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncOptions {
+    pub chunk_size: ChunkSizeProfile,
+    /// Record a CRC-32 of every block, see `generate_signatures_with_crc32`.
+    pub crc32: bool,
+    pub final_chunk_mode: FinalChunkMode,
+}
+
+impl SyncOptions {
+    #[inline]
+    #[must_use]
+    pub fn from_preset(preset: SyncPreset) -> Self {
+        preset.options()
+    }
+
+    /// Generate signatures of `reader`, from its current position to its end.
+    ///
+    /// # Errors
+    /// Returns an error if seeking or reading fails or if the block buffer cannot be allocated.
+    pub fn signatures<R: Read + Seek>(&self, mut reader: R) -> std::io::Result<Signatures> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+        generate_signatures_impl(
+            reader,
+            self.chunk_size.suggest(end.saturating_sub(start)),
+            MAX_BLOCK_BUFFER_SIZE,
+            self.crc32,
+            self.final_chunk_mode,
+        )
+    }
+}
+
+/// Settings for common kinds of files, so that callers do not have to tune each option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPreset {
+    /// Small text files edited a few lines at a time: small blocks keep the lines around an
+    /// edit matching.
+    SourceCode,
+    /// Disk images written in place a filesystem block at a time: blocks are never smaller
+    /// than 4 KiB, so they stay aligned with the writes, and grow with the image to bound the
+    /// signature.
+    VmImage,
+    /// Database files rewritten a page at a time: blocks of 4 to 16 KiB, so a changed page
+    /// costs about one block.
+    Database,
+    /// Compressed audio, video and images, either unchanged or re-encoded throughout: large
+    /// blocks keep the signature small, and CRC-32s reject the many weak hash collisions of
+    /// unrelated data cheaply.
+    Media,
+    /// Files that are only appended to: the shared prefix is copied whole whatever the block
+    /// size, so blocks are large.
+    LogAppend,
+}
+
+impl SyncPreset {
+    pub const ALL: [Self; 5] = [
+        Self::SourceCode,
+        Self::VmImage,
+        Self::Database,
+        Self::Media,
+        Self::LogAppend,
+    ];
+
+    #[must_use]
+    pub fn options(self) -> SyncOptions {
+        let chunk_size =
+            |target_signature_bytes, min_block_size, max_block_size| ChunkSizeProfile {
+                target_signature_bytes,
+                min_block_size,
+                max_block_size,
+                ..ChunkSizeProfile::default()
+            };
+        let (chunk_size, crc32) = match self {
+            Self::SourceCode => (chunk_size(64 * 1024, 256, 4 * 1024), false),
+            Self::VmImage => (chunk_size(1 << 20, 4 * 1024, 1 << 20), false),
+            Self::Database => (chunk_size(1 << 20, 4 * 1024, 16 * 1024), false),
+            Self::Media => (chunk_size(256 * 1024, 64 * 1024, 1 << 20), true),
+            Self::LogAppend => (chunk_size(256 * 1024, 16 * 1024, 1 << 20), false),
+        };
+        SyncOptions {
+            chunk_size,
+            crc32,
+            final_chunk_mode: FinalChunkMode::Exact,
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use crate::apply::{ApplyObserver, apply_commands};
+use crate::output::TrackedWriter;
+use crate::{DeltaCommand, OpKind, OpSpan, ReadAt};
+use std::borrow::Borrow;
+use std::cell::{Cell, RefCell};
+use std::io::{IoSlice, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Where the time of an [`apply_profiled`] call went.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ApplyProfile {
+    /// Time spent repositioning the base.
+    pub seek: Duration,
+    /// Time spent reading copied bytes from the base.
+    pub read: Duration,
+    /// Time spent writing the output, copies and data alike.
+    pub write: Duration,
+    pub seeks: u64,
+    /// Bytes copied from the base, or from the output for self-referential copies.
+    pub copied_bytes: u64,
+    pub data_bytes: u64,
+}
+
+impl ApplyProfile {
+    /// Time spent in IO, seeking, reading and writing together.
+    #[inline]
+    #[must_use]
+    pub fn total(&self) -> Duration {
+        self.seek + self.read + self.write
+    }
+}
+
+/// Base timing its seeks and reads into `profile`.
+struct TimedBase<'a, R> {
+    inner: RefCell<R>,
+    /// Position of `inner` if known.
+    position: Cell<Option<u64>>,
+    profile: &'a RefCell<ApplyProfile>,
+}
+
+impl<R: Read + Seek> ReadAt for TimedBase<'_, R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        if self.position.take() != Some(offset) {
+            let started = Instant::now();
+            inner.seek(SeekFrom::Start(offset))?;
+            let mut profile = self.profile.borrow_mut();
+            profile.seek += started.elapsed();
+            profile.seeks += 1;
+        }
+        let started = Instant::now();
+        let n = inner.read(buf)?;
+        self.profile.borrow_mut().read += started.elapsed();
+        self.position.set(Some(offset + n as u64));
+        Ok(n)
+    }
+}
+
+/// Writer timing its writes into `profile`.
+struct TimedWriter<'a, W> {
+    inner: W,
+    profile: &'a RefCell<ApplyProfile>,
+}
+
+impl<W> TimedWriter<'_, W> {
+    fn timed<T>(&mut self, f: impl FnOnce(&mut W) -> T) -> T {
+        let started = Instant::now();
+        let result = f(&mut self.inner);
+        self.profile.borrow_mut().write += started.elapsed();
+        result
+    }
+}
+
+impl<W: Write> Write for TimedWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.timed(|inner| inner.write(buf))
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> std::io::Result<usize> {
+        self.timed(|inner| inner.write_vectored(bufs))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.timed(Write::flush)
+    }
+}
+
+impl ApplyObserver for &RefCell<ApplyProfile> {
+    fn applied(&mut self, span: &OpSpan, output_range: Range<u64>, _: Option<Range<u64>>) {
+        let mut profile = self.borrow_mut();
+        let len = output_range.end - output_range.start;
+        match span.kind {
+            OpKind::Copy | OpKind::SelfCopy => profile.copied_bytes += len,
+            OpKind::Data => profile.data_bytes += len,
+        }
+    }
+}
+
+/// Same as `apply_delta`, but measures the time spent seeking, reading and writing.
+///
+/// Output is not buffered, so every write reaches `target_writer` as the commands are applied
+/// and the write time is that of the target itself. The profile covers the work done up to a
+/// failure as well.
+///
+/// # Errors
+/// Returns an error if a copy reaches past the end of the base or if IO operations fail.
+pub fn apply_profiled<R: Read + Seek, W: Write, I>(
+    base_reader: R,
+    delta: I,
+    target_writer: W,
+) -> (std::io::Result<()>, ApplyProfile)
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let profile = RefCell::new(ApplyProfile::default());
+    let base = TimedBase {
+        inner: RefCell::new(base_reader),
+        position: Cell::new(None),
+        profile: &profile,
+    };
+    let mut writer = TrackedWriter::new(
+        TimedWriter {
+            inner: target_writer,
+            profile: &profile,
+        },
+        0,
+    );
+    let result =
+        apply_commands(&base, delta, &mut writer, 0, &mut &profile).and_then(|()| writer.flush());
+    (result, profile.into_inner())
+}
@@ -0,0 +1,258 @@
+use std::cell::{Cell, RefCell};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::{Mutex, PoisonError};
+
+/// Positioned reads that do not move a shared cursor.
+///
+/// Unlike `Seek` + `Read`, reading at an offset takes `&self`, so one source can serve
+/// several readers, and every read is a single call (`pread` on unix).
+pub trait ReadAt {
+    /// Read up to `buf.len()` bytes starting at `offset`, returning how many were read.
+    ///
+    /// Returns `Ok(0)` at or past the end of the source.
+    ///
+    /// # Errors
+    /// Returns an error if the underlying read fails.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize>;
+
+    /// Read exactly `buf.len()` bytes starting at `offset`.
+    ///
+    /// # Errors
+    /// Returns an error of kind [`std::io::ErrorKind::UnexpectedEof`] if the source ends
+    /// first, or any error of the underlying read.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let Ok(start) = usize::try_from(offset) else {
+            return Ok(0);
+        };
+        let Some(available) = self.get(start..) else {
+            return Ok(0);
+        };
+        let n = buf.len().min(available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        Ok(n)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+}
+
+/// On Windows, `seek_read` moves the file cursor as a side effect.
+#[cfg(windows)]
+impl ReadAt for std::fs::File {
+    #[inline]
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+}
+
+/// [`ReadAt`] over a base split across several buffers, such as those filled by a vectored
+/// read.
+///
+/// The buffers are read in order as one contiguous source. Reads spanning several buffers are
+/// served from each in turn, without concatenating them. Empty buffers are allowed.
+#[derive(Clone, Debug)]
+pub struct ScatterBase<'a, B> {
+    buffers: &'a [B],
+    /// Offset where every buffer starts in the base.
+    starts: Vec<u64>,
+    len: u64,
+}
+
+impl<'a, B: AsRef<[u8]>> ScatterBase<'a, B> {
+    #[must_use]
+    pub fn new(buffers: &'a [B]) -> Self {
+        let mut len = 0;
+        let starts = buffers
+            .iter()
+            .map(|buffer| {
+                let start = len;
+                len += buffer.as_ref().len() as u64;
+                start
+            })
+            .collect();
+        Self {
+            buffers,
+            starts,
+            len,
+        }
+    }
+
+    /// Total length of the buffers.
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl<B: AsRef<[u8]>> ReadAt for ScatterBase<'_, B> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        if offset >= self.len {
+            return Ok(0);
+        }
+        // The last buffer starting at or before `offset`, which skips empty buffers.
+        let first = self.starts.partition_point(|&start| start <= offset) - 1;
+        let mut skip = offset - self.starts[first];
+        let mut n = 0;
+        for buffer in &self.buffers[first..] {
+            if n == buf.len() {
+                break;
+            }
+            #[allow(clippy::cast_possible_truncation)]
+            let available = &buffer.as_ref()[skip as usize..];
+            let len = available.len().min(buf.len() - n);
+            buf[n..n + len].copy_from_slice(&available[..len]);
+            n += len;
+            skip = 0;
+        }
+        Ok(n)
+    }
+}
+
+/// [`ReadAt`] over any `Read + Seek` source.
+///
+/// The source sits behind a mutex: every read locks it, seeks and reads, so concurrent reads
+/// are serialized. The position left by the previous read is remembered, and reads continuing
+/// from it do not seek again. Use a native implementation such as the one for `File` when
+/// reads should run in parallel.
+#[derive(Debug)]
+pub struct SeekReadAdapter<R> {
+    /// The source, and its position if known.
+    inner: Mutex<(R, Option<u64>)>,
+}
+
+impl<R: Read + Seek> SeekReadAdapter<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner: Mutex::new((inner, None)),
+        }
+    }
+
+    /// Return the wrapped source, positioned anywhere.
+    #[must_use]
+    pub fn into_inner(self) -> R {
+        self.inner
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+            .0
+    }
+}
+
+impl<R: Read + Seek> ReadAt for SeekReadAdapter<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        // A read that panicked left the position unknown, which the next read recovers from.
+        let mut guard = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let (inner, position) = &mut *guard;
+        if position.take() != Some(offset) {
+            inner.seek(SeekFrom::Start(offset))?;
+        }
+        let n = inner.read(buf)?;
+        *position = Some(offset + n as u64);
+        Ok(n)
+    }
+}
+
+/// [`ReadAt`] over a `fetch(offset, length)` function, which must return exactly `length`
+/// bytes.
+pub(crate) struct FetchBase<F>(pub(crate) F);
+
+impl<F: Fn(u64, usize) -> std::io::Result<Vec<u8>>> ReadAt for FetchBase<F> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let bytes = (self.0)(offset, buf.len())?;
+        if bytes.len() != buf.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "fetch returned {} bytes for a read of {} bytes at offset {offset}",
+                    bytes.len(),
+                    buf.len()
+                ),
+            ));
+        }
+        buf.copy_from_slice(&bytes);
+        Ok(bytes.len())
+    }
+}
+
+/// [`ReadAt`] over a source that can only be read forward, skipping the bytes between reads.
+///
+/// Reads must not start before the end of the previous one.
+pub(crate) struct ForwardBase<R> {
+    inner: RefCell<R>,
+    position: Cell<u64>,
+}
+
+impl<R: Read> ForwardBase<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner: RefCell::new(inner),
+            position: Cell::new(0),
+        }
+    }
+}
+
+impl<R: Read> ReadAt for ForwardBase<R> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        let position = self.position.get();
+        if offset < position {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("read from offset {offset} but the base was already read up to {position}"),
+            ));
+        }
+        let mut inner = self.inner.borrow_mut();
+        let skipped = std::io::copy(
+            &mut (&mut *inner).take(offset - position),
+            &mut std::io::sink(),
+        )?;
+        self.position.set(position + skipped);
+        if skipped < offset - position {
+            return Ok(0);
+        }
+        let n = inner.read(buf)?;
+        self.position.set(offset + n as u64);
+        Ok(n)
+    }
+}
@@ -0,0 +1,94 @@
+use crate::xxh3_128;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How literal data from the new file is shown when the crate formats it, so that logging a
+/// delta does not leak file contents by default.
+///
+/// Applies to the `Debug` and `Display` output of [`DeltaCommand`](crate::DeltaCommand) and
+/// to [`Redacted`]. Error messages never include literal data, whatever the policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RedactionPolicy {
+    /// Show only the length and the xxh3-128 of the data.
+    #[default]
+    Redact,
+    /// Also show the first `max_bytes` bytes as hex.
+    HexPreview { max_bytes: usize },
+}
+
+/// Policy used by `Debug` and `Display` impls: zero for [`RedactionPolicy::Redact`], one more
+/// than `max_bytes` for [`RedactionPolicy::HexPreview`].
+static GLOBAL_POLICY: AtomicUsize = AtomicUsize::new(0);
+
+impl RedactionPolicy {
+    /// The policy currently used by `Debug` and `Display` impls, [`RedactionPolicy::Redact`]
+    /// unless changed with [`RedactionPolicy::set_global`].
+    #[must_use]
+    pub fn global() -> Self {
+        match GLOBAL_POLICY.load(Ordering::Relaxed) {
+            0 => Self::Redact,
+            encoded => Self::HexPreview {
+                max_bytes: encoded - 1,
+            },
+        }
+    }
+
+    /// Use this policy in every `Debug` and `Display` impl of the crate, in all threads.
+    pub fn set_global(self) {
+        let encoded = match self {
+            Self::Redact => 0,
+            Self::HexPreview { max_bytes } => max_bytes.saturating_add(1),
+        };
+        GLOBAL_POLICY.store(encoded, Ordering::Relaxed);
+    }
+
+    /// Format `data` under this policy.
+    #[inline]
+    #[must_use]
+    pub fn apply(self, data: &[u8]) -> Redacted<'_> {
+        Redacted { data, policy: self }
+    }
+}
+
+/// Literal data formatted under a [`RedactionPolicy`], as `<len bytes, xxh3 hash>` with an
+/// optional hex preview.
+#[derive(Clone, Copy)]
+pub struct Redacted<'a> {
+    data: &'a [u8],
+    policy: RedactionPolicy,
+}
+
+impl<'a> Redacted<'a> {
+    /// Format `data` under the [global](RedactionPolicy::global) policy.
+    #[must_use]
+    pub fn new(data: &'a [u8]) -> Self {
+        RedactionPolicy::global().apply(data)
+    }
+}
+
+impl std::fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "<{} bytes, xxh3 {:032x}",
+            self.data.len(),
+            xxh3_128(self.data)
+        )?;
+        if let RedactionPolicy::HexPreview { max_bytes } = self.policy {
+            let shown = &self.data[..self.data.len().min(max_bytes)];
+            f.write_str(", ")?;
+            for byte in shown {
+                write!(f, "{byte:02x}")?;
+            }
+            if shown.len() < self.data.len() {
+                f.write_str("..")?;
+            }
+        }
+        f.write_str(">")
+    }
+}
+
+impl std::fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Display::fmt(self, f)
+    }
+}
@@ -0,0 +1,125 @@
+use crate::rolling::RollingChecksum;
+use crate::{SignatureStrong, Signatures, crc32, read_exact_or_eof, try_alloc_buffer, xxh3_128};
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+
+#[cfg(feature = "serde")]
+#[allow(clippy::trivially_copy_pass_by_ref)]
+pub(crate) fn is_zero(value: &u32) -> bool {
+    *value == 0
+}
+
+impl Signatures {
+    /// Generation of the last [`Signatures::resign_dirty`], zero for freshly generated
+    /// signatures.
+    #[inline]
+    #[must_use]
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Generation the block at `block_index` was last hashed in.
+    #[inline]
+    #[must_use]
+    pub fn block_generation(&self, block_index: usize) -> u32 {
+        self.generations.get(block_index).copied().unwrap_or(0)
+    }
+
+    /// Blocks marked by [`Signatures::mark_dirty`] and not re-hashed yet.
+    #[inline]
+    #[must_use]
+    pub fn dirty_blocks(&self) -> &[usize] {
+        &self.dirty
+    }
+
+    /// Mark every block overlapping `byte_range` of the source as changed.
+    ///
+    /// The range may reach past the end of the source, for writes that extend it. Only the
+    /// blocks the source has are marked: [`Signatures::resign_dirty`] re-hashes whatever it
+    /// grew by anyway.
+    pub fn mark_dirty(&mut self, byte_range: Range<u64>) {
+        self.mark_blocks(byte_range.start..byte_range.end.min(self.source_size));
+    }
+
+    /// Mark every block overlapping `byte_range`, whether or not the source has it yet.
+    fn mark_blocks(&mut self, byte_range: Range<u64>) {
+        if self.block_size == 0 || byte_range.is_empty() {
+            return;
+        }
+        let block_size = self.block_size as u64;
+        #[allow(clippy::cast_possible_truncation)]
+        let blocks =
+            (byte_range.start / block_size) as usize..byte_range.end.div_ceil(block_size) as usize;
+        self.dirty.extend(blocks);
+        self.dirty.sort_unstable();
+        self.dirty.dedup();
+    }
+
+    /// Re-hash the blocks marked dirty from the updated source, leaving the others untouched.
+    ///
+    /// The source may have grown or shrunk: the blocks from its old or new end, whichever
+    /// comes first, are re-hashed as well. Re-hashed blocks get the next generation. The
+    /// Adler-32 of the whole source is dropped since it can no longer be known without
+    /// reading everything.
+    ///
+    /// # Errors
+    /// Returns an error if the block size is zero or if seeking or reading the source fails.
+    /// The signatures are left unchanged by a failure to seek to the end, and may be
+    /// partially updated by a later failure.
+    pub fn resign_dirty<R: Read + Seek>(&mut self, mut reader: R) -> std::io::Result<()> {
+        if self.block_size == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "block size must be greater than zero",
+            ));
+        }
+        let block_size = self.block_size as u64;
+        let new_size = reader.seek(SeekFrom::End(0))?;
+        #[allow(clippy::cast_possible_truncation)]
+        let new_count = new_size.div_ceil(block_size) as usize;
+        if new_size != self.source_size {
+            self.mark_blocks(self.source_size.min(new_size)..self.source_size.max(new_size));
+        }
+        let with_crc32 = self
+            .weak_to_strong
+            .values()
+            .flatten()
+            .any(|entry| entry.crc32.is_some());
+
+        let dirty = std::mem::take(&mut self.dirty);
+        for entries in self.weak_to_strong.values_mut() {
+            entries.retain(|entry| dirty.binary_search(&entry.block_index).is_err());
+        }
+        self.weak_to_strong.retain(|_, entries| !entries.is_empty());
+
+        self.generation += 1;
+        self.generations.resize(new_count, 0);
+        self.source_size = new_size;
+        self.file_adler = None;
+
+        let mut buffer = try_alloc_buffer(self.block_size)?;
+        for &block_index in dirty.iter().take_while(|&&index| index < new_count) {
+            let offset = block_index as u64 * block_size;
+            reader.seek(SeekFrom::Start(offset))?;
+            #[allow(clippy::cast_possible_truncation)]
+            let len = (new_size - offset).min(block_size) as usize;
+            let bytes_read = read_exact_or_eof(&mut reader, &mut buffer[..len])?;
+            let block = if self.final_chunk_mode.pads(bytes_read, self.block_size) {
+                buffer[bytes_read..].fill(0);
+                &buffer[..]
+            } else {
+                &buffer[..bytes_read]
+            };
+            self.insert(
+                RollingChecksum::compute(block),
+                SignatureStrong {
+                    strong: xxh3_128(block),
+                    block_index,
+                    crc32: with_crc32.then(|| crc32::crc32(block)),
+                },
+            );
+            self.generations[block_index] = self.generation;
+        }
+        Ok(())
+    }
+}
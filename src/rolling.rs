@@ -1,5 +1,19 @@
 const MOD: u32 = 65521;
 
+/// Continue the Adler-32 sums `a` and `b` over `data`, one byte at a time.
+///
+/// Reference for the vectorized implementation, checked against it with the `verify-simd`
+/// feature and used where no vectorized path is available.
+#[must_use]
+pub fn update_scalar(a: u32, b: u32, data: &[u8]) -> (u32, u32) {
+    let (mut a, mut b) = (a % MOD, b % MOD);
+    for &byte in data {
+        a = (a + u32::from(byte)) % MOD;
+        b = (b + a) % MOD;
+    }
+    (a, b)
+}
+
 pub struct RollingChecksum {
     a: u32,
     b: u32,
@@ -29,27 +43,39 @@ impl RollingChecksum {
         (self.b % MOD) << 16 | (self.a % MOD)
     }
 
+    /// Add `data` to the sums.
+    ///
+    /// # Panics
+    /// With the `verify-simd` feature, panics if the vectorized sums differ from
+    /// [`update_scalar`].
     #[allow(clippy::cast_possible_truncation)]
     #[inline]
     pub fn update(&mut self, data: &[u8]) {
+        #[cfg(feature = "verify-simd")]
+        let expected = update_scalar(self.a, self.b, data);
         let (a, b) = (self.adler32)(self.a as u16, self.b as u16, data);
         (self.a, self.b) = (u32::from(a), u32::from(b));
+        #[cfg(feature = "verify-simd")]
+        assert_eq!(
+            (self.a, self.b),
+            expected,
+            "vectorized Adler-32 disagrees with the scalar reference over {} bytes",
+            data.len()
+        );
     }
 
     #[inline]
     pub fn roll(&mut self, old_byte: u8, new_byte: u8, window_size: usize) {
         let old = u32::from(old_byte);
         let new = u32::from(new_byte);
+        // Both sums stay reduced: wrapping around 2^32 and reducing later would not be
+        // congruent modulo `MOD`.
         #[allow(clippy::cast_possible_truncation)]
-        let n = window_size as u32;
-
-        // Use wrapping arithmetic and defer modulo to value() for better performance
-        self.a = self.a.wrapping_sub(old).wrapping_add(new);
-        self.b = self
-            .b
-            .wrapping_sub(n.wrapping_mul(old))
-            .wrapping_add(self.a)
-            .wrapping_sub(1);
+        let n_old =
+            ((window_size as u64 % u64::from(MOD)) * u64::from(old) % u64::from(MOD)) as u32;
+
+        self.a = (self.a % MOD + MOD - old + new) % MOD;
+        self.b = (self.b % MOD + 2 * MOD - n_old + self.a - 1) % MOD;
     }
 
     #[inline]
@@ -57,30 +83,131 @@ impl RollingChecksum {
         (self.a, self.b) = (1, 0);
     }
 
+    /// Adler-32 of `data`.
+    ///
+    /// # Panics
+    /// With the `verify-simd` feature, panics if the vectorized checksum differs from
+    /// [`update_scalar`].
     #[inline]
     #[must_use]
     pub fn compute(data: &[u8]) -> u32 {
-        simd_adler32::adler32(&data)
+        let value = simd_adler32::adler32(&data);
+        #[cfg(feature = "verify-simd")]
+        {
+            let (a, b) = update_scalar(1, 0, data);
+            assert_eq!(
+                value,
+                b << 16 | a,
+                "vectorized Adler-32 disagrees with the scalar reference over {} bytes",
+                data.len()
+            );
+        }
+        value
     }
 }
 
+/// Adler-32 of the concatenation of two inputs, from their checksums and the length of the
+/// second one, as `adler32_combine` in zlib.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn combine(first: u32, second: u32, second_len: u64) -> u32 {
+    let modulus = u64::from(MOD);
+    let (a1, b1) = (u64::from(first & 0xFFFF), u64::from(first >> 16));
+    let (a2, b2) = (u64::from(second & 0xFFFF), u64::from(second >> 16));
+    // Both sums of the second input start from `a = 1`, which the first input already counts.
+    let a = (a1 + a2 + modulus - 1) % modulus;
+    let b = (b1 + b2 + (second_len % modulus) * ((a1 + modulus - 1) % modulus)) % modulus;
+    ((b << 16) | a) as u32
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
     fn adler32_scalar(data: &[u8]) -> u32 {
-        let mut a: u32 = 1;
-        let mut b: u32 = 0;
-        for &byte in data {
-            a = (a + u32::from(byte)) % MOD;
-            b = (b + a) % MOD;
-        }
+        let (a, b) = update_scalar(1, 0, data);
         (b << 16) | a
     }
 
     #[test]
+    fn test_combine() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i * 31 % 251) as u8).collect();
+        for split in [0, 1, 5552, 65_521, 99_999, 100_000] {
+            let (head, tail) = data.split_at(split);
+            assert_eq!(
+                combine(
+                    RollingChecksum::compute(head),
+                    RollingChecksum::compute(tail),
+                    tail.len() as u64
+                ),
+                RollingChecksum::compute(&data),
+                "split at {split}"
+            );
+        }
+    }
+
+    fn random_data(len: usize) -> Vec<u8> {
+        let mut seed: u64 = 0x5EED;
+        (0..len)
+            .map(|_| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                seed.to_be_bytes()[0]
+            })
+            .collect()
+    }
+
+    /// Lengths around every multiple of the vector widths and of the 5552 byte run after
+    /// which the vectorized sums are reduced, to go through every remainder path.
+    #[test]
+    fn test_update_matches_scalar_at_every_remainder() {
+        const NMAX: usize = 5552;
+        let data = random_data(4 * NMAX + 3);
+        let high: Vec<u8> = vec![0xFF; data.len()];
+        let lengths = (0..=1024).chain((1..=4).flat_map(|k| k * NMAX - 64..=k * NMAX + 3));
+
+        for len in lengths {
+            for input in [&data[..len], &high[..len]] {
+                assert_eq!(
+                    RollingChecksum::compute(input),
+                    adler32_scalar(input),
+                    "{len}"
+                );
+
+                // Continued from sums left by a previous update.
+                let split = len / 3;
+                let mut rolling = RollingChecksum::new();
+                rolling.update(&input[..split]);
+                rolling.update(&input[split..]);
+                assert_eq!(
+                    rolling.value(),
+                    adler32_scalar(input),
+                    "{len} split at {split}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
     fn test_correctness() {
         let data: Vec<u8> = (0..1_000_000).map(|i| i as u8).collect();
         assert_eq!(RollingChecksum::compute(&data), adler32_scalar(&data));
     }
+
+    #[test]
+    fn test_roll_matches_compute() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        for window in [1, 16, 4096] {
+            let mut rolling = RollingChecksum::new();
+            rolling.update(&data[..window]);
+            for start in 1..data.len() - window {
+                rolling.roll(data[start - 1], data[start + window - 1], window);
+                assert_eq!(
+                    rolling.value(),
+                    adler32_scalar(&data[start..start + window]),
+                    "window {window} at {start}"
+                );
+            }
+        }
+    }
 }
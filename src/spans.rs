@@ -0,0 +1,244 @@
+use crate::DeltaCommand;
+use std::borrow::Borrow;
+use std::ops::Range;
+
+/// Kind of a delta command, without its payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OpKind {
+    Data,
+    Copy,
+    SelfCopy,
+}
+
+/// Where a delta command lands in the reconstructed output, and where it reads from the base.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpSpan {
+    pub op_index: usize,
+    pub kind: OpKind,
+    pub output_range: Range<u64>,
+    pub basis_range: Option<Range<u64>>,
+}
+
+impl OpSpan {
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.output_range.end - self.output_range.start
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.output_range.is_empty()
+    }
+}
+
+/// Iterator pairing every command of a delta with its span.
+///
+/// Yields an error and stops at the first command whose output would end past `u64::MAX`.
+pub(crate) struct Spans<I> {
+    inner: I,
+    op_index: usize,
+    /// Output offset of the next command, `None` once a command overflowed it.
+    output_pos: Option<u64>,
+}
+
+impl<I> Spans<I> {
+    pub(crate) fn new(inner: I) -> Self {
+        Self {
+            inner,
+            op_index: 0,
+            output_pos: Some(0),
+        }
+    }
+}
+
+impl<I> Iterator for Spans<I>
+where
+    I: Iterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    type Item = std::io::Result<(OpSpan, I::Item)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = self.output_pos?;
+        let command = self.inner.next()?;
+        let (kind, basis_range) = match command.borrow() {
+            DeltaCommand::Data(_) => (OpKind::Data, None),
+            DeltaCommand::Copy { offset, length } => (
+                OpKind::Copy,
+                Some(*offset..offset.saturating_add(*length as u64)),
+            ),
+            DeltaCommand::SelfCopy { .. } => (OpKind::SelfCopy, None),
+        };
+        self.output_pos = start.checked_add(command.borrow().output_len() as u64);
+        let Some(end) = self.output_pos else {
+            return Some(Err(output_overflow(self.op_index)));
+        };
+        let span = OpSpan {
+            op_index: self.op_index,
+            kind,
+            output_range: start..end,
+            basis_range,
+        };
+        self.op_index += 1;
+        Some(Ok((span, command)))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+/// Error for command `op_index` ending past the largest output offset, which only a corrupt
+/// delta does.
+pub(crate) fn output_overflow(op_index: usize) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("command {op_index} ends past the largest output offset"),
+    )
+}
+
+/// Error payload returned when a copy reaches past the end of the base, which means the delta
+/// is corrupt or the base is not the one it was made against.
+///
+/// It is wrapped in an [`std::io::Error`] of kind [`std::io::ErrorKind::UnexpectedEof`] and can
+/// be recovered with `error.get_ref().and_then(|e| e.downcast_ref::<CopyOutOfBounds>())`, to
+/// tell a bad delta or base from a failing reader.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CopyOutOfBounds {
+    pub op_index: usize,
+    pub basis_range: Range<u64>,
+}
+
+impl std::fmt::Display for CopyOutOfBounds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "command {} copies {:?} past the end of the base",
+            self.op_index, self.basis_range
+        )
+    }
+}
+
+impl std::error::Error for CopyOutOfBounds {}
+
+pub(crate) fn copy_out_of_bounds(op_index: usize, basis_range: Range<u64>) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::UnexpectedEof,
+        CopyOutOfBounds {
+            op_index,
+            basis_range,
+        },
+    )
+}
+
+/// Output range read by a self-referential copy, checked to start before the copy does.
+pub(crate) fn self_copy_source(
+    span: &OpSpan,
+    command: &DeltaCommand,
+) -> std::io::Result<Option<Range<u64>>> {
+    let DeltaCommand::SelfCopy {
+        output_offset,
+        length,
+    } = *command
+    else {
+        return Ok(None);
+    };
+    if length > 0 && output_offset >= span.output_range.start {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "command {} copies output at offset {output_offset}, which is not written before offset {}",
+                span.op_index, span.output_range.start
+            ),
+        ));
+    }
+    Ok(Some(output_offset..output_offset + length as u64))
+}
+
+/// Copy `source` to `start` within `out`, where `source` starts before `start` and may
+/// overlap the copy, repeating the bytes in between.
+#[allow(clippy::cast_possible_truncation)]
+pub(crate) fn copy_within_output(out: &mut [u8], source: &Range<u64>, start: u64) {
+    let distance = (start - source.start) as usize;
+    let (mut from, end, mut to) = (source.start as usize, source.end as usize, start as usize);
+    while from < end {
+        let len = (end - from).min(distance);
+        out.copy_within(from..from + len, to);
+        from += len;
+        to += len;
+    }
+}
+
+/// Walk a delta alongside the output and base ranges of each of its commands.
+///
+/// Yields an error of kind [`std::io::ErrorKind::InvalidData`] and stops at the first command
+/// whose output would end past `u64::MAX`.
+pub fn delta_spans<I>(delta: I) -> impl Iterator<Item = std::io::Result<OpSpan>>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    Spans::new(delta.into_iter()).map(|item| item.map(|(span, _)| span))
+}
+
+/// What applying a delta requires from the base and produces, computed without any IO.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ApplyPlan {
+    /// Size of the reconstructed output.
+    pub final_size: u64,
+    /// Total number of bytes read from the base, counting repeated reads of the same range.
+    pub basis_bytes_needed: u64,
+    /// Sorted, non-overlapping base ranges read by the delta.
+    pub basis_ranges: Vec<Range<u64>>,
+    /// Indices of the commands copying past the end of the base.
+    pub out_of_range_ops: Vec<usize>,
+}
+
+impl ApplyPlan {
+    /// Whether every copy is satisfied by a base of the planned length.
+    #[inline]
+    #[must_use]
+    pub fn is_satisfiable(&self) -> bool {
+        self.out_of_range_ops.is_empty()
+    }
+}
+
+/// Plan the application of `delta` to a base of `basis_len` bytes.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::InvalidData`] if the output of the delta
+/// would end past `u64::MAX`.
+pub fn plan_apply<I>(delta: I, basis_len: u64) -> std::io::Result<ApplyPlan>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut plan = ApplyPlan::default();
+    for span in delta_spans(delta) {
+        let span = span?;
+        plan.final_size = span.output_range.end;
+        let Some(basis_range) = span.basis_range else {
+            continue;
+        };
+        plan.basis_bytes_needed += basis_range.end - basis_range.start;
+        if basis_range.end > basis_len {
+            plan.out_of_range_ops.push(span.op_index);
+        }
+        if !basis_range.is_empty() {
+            plan.basis_ranges.push(basis_range);
+        }
+    }
+
+    plan.basis_ranges.sort_unstable_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(plan.basis_ranges.len());
+    for range in plan.basis_ranges.drain(..) {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    plan.basis_ranges = merged;
+    Ok(plan)
+}
@@ -0,0 +1,208 @@
+use crate::spans::copy_out_of_bounds;
+use crate::{DeltaCommand, Signatures, generate_delta_with_cb};
+use std::borrow::Borrow;
+use std::ops::Range;
+
+/// Combine deltas of independent segments into a single delta for the whole file.
+///
+/// Every part is a delta generated against the segment of the base starting at its
+/// `basis_offset`, and the parts reconstruct consecutive segments of the new file. Copy offsets
+/// are rebased onto the whole base, self-referential copies onto the whole output, and commands
+/// are merged across seams where possible.
+///
+/// # Errors
+/// Returns an error if the basis offsets are not strictly increasing, if a part copies bytes
+/// at or after the basis offset of the next part, or if an offset rebased onto the whole base
+/// or output would be past `u64::MAX`.
+pub fn splice_deltas(parts: &[(Vec<DeltaCommand>, u64)]) -> std::io::Result<Vec<DeltaCommand>> {
+    let mut result: Vec<DeltaCommand> = Vec::new();
+    let mut output_len: u64 = 0;
+
+    for (index, (delta, basis_offset)) in parts.iter().enumerate() {
+        let output_start = output_len;
+        let next_offset = parts.get(index + 1).map(|(_, offset)| *offset);
+        if next_offset.is_some_and(|next| next <= *basis_offset) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("segment {} does not start after segment {index}", index + 1),
+            ));
+        }
+        let overflow = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("segment {index} reaches past the largest offset"),
+            )
+        };
+
+        for command in delta {
+            let command = match command {
+                DeltaCommand::Copy { offset, length } => {
+                    let offset = basis_offset.checked_add(*offset).ok_or_else(overflow)?;
+                    let end = offset.checked_add(*length as u64).ok_or_else(overflow)?;
+                    if next_offset.is_some_and(|next| end > next) {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            format!("segment {index} copies bytes overlapping the next segment"),
+                        ));
+                    }
+                    DeltaCommand::Copy {
+                        offset,
+                        length: *length,
+                    }
+                }
+                DeltaCommand::SelfCopy {
+                    output_offset,
+                    length,
+                } => DeltaCommand::SelfCopy {
+                    output_offset: output_start
+                        .checked_add(*output_offset)
+                        .ok_or_else(overflow)?,
+                    length: *length,
+                },
+                DeltaCommand::Data(data) => DeltaCommand::Data(data.clone()),
+            };
+            output_len = output_len
+                .checked_add(command.output_len() as u64)
+                .ok_or_else(overflow)?;
+            push_merged(&mut result, command);
+        }
+    }
+    Ok(result)
+}
+
+/// Canonical form of `delta`: adjacent data merged, contiguous copies merged and empty
+/// commands dropped.
+///
+/// Deltas that only differ in how their commands are split have the same canonical form.
+#[must_use]
+pub fn optimize_delta<I>(delta: I) -> Vec<DeltaCommand>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut result = Vec::new();
+    for command in delta {
+        push_merged(&mut result, command.borrow().clone());
+    }
+    result
+}
+
+/// Push `command`, merging it into the last one when they are contiguous.
+pub(crate) fn push_merged(delta: &mut Vec<DeltaCommand>, command: DeltaCommand) {
+    match (delta.last_mut(), command) {
+        (_, DeltaCommand::Data(data)) if data.is_empty() => {}
+        (_, DeltaCommand::Copy { length: 0, .. } | DeltaCommand::SelfCopy { length: 0, .. }) => {}
+        (Some(DeltaCommand::Data(pending)), DeltaCommand::Data(data)) => {
+            pending.extend_from_slice(&data);
+        }
+        (
+            Some(DeltaCommand::Copy { offset, length }),
+            DeltaCommand::Copy {
+                offset: next_offset,
+                length: next_length,
+            },
+        ) if *offset + *length as u64 == next_offset => *length += next_length,
+        (
+            Some(DeltaCommand::SelfCopy {
+                output_offset,
+                length,
+            }),
+            DeltaCommand::SelfCopy {
+                output_offset: next_offset,
+                length: next_length,
+            },
+        ) if *output_offset + *length as u64 == next_offset => *length += next_length,
+        (_, command) => delta.push(command),
+    }
+}
+
+/// Turn `Data` payloads of `delta` that contain blocks of the base into copies.
+///
+/// `generate_delta` already finds matches at any offset, so this is mostly useful for deltas
+/// produced elsewhere: hand-built ones, ones that lost their copies to a cost model, or ones
+/// generated against a different signature of the same base.
+///
+/// # Errors
+/// Returns an error if matching a payload fails.
+pub fn postmatch_delta(
+    old_signatures: &Signatures,
+    delta: Vec<DeltaCommand>,
+) -> std::io::Result<Vec<DeltaCommand>> {
+    let mut result = Vec::with_capacity(delta.len());
+    for command in delta {
+        match command {
+            DeltaCommand::Data(data) if data.len() >= old_signatures.block_size() => {
+                generate_delta_with_cb(old_signatures, &data[..], |command| {
+                    push_merged(&mut result, command);
+                    Ok(())
+                })?;
+            }
+            command => push_merged(&mut result, command),
+        }
+    }
+    Ok(result)
+}
+
+/// Delta taking the output of `delta` back to `old`, the base it was applied to.
+///
+/// Every byte of `old` that `delta` copies into the output becomes a copy from that output,
+/// preferring the longest copies when several cover the same bytes; the rest of `old` is sent
+/// as data. Bytes written by [`DeltaCommand::SelfCopy`] commands are not reused.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::UnexpectedEof`] with a
+/// [`CopyOutOfBounds`](crate::CopyOutOfBounds) payload if `delta` copies past the end of `old`.
+#[allow(clippy::cast_possible_truncation)]
+pub fn invert_delta<I>(old: &[u8], delta: I) -> std::io::Result<Vec<DeltaCommand>>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    // `(basis range, output offset)` of every copy, the end of `old` bounding them all.
+    let mut sources = Vec::new();
+    let mut output_len = 0;
+    for (op_index, command) in delta.into_iter().enumerate() {
+        let command = command.borrow();
+        if let DeltaCommand::Copy { offset, length } = *command {
+            let end = offset
+                .checked_add(length as u64)
+                .filter(|&end| end <= old.len() as u64)
+                .ok_or_else(|| {
+                    copy_out_of_bounds(op_index, offset..offset.saturating_add(length as u64))
+                })?;
+            sources.push((offset as usize..end as usize, output_len));
+        }
+        output_len += command.output_len() as u64;
+    }
+    sources.sort_unstable_by_key(|(range, _)| range.start);
+
+    let mut result = Vec::new();
+    let mut pos = 0;
+    let mut next = 0;
+    while pos < old.len() {
+        let mut best: Option<&(Range<usize>, u64)> = None;
+        while let Some(source) = sources.get(next).filter(|(range, _)| range.start <= pos) {
+            if source.0.end > best.map_or(pos, |(range, _)| range.end) {
+                best = Some(source);
+            }
+            next += 1;
+        }
+        if let Some((range, output_offset)) = best {
+            push_merged(
+                &mut result,
+                DeltaCommand::Copy {
+                    offset: output_offset + (pos - range.start) as u64,
+                    length: range.end - pos,
+                },
+            );
+            pos = range.end;
+        } else {
+            let gap_end = sources
+                .get(next)
+                .map_or(old.len(), |(range, _)| range.start);
+            push_merged(&mut result, DeltaCommand::Data(old[pos..gap_end].to_vec()));
+            pos = gap_end;
+        }
+    }
+    Ok(result)
+}
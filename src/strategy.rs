@@ -0,0 +1,181 @@
+use crate::cdc::{CdcParams, for_each_chunk};
+use crate::hierarchical::{Matched, Scale, match_scales};
+use crate::rolling::RollingChecksum;
+use crate::splice::push_merged;
+use crate::{
+    DeltaCommand, SignatureStrong, SignatureWeak, WeakMap, find_block, read_exact_or_eof,
+    try_alloc_buffer, xxh3_128,
+};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// How a base is cut into chunks before hashing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkStrategy {
+    /// Blocks of the given size, the last one possibly shorter. Matches are found at any offset
+    /// with a rolling checksum, as `generate_delta` does.
+    Fixed(usize),
+    /// Content-defined chunks, cut as [`for_each_chunk`] does. The new data is cut the same
+    /// way and every chunk is looked up whole, which is cheap but only finds chunks whose
+    /// boundaries survived the edit.
+    Cdc(CdcParams),
+}
+
+/// Signatures of chunks of any length, cut by a [`ChunkStrategy`].
+///
+/// Built by [`generate_signatures_with_strategy`] and matched by
+/// [`generate_delta_with_strategy`], which cuts the new data with the same strategy.
+#[derive(Clone, Debug)]
+pub struct ChunkSignatures {
+    strategy: ChunkStrategy,
+    source_size: u64,
+    /// `(offset, length)` of every chunk.
+    chunks: Vec<(u64, usize)>,
+    by_length: BTreeMap<usize, WeakMap<Vec<SignatureStrong>>>,
+}
+
+impl ChunkSignatures {
+    #[inline]
+    #[must_use]
+    pub fn strategy(&self) -> ChunkStrategy {
+        self.strategy
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn source_size(&self) -> u64 {
+        self.source_size
+    }
+
+    /// `(offset, length)` of every chunk of the source, in order.
+    #[inline]
+    #[must_use]
+    pub fn chunks(&self) -> &[(u64, usize)] {
+        &self.chunks
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        let block_index = self.chunks.len();
+        self.chunks.push((self.source_size, chunk.len()));
+        self.by_length
+            .entry(chunk.len())
+            .or_default()
+            .entry(RollingChecksum::compute(chunk))
+            .or_default()
+            .push(SignatureStrong {
+                strong: xxh3_128(chunk),
+                block_index,
+                crc32: None,
+            });
+        self.source_size += chunk.len() as u64;
+    }
+
+    /// Base offset of the chunk with the weak hash and content of `chunk`, if any.
+    fn find(&self, weak: SignatureWeak, chunk: &[u8]) -> Option<u64> {
+        let entries = self.by_length.get(&chunk.len())?.get(&weak)?;
+        let block_index = find_block(entries, chunk)?;
+        Some(self.chunks[block_index].0)
+    }
+}
+
+/// Generate signatures of `reader`, cut into chunks by `strategy`.
+///
+/// # Errors
+/// Returns an error if the fixed block size is zero, if the CDC parameters are invalid, if
+/// reading from the reader fails or if the block buffer cannot be allocated.
+pub fn generate_signatures_with_strategy<R: Read>(
+    mut reader: R,
+    strategy: ChunkStrategy,
+) -> std::io::Result<ChunkSignatures> {
+    let mut signatures = ChunkSignatures {
+        strategy,
+        source_size: 0,
+        chunks: Vec::new(),
+        by_length: BTreeMap::new(),
+    };
+    match strategy {
+        ChunkStrategy::Fixed(0) => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "block size must be greater than zero",
+            ));
+        }
+        ChunkStrategy::Fixed(block_size) => {
+            let mut buffer = try_alloc_buffer(block_size)?;
+            loop {
+                let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+                if bytes_read == 0 {
+                    break;
+                }
+                signatures.push(&buffer[..bytes_read]);
+            }
+        }
+        ChunkStrategy::Cdc(params) => for_each_chunk(reader, params, |chunk| {
+            signatures.push(chunk);
+            Ok(())
+        })?,
+    }
+    Ok(signatures)
+}
+
+/// Generate a delta of the new data read from `reader` against `signatures`, cutting it with
+/// the strategy of the signatures.
+///
+/// Fixed-size blocks are searched at every position, which needs the whole new data in
+/// memory. CDC chunks are matched as they are cut, without buffering more than one chunk. The
+/// result applies with `apply_delta` like any other delta.
+///
+/// # Errors
+/// Returns an error if reading from the reader fails.
+pub fn generate_delta_with_strategy<R: Read>(
+    signatures: &ChunkSignatures,
+    mut reader: R,
+) -> std::io::Result<Vec<DeltaCommand>> {
+    match signatures.strategy {
+        ChunkStrategy::Fixed(_) => {
+            let mut new_data = Vec::new();
+            reader.read_to_end(&mut new_data)?;
+            // A shorter last block is a second length to search for.
+            let scales = signatures
+                .by_length
+                .keys()
+                .map(|&length| Scale::new(length, move |weak, block| signatures.find(weak, block)))
+                .collect();
+            let Matched {
+                mut delta,
+                mut pending_data,
+                pos,
+            } = match_scales(scales, &new_data);
+            pending_data.extend_from_slice(&new_data[pos..]);
+            push_merged(&mut delta, DeltaCommand::Data(pending_data));
+            Ok(delta)
+        }
+        ChunkStrategy::Cdc(params) => {
+            let mut delta = Vec::new();
+            for_each_chunk(reader, params, |chunk| {
+                let command = match signatures.find(RollingChecksum::compute(chunk), chunk) {
+                    Some(offset) => DeltaCommand::Copy {
+                        offset,
+                        length: chunk.len(),
+                    },
+                    None => DeltaCommand::Data(chunk.to_vec()),
+                };
+                push_merged(&mut delta, command);
+                Ok(())
+            })?;
+            Ok(delta)
+        }
+    }
+}
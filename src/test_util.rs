@@ -0,0 +1,222 @@
+//! Helpers for downstream crates testing their own readers and writers against the algorithm.
+
+use crate::{
+    DeltaCommand, HierarchicalEngine, RollingEngine, SyncEngine, TextEngine, apply_delta,
+    generate_delta, generate_signatures,
+};
+use std::io::{Cursor, Read};
+
+/// Reader that keeps a copy of everything read through it.
+struct TeeReader<R> {
+    inner: R,
+    copy: Vec<u8>,
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Run signature, delta and apply over `old` and `new` and check `new` is reconstructed.
+///
+/// Both readers are consumed once, directly by `generate_signatures` and
+/// `generate_delta`. Returns `false` if any step fails or the reconstruction differs.
+pub fn verify_roundtrip<Ro: Read, Rn: Read>(old: Ro, new: Rn) -> bool {
+    let mut old = TeeReader {
+        inner: old,
+        copy: Vec::new(),
+    };
+    let mut new = TeeReader {
+        inner: new,
+        copy: Vec::new(),
+    };
+
+    let Ok(signatures) = generate_signatures(&mut old) else {
+        return false;
+    };
+    let Ok(delta) = generate_delta(&signatures, &mut new) else {
+        return false;
+    };
+
+    let mut reconstructed = Vec::new();
+    if apply_delta(Cursor::new(&old.copy), &delta, &mut reconstructed).is_err() {
+        return false;
+    }
+    reconstructed == new.copy
+}
+
+/// Data with no repeated block, from a fixed seed.
+fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            (state >> 56) as u8
+        })
+        .collect()
+}
+
+/// Kind of change between the base and the new file of a [`Fixture`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditProfile {
+    /// Eight bytes overwritten every 64 KiB.
+    ScatteredEdits,
+    /// 4 KiB of new bytes inserted in the middle.
+    Insertion,
+    /// 4 KiB removed at a third of the file.
+    Deletion,
+    /// A 64 KiB region moved from a quarter of the file to three quarters.
+    Reorder,
+}
+
+impl EditProfile {
+    pub const ALL: [Self; 4] = [
+        Self::ScatteredEdits,
+        Self::Insertion,
+        Self::Deletion,
+        Self::Reorder,
+    ];
+}
+
+/// A base and a new file, with the size of the change between them.
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    pub old: Vec<u8>,
+    pub new: Vec<u8>,
+    /// Number of places where the new file stops following the base.
+    pub edits: u64,
+    /// Bytes of the new file that do not come from the base.
+    pub new_bytes: u64,
+}
+
+/// Build the fixture of `profile` over a pseudo-random base of `size` bytes.
+///
+/// The data is the same on every call, so sizes measured on it are comparable across changes.
+///
+/// # Panics
+/// Panics if `size` is smaller than 256 KiB.
+#[must_use]
+pub fn fixture(profile: EditProfile, size: usize) -> Fixture {
+    assert!(size >= 256 * 1024, "fixtures need at least 256 KiB");
+    let old = pseudo_random(size, 0x5EED);
+    let mut new = old.clone();
+    let (edits, new_bytes) = match profile {
+        EditProfile::ScatteredEdits => {
+            let mut edits = 0;
+            for (i, pos) in (1000..size - 8).step_by(64 * 1024).enumerate() {
+                new[pos..pos + 8].copy_from_slice(&pseudo_random(8, i as u64));
+                edits += 1;
+            }
+            (edits, edits * 8)
+        }
+        EditProfile::Insertion => {
+            new.splice(size / 2..size / 2, pseudo_random(4096, 1));
+            (1, 4096)
+        }
+        EditProfile::Deletion => {
+            new.drain(size / 3..size / 3 + 4096);
+            (1, 0)
+        }
+        EditProfile::Reorder => {
+            let region: Vec<u8> = new.drain(size / 4..size / 4 + 64 * 1024).collect();
+            let to = size * 3 / 4 - region.len();
+            new.splice(to..to, region);
+            (3, 0)
+        }
+    };
+    Fixture {
+        old,
+        new,
+        edits,
+        new_bytes,
+    }
+}
+
+/// Bound on the encoded size of a delta, from the size of the change it encodes.
+///
+/// Each delta generation engine has one next to it, so that trading delta size for speed shows
+/// up in the diff.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeltaSizeEnvelope {
+    /// Encoded bytes allowed per byte of the new file not found in the base.
+    pub per_new_byte: u64,
+    /// Whole blocks sent as data per edit, for the matches an edit breaks.
+    pub blocks_per_edit: u64,
+    /// Frame overhead per edit.
+    pub bytes_per_edit: u64,
+    /// Header and final frames.
+    pub fixed: u64,
+}
+
+impl DeltaSizeEnvelope {
+    /// Largest encoded size allowed for a delta of `fixture` at `block_size`.
+    #[must_use]
+    pub fn bound(&self, fixture: &Fixture, block_size: usize) -> u64 {
+        self.per_new_byte * fixture.new_bytes
+            + fixture.edits * (self.blocks_per_edit * block_size as u64 + self.bytes_per_edit)
+            + self.fixed
+    }
+}
+
+fn run<E: SyncEngine>(engine: &E, old: &[u8], new: &[u8]) -> std::io::Result<Vec<DeltaCommand>> {
+    engine.delta(&engine.signature(old)?, new)
+}
+
+/// Delta generation engines whose output size is checked against an envelope.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// `generate_delta`.
+    Rolling,
+    /// `generate_delta_hierarchical`, with scales of 16 and 1 times the block size.
+    Hierarchical,
+    /// `generate_text_delta`, with the block size as target.
+    Text,
+}
+
+impl Engine {
+    pub const ALL: [Self; 3] = [Self::Rolling, Self::Hierarchical, Self::Text];
+
+    /// The size envelope defined next to the engine.
+    #[must_use]
+    pub fn delta_size_envelope(self) -> DeltaSizeEnvelope {
+        match self {
+            Self::Rolling => crate::DELTA_SIZE_ENVELOPE,
+            Self::Hierarchical => crate::hierarchical::DELTA_SIZE_ENVELOPE,
+            Self::Text => crate::text::DELTA_SIZE_ENVELOPE,
+        }
+    }
+
+    /// Generate the delta from `old` to `new` with this engine.
+    ///
+    /// # Errors
+    /// Returns an error if signature or delta generation fails, e.g. for a zero block size.
+    pub fn generate_delta(
+        self,
+        old: &[u8],
+        new: &[u8],
+        block_size: usize,
+    ) -> std::io::Result<Vec<DeltaCommand>> {
+        match self {
+            Self::Rolling => run(&RollingEngine { block_size }, old, new),
+            Self::Hierarchical => run(
+                &HierarchicalEngine {
+                    block_sizes: vec![block_size * 16, block_size],
+                },
+                old,
+                new,
+            ),
+            Self::Text => run(
+                &TextEngine {
+                    target_block_size: block_size,
+                },
+                old,
+                new,
+            ),
+        }
+    }
+}
@@ -0,0 +1,168 @@
+use crate::hierarchical::{Matched, Scale, match_scales};
+use crate::rolling::RollingChecksum;
+use crate::splice::push_merged;
+use crate::{DeltaCommand, SignatureStrong, WeakMap, find_block, xxh3_128};
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// Longest UTF-8 sequence minus its first byte: how far a boundary may move.
+const MAX_CONTINUATION_BYTES: usize = 3;
+
+#[inline]
+fn is_continuation(byte: u8) -> bool {
+    byte & 0b1100_0000 == 0b1000_0000
+}
+
+/// Signatures of variable-length blocks cut on UTF-8 character boundaries.
+///
+/// Built by [`generate_text_signatures`] and matched by [`generate_text_delta`].
+#[derive(Clone, Debug)]
+pub struct TextSignatures {
+    target_block_size: usize,
+    source_size: u64,
+    /// `(offset, length)` of every block.
+    blocks: Vec<(u64, usize)>,
+    by_length: BTreeMap<usize, WeakMap<Vec<SignatureStrong>>>,
+}
+
+impl TextSignatures {
+    #[inline]
+    #[must_use]
+    pub fn target_block_size(&self) -> usize {
+        self.target_block_size
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn source_size(&self) -> u64 {
+        self.source_size
+    }
+
+    /// `(offset, length)` of every block of the source, in order.
+    #[inline]
+    #[must_use]
+    pub fn blocks(&self) -> &[(u64, usize)] {
+        &self.blocks
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    #[inline]
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+}
+
+/// Generate signatures of text, cutting blocks so that no UTF-8 character is split.
+///
+/// Every block is `target_block_size` bytes long, moved back by up to three bytes when its end
+/// falls inside a multi-byte character, or forward past the character when the target is too
+/// small to move back. Where no character boundary is that close (the input is not valid UTF-8
+/// there), the block keeps its target size. The last block may be shorter.
+///
+/// # Errors
+/// Returns an error if `target_block_size` is zero or if reading from the reader fails.
+pub fn generate_text_signatures<R: Read>(
+    reader: R,
+    target_block_size: usize,
+) -> std::io::Result<TextSignatures> {
+    if target_block_size == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "block size must be greater than zero",
+        ));
+    }
+
+    let mut signatures = TextSignatures {
+        target_block_size,
+        source_size: 0,
+        blocks: Vec::new(),
+        by_length: BTreeMap::new(),
+    };
+    // Read ahead far enough to find the end of a character split by the target cut.
+    let lookahead = target_block_size + MAX_CONTINUATION_BYTES + 1;
+    let mut reader = reader.take(0);
+    let mut buffer = Vec::with_capacity(lookahead);
+    loop {
+        let want = lookahead.saturating_sub(buffer.len());
+        reader.set_limit(want as u64);
+        reader.read_to_end(&mut buffer)?;
+        if buffer.is_empty() {
+            break;
+        }
+
+        let mut cut = buffer.len().min(target_block_size);
+        if cut < buffer.len() && is_continuation(buffer[cut]) {
+            let back = (1..=MAX_CONTINUATION_BYTES)
+                .filter_map(|n| cut.checked_sub(n))
+                .take_while(|&c| c > 0)
+                .find(|&c| !is_continuation(buffer[c]));
+            // Reading stops short of the lookahead only at the end of the input.
+            let forward = (cut + 1..=(cut + MAX_CONTINUATION_BYTES).min(buffer.len()))
+                .find(|&c| c == buffer.len() || !is_continuation(buffer[c]));
+            cut = back.or(forward).unwrap_or(cut);
+        }
+
+        let block = &buffer[..cut];
+        let block_index = signatures.blocks.len();
+        signatures.blocks.push((signatures.source_size, cut));
+        signatures
+            .by_length
+            .entry(cut)
+            .or_default()
+            .entry(RollingChecksum::compute(block))
+            .or_default()
+            .push(SignatureStrong {
+                strong: xxh3_128(block),
+                block_index,
+                crc32: None,
+            });
+        signatures.source_size += cut as u64;
+        buffer.drain(..cut);
+    }
+    Ok(signatures)
+}
+
+/// Encoded size allowed for a `generate_text_delta` delta by the quality tests. Blocks may be
+/// a few bytes longer than the target, and an edit can shift the cut of the block after it.
+#[cfg(feature = "test-util")]
+pub(crate) const DELTA_SIZE_ENVELOPE: crate::test_util::DeltaSizeEnvelope =
+    crate::test_util::DeltaSizeEnvelope {
+        per_new_byte: 3,
+        blocks_per_edit: 3,
+        bytes_per_edit: 64,
+        fixed: 64,
+    };
+
+/// Generate a delta of `new_data` against text signatures.
+///
+/// Blocks of every length present in `signatures` are searched at every position, so matches
+/// are found at any offset despite the variable block lengths. The result applies with
+/// `apply_delta` like any other delta.
+#[must_use]
+pub fn generate_text_delta(signatures: &TextSignatures, new_data: &[u8]) -> Vec<DeltaCommand> {
+    let scales = signatures
+        .by_length
+        .iter()
+        .map(|(&length, weak_to_strong)| {
+            Scale::new(length, move |weak, block| {
+                let block_index = find_block(weak_to_strong.get(&weak)?, block)?;
+                Some(signatures.blocks[block_index].0)
+            })
+        })
+        .collect();
+    let Matched {
+        mut delta,
+        mut pending_data,
+        pos,
+    } = match_scales(scales, new_data);
+
+    pending_data.extend_from_slice(&new_data[pos..]);
+    push_merged(&mut delta, DeltaCommand::Data(pending_data));
+    delta
+}
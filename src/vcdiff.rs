@@ -0,0 +1,176 @@
+//! Export of deltas as VCDIFF (RFC 3284) streams, as read by `xdelta3` and `open-vcdiff`.
+//!
+//! Only the subset needed to express a delta is emitted:
+//!
+//! - the default instruction code table, using only `ADD` (index 1) and `COPY` with mode
+//!   `VCD_SELF` (index 19), each with its size in the instruction section;
+//! - windows of at most [`VCDIFF_WINDOW_SIZE`] target bytes, copying from a source segment of
+//!   the base file (`VCD_SOURCE`);
+//! - no `RUN` instructions, no copies from the target window, no secondary compressor, no
+//!   custom code table, no application header and no Adler-32 window checksum.
+//!
+//! Self-referential copies would need copies from the target, across windows, and are
+//! rejected.
+
+use crate::DeltaCommand;
+use std::borrow::Borrow;
+use std::io::{BufWriter, Write};
+
+/// Largest number of target bytes written in a single VCDIFF window.
+pub const VCDIFF_WINDOW_SIZE: usize = 1 << 22;
+
+const VCDIFF_MAGIC: [u8; 4] = [0xD6, 0xC3, 0xC4, 0x00];
+const VCD_SOURCE: u8 = 0x01;
+const INST_ADD: u8 = 1;
+const INST_COPY_SELF: u8 = 19;
+
+enum Op {
+    Add(usize),
+    Copy { offset: u64, length: usize },
+}
+
+#[derive(Default)]
+struct Window {
+    ops: Vec<Op>,
+    data: Vec<u8>,
+    target_len: usize,
+}
+
+/// Big-endian base-128 integer, as defined in section 2 of the RFC.
+fn push_varint(out: &mut Vec<u8>, value: u64) {
+    let mut buf = [0u8; 10];
+    let mut pos = buf.len();
+    let mut value = value;
+    loop {
+        pos -= 1;
+        #[allow(clippy::cast_possible_truncation)]
+        let byte = (value & 0x7F) as u8;
+        buf[pos] = if pos == buf.len() - 1 {
+            byte
+        } else {
+            byte | 0x80
+        };
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&buf[pos..]);
+}
+
+impl Window {
+    fn push(&mut self, op: Op, len: usize) {
+        self.ops.push(op);
+        self.target_len += len;
+    }
+
+    fn write<W: Write>(&mut self, writer: &mut W) -> std::io::Result<()> {
+        if self.target_len == 0 {
+            return Ok(());
+        }
+
+        let segment = self
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Copy { offset, length } => Some((*offset, *offset + *length as u64)),
+                Op::Add(_) => None,
+            })
+            .reduce(|(start, end), (s, e)| (start.min(s), end.max(e)));
+
+        let mut instructions = Vec::new();
+        let mut addresses = Vec::new();
+        for op in &self.ops {
+            match op {
+                Op::Add(length) => {
+                    instructions.push(INST_ADD);
+                    push_varint(&mut instructions, *length as u64);
+                }
+                Op::Copy { offset, length } => {
+                    let (segment_start, _) = segment.unwrap_or_default();
+                    instructions.push(INST_COPY_SELF);
+                    push_varint(&mut instructions, *length as u64);
+                    push_varint(&mut addresses, offset - segment_start);
+                }
+            }
+        }
+
+        let mut encoding = Vec::new();
+        push_varint(&mut encoding, self.target_len as u64);
+        encoding.push(0); // Delta_Indicator: no secondary compression.
+        push_varint(&mut encoding, self.data.len() as u64);
+        push_varint(&mut encoding, instructions.len() as u64);
+        push_varint(&mut encoding, addresses.len() as u64);
+
+        let mut header = Vec::new();
+        if let Some((start, end)) = segment {
+            header.push(VCD_SOURCE);
+            push_varint(&mut header, end - start);
+            push_varint(&mut header, start);
+        } else {
+            header.push(0);
+        }
+        let encoding_len = encoding.len() + self.data.len() + instructions.len() + addresses.len();
+        push_varint(&mut header, encoding_len as u64);
+
+        writer.write_all(&header)?;
+        writer.write_all(&encoding)?;
+        writer.write_all(&self.data)?;
+        writer.write_all(&instructions)?;
+        writer.write_all(&addresses)?;
+
+        *self = Self::default();
+        Ok(())
+    }
+}
+
+/// Encode a delta as a VCDIFF stream that reconstructs the new file from the base file.
+///
+/// See the [module documentation](self) for the subset of the format that is produced.
+///
+/// # Errors
+/// Returns an error if the delta contains a self-referential copy or if writing to the writer
+/// fails.
+pub fn write_vcdiff<W: Write, I>(delta: I, writer: W) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&VCDIFF_MAGIC)?;
+    writer.write_all(&[0])?; // Hdr_Indicator: no secondary compressor, no code table.
+
+    let mut window = Window::default();
+    for command in delta {
+        let mut done = 0;
+        let total = command.borrow().output_len();
+        while done < total {
+            let len = (total - done).min(VCDIFF_WINDOW_SIZE - window.target_len);
+            match command.borrow() {
+                DeltaCommand::Data(data) => {
+                    window.data.extend_from_slice(&data[done..done + len]);
+                    window.push(Op::Add(len), len);
+                }
+                DeltaCommand::Copy { offset, .. } => window.push(
+                    Op::Copy {
+                        offset: offset + done as u64,
+                        length: len,
+                    },
+                    len,
+                ),
+                DeltaCommand::SelfCopy { .. } => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Unsupported,
+                        "VCDIFF export does not support self-referential copies",
+                    ));
+                }
+            }
+            done += len;
+            if window.target_len == VCDIFF_WINDOW_SIZE {
+                window.write(&mut writer)?;
+            }
+        }
+    }
+    window.write(&mut writer)?;
+    writer.flush()
+}
@@ -0,0 +1,302 @@
+use crate::{
+    DeltaCommand, FinalChunkMode, Signatures, apply_delta, generate_delta_with_cb,
+    read_exact_or_eof, try_alloc_buffer, xxh3_128,
+};
+use std::borrow::Borrow;
+use std::io::{Read, Seek, SeekFrom, Write};
+use twox_hash::XxHash3_128;
+
+/// Length and xxh3-128 of the new data, recorded by [`generate_delta_with_digest`] and checked
+/// by [`apply_verified`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OutputDigest {
+    pub len: u64,
+    pub hash: u128,
+}
+
+impl std::fmt::Display for OutputDigest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} bytes with hash {:032x}", self.len, self.hash)
+    }
+}
+
+/// Error payload returned when the output of [`apply_verified`] does not match its digest.
+///
+/// It is wrapped in an [`std::io::Error`] of kind [`std::io::ErrorKind::InvalidData`] and can
+/// be recovered with `error.get_ref().and_then(|e| e.downcast_ref::<OutputMismatch>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputMismatch {
+    pub expected: OutputDigest,
+    pub actual: OutputDigest,
+}
+
+impl std::fmt::Display for OutputMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "reconstructed output is {}, expected {}; the base may not be the one the delta was made against",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for OutputMismatch {}
+
+/// Reader or writer hashing every byte that goes through it.
+struct Digesting<T> {
+    inner: T,
+    hasher: XxHash3_128,
+    len: u64,
+}
+
+impl<T> Digesting<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            hasher: XxHash3_128::new(),
+            len: 0,
+        }
+    }
+
+    fn digest(&self) -> OutputDigest {
+        OutputDigest {
+            len: self.len,
+            hash: self.hasher.finish_128(),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.hasher.write(bytes);
+        self.len += bytes.len() as u64;
+    }
+}
+
+impl<R: Read> Read for Digesting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Digesting<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Same as `generate_delta`, also returning the digest of the new data, hashed as it is read.
+///
+/// # Errors
+/// Returns an error if reading from the reader fails.
+pub fn generate_delta_with_digest<R: Read>(
+    old_signatures: &Signatures,
+    reader: R,
+) -> std::io::Result<(Vec<DeltaCommand>, OutputDigest)> {
+    let mut reader = Digesting::new(reader);
+    let mut delta = Vec::new();
+    generate_delta_with_cb(old_signatures, &mut reader, |command| {
+        delta.push(command);
+        Ok(())
+    })?;
+    Ok((delta, reader.digest()))
+}
+
+/// Same as `apply_delta`, checking that the output matches `expected`.
+///
+/// The output is hashed as it is written, so memory use does not depend on its size. A
+/// mismatch means the base is not the one the delta was made against, or that the delta or the
+/// base was corrupted. The output has been written by then and must be discarded.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::InvalidData`] with an [`OutputMismatch`]
+/// payload if the output length or hash does not match `expected`, or any error of
+/// `apply_delta`.
+pub fn apply_verified<R: Read + Seek, W: Write, I>(
+    base_reader: R,
+    delta: I,
+    expected: OutputDigest,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let mut writer = Digesting::new(target_writer);
+    apply_delta(base_reader, delta, &mut writer)?;
+    let actual = writer.digest();
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            OutputMismatch { expected, actual },
+        ))
+    }
+}
+
+/// Fingerprint of a base: its size and the xxh3-128 of its strong block hashes in order.
+///
+/// Taken from the signatures with [`Signatures::fingerprint`] and sent along with the delta,
+/// then re-derived from the base by [`apply_delta_checked`] before applying, so a delta is
+/// never applied to a different file than the one it was made against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BasisFingerprint {
+    pub block_size: usize,
+    pub final_chunk_mode: FinalChunkMode,
+    pub source_size: u64,
+    pub hash: u128,
+}
+
+impl std::fmt::Display for BasisFingerprint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes with fingerprint {:032x}",
+            self.source_size, self.hash
+        )
+    }
+}
+
+/// Error payload returned when the base given to [`apply_delta_checked`] does not match the
+/// fingerprint.
+///
+/// It is wrapped in an [`std::io::Error`] of kind [`std::io::ErrorKind::InvalidData`] and can
+/// be recovered with `error.get_ref().and_then(|e| e.downcast_ref::<BasisMismatch>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BasisMismatch {
+    pub expected: BasisFingerprint,
+    pub actual: BasisFingerprint,
+}
+
+impl std::fmt::Display for BasisMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "base is {}, but the delta was made against {}",
+            self.actual, self.expected
+        )
+    }
+}
+
+impl std::error::Error for BasisMismatch {}
+
+impl Signatures {
+    /// Fingerprint of the source the signatures were generated from.
+    ///
+    /// Returns `None` if some blocks were left out, e.g. by
+    /// [`generate_signatures_excluding_tail`](crate::generate_signatures_excluding_tail), as the
+    /// fingerprint covers every block.
+    #[must_use]
+    pub fn fingerprint(&self) -> Option<BasisFingerprint> {
+        let mut blocks: Vec<_> = self
+            .weak_to_strong
+            .values()
+            .flatten()
+            .map(|entry| (entry.block_index, entry.strong))
+            .collect();
+        blocks.sort_unstable_by_key(|&(block_index, _)| block_index);
+        let expected_blocks = if self.block_size == 0 {
+            0
+        } else {
+            self.source_size.div_ceil(self.block_size as u64)
+        };
+        if blocks.len() as u64 != expected_blocks
+            || blocks
+                .iter()
+                .enumerate()
+                .any(|(position, &(block_index, _))| position != block_index)
+        {
+            return None;
+        }
+
+        let mut hasher = XxHash3_128::new();
+        for (_, strong) in blocks {
+            hasher.write(&strong.to_le_bytes());
+        }
+        Some(BasisFingerprint {
+            block_size: self.block_size,
+            final_chunk_mode: self.final_chunk_mode,
+            source_size: self.source_size,
+            hash: hasher.finish_128(),
+        })
+    }
+}
+
+/// Fingerprint of the base read from `reader`, hashed the way `expected` was.
+fn basis_fingerprint<R: Read>(
+    mut reader: R,
+    expected: &BasisFingerprint,
+) -> std::io::Result<BasisFingerprint> {
+    let block_size = expected.block_size;
+    let mut fingerprint = BasisFingerprint {
+        source_size: 0,
+        hash: 0,
+        ..*expected
+    };
+    let mut hasher = XxHash3_128::new();
+    if block_size > 0 {
+        let mut buffer = try_alloc_buffer(block_size)?;
+        loop {
+            let bytes_read = read_exact_or_eof(&mut reader, &mut buffer)?;
+            if bytes_read == 0 {
+                break;
+            }
+            fingerprint.source_size += bytes_read as u64;
+            let block = if expected.final_chunk_mode.pads(bytes_read, block_size) {
+                buffer[bytes_read..].fill(0);
+                &buffer[..]
+            } else {
+                &buffer[..bytes_read]
+            };
+            hasher.write(&xxh3_128(block).to_le_bytes());
+        }
+    } else if reader.read(&mut [0u8])? > 0 {
+        fingerprint.source_size = 1;
+    }
+    fingerprint.hash = hasher.finish_128();
+    Ok(fingerprint)
+}
+
+/// Same as `apply_delta`, refusing to apply the delta to a base that does not match
+/// `expected`.
+///
+/// The whole base is read and hashed once before anything is written, which doubles the
+/// reads of the base. `apply_delta` skips the check.
+///
+/// # Errors
+/// Returns an error of kind [`std::io::ErrorKind::InvalidData`] with a [`BasisMismatch`]
+/// payload if the base does not match `expected`, in which case nothing is written, or any
+/// error of `apply_delta`.
+pub fn apply_delta_checked<R: Read + Seek, W: Write, I>(
+    mut base_reader: R,
+    delta: I,
+    expected: &BasisFingerprint,
+    target_writer: W,
+) -> std::io::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    base_reader.seek(SeekFrom::Start(0))?;
+    let actual = basis_fingerprint(&mut base_reader, expected)?;
+    if actual != *expected {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            BasisMismatch {
+                expected: *expected,
+                actual,
+            },
+        ));
+    }
+    base_reader.seek(SeekFrom::Start(0))?;
+    apply_delta(base_reader, delta, target_writer)
+}
@@ -0,0 +1,210 @@
+use crate::apply::apply_commands;
+use crate::output::{OpWriter, TrackedWriter};
+use crate::{DeltaCommand, SeekReadAdapter};
+use std::borrow::Borrow;
+use std::io::{Read, Seek, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+const SLICE_SIZE: usize = 64 * 1024;
+
+/// Error payload returned when the output of a command takes longer than allowed to be written.
+///
+/// It is wrapped in an [`std::io::Error`] of kind [`std::io::ErrorKind::TimedOut`] and can be
+/// recovered with `error.get_ref().and_then(|e| e.downcast_ref::<ApplyStalled>())`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApplyStalled {
+    pub op_index: usize,
+    pub elapsed: Duration,
+}
+
+impl std::fmt::Display for ApplyStalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "writing delta command {} stalled for {:?}",
+            self.op_index, self.elapsed
+        )
+    }
+}
+
+impl std::error::Error for ApplyStalled {}
+
+enum WriterMsg {
+    /// Output of consecutive commands, with the index of each command and the end of its
+    /// bytes in the buffer.
+    Write(Vec<u8>, Vec<(usize, usize)>),
+    Flush(usize),
+}
+
+struct Watchdog<W> {
+    sender: mpsc::SyncSender<WriterMsg>,
+    acks: mpsc::Receiver<std::io::Result<()>>,
+    handle: std::thread::JoinHandle<W>,
+    max_op_duration: Duration,
+    /// Index of the command the writer thread is writing.
+    writing: Arc<AtomicUsize>,
+}
+
+impl<W: Write + Send + 'static> Watchdog<W> {
+    fn spawn(writer: W, max_op_duration: Duration) -> Self {
+        let (sender, messages) = mpsc::sync_channel::<WriterMsg>(0);
+        let (ack_sender, acks) = mpsc::channel();
+        let writing = Arc::new(AtomicUsize::new(0));
+        let thread_writing = Arc::clone(&writing);
+        let handle = std::thread::spawn(move || {
+            let mut writer = TrackedWriter::new(writer, 0);
+            for msg in messages {
+                let result = match msg {
+                    WriterMsg::Write(buf, ops) => {
+                        let mut start = 0;
+                        ops.into_iter().try_for_each(|(op_index, end)| {
+                            thread_writing.store(op_index, Ordering::Relaxed);
+                            writer.op_index = op_index;
+                            writer.write_all(&buf[std::mem::replace(&mut start, end)..end])
+                        })
+                    }
+                    WriterMsg::Flush(op_index) => {
+                        thread_writing.store(op_index, Ordering::Relaxed);
+                        writer.op_index = op_index;
+                        writer.flush()
+                    }
+                };
+                if ack_sender.send(result).is_err() {
+                    break;
+                }
+            }
+            writer.into_inner()
+        });
+        Self {
+            sender,
+            acks,
+            handle,
+            max_op_duration,
+            writing,
+        }
+    }
+
+    /// Hand `msg` to the writer thread and wait for it, for at most `max_op_duration`.
+    fn send(&self, msg: WriterMsg) -> std::io::Result<()> {
+        let op_start = Instant::now();
+        let stalled = || {
+            std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                ApplyStalled {
+                    op_index: self.writing.load(Ordering::Relaxed),
+                    elapsed: op_start.elapsed(),
+                },
+            )
+        };
+        let gone = || std::io::Error::other("writer thread exited unexpectedly");
+
+        self.sender.send(msg).map_err(|_| gone())?;
+        let remaining = self.max_op_duration.saturating_sub(op_start.elapsed());
+        match self.acks.recv_timeout(remaining) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(stalled()),
+            Err(RecvTimeoutError::Disconnected) => Err(gone()),
+        }
+    }
+}
+
+/// Writer handing its bytes to the watchdog thread in slices, each timed on its own.
+struct WatchedWriter<W> {
+    watchdog: Watchdog<W>,
+    op_index: usize,
+    /// Number of commands started.
+    op_count: usize,
+    /// Bytes not handed to the watchdog thread yet.
+    buffer: Vec<u8>,
+    /// Commands with bytes in `buffer`, and the end of their bytes.
+    buffered_ops: Vec<(usize, usize)>,
+}
+
+impl<W: Write + Send + 'static> WatchedWriter<W> {
+    /// Hand the buffered bytes to the watchdog thread.
+    fn send_buffer(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let buf = std::mem::replace(&mut self.buffer, Vec::with_capacity(SLICE_SIZE));
+        let ops = std::mem::take(&mut self.buffered_ops);
+        self.watchdog.send(WriterMsg::Write(buf, ops))
+    }
+}
+
+impl<W: Write + Send + 'static> Write for WatchedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(SLICE_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..len]);
+        match self.buffered_ops.last_mut() {
+            Some((op_index, end)) if *op_index == self.op_index => *end = self.buffer.len(),
+            _ => self.buffered_ops.push((self.op_index, self.buffer.len())),
+        }
+        if self.buffer.len() == SLICE_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.send_buffer()?;
+        self.watchdog.send(WriterMsg::Flush(self.op_index))
+    }
+}
+
+impl<W: Write + Send + 'static> OpWriter for WatchedWriter<W> {
+    fn start_op(&mut self, op_index: usize) {
+        self.op_index = op_index;
+        self.op_count = op_index + 1;
+    }
+}
+
+/// Same as `apply_delta`, but fails if the writer takes longer than `max_op_duration` to
+/// write a slice of the output.
+///
+/// Output is buffered and written on a dedicated thread in slices of 64 KiB, so a writer that
+/// hangs (e.g. on a network filesystem) is detected while the apply is waiting for it. Each
+/// slice, and the final flush, is timed from when it is handed to that thread: reads from the
+/// base are not watched, and a long command that keeps making progress never stalls. On
+/// success the writer is flushed and returned.
+///
+/// When a stall is detected, the error carries an [`ApplyStalled`] with the index of the
+/// command being written. The output then holds every command before that index, plus a
+/// prefix of the output from there on. The writer thread cannot be interrupted: it is detached and
+/// exits, dropping the writer, as soon as the blocked write returns.
+///
+/// # Errors
+/// Returns an error if a command stalls, or if reading the base or writing the output fails.
+pub fn apply_delta_with_watchdog<R, W, I>(
+    base_reader: R,
+    delta: I,
+    target_writer: W,
+    max_op_duration: Duration,
+) -> std::io::Result<W>
+where
+    R: Read + Seek,
+    W: Write + Send + 'static,
+    I: IntoIterator,
+    I::Item: Borrow<DeltaCommand>,
+{
+    let base = SeekReadAdapter::new(base_reader);
+    let mut writer = WatchedWriter {
+        watchdog: Watchdog::spawn(target_writer, max_op_duration),
+        op_index: 0,
+        op_count: 0,
+        buffer: Vec::with_capacity(SLICE_SIZE),
+        buffered_ops: Vec::new(),
+    };
+    apply_commands(&base, delta, &mut writer, 0, &mut ())?;
+
+    writer.start_op(writer.op_count);
+    writer.flush()?;
+    let Watchdog { sender, handle, .. } = writer.watchdog;
+    drop(sender);
+    handle
+        .join()
+        .map_err(|_| std::io::Error::other("writer thread panicked"))
+}
@@ -0,0 +1,1598 @@
+use libsync3::archive::{self, ArchiveOptions};
+use libsync3::encoding::{DELTA_HEADER_LEN, MAX_CHECKED_DATA_LEN, OP_DATA};
+use libsync3::fs::{RecoveryPolicy, TempGuard, apply_delta_to_path, recover_temp_files};
+use libsync3::{
+    ApplyEvent, ApplyPlan, ApplyStalled, ApplyWriteFailed, BasisMismatch, CopyOutOfBounds,
+    DataChecksumMismatch, DeltaCommand, FinalChunkMode, OutputDigest, OutputMismatch, ReadAt,
+    ScatterBase, SectorAlignedReader, SeekReadAdapter, Signatures, apply_delta, apply_delta_at,
+    apply_delta_checked, apply_delta_forward_only, apply_delta_from_stream, apply_delta_resume,
+    apply_delta_with_events, apply_delta_with_fetch, apply_delta_with_watchdog, apply_dry_run,
+    apply_encoded, apply_encoded_with_checksums, apply_parallel_to_slice, apply_profiled,
+    apply_scatter, apply_to_slice, apply_verified, delta_bounded_memory, delta_spans,
+    generate_delta, generate_delta_with_digest, generate_signatures,
+    generate_signatures_excluding_tail, generate_signatures_with_block_size,
+    generate_signatures_with_final_chunk_mode, library_info, plan_apply, read_delta, write_delta,
+    write_delta_with_checksums,
+};
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+fn random_data(len: usize) -> Vec<u8> {
+    let mut seed: u64 = 0xDEAD_BEEF;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            seed.to_be_bytes()[0]
+        })
+        .collect()
+}
+
+fn sample_data() -> (Vec<u8>, Vec<u8>) {
+    let original: Vec<u8> = (0..=255).cycle().take(4096).collect();
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 300]);
+    modified[2000..2100].fill(0xBB);
+    modified.drain(3000..3100);
+    (original, modified)
+}
+
+fn sample_delta(original: &[u8], modified: &[u8]) -> Vec<DeltaCommand> {
+    let signatures = generate_signatures_with_block_size(original, 64).unwrap();
+    generate_delta(&signatures, modified).unwrap()
+}
+
+/// Writer that blocks once `block_after` bytes have been written, until the gate is opened.
+#[derive(Debug)]
+struct BlockingWriter {
+    written: Vec<u8>,
+    block_after: usize,
+    gate: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Write for BlockingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.written.len() >= self.block_after {
+            let (open, condvar) = &*self.gate;
+            let mut open = open.lock().unwrap();
+            while !*open {
+                open = condvar.wait(open).unwrap();
+            }
+        }
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writer that accepts `capacity` bytes and then fails like a full disk.
+#[derive(Debug)]
+struct FullWriter {
+    written: Vec<u8>,
+    capacity: usize,
+}
+
+impl Write for FullWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.capacity - self.written.len());
+        if n == 0 && !buf.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::StorageFull,
+                "no space left",
+            ));
+        }
+        self.written.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_apply_with_watchdog_stalled() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let gate = Arc::new((Mutex::new(false), Condvar::new()));
+    let writer = BlockingWriter {
+        written: Vec::new(),
+        block_after: 200,
+        gate: Arc::clone(&gate),
+    };
+
+    let start = Instant::now();
+    let err = apply_delta_with_watchdog(
+        Cursor::new(&original),
+        &delta,
+        writer,
+        Duration::from_millis(100),
+    )
+    .unwrap_err();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+    let stalled = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ApplyStalled>())
+        .unwrap();
+    let expected = delta_spans(&delta)
+        .map(Result::unwrap)
+        .find(|span| span.output_range.start >= 200)
+        .unwrap();
+    assert_eq!(stalled.op_index, expected.op_index);
+    assert!(stalled.elapsed >= Duration::from_millis(100));
+
+    let (open, condvar) = &*gate;
+    *open.lock().unwrap() = true;
+    condvar.notify_all();
+}
+
+/// Writer that sleeps before every write.
+struct SlowWriter {
+    written: Vec<u8>,
+    delay: Duration,
+}
+
+impl Write for SlowWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::thread::sleep(self.delay);
+        self.written.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_apply_with_watchdog_slow_progress() {
+    // A single copy taking several times the limit, while every slice is written well within
+    // it.
+    let original = random_data(16 * 64 * 1024);
+    let delta = [DeltaCommand::Copy {
+        offset: 0,
+        length: original.len(),
+    }];
+    let writer = SlowWriter {
+        written: Vec::new(),
+        delay: Duration::from_millis(25),
+    };
+
+    let start = Instant::now();
+    let writer = apply_delta_with_watchdog(
+        Cursor::new(&original),
+        &delta,
+        writer,
+        Duration::from_millis(200),
+    )
+    .unwrap();
+    assert!(start.elapsed() > Duration::from_millis(200));
+    assert_eq!(writer.written, original);
+}
+
+#[test]
+fn test_apply_with_watchdog_matches_apply() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+
+    let mut expected = Vec::new();
+    apply_delta(Cursor::new(&original), &delta, &mut expected).unwrap();
+    let writer = apply_delta_with_watchdog(
+        Cursor::new(&original),
+        &delta,
+        Vec::new(),
+        Duration::from_secs(10),
+    )
+    .unwrap();
+    assert_eq!(writer, expected);
+}
+
+#[test]
+fn test_apply_with_watchdog_spans_slices() {
+    let original = random_data(200_000);
+    let mut modified = original.clone();
+    modified.splice(70_000..70_000, [0xAA; 1000]);
+    modified[150_000..150_100].fill(0xBB);
+    let delta = sample_delta(&original, &modified);
+
+    let writer = apply_delta_with_watchdog(
+        Cursor::new(&original),
+        &delta,
+        Vec::new(),
+        Duration::from_secs(10),
+    )
+    .unwrap();
+    assert_eq!(writer, modified);
+}
+
+#[test]
+fn test_apply_with_fetch() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+
+    let mut expected = Vec::new();
+    apply_delta(Cursor::new(&original), &delta, &mut expected).unwrap();
+
+    let fetch = |offset: u64, length: usize| {
+        let start = usize::try_from(offset).unwrap();
+        Ok(original[start..start + length].to_vec())
+    };
+    let mut reconstructed = Vec::new();
+    apply_delta_with_fetch(fetch, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, expected);
+
+    let short_fetch = |_: u64, length: usize| Ok(vec![0; length / 2]);
+    assert!(apply_delta_with_fetch(short_fetch, &delta, Vec::new()).is_err());
+}
+
+#[test]
+fn test_plan_apply() {
+    let delta = vec![
+        DeltaCommand::Copy {
+            offset: 64,
+            length: 32,
+        },
+        DeltaCommand::Data(vec![0; 10]),
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 16,
+        },
+        DeltaCommand::Copy {
+            offset: 80,
+            length: 40,
+        },
+        DeltaCommand::Copy {
+            offset: 200,
+            length: 8,
+        },
+    ];
+
+    let plan = plan_apply(&delta, 128).unwrap();
+    assert_eq!(
+        plan,
+        ApplyPlan {
+            final_size: 106,
+            basis_bytes_needed: 96,
+            basis_ranges: vec![0..16, 64..120, 200..208],
+            out_of_range_ops: vec![4],
+        }
+    );
+    assert!(!plan.is_satisfiable());
+    assert!(plan_apply(&delta, 208).unwrap().is_satisfiable());
+
+    // Outputs ending past `u64::MAX` are errors, not wrapped sizes.
+    let huge = DeltaCommand::Copy {
+        offset: 0,
+        length: usize::MAX,
+    };
+    let overflowing = [huge.clone(), huge];
+    assert_eq!(
+        plan_apply(&overflowing, 10).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+    let spans: Vec<_> = delta_spans(&overflowing).collect();
+    assert_eq!(spans.len(), 2);
+    assert!(spans[0].is_ok());
+    assert_eq!(
+        spans[1].as_ref().unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let plan = plan_apply(&delta, original.len() as u64).unwrap();
+    assert!(plan.is_satisfiable());
+    assert_eq!(plan.final_size, modified.len() as u64);
+}
+
+/// Reader that deliberately does not implement `Seek`.
+struct ForwardReader<'a>(&'a [u8]);
+
+impl Read for ForwardReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[test]
+fn test_apply_forward_only() {
+    let original = random_data(4096);
+    let mut modified = original.clone();
+    modified[1000..1010].fill(0xAA);
+    modified.drain(2000..2500);
+    modified.extend_from_slice(&[0xBB; 700]);
+    let delta = sample_delta(&original, &modified);
+
+    let mut reconstructed = Vec::new();
+    apply_delta_forward_only(ForwardReader(&original), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    let backwards = [
+        DeltaCommand::Copy {
+            offset: 100,
+            length: 10,
+        },
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 10,
+        },
+    ];
+    assert!(apply_delta_forward_only(ForwardReader(&original), &backwards, Vec::new()).is_err());
+
+    let past_end = [DeltaCommand::Copy {
+        offset: 4090,
+        length: 10,
+    }];
+    assert!(apply_delta_forward_only(ForwardReader(&original), &past_end, Vec::new()).is_err());
+
+    // A truncated base fails the copy reaching past its end, as with `apply_delta`.
+    let truncated = [
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 10,
+        },
+        DeltaCommand::Copy {
+            offset: 20,
+            length: 200,
+        },
+    ];
+    let err = apply_delta_forward_only(ForwardReader(&original[..100]), &truncated, Vec::new())
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(
+        err.get_ref()
+            .and_then(|e| e.downcast_ref::<CopyOutOfBounds>()),
+        Some(&CopyOutOfBounds {
+            op_index: 1,
+            basis_range: 20..220
+        })
+    );
+}
+
+/// Reader that rejects reads that are not sector-aligned, like a raw block device.
+struct StrictDevice {
+    inner: std::fs::File,
+    sector_size: u64,
+}
+
+impl Read for StrictDevice {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let pos = self.inner.stream_position()?;
+        if !pos.is_multiple_of(self.sector_size)
+            || !(buf.len() as u64).is_multiple_of(self.sector_size)
+        {
+            return Err(std::io::Error::from_raw_os_error(22));
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for StrictDevice {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_apply_from_sector_aligned_device() {
+    let original = random_data(10_000);
+    let mut modified = original.clone();
+    modified.splice(333..333, [0xAA; 17]);
+    modified.drain(7000..7100);
+    let delta = sample_delta(&original, &modified);
+
+    let path = std::env::temp_dir().join(format!("libsync3-device-{}", std::process::id()));
+    std::fs::write(&path, &original).unwrap();
+    let device = || StrictDevice {
+        inner: std::fs::File::open(&path).unwrap(),
+        sector_size: 512,
+    };
+
+    let mut reconstructed = Vec::new();
+    assert!(apply_delta(device(), &delta, &mut reconstructed).is_err());
+
+    let mut base = SectorAlignedReader::new(device(), 512, 8);
+    assert_eq!(base.len().unwrap(), original.len() as u64);
+    let mut reconstructed = Vec::new();
+    apply_delta(&mut base, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    let signatures =
+        generate_signatures_with_block_size(SectorAlignedReader::new(device(), 512, 1), 64)
+            .unwrap();
+    assert_eq!(signatures.source_size(), original.len() as u64);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_apply_write_failure_context() {
+    let original = random_data(200_000);
+    let mut modified = original.clone();
+    modified.splice(1000..1000, [0xAA; 5000]);
+    modified.drain(150_000..150_100);
+    let delta = sample_delta(&original, &modified);
+
+    let mut writer = FullWriter {
+        written: Vec::new(),
+        capacity: 100_000,
+    };
+    let err = apply_delta(Cursor::new(&original), &delta, &mut writer).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::StorageFull);
+    let failed = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ApplyWriteFailed>())
+        .unwrap();
+    assert_eq!(failed.output_offset, 100_000);
+    assert_eq!(failed.source.kind(), std::io::ErrorKind::StorageFull);
+    let span = delta_spans(&delta).nth(failed.op_index).unwrap().unwrap();
+    assert!(span.output_range.start <= 100_000);
+    assert_eq!(writer.written, modified[..100_000]);
+
+    let mut resumed = writer.written;
+    apply_delta_resume(
+        Cursor::new(&original),
+        &delta,
+        &mut resumed,
+        failed.output_offset,
+    )
+    .unwrap();
+    assert_eq!(resumed, modified);
+
+    let writer = FullWriter {
+        written: Vec::new(),
+        capacity: 100_000,
+    };
+    let err = apply_delta_with_watchdog(
+        Cursor::new(original.clone()),
+        delta.clone(),
+        writer,
+        Duration::from_secs(10),
+    )
+    .unwrap_err();
+    let failed = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<ApplyWriteFailed>())
+        .unwrap();
+    assert_eq!(failed.output_offset, 100_000);
+    let span = delta_spans(&delta).nth(failed.op_index).unwrap().unwrap();
+    assert!(span.output_range.contains(&100_000));
+}
+
+#[test]
+fn test_apply_delta_at() {
+    let original = random_data(300_000);
+    let mut modified = original[200_000..].to_vec();
+    modified.extend_from_slice(&[0xAA; 1000]);
+    modified.extend_from_slice(&original[..150_000]);
+    let delta = sample_delta(&original, &modified);
+
+    let mut reconstructed = Vec::new();
+    apply_delta_at(&original, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    let adapter = SeekReadAdapter::new(Cursor::new(&original));
+    let mut reconstructed = Vec::new();
+    apply_delta_at(&adapter, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    let path = std::env::temp_dir().join(format!("libsync3-read-at-{}", std::process::id()));
+    std::fs::write(&path, &original).unwrap();
+    let file = std::fs::File::open(&path).unwrap();
+    let mut reconstructed = Vec::new();
+    apply_delta_at(&file, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+    std::fs::remove_file(&path).unwrap();
+
+    let mut reconstructed = Vec::new();
+    let err = apply_delta_at(&original[..1000], &delta, &mut reconstructed).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_apply_scatter() {
+    let original = random_data(300_000);
+    let mut modified = original[200_000..].to_vec();
+    modified.extend_from_slice(&[0xAA; 1000]);
+    modified.extend_from_slice(&original[..150_000]);
+    let delta = sample_delta(&original, &modified);
+    let mut contiguous = Vec::new();
+    apply_delta_at(&original, &delta, &mut contiguous).unwrap();
+
+    // Uneven buffers, with empty ones, so that copies span several boundaries.
+    let cuts = [
+        0, 1, 1, 65, 1000, 1000, 77_777, 199_999, 200_001, 300_000, 300_000,
+    ];
+    let buffers: Vec<Vec<u8>> = cuts
+        .windows(2)
+        .map(|cut| original[cut[0]..cut[1]].to_vec())
+        .collect();
+    let mut reconstructed = Vec::new();
+    apply_scatter(&buffers, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, contiguous);
+
+    let slices: Vec<&[u8]> = buffers.iter().map(Vec::as_slice).collect();
+    let mut reconstructed = Vec::new();
+    apply_scatter(&slices, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, contiguous);
+
+    let base = ScatterBase::new(&slices);
+    assert_eq!(base.len(), 300_000);
+    let mut buf = [0u8; 100];
+    base.read_exact_at(&mut buf, 950).unwrap();
+    assert_eq!(buf, original[950..1050]);
+    assert_eq!(base.read_at(&mut buf, 299_950).unwrap(), 50);
+    assert_eq!(base.read_at(&mut buf, 300_000).unwrap(), 0);
+
+    let mut reconstructed = Vec::new();
+    let err = apply_scatter(&buffers[..4], &delta, &mut reconstructed).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_read_at() {
+    let data: Vec<u8> = (0..100).collect();
+    let mut buf = [0u8; 10];
+    assert_eq!(data.read_at(&mut buf, 95).unwrap(), 5);
+    assert_eq!(buf[..5], [95, 96, 97, 98, 99]);
+    assert_eq!(data.read_at(&mut buf, 100).unwrap(), 0);
+    assert_eq!(data.read_at(&mut buf, u64::MAX).unwrap(), 0);
+    assert!(data.read_exact_at(&mut buf, 95).is_err());
+
+    let adapter = SeekReadAdapter::new(Cursor::new(data.clone()));
+    std::thread::scope(|scope| {
+        for start in [0u8, 30, 60] {
+            let adapter = &adapter;
+            scope.spawn(move || {
+                let mut buf = [0u8; 10];
+                for _ in 0..100 {
+                    adapter.read_exact_at(&mut buf, u64::from(start)).unwrap();
+                    assert_eq!(buf[0], start);
+                }
+            });
+        }
+    });
+    assert_eq!(adapter.into_inner().into_inner(), data);
+}
+
+/// Source counting its seeks, that panics on the read at `panic_at`.
+struct SeekCounting {
+    inner: Cursor<Vec<u8>>,
+    seeks: usize,
+    panic_at: Option<u64>,
+}
+
+impl Read for SeekCounting {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.panic_at == Some(self.inner.position()) {
+            self.panic_at = None;
+            panic!("read failed");
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for SeekCounting {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.seeks += 1;
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_apply_delta_reads_nearby_copies_without_seeking() {
+    let original = random_data(100_000);
+    // Small edits every few KiB leave copies that continue one another in the base.
+    let mut modified = original.clone();
+    for offset in (1000..100_000).step_by(5000) {
+        modified[offset] ^= 0xFF;
+    }
+    let delta = sample_delta(&original, &modified);
+    let copies = delta
+        .iter()
+        .filter(|command| matches!(command, DeltaCommand::Copy { .. }))
+        .count();
+    assert!(copies > 10);
+
+    let mut base = SeekCounting {
+        inner: Cursor::new(original),
+        seeks: 0,
+        panic_at: None,
+    };
+    let mut reconstructed = Vec::new();
+    apply_delta(&mut base, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+    assert!(
+        base.seeks < copies / 4,
+        "{} seeks for {copies} copies",
+        base.seeks
+    );
+}
+
+#[test]
+fn test_seek_read_adapter_recovers_from_panic() {
+    let data: Vec<u8> = (0..100).collect();
+    let adapter = SeekReadAdapter::new(SeekCounting {
+        inner: Cursor::new(data.clone()),
+        seeks: 0,
+        panic_at: Some(50),
+    });
+    let mut buf = [0u8; 10];
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        adapter.read_exact_at(&mut buf, 50)
+    }));
+    assert!(panicked.is_err());
+
+    adapter.read_exact_at(&mut buf, 50).unwrap();
+    assert_eq!(buf[..], data[50..60]);
+    let inner = adapter.into_inner();
+    assert_eq!(inner.inner.into_inner(), data);
+}
+
+#[test]
+fn test_apply_copy_past_end_of_base() {
+    let (original, _) = sample_data();
+    let bogus = [
+        DeltaCommand::Data(b"header".to_vec()),
+        DeltaCommand::Copy {
+            offset: 10_000_000,
+            length: 64,
+        },
+    ];
+    let base = || Cursor::new(&original);
+    let mut out = vec![0u8; 100];
+    let mut encoded = Vec::new();
+    write_delta(&bogus, &mut encoded).unwrap();
+    let errors = [
+        apply_delta(base(), &bogus, Vec::new()).unwrap_err(),
+        apply_delta_resume(base(), &bogus, Vec::new(), 3).unwrap_err(),
+        apply_delta_at(&original, &bogus, Vec::new()).unwrap_err(),
+        apply_delta_forward_only(&original[..], &bogus, Vec::new()).unwrap_err(),
+        apply_delta_with_events(base(), &bogus, Vec::new(), |_| {}).unwrap_err(),
+        apply_delta_with_watchdog(base(), &bogus, Vec::new(), Duration::from_secs(10)).unwrap_err(),
+        apply_profiled(base(), &bogus, Vec::new()).0.unwrap_err(),
+        apply_dry_run(base(), &bogus).unwrap_err(),
+        apply_to_slice(base(), &bogus, &mut out).unwrap_err(),
+        apply_parallel_to_slice(|| Ok(base()), &bogus, &mut out, 2).unwrap_err(),
+        apply_encoded(base(), &encoded[..], Vec::new()).unwrap_err(),
+    ];
+    for err in errors {
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof, "{err}");
+        let out_of_bounds = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<CopyOutOfBounds>())
+            .unwrap_or_else(|| panic!("{err}"));
+        assert_eq!(out_of_bounds.op_index, 1);
+        assert_eq!(out_of_bounds.basis_range, 10_000_000..10_000_064);
+        assert!(err.to_string().contains("command 1"), "{err}");
+    }
+
+    // A base file truncated after the delta was made.
+    let original = random_data(10_000);
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 300]);
+    let delta = sample_delta(&original, &modified);
+    let dir = fresh_dir("truncated-base");
+    let path = dir.join("base");
+    std::fs::write(&path, &original[..original.len() / 2]).unwrap();
+    let mut reconstructed = Vec::new();
+    let err = apply_delta(
+        std::fs::File::open(&path).unwrap(),
+        &delta,
+        &mut reconstructed,
+    )
+    .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(reconstructed.len() < modified.len());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_apply_verified() {
+    let original = random_data(50_000);
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 300]);
+    modified[30_000] ^= 0xFF;
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let (delta, digest) = generate_delta_with_digest(&signatures, &modified[..]).unwrap();
+    assert_eq!(delta, sample_delta(&original, &modified));
+    assert_eq!(digest.len, modified.len() as u64);
+
+    let mut reconstructed = Vec::new();
+    apply_verified(Cursor::new(&original), &delta, digest, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    // The wrong base of the same length.
+    let mut wrong_base = original.clone();
+    wrong_base[10_000] ^= 0x01;
+    let err = apply_verified(Cursor::new(&wrong_base), &delta, digest, Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let mismatch = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<OutputMismatch>())
+        .unwrap();
+    assert_eq!(mismatch.expected, digest);
+    assert_eq!(mismatch.actual.len, digest.len);
+    assert_ne!(mismatch.actual.hash, digest.hash);
+
+    // A digest of another length.
+    let shorter = OutputDigest {
+        len: digest.len - 1,
+        ..digest
+    };
+    let err = apply_verified(Cursor::new(&original), &delta, shorter, Vec::new()).unwrap_err();
+    assert!(
+        err.to_string().contains(&format!("{} bytes", digest.len)),
+        "{err}"
+    );
+}
+
+#[test]
+fn test_apply_delta_checked() {
+    let original = random_data(50_000);
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 300]);
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let fingerprint = signatures.fingerprint().unwrap();
+    let delta = generate_delta(&signatures, &modified[..]).unwrap();
+
+    let mut reconstructed = Vec::new();
+    apply_delta_checked(
+        Cursor::new(&original),
+        &delta,
+        &fingerprint,
+        &mut reconstructed,
+    )
+    .unwrap();
+    assert_eq!(reconstructed, modified);
+
+    // Another file, then the same file with one byte changed or appended.
+    let mut changed = original.clone();
+    changed[25_000] ^= 0x01;
+    let mut appended = original.clone();
+    appended.push(0);
+    for wrong_base in [random_data(60_000)[10_000..].to_vec(), changed, appended] {
+        let mut reconstructed = Vec::new();
+        let err = apply_delta_checked(
+            Cursor::new(&wrong_base),
+            &delta,
+            &fingerprint,
+            &mut reconstructed,
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let mismatch = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<BasisMismatch>())
+            .unwrap();
+        assert_eq!(mismatch.expected, fingerprint);
+        assert_eq!(mismatch.actual.source_size, wrong_base.len() as u64);
+        assert!(reconstructed.is_empty());
+    }
+
+    // Padded last blocks are hashed the same way when re-deriving the fingerprint.
+    let padded =
+        generate_signatures_with_final_chunk_mode(&original[..], 4096, FinalChunkMode::PadZero)
+            .unwrap();
+    let mut reconstructed = Vec::new();
+    apply_delta_checked(
+        Cursor::new(&original),
+        generate_delta(&padded, &modified[..]).unwrap(),
+        &padded.fingerprint().unwrap(),
+        &mut reconstructed,
+    )
+    .unwrap();
+    assert_eq!(reconstructed, modified);
+
+    let partial = generate_signatures_excluding_tail(&original[..], 64, 1000).unwrap();
+    assert!(partial.fingerprint().is_none());
+    let empty = generate_signatures(&[][..]).unwrap().fingerprint().unwrap();
+    apply_delta_checked(Cursor::new(&[]), &[], &empty, Vec::new()).unwrap();
+}
+
+#[test]
+fn test_apply_dry_run() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    assert_eq!(
+        apply_dry_run(Cursor::new(&original), &delta).unwrap(),
+        modified.len() as u64
+    );
+    assert_eq!(apply_dry_run(Cursor::new(&original), &[]).unwrap(), 0);
+
+    // A copy past the end of the base.
+    let mut out_of_range = delta.clone();
+    out_of_range.push(DeltaCommand::Copy {
+        offset: original.len() as u64 - 10,
+        length: 20,
+    });
+    let err = apply_dry_run(Cursor::new(&original), &out_of_range).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(
+        err.to_string()
+            .contains(&format!("command {}", delta.len()))
+    );
+
+    // The same delta against a truncated base.
+    let err = apply_dry_run(Cursor::new(&original[..10]), &delta).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+/// Reader that sleeps before every read.
+struct SlowReader<R> {
+    inner: R,
+    delay: Duration,
+}
+
+impl<R: Read> Read for SlowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::thread::sleep(self.delay);
+        self.inner.read(buf)
+    }
+}
+
+impl<R: Seek> Seek for SlowReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_apply_profiled() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let copies = delta
+        .iter()
+        .filter(|command| matches!(command, DeltaCommand::Copy { .. }))
+        .count();
+    let base = SlowReader {
+        inner: Cursor::new(&original),
+        delay: Duration::from_millis(2),
+    };
+
+    let mut reconstructed = Vec::new();
+    let started = Instant::now();
+    let (result, profile) = apply_profiled(base, &delta, &mut reconstructed);
+    let elapsed = started.elapsed();
+    result.unwrap();
+    assert_eq!(reconstructed, modified);
+
+    assert_eq!(
+        profile.copied_bytes + profile.data_bytes,
+        modified.len() as u64
+    );
+    // Nearby copies share a read, but every seek is followed by one.
+    assert!(profile.seeks > 0 && profile.seeks < copies as u64);
+    assert!(profile.read >= Duration::from_millis(2) * u32::try_from(profile.seeks).unwrap());
+    assert!(profile.write < profile.read);
+    assert_eq!(profile.total(), profile.seek + profile.read + profile.write);
+    assert!(profile.total() <= elapsed);
+
+    // The profile is returned with the error, covering the work done until then.
+    let (result, profile) = apply_profiled(Cursor::new(&original[..10]), &delta, Vec::new());
+    assert_eq!(
+        result.unwrap_err().kind(),
+        std::io::ErrorKind::UnexpectedEof
+    );
+    assert_eq!(profile.copied_bytes, 0);
+}
+
+fn fresh_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("libsync3-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_apply_delta_to_path() {
+    let dir = fresh_dir("to-path");
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let base = dir.join("base");
+    std::fs::write(&base, &original).unwrap();
+
+    apply_delta_to_path(&base, &delta, dir.join("new")).unwrap();
+    assert_eq!(std::fs::read(dir.join("new")).unwrap(), modified);
+
+    // In place, and a failed apply leaves the target untouched with no temporary file.
+    apply_delta_to_path(&base, &delta, &base).unwrap();
+    assert_eq!(std::fs::read(&base).unwrap(), modified);
+    let bad = [DeltaCommand::Copy {
+        offset: 1 << 20,
+        length: 10,
+    }];
+    assert!(apply_delta_to_path(&base, bad, &base).is_err());
+    assert_eq!(std::fs::read(&base).unwrap(), modified);
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    // Dropping the guard without committing removes the temporary file.
+    let guard = TempGuard::new(dir.join("dropped")).unwrap();
+    let temp = guard.path().to_path_buf();
+    assert!(temp.exists());
+    drop(guard);
+    assert!(!temp.exists());
+    assert!(!dir.join("dropped").exists());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_recover_temp_files() {
+    let dir = fresh_dir("recover");
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let base = dir.join("base.bin");
+    std::fs::write(&base, &original).unwrap();
+    let unrelated = [
+        "notes.tmp",
+        ".hidden",
+        ".base.bin.x.libsync3-tmp",
+        "base.bin",
+    ];
+    for name in &unrelated[..3] {
+        std::fs::write(dir.join(name), b"keep").unwrap();
+    }
+
+    // A crash halfway through: the guard is neither committed nor dropped.
+    let mut guard = TempGuard::new(&base).unwrap();
+    guard.file().write_all(&modified[..1000]).unwrap();
+    std::mem::forget(guard);
+
+    let orphans = recover_temp_files(&dir, RecoveryPolicy::Adopt).unwrap();
+    assert_eq!(orphans.len(), 1);
+    assert_eq!(orphans[0].target, base);
+    assert_eq!(orphans[0].len, 1000);
+
+    let mut guard = TempGuard::adopt(&orphans[0]).unwrap();
+    apply_delta_resume(
+        std::fs::File::open(&base).unwrap(),
+        &delta,
+        guard.file(),
+        orphans[0].len,
+    )
+    .unwrap();
+    guard.commit().unwrap();
+    assert_eq!(std::fs::read(&base).unwrap(), modified);
+    assert!(
+        recover_temp_files(&dir, RecoveryPolicy::Adopt)
+            .unwrap()
+            .is_empty()
+    );
+
+    // Two more crashes, swept away this time.
+    for _ in 0..2 {
+        std::mem::forget(TempGuard::new(&base).unwrap());
+    }
+    let removed = recover_temp_files(&dir, RecoveryPolicy::Remove).unwrap();
+    assert_eq!(removed.len(), 2);
+    assert!(removed.iter().all(|orphan| !orphan.path.exists()));
+
+    let mut left: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort_unstable();
+    let mut expected = unrelated.map(String::from).to_vec();
+    expected.sort_unstable();
+    assert_eq!(left, expected);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_apply_delta_with_events() {
+    let original = random_data(300_000);
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 300]);
+    modified.drain(150_000..150_100);
+    let mut delta = sample_delta(&original, &modified);
+    // One copy longer than the apply buffer, reported in pieces.
+    delta.push(DeltaCommand::Copy {
+        offset: 0,
+        length: 200_000,
+    });
+    modified.extend_from_slice(&original[..200_000]);
+
+    let mut events = Vec::new();
+    let mut reconstructed = Vec::new();
+    apply_delta_with_events(
+        Cursor::new(&original),
+        &delta,
+        &mut reconstructed,
+        |event| {
+            events.push(event);
+        },
+    )
+    .unwrap();
+    assert_eq!(reconstructed, modified);
+    assert_eq!(events.pop(), Some(ApplyEvent::Flushed));
+
+    // The events tile the output, each within the span of its command.
+    let spans: Vec<_> = delta_spans(&delta).map(Result::unwrap).collect();
+    let mut output_pos = 0;
+    for event in &events {
+        let (op_index, output_range) = match event {
+            ApplyEvent::CopyApplied {
+                op_index,
+                basis_range,
+                output_range,
+            } => {
+                let span = &spans[*op_index];
+                let basis = span.basis_range.as_ref().unwrap();
+                assert_eq!(
+                    basis_range.start - basis.start,
+                    output_range.start - span.output_range.start
+                );
+                assert_eq!(
+                    basis_range.end - basis_range.start,
+                    output_range.end - output_range.start
+                );
+                (*op_index, output_range)
+            }
+            ApplyEvent::DataApplied {
+                op_index,
+                output_range,
+            } => {
+                assert_eq!(output_range, &spans[*op_index].output_range);
+                (*op_index, output_range)
+            }
+            ApplyEvent::SelfCopyApplied { .. } => panic!("no self-referential copies"),
+            ApplyEvent::Flushed => panic!("flushed before the end"),
+        };
+        assert_eq!(output_range.start, output_pos);
+        assert!(output_range.end > output_range.start);
+        assert!(output_range.end <= spans[op_index].output_range.end);
+        output_pos = output_range.end;
+    }
+    assert_eq!(output_pos, modified.len() as u64);
+    assert!(events.len() > delta.len());
+
+    let mut events = Vec::new();
+    let short = [DeltaCommand::Copy {
+        offset: 0,
+        length: 10,
+    }];
+    assert!(
+        apply_delta_with_events(Cursor::new(&original[..5]), short, Vec::new(), |event| {
+            events.push(event);
+        })
+        .is_err()
+    );
+    assert!(events.is_empty());
+}
+
+/// Reader without `Seek`.
+struct StreamReader<'a>(&'a [u8]);
+
+impl Read for StreamReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+#[test]
+fn test_apply_delta_from_stream() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let mut reconstructed = Vec::new();
+    apply_delta_from_stream(StreamReader(&original), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+    assert!(apply_delta_from_stream(StreamReader(&original[..10]), &delta, Vec::new()).is_err());
+}
+
+/// Writer checking every byte against `expected(offset)` instead of keeping the output.
+struct CheckingWriter<F> {
+    expected: F,
+    written: u64,
+}
+
+impl<F: Fn(u64) -> u8> Write for CheckingWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for &byte in buf {
+            assert_eq!(
+                byte,
+                (self.expected)(self.written),
+                "offset {}",
+                self.written
+            );
+            self.written += 1;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_apply_encoded() {
+    let original = random_data(200_000);
+    let mut modified: Vec<u8> = original.iter().map(|byte| byte ^ 0x5A).collect();
+    modified[50_000..60_240].copy_from_slice(&original[10_240..20_480]);
+    modified.extend_from_slice(&original[..777]);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    let mut encoded = Vec::new();
+    delta_bounded_memory(&modified[..], &signatures, &mut encoded, 8192).unwrap();
+    let mut reconstructed = Vec::new();
+    apply_encoded(Cursor::new(&original), &encoded[..], &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    // A 64 MiB data frame generated on the fly and checked as it is written, so neither the
+    // delta nor the output is ever held in memory.
+    let data_len: u64 = 64 * 1024 * 1024;
+    let mut head = Vec::new();
+    write_delta(&[], &mut head).unwrap();
+    head.push(OP_DATA);
+    head.extend_from_slice(&[0x80, 0x80, 0x80, 0x20]);
+    let mut tail = Vec::new();
+    write_delta(
+        &[DeltaCommand::Copy {
+            offset: 100,
+            length: 1000,
+        }],
+        &mut tail,
+    )
+    .unwrap();
+    let delta_reader = head[..]
+        .chain(std::io::repeat(0xAB).take(data_len))
+        .chain(&tail[DELTA_HEADER_LEN..]);
+    let mut checker = CheckingWriter {
+        expected: |offset| {
+            if offset < data_len {
+                0xAB
+            } else {
+                #[allow(clippy::cast_possible_truncation)]
+                original[100 + (offset - data_len) as usize]
+            }
+        },
+        written: 0,
+    };
+    apply_encoded(Cursor::new(&original), delta_reader, &mut checker).unwrap();
+    assert_eq!(checker.written, data_len + 1000);
+
+    // Truncated deltas and copies past the end of the base are errors.
+    for len in [0, 3, encoded.len() - 1] {
+        assert!(apply_encoded(Cursor::new(&original), &encoded[..len], Vec::new()).is_err());
+    }
+    let err = apply_encoded(Cursor::new(&original[..1000]), &encoded[..], Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+    // Self-copies read back the output, but not from deltas of version 1.
+    let mut self_copy = Vec::new();
+    write_delta(
+        &[
+            DeltaCommand::Data(b"ab".to_vec()),
+            DeltaCommand::SelfCopy {
+                output_offset: 0,
+                length: 4,
+            },
+        ],
+        &mut self_copy,
+    )
+    .unwrap();
+    let mut reconstructed = Vec::new();
+    apply_encoded(Cursor::new(&original), &self_copy[..], &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, b"ababab");
+    self_copy[4] = 1;
+    let err = apply_encoded(Cursor::new(&original), &self_copy[..], Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // Copies ending past `u64::MAX` are rejected by both decoders.
+    for opcode in [0x00, 0x02] {
+        let mut overflowing = b"LS3D\x02".to_vec();
+        overflowing.push(opcode);
+        overflowing.extend_from_slice(&[0xFF; 9]);
+        overflowing.extend_from_slice(&[0x01, 0x01]);
+        let err = read_delta(&overflowing[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let err = apply_encoded(Cursor::new(&original), &overflowing[..], Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_apply_encoded_with_checksums() {
+    let original = random_data(200_000);
+    let mut modified = original.clone();
+    modified[20_000..120_000]
+        .iter_mut()
+        .for_each(|byte| *byte ^= 0x5A);
+    let delta = sample_delta(&original, &modified);
+    let mut encoded = Vec::new();
+    write_delta_with_checksums(&delta, &mut encoded).unwrap();
+
+    // Data longer than a checked frame reads back as several commands.
+    let decoded = read_delta(&encoded[..]).unwrap();
+    assert!(decoded.len() > delta.len());
+    let mut reconstructed = Vec::new();
+    apply_delta(Cursor::new(&original), &decoded, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+    let mut reconstructed = Vec::new();
+    apply_encoded(Cursor::new(&original), &encoded[..], &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+    let mut reconstructed = Vec::new();
+    apply_encoded_with_checksums(Cursor::new(&original), &encoded[..], &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    // A corrupted payload or checksum is caught before any of the frame is written.
+    let delta = [
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 1000,
+        },
+        DeltaCommand::Data(vec![0xAA; 100]),
+        DeltaCommand::Copy {
+            offset: 2000,
+            length: 500,
+        },
+    ];
+    let mut encoded = Vec::new();
+    write_delta_with_checksums(&delta, &mut encoded).unwrap();
+    // Header, copy frame, then the opcode and length of the data frame.
+    let payload = DELTA_HEADER_LEN + 4 + 2;
+    for corrupt in [payload, payload + 99, payload + 100, payload + 103] {
+        let mut corrupted = encoded.clone();
+        corrupted[corrupt] ^= 0x01;
+        let err = read_delta(&corrupted[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("command 1"), "{err}");
+
+        let mut reconstructed = Vec::new();
+        let err =
+            apply_encoded(Cursor::new(&original), &corrupted[..], &mut reconstructed).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        let mismatch = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<DataChecksumMismatch>())
+            .unwrap();
+        assert_eq!(mismatch.op_index, 1);
+        assert_ne!(mismatch.expected, mismatch.actual);
+        assert!(original[..1000].starts_with(&reconstructed));
+    }
+
+    // Requiring checksums rejects plain data frames, even empty ones, but not copies.
+    let mut plain = Vec::new();
+    write_delta(&delta, &mut plain).unwrap();
+    let mut reconstructed = Vec::new();
+    let err = apply_encoded_with_checksums(Cursor::new(&original), &plain[..], &mut reconstructed)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("command 1"), "{err}");
+    assert!(original[..1000].starts_with(&reconstructed));
+    let mut empty = Vec::new();
+    write_delta(&[DeltaCommand::Data(Vec::new())], &mut empty).unwrap();
+    assert!(apply_encoded_with_checksums(Cursor::new(&original), &empty[..], Vec::new()).is_err());
+    let mut copies = Vec::new();
+    write_delta(&delta[..1], &mut copies).unwrap();
+    apply_encoded_with_checksums(Cursor::new(&original), &copies[..], Vec::new()).unwrap();
+
+    // Checked frames are bounded, so they can be checked before they are written.
+    let mut oversized = b"LS3D\x03\x03\x81\x80\x04".to_vec();
+    oversized.extend(std::iter::repeat_n(0, MAX_CHECKED_DATA_LEN + 5));
+    let err = apply_encoded(Cursor::new(&original), &oversized[..], Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(err.to_string().contains("more than"), "{err}");
+}
+
+#[test]
+fn test_archive_roundtrip() {
+    let dir = fresh_dir("archive");
+    let (original, modified) = sample_data();
+    let path = dir.join("v1.ls3a");
+    let opts = ArchiveOptions {
+        block_size: 64,
+        crc32: true,
+    };
+    let written =
+        archive::write_with_signature(&original[..], std::fs::File::create(&path).unwrap(), opts)
+            .unwrap();
+
+    let signatures = archive::read_signature(&path).unwrap();
+    assert_eq!(signatures.block_size(), 64);
+    assert_eq!(signatures.source_size(), original.len() as u64);
+    assert_eq!(signatures.len(), written.len());
+    assert_eq!(signatures.file_adler(), written.file_adler());
+    assert_eq!(
+        generate_delta(&signatures, &modified[..]).unwrap(),
+        generate_delta(&written, &modified[..]).unwrap()
+    );
+
+    let mut payload = archive::payload_reader(&path).unwrap();
+    assert_eq!(payload.len(), original.len() as u64);
+    let mut read_back = Vec::new();
+    payload.read_to_end(&mut read_back).unwrap();
+    assert_eq!(read_back, original);
+
+    // A recipient holding the archive applies a delta for the next version to its payload.
+    let delta = generate_delta(&signatures, &modified[..]).unwrap();
+    let mut out = Vec::new();
+    payload.rewind().unwrap();
+    apply_delta(&mut payload, &delta, &mut out).unwrap();
+    assert_eq!(out, modified);
+
+    let empty = dir.join("empty.ls3a");
+    archive::write_with_signature(&b""[..], std::fs::File::create(&empty).unwrap(), opts).unwrap();
+    assert!(archive::read_signature(&empty).unwrap().is_empty());
+    assert!(archive::payload_reader(&empty).unwrap().is_empty());
+}
+
+/// Archive written by version 2 of the footer, with the signatures section of its own layout:
+/// 4096 bytes of `(0..=255).cycle()` in blocks of 1024 with CRC-32s.
+fn version_2_archive() -> Vec<u8> {
+    std::fs::read(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/archive/v2.ls3a"),
+    )
+    .unwrap()
+}
+
+/// Turn a version 2 or 3 archive into a version 1 one, whose footer has no checksum: payload
+/// length, signatures length, version, magic.
+fn strip_archive_checksum(archive: &mut Vec<u8>) {
+    let footer = archive.split_off(archive.len() - 37);
+    archive.extend_from_slice(&footer[..16]);
+    archive.push(1);
+    archive.extend_from_slice(&archive::ARCHIVE_MAGIC);
+}
+
+#[test]
+fn test_archive_reads_older_versions() {
+    let dir = fresh_dir("archive-older");
+    let (original, modified) = sample_data();
+    let mut version_1 = version_2_archive();
+    strip_archive_checksum(&mut version_1);
+
+    for (name, archive) in [("v1.ls3a", version_1), ("v2.ls3a", version_2_archive())] {
+        let path = dir.join(name);
+        std::fs::write(&path, &archive).unwrap();
+        let signatures = archive::read_signature(&path).unwrap();
+        assert_eq!(signatures.block_size(), 1024);
+        assert_eq!(signatures.source_size(), original.len() as u64);
+        let delta = generate_delta(&signatures, &modified[..]).unwrap();
+        let mut out = Vec::new();
+        apply_delta(archive::payload_reader(&path).unwrap(), &delta, &mut out).unwrap();
+        assert_eq!(out, modified);
+    }
+}
+
+#[test]
+fn test_archive_embeds_encoded_signatures() {
+    let (original, _) = sample_data();
+    let mut archive = Vec::new();
+    let signatures =
+        archive::write_with_signature(&original[..], &mut archive, ArchiveOptions::default())
+            .unwrap();
+    let mut encoded = Vec::new();
+    signatures.write_to(&mut encoded).unwrap();
+    assert_eq!(
+        archive[original.len()..original.len() + encoded.len()],
+        encoded[..]
+    );
+}
+
+/// Every format version `library_info` claims is produced or accepted by the codecs.
+#[test]
+fn test_library_info_versions_roundtrip() {
+    let info = library_info();
+    assert_eq!(info.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(info.default_block_size, 4096);
+    assert_eq!(
+        generate_signatures(&[0u8; 10_000][..])
+            .unwrap()
+            .block_size(),
+        info.default_block_size
+    );
+
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let mut encoded = Vec::new();
+    write_delta(&delta, &mut encoded).unwrap();
+    assert_eq!(encoded[4], info.delta_version_written);
+    for &version in &info.delta_versions_read {
+        encoded[4] = version;
+        assert_eq!(read_delta(&encoded[..]).unwrap(), delta);
+    }
+
+    let signatures = generate_signatures(&original[..]).unwrap();
+    let mut encoded = Vec::new();
+    signatures.write_to(&mut encoded).unwrap();
+    assert_eq!(encoded[4], info.signature_version_written);
+    for &version in &info.signature_versions_read {
+        encoded[4] = version;
+        let decoded = Signatures::read_from(&encoded[..]).unwrap();
+        assert_eq!(
+            generate_delta(&decoded, &modified[..]).unwrap(),
+            generate_delta(&signatures, &modified[..]).unwrap()
+        );
+    }
+
+    let dir = fresh_dir("library-info");
+    let mut written = Vec::new();
+    archive::write_with_signature(&original[..], &mut written, ArchiveOptions::default()).unwrap();
+    assert_eq!(written[written.len() - 5], info.archive_version_written);
+    assert!(
+        info.archive_versions_read
+            .contains(&info.archive_version_written)
+    );
+    for &version in &info.archive_versions_read {
+        let archive = match version {
+            3 => written.clone(),
+            2 => version_2_archive(),
+            1 => {
+                let mut archive = version_2_archive();
+                strip_archive_checksum(&mut archive);
+                archive
+            }
+            _ => panic!("no test archive for footer version {version}"),
+        };
+        let path = dir.join(format!("v{version}.ls3a"));
+        std::fs::write(&path, &archive).unwrap();
+        let signatures = archive::read_signature(&path).unwrap();
+        let mut out = Vec::new();
+        apply_delta(
+            archive::payload_reader(&path).unwrap(),
+            generate_delta(&signatures, &modified[..]).unwrap(),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out, modified);
+    }
+}
+
+#[test]
+fn test_archive_rejects_invalid_files() {
+    let dir = fresh_dir("archive-invalid");
+    let (original, _) = sample_data();
+    let path = dir.join("archive.ls3a");
+    let mut archive = Vec::new();
+    archive::write_with_signature(&original[..], &mut archive, ArchiveOptions::default()).unwrap();
+
+    let mut corrupt = archive.clone();
+    corrupt[original.len() + 2] ^= 0xFF;
+    std::fs::write(&path, &corrupt).unwrap();
+    let err = archive::read_signature(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    let mut future = archive.clone();
+    let version = future.len() - 5;
+    future[version] = 4;
+    std::fs::write(&path, &future).unwrap();
+    let err = archive::payload_reader(&path).unwrap_err();
+    assert!(err.to_string().contains("version 4"), "{err}");
+
+    std::fs::write(&path, &original).unwrap();
+    let err = archive::read_signature(&path).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    std::fs::write(&path, &archive[archive.len() - 20..]).unwrap();
+    assert!(archive::read_signature(&path).is_err());
+}
+
+#[test]
+fn test_apply_to_slice() {
+    let (original, modified) = sample_data();
+    let delta = sample_delta(&original, &modified);
+    let plan = plan_apply(&delta, original.len() as u64).unwrap();
+
+    let mut out = vec![0u8; usize::try_from(plan.final_size).unwrap()];
+    let written = apply_to_slice(Cursor::new(&original), &delta, &mut out).unwrap();
+    assert_eq!(written, modified.len());
+    assert_eq!(out, modified);
+
+    // Room to spare is left untouched.
+    let mut out = vec![0xEE; modified.len() + 16];
+    let written = apply_to_slice(Cursor::new(&original), &delta, &mut out).unwrap();
+    assert_eq!(&out[..written], &modified[..]);
+    assert!(out[written..].iter().all(|&byte| byte == 0xEE));
+
+    let mut short = vec![0u8; modified.len() - 1];
+    let err = apply_to_slice(Cursor::new(&original), &delta, &mut short).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+
+    let mut out = vec![0u8; modified.len()];
+    let err = apply_to_slice(Cursor::new(&original[..10]), &delta, &mut out).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn test_apply_parallel_to_slice() {
+    let dir = fresh_dir("parallel-apply");
+    let base = random_data(4 << 20);
+    let path = dir.join("base");
+    std::fs::write(&path, &base).unwrap();
+
+    // Base regions of varied sizes in a scrambled order, with a little literal data.
+    let mut delta = Vec::new();
+    for i in 0..200u8 {
+        let length = 20_000 + usize::from(i) * 7_919 % 50_000;
+        let offset = u64::from(i) * 1_000_003 % (base.len() - length) as u64;
+        delta.push(DeltaCommand::Copy { offset, length });
+        if i % 10 == 0 {
+            delta.push(DeltaCommand::Data(vec![i; 100]));
+        }
+    }
+    let mut expected = Vec::new();
+    apply_delta(Cursor::new(&base), &delta, &mut expected).unwrap();
+
+    for threads in [1, 3, 4, 0] {
+        let mut out = vec![0u8; expected.len()];
+        let written =
+            apply_parallel_to_slice(|| std::fs::File::open(&path), &delta, &mut out, threads)
+                .unwrap();
+        assert_eq!(written, expected.len());
+        assert!(out == expected, "{threads} threads");
+    }
+
+    let mut short = vec![0u8; expected.len() - 1];
+    let err =
+        apply_parallel_to_slice(|| Ok(Cursor::new(&base)), &delta, &mut short, 4).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WriteZero);
+    assert!(short.iter().all(|&byte| byte == 0));
+
+    let mut out = vec![0u8; expected.len()];
+    let err = apply_parallel_to_slice(|| Ok(Cursor::new(&base[..1 << 20])), &delta, &mut out, 4)
+        .unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+}
+
+/// Base counting the positioned reads it serves.
+struct CountingReadAt<'a> {
+    data: &'a [u8],
+    reads: std::sync::atomic::AtomicUsize,
+}
+
+impl ReadAt for CountingReadAt<'_> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+        self.reads
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        self.data.read_at(buf, offset)
+    }
+}
+
+#[test]
+fn test_apply_delta_at_batches_nearby_copies() {
+    let original = random_data(2 << 20);
+    let mut modified = original.clone();
+    for chunk in modified.chunks_mut(200) {
+        chunk[0] ^= 0xFF;
+    }
+    let delta = sample_delta(&original, &modified);
+    let copies = delta
+        .iter()
+        .filter(|command| matches!(command, DeltaCommand::Copy { .. }))
+        .count();
+    assert!(copies > 10_000, "{copies} copies");
+
+    let base = CountingReadAt {
+        data: &original,
+        reads: std::sync::atomic::AtomicUsize::new(0),
+    };
+    let mut reconstructed = Vec::new();
+    apply_delta_at(&base, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+    let reads = base.reads.into_inner();
+    assert!(reads * 10 < copies, "{reads} reads for {copies} copies");
+
+    // A copy past the end still fails on its own command, after the output before it.
+    let mut reconstructed = Vec::new();
+    let err = apply_delta_at(&original[..1 << 20], &delta, &mut reconstructed).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    let first_failing = delta_spans(&delta)
+        .map(Result::unwrap)
+        .find(|span| {
+            span.basis_range
+                .as_ref()
+                .is_some_and(|range| range.end > 1 << 20)
+        })
+        .unwrap();
+    assert!(
+        err.to_string()
+            .starts_with(&format!("command {} ", first_failing.op_index)),
+        "{err}"
+    );
+}
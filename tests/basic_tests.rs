@@ -1,8 +1,28 @@
+use libsync3::cdc::CdcParams;
+use libsync3::encoding::{
+    encoded_delta_size, encoded_delta_size_upper_bound, encoded_signature_size,
+};
+use libsync3::rolling::RollingChecksum;
+use libsync3::vcdiff::VCDIFF_WINDOW_SIZE;
 use libsync3::{
-    DeltaCommand, apply_delta, generate_delta, generate_delta_with_cb, generate_signatures,
-    generate_signatures_with_block_size,
+    AgreedParams, Capabilities, ChunkSizeProfile, ChunkStrategy, CopyOutOfBounds, CostModel,
+    DEFAULT_MAX_DATA_LEN, DeltaBuilder, DeltaCommand, DeltaOutcome, DynEngine, ExportFormat,
+    FinalChunkMode, HierarchicalEngine, Matcher, OpKind, OpSpan, RedactionPolicy, RollingEngine,
+    SELF_COPY_WINDOW, SignatureStrong, Signatures, SyncEngine, TextEngine, apply_delta,
+    apply_delta_at, apply_delta_forward_only, apply_delta_resume, apply_dry_run,
+    apply_parallel_to_slice, apply_to_slice, delta_bounded_memory, delta_content_hash,
+    delta_or_full, delta_spans, estimate_delta_size, first_difference, generate_delta,
+    generate_delta_hierarchical, generate_delta_parallel, generate_delta_with_alignment,
+    generate_delta_with_cb, generate_delta_with_cost, generate_delta_with_strategy,
+    generate_signatures, generate_signatures_auto, generate_signatures_excluding_tail,
+    generate_signatures_for_path, generate_signatures_parallel, generate_signatures_pow2,
+    generate_signatures_with_block_size, generate_signatures_with_buffer_limit,
+    generate_signatures_with_crc32, generate_signatures_with_final_chunk_mode,
+    generate_signatures_with_strategy, generate_text_delta, generate_text_signatures, invert_delta,
+    negotiate, optimize_delta, plan_apply, postmatch_delta, read_delta, splice_deltas,
+    suggest_block_size, write_delta, write_vcdiff, xxh3_128,
 };
-use std::io::Cursor;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 
 fn make_delta(original: &[u8], modified: &[u8], block_size: Option<usize>) -> Vec<DeltaCommand> {
     let signatures = match block_size {
@@ -12,12 +32,26 @@ fn make_delta(original: &[u8], modified: &[u8], block_size: Option<usize>) -> Ve
     generate_delta(&signatures, modified).unwrap()
 }
 
+fn random_data(len: usize) -> Vec<u8> {
+    let mut seed: u64 = 0xDEAD_BEEF;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            seed.to_be_bytes()[0]
+        })
+        .collect()
+}
+
 fn apply_patch(original: &[u8], delta: &[DeltaCommand]) -> Vec<u8> {
     let mut reconstructed = Vec::new();
     apply_delta(Cursor::new(original), delta, &mut reconstructed).unwrap();
     reconstructed
 }
 
+fn to_usize_range(range: &std::ops::Range<u64>) -> std::ops::Range<usize> {
+    usize::try_from(range.start).unwrap()..usize::try_from(range.end).unwrap()
+}
+
 fn roundtrip(
     original: &[u8],
     modified: &[u8],
@@ -79,6 +113,7 @@ fn test_completely_different_data_with_small_window() {
 }
 
 #[test]
+#[allow(clippy::cast_possible_truncation, clippy::len_zero)]
 fn test_1mb_with_prepended_byte_rolling_checksum() {
     const ONE_MB: usize = 1024 * 1024;
     let block_size = 4096;
@@ -393,3 +428,2680 @@ fn test_generate_delta_with_channel() {
     apply_delta(Cursor::new(&original), &delta, &mut reconstructed).unwrap();
     assert_eq!(reconstructed, modified);
 }
+
+#[test]
+fn test_signatures_from_precomputed() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..200).collect();
+    let mut modified = original.clone();
+    modified.splice(40..40, [0xAA; 7]);
+    modified.drain(120..130);
+
+    let hashes = original
+        .chunks(block_size)
+        .map(|chunk| (RollingChecksum::compute(chunk), xxh3_128(chunk)));
+    let precomputed =
+        Signatures::from_precomputed(block_size, original.len() as u64, hashes).unwrap();
+    let generated = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+
+    assert_eq!(precomputed.len(), generated.len());
+    let delta = generate_delta(&precomputed, &modified[..]).unwrap();
+    assert_eq!(delta, generate_delta(&generated, &modified[..]).unwrap());
+    assert_eq!(apply_patch(&original, &delta), modified);
+}
+
+#[test]
+fn test_signatures_from_precomputed_count_mismatch() {
+    let original: Vec<u8> = (0..50).collect();
+    let hashes: Vec<_> = original
+        .chunks(16)
+        .map(|chunk| (RollingChecksum::compute(chunk), xxh3_128(chunk)))
+        .collect();
+
+    assert!(Signatures::from_precomputed(16, 50, hashes[..3].iter().copied()).is_err());
+    assert!(Signatures::from_precomputed(16, 80, hashes.iter().copied()).is_err());
+    assert!(Signatures::from_precomputed(0, 50, hashes.iter().copied()).is_err());
+    assert!(Signatures::from_precomputed(16, 50, hashes).is_ok());
+}
+
+#[test]
+fn test_apply_delta_resume() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+    let mut modified = original.clone();
+    modified.splice(20..20, [0xAA; 9]);
+    modified[100..140].fill(0xBB);
+    modified.drain(200..230);
+
+    let delta = make_delta(&original, &modified, Some(block_size));
+    let full = apply_patch(&original, &delta);
+
+    for already_written in [0, 1, 20, 29, modified.len() / 2, modified.len()] {
+        let mut resumed = full[..already_written].to_vec();
+        apply_delta_resume(
+            Cursor::new(&original),
+            &delta,
+            &mut resumed,
+            already_written as u64,
+        )
+        .unwrap();
+        assert_eq!(resumed, full, "resuming from {already_written}");
+    }
+}
+
+#[test]
+fn test_signatures_chunk_offsets() {
+    let original: Vec<u8> = (0..50).collect();
+    let signatures = generate_signatures_with_block_size(&original[..], 16).unwrap();
+
+    assert_eq!(signatures.source_size(), 50);
+    let offsets: Vec<_> = signatures.chunk_offsets().collect();
+    assert_eq!(offsets, [(0, 0, 16), (1, 16, 16), (2, 32, 16), (3, 48, 2)]);
+
+    let aligned = generate_signatures_with_block_size(&original[..48], 16).unwrap();
+    assert_eq!(aligned.chunk_offsets().last(), Some((2, 32, 16)));
+}
+
+#[test]
+fn test_generate_signatures_with_crc32() {
+    let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut modified = original.clone();
+    modified.splice(1000..1000, [0xAA; 300]);
+    modified.drain(6000..6100);
+
+    let plain = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let with_crc = generate_signatures_with_crc32(&original[..], 64).unwrap();
+    assert_eq!(with_crc.len(), plain.len());
+    assert_eq!(with_crc.source_size(), plain.source_size());
+
+    let delta = generate_delta(&with_crc, &modified[..]).unwrap();
+    assert_eq!(delta, generate_delta(&plain, &modified[..]).unwrap());
+    assert_eq!(apply_patch(&original, &delta), modified);
+}
+
+#[test]
+fn test_crc32_checked_before_strong_hash() {
+    // Both blocks have the same Adler-32 but different contents.
+    let original = [0u8, 2, 0, 0];
+    let modified = [1u8, 0, 1, 0];
+    let weak = RollingChecksum::compute(&original);
+    assert_eq!(weak, RollingChecksum::compute(&modified));
+    let crc32 = generate_signatures_with_crc32(&original[..], 4)
+        .unwrap()
+        .weak(weak)
+        .unwrap()[0]
+        .crc32;
+    assert!(crc32.is_some());
+
+    // An entry with the strong hash of the new block is rejected on the CRC-32 of the old one.
+    for (crc32, copied) in [(None, true), (crc32, false)] {
+        let mut signatures = Signatures::new(4);
+        signatures.insert(
+            weak,
+            SignatureStrong {
+                strong: xxh3_128(&modified),
+                block_index: 0,
+                crc32,
+            },
+        );
+        let delta = generate_delta(&signatures, &modified[..]).unwrap();
+        assert_eq!(matches!(delta[..], [DeltaCommand::Copy { .. }]), copied);
+    }
+}
+
+#[test]
+fn test_signatures_full_and_partial_chunks() {
+    let original: Vec<u8> = (0..50).collect();
+
+    let signatures = generate_signatures_with_block_size(&original[..], 16).unwrap();
+    assert_eq!(signatures.full_chunk_count(), 3);
+    assert!(signatures.has_partial_tail());
+
+    let aligned = generate_signatures_with_block_size(&original[..48], 16).unwrap();
+    assert_eq!(aligned.full_chunk_count(), 3);
+    assert!(!aligned.has_partial_tail());
+
+    let short = generate_signatures_with_block_size(&original[..10], 16).unwrap();
+    assert_eq!(short.full_chunk_count(), 0);
+    assert!(short.has_partial_tail());
+
+    let empty = generate_signatures_with_block_size(&[][..], 16).unwrap();
+    assert_eq!(empty.full_chunk_count(), 0);
+    assert!(!empty.has_partial_tail());
+}
+
+struct TrackingReader<'a> {
+    inner: Cursor<&'a [u8]>,
+    reads: Vec<(u64, usize)>,
+}
+
+impl Read for TrackingReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let position = self.inner.position();
+        let read = self.inner.read(buf)?;
+        if read > 0 {
+            self.reads.push((position, read));
+        }
+        Ok(read)
+    }
+}
+
+impl Seek for TrackingReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn test_signatures_restrict_to_range() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+    let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+
+    let restricted = signatures.restrict_to_range(70..120);
+    assert_eq!(restricted.len(), 4);
+    assert!(signatures.restrict_to_range(5..5).is_empty());
+
+    let delta = generate_delta(&restricted, &original[..]).unwrap();
+    assert_eq!(
+        delta
+            .iter()
+            .filter(|cmd| matches!(cmd, DeltaCommand::Copy { .. }))
+            .collect::<Vec<_>>(),
+        [&DeltaCommand::Copy {
+            offset: 64,
+            length: 64
+        }]
+    );
+
+    let mut base = TrackingReader {
+        inner: Cursor::new(&original[..]),
+        reads: Vec::new(),
+    };
+    let mut reconstructed = Vec::new();
+    apply_delta(&mut base, &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, original);
+    assert!(
+        base.reads
+            .iter()
+            .all(|&(offset, len)| offset >= 64 && offset + len as u64 <= 128)
+    );
+}
+
+#[test]
+fn test_generate_delta_with_cost() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+    let mut modified = original[..128].to_vec();
+    for block in [12, 9, 14] {
+        modified.extend_from_slice(&[0xEE; 5]);
+        modified.extend_from_slice(&original[block * block_size..(block + 1) * block_size]);
+    }
+
+    let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+    let size_optimal = generate_delta(&signatures, &modified[..]).unwrap();
+    let cost_model = CostModel {
+        copy_cost: 4,
+        insert_byte_cost: 1,
+        seek_cost: 64,
+    };
+    let cost_optimal = generate_delta_with_cost(&signatures, &modified, &cost_model).unwrap();
+
+    let copies = |delta: &[DeltaCommand]| {
+        delta
+            .iter()
+            .filter(|cmd| matches!(cmd, DeltaCommand::Copy { .. }))
+            .count()
+    };
+    assert_eq!(copies(&size_optimal), 4);
+    assert_eq!(copies(&cost_optimal), 1);
+    assert!(cost_model.cost(&cost_optimal) < cost_model.cost(&size_optimal));
+    assert_eq!(apply_patch(&original, &cost_optimal), modified);
+}
+
+#[test]
+fn test_delta_spans() {
+    let delta = vec![
+        DeltaCommand::Copy {
+            offset: 32,
+            length: 16,
+        },
+        DeltaCommand::Data(vec![1, 2, 3]),
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 5,
+        },
+    ];
+
+    let spans: Vec<_> = delta_spans(&delta).map(Result::unwrap).collect();
+    assert_eq!(
+        spans,
+        [
+            OpSpan {
+                op_index: 0,
+                kind: OpKind::Copy,
+                output_range: 0..16,
+                basis_range: Some(32..48),
+            },
+            OpSpan {
+                op_index: 1,
+                kind: OpKind::Data,
+                output_range: 16..19,
+                basis_range: None,
+            },
+            OpSpan {
+                op_index: 2,
+                kind: OpKind::Copy,
+                output_range: 19..24,
+                basis_range: Some(0..5),
+            },
+        ]
+    );
+
+    let original: Vec<u8> = (0..50).collect();
+    let mut modified = original.clone();
+    modified.splice(20..20, [0xAA; 9]);
+    let delta = make_delta(&original, &modified, Some(16));
+    let spans: Vec<_> = delta_spans(&delta).map(Result::unwrap).collect();
+    assert_eq!(
+        spans.last().unwrap().output_range.end,
+        modified.len() as u64
+    );
+    for span in spans.iter().filter(|span| span.kind == OpKind::Copy) {
+        let basis = span.basis_range.clone().unwrap();
+        assert_eq!(
+            modified[to_usize_range(&span.output_range)],
+            original[to_usize_range(&basis)]
+        );
+    }
+}
+
+#[test]
+fn test_splice_deltas() {
+    let block_size = 16;
+    let segment_size = 256;
+    let original: Vec<u8> = (0..=255).cycle().take(segment_size * 4).collect();
+    let mut new_segments: Vec<Vec<u8>> =
+        original.chunks(segment_size).map(<[u8]>::to_vec).collect();
+    new_segments[0].splice(10..10, [0xAA; 5]);
+    new_segments[2][100..140].fill(0xBB);
+    new_segments[3].drain(30..60);
+
+    let parts: Vec<_> = original
+        .chunks(segment_size)
+        .zip(&new_segments)
+        .enumerate()
+        .map(|(index, (old, new))| {
+            let signatures = generate_signatures_with_block_size(old, block_size).unwrap();
+            let delta = generate_delta(&signatures, &new[..]).unwrap();
+            (delta, (index * segment_size) as u64)
+        })
+        .collect();
+    let spliced = splice_deltas(&parts).unwrap();
+
+    let modified = new_segments.concat();
+    let monolithic = make_delta(&original, &modified, Some(block_size));
+    assert_eq!(apply_patch(&original, &spliced), modified);
+    assert_eq!(apply_patch(&original, &monolithic), modified);
+    assert!(spliced.len() < parts.iter().map(|(delta, _)| delta.len()).sum());
+
+    let mut misordered = parts.clone();
+    misordered.swap(1, 2);
+    assert!(splice_deltas(&misordered).is_err());
+
+    let mut overlapping = parts;
+    overlapping[1].1 = 10;
+    assert!(splice_deltas(&overlapping).is_err());
+
+    // Offsets rebased past `u64::MAX` are errors rather than wrapping around.
+    let copy = DeltaCommand::Copy {
+        offset: 10,
+        length: 1,
+    };
+    let self_copy = DeltaCommand::SelfCopy {
+        output_offset: u64::MAX,
+        length: 1,
+    };
+    for parts in [
+        vec![(vec![copy.clone()], u64::MAX - 5)],
+        vec![(vec![copy], 0), (vec![self_copy], 100)],
+    ] {
+        let err = splice_deltas(&parts).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+fn test_invert_delta() {
+    let original = random_data(64 * 1024);
+    let mut inserted = original.clone();
+    inserted.splice(5000..5000, random_data(300));
+    let mut deleted = original.clone();
+    deleted.drain(10_000..30_000);
+    let reordered = [&original[40_000..], &original[..40_000]].concat();
+    let mut duplicated = original[..20_000].to_vec();
+    duplicated.extend_from_slice(&original[..20_000]);
+    let unrelated: Vec<u8> = original.iter().rev().copied().collect();
+
+    for (modified, block_size) in [
+        (inserted, None),
+        (deleted, Some(64)),
+        (reordered, Some(1000)),
+        (duplicated, None),
+        (unrelated, None),
+        (Vec::new(), None),
+    ] {
+        let delta = make_delta(&original, &modified, block_size);
+        let inverse = invert_delta(&original, &delta).unwrap();
+        assert_eq!(apply_patch(&modified, &inverse), original);
+    }
+
+    // Overlapping copies: the longest one covering each position is used.
+    let delta = [
+        DeltaCommand::Copy {
+            offset: 100,
+            length: 50,
+        },
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 120,
+        },
+    ];
+    let modified = apply_patch(&original[..200], &delta);
+    let inverse = invert_delta(&original[..200], &delta).unwrap();
+    assert_eq!(apply_patch(&modified, &inverse), &original[..200]);
+    assert!(matches!(
+        inverse[..],
+        [
+            DeltaCommand::Copy {
+                offset: 50,
+                length: 120
+            },
+            DeltaCommand::Copy {
+                offset: 20,
+                length: 30
+            },
+            DeltaCommand::Data(ref data),
+        ] if data[..] == original[150..200]
+    ));
+
+    let err = invert_delta(&original[..100], &delta).unwrap_err();
+    let out_of_bounds = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<CopyOutOfBounds>())
+        .unwrap();
+    assert_eq!(out_of_bounds.op_index, 0);
+    assert_eq!(out_of_bounds.basis_range, 100..150);
+}
+
+#[test]
+fn test_invert_delta_overflowing_copy() {
+    let original = random_data(1000);
+    let delta = [
+        DeltaCommand::Data(vec![1, 2, 3]),
+        DeltaCommand::Copy {
+            offset: u64::MAX,
+            length: 2,
+        },
+    ];
+    let err = invert_delta(&original, &delta).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    let out_of_bounds = err
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<CopyOutOfBounds>())
+        .unwrap();
+    assert_eq!(out_of_bounds.op_index, 1);
+    assert_eq!(out_of_bounds.basis_range, u64::MAX..u64::MAX);
+}
+
+#[test]
+fn test_generate_signatures_pow2() {
+    let original: Vec<u8> = (0..=255).cycle().take(20_000).collect();
+    let mut modified = original.clone();
+    modified.splice(1000..1000, [0xAA; 33]);
+    modified.drain(9000..9500);
+
+    for shift in [4, 6, 9, 12] {
+        let signatures = generate_signatures_pow2(&original[..], shift).unwrap();
+        assert_eq!(signatures.block_size(), 1 << shift);
+
+        let delta = generate_delta(&signatures, &modified[..]).unwrap();
+        assert_eq!(apply_patch(&original, &delta), modified);
+    }
+
+    assert!(generate_signatures_pow2(&original[..], 64).is_err());
+}
+
+#[test]
+fn test_zero_block_size() {
+    let data = random_data(1000);
+    let errors = [
+        generate_signatures_with_block_size(&data[..], 0).unwrap_err(),
+        generate_signatures_with_buffer_limit(&data[..], 0, 64).unwrap_err(),
+        generate_signatures_with_crc32(&data[..], 0).unwrap_err(),
+        generate_signatures_with_final_chunk_mode(&data[..], 0, FinalChunkMode::PadZero)
+            .unwrap_err(),
+        generate_signatures_excluding_tail(&data[..], 0, 10).unwrap_err(),
+        generate_signatures_parallel(&data, 0, 2).unwrap_err(),
+        generate_signatures_with_block_size(&[][..], 0).unwrap_err(),
+    ];
+    for err in errors {
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+    }
+
+    // Signatures without blocks still send the new data whole.
+    let empty = Signatures::new(0);
+    let delta = generate_delta(&empty, &data[..]).unwrap();
+    assert_eq!(delta, [DeltaCommand::Data(data.clone())]);
+    assert_eq!(apply_patch(&[], &delta), data);
+    assert!(generate_delta(&empty, &[][..]).unwrap().is_empty());
+}
+
+#[test]
+fn test_signatures_export_import() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..50).collect();
+    let mut modified = original.clone();
+    modified.splice(20..20, [0xAA; 9]);
+
+    let cases = [
+        generate_signatures_with_block_size(&original[..], block_size).unwrap(),
+        // A single block, shorter than the block size.
+        generate_signatures_with_block_size(&original[..10], block_size).unwrap(),
+        generate_signatures_with_block_size(&[][..], block_size).unwrap(),
+        Signatures::new(0),
+        generate_signatures_with_final_chunk_mode(
+            &original[..],
+            block_size,
+            FinalChunkMode::PadZero,
+        )
+        .unwrap(),
+        generate_signatures_with_crc32(&original[..], block_size).unwrap(),
+        generate_signatures_excluding_tail(&original[..], block_size, 20).unwrap(),
+    ];
+    for signatures in cases {
+        let expected = generate_delta(&signatures, &modified[..]).unwrap();
+        for format in [ExportFormat::Csv, ExportFormat::NdJson] {
+            let mut exported = Vec::new();
+            signatures.export(&mut exported, format).unwrap();
+            let imported = Signatures::import(&exported[..], format).unwrap();
+
+            assert_eq!(imported.block_size(), signatures.block_size());
+            assert_eq!(imported.source_size(), signatures.source_size());
+            assert_eq!(imported.final_chunk_mode(), signatures.final_chunk_mode());
+            assert_eq!(imported.file_adler(), signatures.file_adler());
+            assert_eq!(imported.len(), signatures.len());
+            let mut reexported = Vec::new();
+            imported.export(&mut reexported, format).unwrap();
+            assert_eq!(reexported, exported);
+            assert_eq!(generate_delta(&imported, &modified[..]).unwrap(), expected);
+        }
+    }
+
+    // Records without the signature record in front of them are rejected.
+    let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+    let mut exported = Vec::new();
+    signatures
+        .export(&mut exported, ExportFormat::NdJson)
+        .unwrap();
+    let text = String::from_utf8(exported).unwrap();
+    let (_, blocks) = text.split_once('\n').unwrap();
+    assert!(Signatures::import(blocks.as_bytes(), ExportFormat::NdJson).is_err());
+    assert!(Signatures::import(&b""[..], ExportFormat::NdJson).is_err());
+}
+
+#[test]
+fn test_signatures_binary_roundtrip() {
+    let original = random_data(10_000);
+    let mut modified = original.clone();
+    modified.splice(3000..3000, [0xAA; 9]);
+    modified[7000] ^= 0xFF;
+
+    let cases = [
+        generate_signatures_with_block_size(&original[..], 64).unwrap(),
+        generate_signatures_with_crc32(&original[..], 100).unwrap(),
+        generate_signatures_with_final_chunk_mode(&original[..], 3000, FinalChunkMode::PadZero)
+            .unwrap(),
+        generate_signatures_excluding_tail(&original[..], 64, 500).unwrap(),
+        generate_signatures_parallel(&original, 64, 2).unwrap(),
+        Signatures::new(64),
+    ];
+    for signatures in cases {
+        let mut encoded = Vec::new();
+        signatures.write_to(&mut encoded).unwrap();
+        assert_eq!(encoded[..5], *b"LS3S\x01");
+        let decoded = Signatures::read_from(&encoded[..]).unwrap();
+
+        assert_eq!(decoded.block_size(), signatures.block_size());
+        assert_eq!(decoded.source_size(), signatures.source_size());
+        assert_eq!(decoded.final_chunk_mode(), signatures.final_chunk_mode());
+        assert_eq!(decoded.file_adler(), signatures.file_adler());
+        let (mut expected, mut actual) = (Vec::new(), Vec::new());
+        signatures.export(&mut expected, ExportFormat::Csv).unwrap();
+        decoded.export(&mut actual, ExportFormat::Csv).unwrap();
+        assert_eq!(actual, expected);
+        assert_eq!(
+            generate_delta(&decoded, &modified[..]).unwrap(),
+            generate_delta(&signatures, &modified[..]).unwrap()
+        );
+
+        for len in 0..encoded.len() {
+            let err = Signatures::read_from(&encoded[..len]).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof, "{len} bytes");
+        }
+    }
+
+    // One byte of index gap per block on top of its weak and strong hashes.
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let mut encoded = Vec::new();
+    signatures.write_to(&mut encoded).unwrap();
+    assert!(encoded.len() <= 32 + signatures.len() * (1 + 4 + 16));
+
+    let mut wrong_magic = encoded.clone();
+    wrong_magic[0] = b'X';
+    let err = Signatures::read_from(&wrong_magic[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let mut wrong_version = encoded.clone();
+    wrong_version[4] = 2;
+    assert!(Signatures::read_from(&wrong_version[..]).is_err());
+    let mut wrong_flags = encoded;
+    wrong_flags[5] |= 0x80;
+    assert!(Signatures::read_from(&wrong_flags[..]).is_err());
+}
+
+#[test]
+fn test_encoded_signature_size() {
+    let data = random_data(100_000);
+    for len in [0, 1, 63, 64, 65, 10_000, 100_000] {
+        for block_size in [1, 64, 100, 4096, 200_000] {
+            for crc32 in [false, true] {
+                let signatures = if crc32 {
+                    generate_signatures_with_crc32(&data[..len], block_size).unwrap()
+                } else {
+                    generate_signatures_with_block_size(&data[..len], block_size).unwrap()
+                };
+                let mut encoded = Vec::new();
+                signatures.write_to(&mut encoded).unwrap();
+                assert_eq!(
+                    encoded_signature_size(len as u64, block_size, crc32),
+                    encoded.len() as u64,
+                    "{len} bytes in blocks of {block_size}, crc32 {crc32}"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_signatures_binary_resign_state() {
+    let mut data = random_data(10_000);
+    let mut signatures = generate_signatures_with_block_size(&data[..], 64).unwrap();
+    data[1000] ^= 0xFF;
+    data.extend_from_slice(&[7; 100]);
+    signatures.mark_dirty(1000..1001);
+    signatures.resign_dirty(Cursor::new(&data)).unwrap();
+    signatures.mark_dirty(5000..5200);
+
+    let mut encoded = Vec::new();
+    signatures.write_to(&mut encoded).unwrap();
+    let decoded = Signatures::read_from(&encoded[..]).unwrap();
+    assert_eq!(decoded.generation(), 1);
+    assert_eq!(decoded.dirty_blocks(), signatures.dirty_blocks());
+    for block_index in 0..=signatures.len() {
+        assert_eq!(
+            decoded.block_generation(block_index),
+            signatures.block_generation(block_index)
+        );
+    }
+    let mut reencoded = Vec::new();
+    decoded.write_to(&mut reencoded).unwrap();
+    assert_eq!(reencoded, encoded);
+
+    for len in 0..encoded.len() {
+        let err = Signatures::read_from(&encoded[..len]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof, "{len} bytes");
+    }
+}
+
+#[test]
+fn test_signatures_binary_malformed_indices() {
+    let mut duplicated = Signatures::new(64);
+    for strong in [1, 2] {
+        duplicated.insert(
+            strong,
+            SignatureStrong {
+                strong: u128::from(strong),
+                block_index: 3,
+                crc32: None,
+            },
+        );
+    }
+    let err = duplicated.write_to(Vec::new()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+    // Two blocks, the first at the largest index: the second one has no index left.
+    let mut encoded = b"LS3S\x01\x00\x40\x00\x02".to_vec();
+    encoded.extend_from_slice(&[0xFF; 9]);
+    encoded.push(0x01);
+    encoded.extend_from_slice(&[0; 4 + 16]);
+    encoded.push(0x00);
+    encoded.extend_from_slice(&[0; 4 + 16]);
+    let err = Signatures::read_from(&encoded[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn test_signatures_binary_inconsistent_block_count() {
+    // Blocks of 16 bytes over 32 bytes, with the re-signing state and no block hashes.
+    let header = b"LS3S\x01\x08\x10\x20\x01";
+    let with_state = |state: &[u8]| [&header[..], state, &[0x00]].concat();
+    assert!(Signatures::read_from(&with_state(&[0x02, 0x01, 0x01, 0x01, 0x01])[..]).is_ok());
+
+    for encoded in [
+        // Zero block size.
+        b"LS3S\x01\x00\x00\x00\x00".to_vec(),
+        // One generation for two blocks.
+        with_state(&[0x01, 0x01, 0x00]),
+        // Three generations for two blocks.
+        with_state(&[0x03, 0x01, 0x01, 0x01, 0x00]),
+        // A dirty block past the last one.
+        with_state(&[0x00, 0x01, 0x02]),
+    ] {
+        let err = Signatures::read_from(&encoded[..]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData, "{encoded:x?}");
+    }
+
+    // Writes past the end of the source only mark the blocks it has.
+    let data = random_data(1000);
+    let mut signatures = generate_signatures_with_block_size(&data[..], 64).unwrap();
+    signatures.mark_dirty(900..5000);
+    assert_eq!(signatures.dirty_blocks(), [14, 15]);
+    let mut encoded = Vec::new();
+    signatures.write_to(&mut encoded).unwrap();
+    let decoded = Signatures::read_from(&encoded[..]).unwrap();
+    assert_eq!(decoded.dirty_blocks(), signatures.dirty_blocks());
+}
+
+#[test]
+fn test_signatures_import_mangled_csv() {
+    let original: Vec<u8> = (0..50).collect();
+    let signatures = generate_signatures_with_block_size(&original[..], 16).unwrap();
+    let mut exported = Vec::new();
+    signatures.export(&mut exported, ExportFormat::Csv).unwrap();
+
+    let text = String::from_utf8(exported).unwrap();
+    let mangled = format!(
+        "\u{feff}{}\r\n\r\n",
+        text.lines()
+            .map(|line| format!("{line}  "))
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    );
+    let imported = Signatures::import(mangled.as_bytes(), ExportFormat::Csv).unwrap();
+    assert_eq!(imported.len(), 4);
+    assert_eq!(imported.source_size(), 50);
+
+    let reordered = text.replacen("\n1,", "\n2,", 1);
+    assert!(Signatures::import(reordered.as_bytes(), ExportFormat::Csv).is_err());
+    let truncated = text.replacen(",16,", ",15,", 1);
+    assert!(Signatures::import(truncated.as_bytes(), ExportFormat::Csv).is_err());
+}
+
+#[test]
+fn test_encoded_delta_roundtrip() {
+    let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut modified = original.clone();
+    modified.splice(1000..1000, [0xAA; 300]);
+    modified.drain(6000..6100);
+
+    let delta = make_delta(&original, &modified, Some(64));
+    let mut encoded = Vec::new();
+    write_delta(&delta, &mut encoded).unwrap();
+    assert_eq!(read_delta(&encoded[..]).unwrap(), delta);
+
+    assert!(read_delta(&encoded[..encoded.len() - 1]).is_err());
+    assert!(read_delta(&b"nope!"[..]).is_err());
+}
+
+/// Exact bytes of the wire format. A failure here means encoded deltas already in storage
+/// would no longer read back: bump `DELTA_VERSION` instead of editing the expectations.
+#[test]
+fn test_encoded_delta_golden_bytes() {
+    use libsync3::encoding::{DELTA_VERSION, OP_COPY, OP_DATA, OP_SELF_COPY};
+
+    let encode = |delta: &[DeltaCommand]| {
+        let mut encoded = Vec::new();
+        write_delta(delta, &mut encoded).unwrap();
+        encoded
+    };
+    assert_eq!(
+        (OP_COPY, OP_DATA, OP_SELF_COPY, DELTA_VERSION),
+        (0x00, 0x01, 0x02, 2)
+    );
+    assert_eq!(encode(&[]), b"LS3D\x02");
+
+    let cases: [(DeltaCommand, &[u8]); 7] = [
+        (
+            DeltaCommand::Copy {
+                offset: 0,
+                length: 0,
+            },
+            &[0x00, 0x00, 0x00],
+        ),
+        (
+            DeltaCommand::Copy {
+                offset: 300,
+                length: 1,
+            },
+            &[0x00, 0xAC, 0x02, 0x01],
+        ),
+        (
+            DeltaCommand::Copy {
+                offset: 0x1234_5678_9ABC,
+                length: 127,
+            },
+            &[0x00, 0xBC, 0xB5, 0xE2, 0xB3, 0xC5, 0xC6, 0x04, 0x7F],
+        ),
+        (
+            DeltaCommand::Copy {
+                offset: u64::MAX - 128,
+                length: 128,
+            },
+            &[
+                0x00, 0xFF, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x80, 0x01,
+            ],
+        ),
+        (DeltaCommand::Data(Vec::new()), &[0x01, 0x00]),
+        (
+            DeltaCommand::Data(b"abc".to_vec()),
+            &[0x01, 0x03, b'a', b'b', b'c'],
+        ),
+        (
+            DeltaCommand::SelfCopy {
+                output_offset: 300,
+                length: 1,
+            },
+            &[0x02, 0xAC, 0x02, 0x01],
+        ),
+    ];
+    for (command, frame) in cases {
+        let encoded = encode(std::slice::from_ref(&command));
+        assert_eq!(&encoded[..5], b"LS3D\x02");
+        assert_eq!(&encoded[5..], frame, "{command:?}");
+        assert_eq!(read_delta(&encoded[..]).unwrap(), [command]);
+    }
+
+    // Version 1 has every frame but self-copies.
+    let mut version_1 = b"LS3D\x01\x00\xAC\x02\x01\x01\x03abc".to_vec();
+    assert_eq!(
+        read_delta(&version_1[..]).unwrap(),
+        [
+            DeltaCommand::Copy {
+                offset: 300,
+                length: 1
+            },
+            DeltaCommand::Data(b"abc".to_vec()),
+        ]
+    );
+    version_1.extend_from_slice(&[0x02, 0xAC, 0x02, 0x01]);
+    assert_eq!(
+        read_delta(&version_1[..]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+
+    let mut reserved = encode(&[]);
+    reserved.push(0x03);
+    assert_eq!(
+        read_delta(&reserved[..]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+/// Exact bytes of checked data frames, pinned like those of `test_encoded_delta_golden_bytes`.
+#[test]
+fn test_checked_delta_golden_bytes() {
+    use libsync3::encoding::{CHECKED_DELTA_VERSION, OP_CHECKED_DATA};
+    use libsync3::write_delta_with_checksums;
+
+    assert_eq!((OP_CHECKED_DATA, CHECKED_DELTA_VERSION), (0x03, 3));
+    let mut checked = Vec::new();
+    write_delta_with_checksums(
+        [
+            DeltaCommand::Copy {
+                offset: 300,
+                length: 1,
+            },
+            DeltaCommand::Data(b"abc".to_vec()),
+        ],
+        &mut checked,
+    )
+    .unwrap();
+    assert_eq!(
+        checked,
+        b"LS3D\x03\x00\xAC\x02\x01\x03\x03abc\xB7\x3F\x4B\x36"
+    );
+    checked.push(0x04);
+    assert_eq!(
+        read_delta(&checked[..]).unwrap_err().kind(),
+        std::io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn test_estimate_delta_size() {
+    let original: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+    let mut modified = original.clone();
+    modified.splice(1000..1000, [0xAA; 3000]);
+    for i in (20_000..80_000).step_by(997) {
+        modified[i] = modified[i].wrapping_add(1);
+    }
+
+    // Unmatched data longer than a single `Data` command, and a matched short tail.
+    let mut unmatched = random_data(DEFAULT_MAX_DATA_LEN * 2 + 100);
+    unmatched.extend_from_slice(&original[original.len() - 100..]);
+
+    let signatures = generate_signatures_with_block_size(&original[..], 512).unwrap();
+    let empty = generate_signatures(&[][..]).unwrap();
+    for (signatures, new) in [
+        (&signatures, &modified),
+        (&signatures, &unmatched),
+        (&empty, &modified),
+    ] {
+        let estimate = estimate_delta_size(signatures, &new[..]).unwrap();
+        let delta = generate_delta(signatures, &new[..]).unwrap();
+        let mut encoded = Vec::new();
+        write_delta(&delta, &mut encoded).unwrap();
+        assert_eq!(estimate, encoded.len());
+    }
+}
+
+#[test]
+fn test_delta_or_full() {
+    let original = random_data(100_000);
+    let signatures = generate_signatures_with_block_size(&original[..], 512).unwrap();
+
+    let mut similar = original.clone();
+    similar.splice(1000..1000, [0xAA; 3000]);
+    similar[50_000] ^= 0xFF;
+    assert_eq!(
+        delta_or_full(&signatures, &similar[..]).unwrap(),
+        DeltaOutcome::Delta(generate_delta(&signatures, &similar[..]).unwrap())
+    );
+
+    let disjoint: Vec<u8> = random_data(200_000)[100_000..].to_vec();
+    assert!(estimate_delta_size(&signatures, &disjoint[..]).unwrap() > disjoint.len());
+    assert_eq!(
+        delta_or_full(&signatures, &disjoint[..]).unwrap(),
+        DeltaOutcome::SendFull
+    );
+    assert_eq!(
+        delta_or_full(&signatures, &[][..]).unwrap(),
+        DeltaOutcome::SendFull
+    );
+}
+
+#[test]
+fn test_encoded_delta_size_upper_bound() {
+    let mut state: u32 = 0x1234_5678;
+    let mut random = |len: usize| -> Vec<u8> {
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state.to_le_bytes()[0]
+            })
+            .collect()
+    };
+    let original = random(50_000);
+    let unrelated = random(50_000);
+    let mut edited = original.clone();
+    for i in (0..edited.len()).step_by(101) {
+        edited[i] ^= 0xFF;
+    }
+    let mut shifted = original.clone();
+    shifted.splice(17..17, [0xAA; 5]);
+
+    for block_size in [1, 7, 64, 4096] {
+        let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+        for new in [&original, &unrelated, &edited, &shifted, &Vec::new()] {
+            let delta = generate_delta(&signatures, &new[..]).unwrap();
+            let mut encoded = Vec::new();
+            write_delta(&delta, &mut encoded).unwrap();
+            assert_eq!(encoded_delta_size(&delta), encoded.len());
+
+            let bound = encoded_delta_size_upper_bound(new.len() as u64, block_size);
+            assert!(encoded.len() as u64 <= bound, "{block_size} {}", new.len());
+        }
+    }
+}
+
+#[test]
+fn test_generate_signatures_with_buffer_limit() {
+    let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let buffered = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+
+    for buffer_limit in [1, 100, 1000, 1023] {
+        let streamed =
+            generate_signatures_with_buffer_limit(&original[..], 1024, buffer_limit).unwrap();
+        assert_eq!(streamed.source_size(), buffered.source_size());
+
+        let mut expected = Vec::new();
+        buffered.export(&mut expected, ExportFormat::Csv).unwrap();
+        let mut actual = Vec::new();
+        streamed.export(&mut actual, ExportFormat::Csv).unwrap();
+        assert_eq!(actual, expected);
+    }
+}
+
+#[test]
+fn test_generate_delta_huge_block_size() {
+    let signatures = Signatures::new(usize::MAX);
+    let err = generate_delta(&signatures, &b"data"[..]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::OutOfMemory);
+}
+
+#[test]
+fn test_postmatch_delta() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+    let mut modified = vec![0xAA; 5];
+    modified.extend_from_slice(&original[32..96]);
+    modified.extend_from_slice(&[0xBB; 3]);
+
+    let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+    let delta = postmatch_delta(&signatures, vec![DeltaCommand::Data(modified.clone())]).unwrap();
+
+    assert_eq!(
+        delta,
+        [
+            DeltaCommand::Data(vec![0xAA; 5]),
+            DeltaCommand::Copy {
+                offset: 32,
+                length: 64
+            },
+            DeltaCommand::Data(vec![0xBB; 3]),
+        ]
+    );
+    assert_eq!(apply_patch(&original, &delta), modified);
+}
+
+#[test]
+fn test_signatures_split_merge() {
+    let original: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let signatures = generate_signatures_with_block_size(&original[..], 16).unwrap();
+    let export = |signatures: &Signatures| {
+        let mut exported = Vec::new();
+        signatures.export(&mut exported, ExportFormat::Csv).unwrap();
+        exported
+    };
+
+    for shards in [1, 3, 7, 100] {
+        let parts = signatures.split(shards);
+        assert_eq!(parts.len(), shards);
+        assert_eq!(
+            parts.iter().map(Signatures::len).sum::<usize>(),
+            signatures.len()
+        );
+
+        let merged = Signatures::merge(&parts).unwrap();
+        assert_eq!(export(&merged), export(&signatures));
+    }
+
+    let mut parts = signatures.split(4);
+    parts.remove(1);
+    assert!(Signatures::merge(&parts).is_err());
+
+    let other = generate_signatures_with_block_size(&original[..], 32).unwrap();
+    assert!(Signatures::merge(&[signatures.split(2)[0].clone(), other]).is_err());
+    assert!(Signatures::merge(&[]).is_err());
+}
+
+#[test]
+fn test_analysis_report() {
+    use libsync3::analysis::analyze;
+
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+
+    let delta = make_delta(&original, &original, Some(block_size));
+    let report = analyze(&original, &original, &delta, block_size);
+    assert_eq!(report.reusable_bytes, 256);
+    assert_eq!(report.matched_bytes, 256);
+    assert_eq!(report.missed_bytes(), 0);
+
+    let mut modified = original[5..35].to_vec();
+    modified.extend_from_slice(&[0xAA; 4]);
+    modified.extend_from_slice(&original[100..110]);
+    let delta = make_delta(&original, &modified, Some(block_size));
+    let report = analyze(&original, &modified, &delta, block_size);
+    assert_eq!(report.new_len, 44);
+    assert_eq!(report.reusable_bytes, 40);
+    assert_eq!(report.matched_bytes, 16);
+    assert_eq!(report.literal_bytes, 28);
+    assert_eq!(report.missed_below_block_size, 10);
+    assert_eq!(report.missed_misalignment, 14);
+    assert_eq!(report.missed_bytes(), 24);
+}
+
+#[test]
+fn test_delta_efficiency() {
+    use libsync3::analysis::{analyze, delta_efficiency};
+
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+
+    let delta = make_delta(&original, &original, Some(block_size));
+    assert!(close(delta_efficiency(&delta, 256), 1.0));
+    assert!(close(
+        analyze(&original, &original, &delta, block_size).efficiency(),
+        1.0
+    ));
+
+    // Nothing to reuse: nothing copied, which is also the best any matcher can do.
+    let disjoint = vec![0xAA; 300];
+    let delta = make_delta(&original, &disjoint, Some(block_size));
+    assert!(close(delta_efficiency(&delta, 300), 0.0));
+    assert!(close(
+        analyze(&original, &disjoint, &delta, block_size).efficiency(),
+        1.0
+    ));
+
+    let mut modified = original[5..35].to_vec();
+    modified.extend_from_slice(&[0xAA; 4]);
+    modified.extend_from_slice(&original[100..110]);
+    let delta = make_delta(&original, &modified, Some(block_size));
+    assert!(close(delta_efficiency(&delta, 44), 16.0 / 44.0));
+    assert!(close(
+        analyze(&original, &modified, &delta, block_size).efficiency(),
+        16.0 / 40.0
+    ));
+
+    assert!(close(delta_efficiency(&[], 0), 1.0));
+}
+
+#[test]
+fn test_generate_delta_with_alignment() {
+    let block_size = 16;
+    let original: Vec<u8> = (0..=255).collect();
+    let mut modified = b"HEADER!".to_vec();
+    modified.extend_from_slice(&original);
+
+    let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+    let delta = generate_delta_with_alignment(&signatures, &modified[..], 7).unwrap();
+    assert_eq!(
+        delta,
+        [
+            DeltaCommand::Data(b"HEADER!".to_vec()),
+            DeltaCommand::Copy {
+                offset: 0,
+                length: 256
+            },
+        ]
+    );
+    assert_eq!(apply_patch(&original, &delta), modified);
+
+    let mut edited = modified.clone();
+    edited[100] ^= 0xFF;
+    let delta = generate_delta_with_alignment(&signatures, &edited[..], 7).unwrap();
+    assert_eq!(delta.len(), 4);
+    assert_eq!(apply_patch(&original, &delta), edited);
+}
+
+/// Minimal VCDIFF decoder for the subset produced by `write_vcdiff`.
+fn decode_vcdiff(base: &[u8], mut stream: &[u8]) -> Vec<u8> {
+    fn varint(input: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        loop {
+            let byte = input[0];
+            *input = &input[1..];
+            value = (value << 7) | u64::from(byte & 0x7F);
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+    fn take<'a>(input: &mut &'a [u8], len: u64) -> &'a [u8] {
+        let (head, tail) = input.split_at(usize::try_from(len).unwrap());
+        *input = tail;
+        head
+    }
+
+    assert_eq!(take(&mut stream, 5), [0xD6, 0xC3, 0xC4, 0x00, 0x00]);
+    let mut target = Vec::new();
+    while !stream.is_empty() {
+        let win_indicator = take(&mut stream, 1)[0];
+        let (segment_len, segment_pos) = if win_indicator & 0x01 == 0 {
+            (0, 0)
+        } else {
+            (varint(&mut stream), varint(&mut stream))
+        };
+        let segment = &base[to_usize_range(&(segment_pos..segment_pos + segment_len))];
+        let _encoding_len = varint(&mut stream);
+        let window_len = varint(&mut stream);
+        assert_eq!(take(&mut stream, 1)[0], 0);
+        let (data_len, inst_len, addr_len) = (
+            varint(&mut stream),
+            varint(&mut stream),
+            varint(&mut stream),
+        );
+        let mut data = take(&mut stream, data_len);
+        let mut inst = take(&mut stream, inst_len);
+        let mut addr = take(&mut stream, addr_len);
+
+        let window_start = target.len();
+        while !inst.is_empty() {
+            let code = take(&mut inst, 1)[0];
+            let size = varint(&mut inst);
+            match code {
+                1 => target.extend_from_slice(take(&mut data, size)),
+                19 => {
+                    let at = varint(&mut addr);
+                    target.extend_from_slice(&segment[to_usize_range(&(at..at + size))]);
+                }
+                _ => panic!("unexpected instruction {code}"),
+            }
+        }
+        assert_eq!((target.len() - window_start) as u64, window_len);
+        assert!(data.is_empty() && addr.is_empty());
+    }
+    target
+}
+
+#[test]
+fn test_write_vcdiff() {
+    let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut modified = original.clone();
+    modified.splice(1000..1000, [0xAA; 300]);
+    modified.drain(6000..6100);
+    modified.extend_from_slice(&original[..500]);
+
+    let delta = make_delta(&original, &modified, Some(64));
+    let mut encoded = Vec::new();
+    write_vcdiff(&delta, &mut encoded).unwrap();
+    assert_eq!(decode_vcdiff(&original, &encoded), modified);
+
+    let mut encoded = Vec::new();
+    write_vcdiff(&[DeltaCommand::Data(b"only new".to_vec())], &mut encoded).unwrap();
+    assert_eq!(decode_vcdiff(&[], &encoded), b"only new");
+
+    let mut encoded = Vec::new();
+    write_vcdiff(Vec::<DeltaCommand>::new(), &mut encoded).unwrap();
+    assert!(decode_vcdiff(&[], &encoded).is_empty());
+}
+
+#[test]
+fn test_write_vcdiff_multiple_windows() {
+    let original: Vec<u8> = (0..=255).cycle().take(VCDIFF_WINDOW_SIZE + 1000).collect();
+    let delta = [
+        DeltaCommand::Data(vec![1; 10]),
+        DeltaCommand::Copy {
+            offset: 0,
+            length: original.len(),
+        },
+        DeltaCommand::Data(vec![2; VCDIFF_WINDOW_SIZE]),
+    ];
+    let mut expected = vec![1; 10];
+    expected.extend_from_slice(&original);
+    expected.resize(expected.len() + VCDIFF_WINDOW_SIZE, 2);
+
+    let mut encoded = Vec::new();
+    write_vcdiff(&delta, &mut encoded).unwrap();
+    assert_eq!(decode_vcdiff(&original, &encoded), expected);
+}
+
+#[test]
+fn test_negotiate() {
+    let ours = Capabilities {
+        block_sizes: vec![1024, 4096, 8192],
+        delta_versions: vec![1],
+        crc32: true,
+    };
+
+    let overlapping = Capabilities {
+        block_sizes: vec![4096, 8192, 16384],
+        delta_versions: vec![1, 2],
+        crc32: false,
+    };
+    let agreed = negotiate(&ours, &overlapping).unwrap();
+    assert_eq!(
+        agreed,
+        AgreedParams {
+            block_size: 8192,
+            delta_version: 1,
+            crc32: false,
+        }
+    );
+    assert_eq!(negotiate(&overlapping, &ours).unwrap(), agreed);
+
+    let subset = Capabilities {
+        block_sizes: vec![4096],
+        ..ours.clone()
+    };
+    assert_eq!(negotiate(&ours, &subset).unwrap().block_size, 4096);
+    assert!(negotiate(&ours, &subset).unwrap().crc32);
+
+    let disjoint = Capabilities {
+        block_sizes: vec![512, 2048],
+        ..ours.clone()
+    };
+    let err = negotiate(&ours, &disjoint).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+    assert_eq!(
+        err.to_string(),
+        "no common block size: ours are [1024, 4096, 8192], theirs are [512, 2048]"
+    );
+
+    let newer = Capabilities {
+        delta_versions: vec![2],
+        ..ours.clone()
+    };
+    assert!(negotiate(&ours, &newer).is_err());
+
+    let defaults = negotiate(&Capabilities::default(), &Capabilities::default()).unwrap();
+    assert_eq!(defaults.block_size, 65536);
+}
+
+#[test]
+fn test_delta_builder_matches_generate_delta() {
+    let original: Vec<u8> = (0..50_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 300]);
+    modified[20_000..20_100].fill(0xBB);
+    modified.drain(30_000..30_500);
+    modified.extend_from_slice(&original[..1000]);
+
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let expected = generate_delta(&signatures, &modified[..]).unwrap();
+
+    for piece in [1, 7, 64, 100, 4096, modified.len()] {
+        let mut builder = DeltaBuilder::new(&signatures).unwrap();
+        let mut delta = Vec::new();
+        for chunk in modified.chunks(piece) {
+            delta.extend(builder.push(chunk));
+        }
+        delta.extend(builder.finish());
+        assert_eq!(delta, expected, "pieces of {piece} bytes");
+    }
+
+    let builder = DeltaBuilder::new(&signatures).unwrap();
+    assert!(builder.finish().is_empty());
+}
+
+#[test]
+fn test_delta_builder_emits_before_finish() {
+    let original: Vec<u8> = (0..10_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+    let mut modified = vec![0xAA; 10];
+    modified.extend_from_slice(&original);
+
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let mut builder = DeltaBuilder::new(&signatures).unwrap();
+    let early = builder.push(&modified[..5000]);
+    assert_eq!(early, [DeltaCommand::Data(vec![0xAA; 10])]);
+    let mut delta = early;
+    delta.extend(builder.push(&modified[5000..]));
+    delta.extend(builder.finish());
+    assert_eq!(apply_patch(&original, &delta), modified);
+}
+
+#[test]
+fn test_delta_builder_caps_unmatched_data() {
+    let original = random_data(100_000);
+    // Nothing in common with the base, then a stretch of it.
+    let mut modified: Vec<u8> = random_data(10 * 1024 * 1024 + 100_000)[100_000..].to_vec();
+    modified.extend_from_slice(&original[..48 * 1024]);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+
+    let max_data_len = 1024 * 1024;
+    let mut delta = Vec::new();
+    DeltaBuilder::new(&signatures)
+        .unwrap()
+        .with_max_data_len(max_data_len)
+        .generate(&modified[..], |command| {
+            delta.push(command);
+            Ok(())
+        })
+        .unwrap();
+    let data_lens: Vec<usize> = delta
+        .iter()
+        .filter_map(|command| match command {
+            DeltaCommand::Data(data) => Some(data.len()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(data_lens, [max_data_len; 10]);
+    assert_eq!(
+        delta.last(),
+        Some(&DeltaCommand::Copy {
+            offset: 0,
+            length: 48 * 1024,
+        })
+    );
+
+    let mut reconstructed = vec![0u8; modified.len()];
+    assert_eq!(
+        apply_to_slice(Cursor::new(&original), &delta, &mut reconstructed).unwrap(),
+        modified.len()
+    );
+    assert_eq!(reconstructed, modified);
+}
+
+#[test]
+fn test_generate_delta_caps_unmatched_data_by_default() {
+    /// Reader counting the bytes read from it.
+    struct Counting<'a> {
+        inner: &'a [u8],
+        read: &'a std::cell::Cell<usize>,
+    }
+
+    impl Read for Counting<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.read.set(self.read.get() + n);
+            Ok(n)
+        }
+    }
+
+    let original = random_data(100_000);
+    let modified = random_data(5 * 1024 * 1024 + 100_000)[100_000..].to_vec();
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+
+    // Bytes read but not passed on yet never exceed the cap and the matching window.
+    let read = std::cell::Cell::new(0);
+    let reader = Counting {
+        inner: &modified,
+        read: &read,
+    };
+    let mut emitted = 0;
+    let mut max_held = 0;
+    generate_delta_with_cb(&signatures, reader, |command| {
+        let DeltaCommand::Data(data) = &command else {
+            panic!("{command}");
+        };
+        assert!(data.len() <= DEFAULT_MAX_DATA_LEN);
+        max_held = max_held.max(read.get() - emitted);
+        emitted += data.len();
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(emitted, modified.len());
+    assert!(max_held <= DEFAULT_MAX_DATA_LEN + 2 * 1024, "{max_held}");
+
+    assert_eq!(
+        generate_delta_parallel(&signatures, &modified, 2).unwrap(),
+        generate_delta(&signatures, &modified[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_generate_signatures_excluding_tail() {
+    let original = random_data(10_000);
+
+    let all = generate_signatures_excluding_tail(&original[..], 64, 0).unwrap();
+    assert_eq!(all.len(), original.len().div_ceil(64));
+
+    // 10_000 bytes end with a 16-byte partial block; the tail covers it and the block before.
+    let signatures = generate_signatures_excluding_tail(&original[..], 64, 50).unwrap();
+    assert_eq!(signatures.len(), 9984 / 64 - 1);
+    assert_eq!(signatures.source_size(), original.len() as u64);
+
+    let delta = generate_delta(&signatures, &original[..]).unwrap();
+    assert_eq!(apply_patch(&original, &delta), original);
+    assert_eq!(
+        delta.last(),
+        Some(&DeltaCommand::Data(original[9920..].to_vec()))
+    );
+    let copied_end = delta_spans(&delta)
+        .map(Result::unwrap)
+        .filter(|span| span.kind == OpKind::Copy)
+        .map(|span| span.basis_range.unwrap().end)
+        .max();
+    assert_eq!(copied_end, Some(9920));
+
+    let none = generate_signatures_excluding_tail(&original[..], 64, 20_000).unwrap();
+    assert!(none.is_empty());
+}
+
+#[test]
+fn test_chunk_size_profile() {
+    let profile = ChunkSizeProfile::default();
+    let mut previous = 0;
+    for shift in 0..50 {
+        for file_size in [(1u64 << shift) - 1, 1 << shift, (1 << shift) + 12_345] {
+            let block_size = profile.suggest(file_size);
+            assert!(block_size >= previous, "{file_size}");
+            assert!((512..=64 * 1024).contains(&block_size));
+            assert!(block_size.is_power_of_two());
+            previous = block_size;
+        }
+    }
+    assert_eq!(suggest_block_size(0), 512);
+    assert_eq!(suggest_block_size(u64::MAX), 64 * 1024);
+
+    let fine = ChunkSizeProfile {
+        target_signature_bytes: 4096,
+        min_block_size: 16,
+        max_block_size: 1 << 20,
+        bytes_per_block: 20,
+    };
+    for file_size in [100_000u64, 1_000_000, 3_000_000] {
+        let block_size = fine.suggest(file_size);
+        let signature_bytes = file_size.div_ceil(block_size as u64) * fine.bytes_per_block;
+        assert!(
+            signature_bytes <= fine.target_signature_bytes,
+            "{file_size}"
+        );
+        assert!(
+            signature_bytes > fine.target_signature_bytes / 2,
+            "{file_size}"
+        );
+    }
+}
+
+#[test]
+fn test_generate_signatures_auto() {
+    let profile = ChunkSizeProfile {
+        target_signature_bytes: 1000,
+        min_block_size: 16,
+        max_block_size: 4096,
+        bytes_per_block: 20,
+    };
+    let original = random_data(20_000);
+
+    let mut reader = Cursor::new(&original);
+    reader.seek(SeekFrom::Start(4000)).unwrap();
+    let signatures = generate_signatures_auto(reader, &profile).unwrap();
+    assert_eq!(signatures.block_size(), profile.suggest(16_000));
+    assert_eq!(signatures.source_size(), 16_000);
+
+    let path = std::env::temp_dir().join(format!("libsync3-auto-{}", std::process::id()));
+    std::fs::write(&path, &original).unwrap();
+    let signatures = generate_signatures_for_path(&path, &profile).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(signatures.block_size(), 512);
+    assert!(signatures.len() as u64 * profile.bytes_per_block <= profile.target_signature_bytes);
+
+    let mut modified = original.clone();
+    modified.splice(5000..5000, [0xAA; 10]);
+    let delta = generate_delta(&signatures, &modified[..]).unwrap();
+    assert_eq!(apply_patch(&original, &delta), modified);
+}
+
+#[test]
+fn test_delta_content_hash() {
+    let split = vec![
+        DeltaCommand::Data(b"he".to_vec()),
+        DeltaCommand::Data(b"llo".to_vec()),
+        DeltaCommand::Copy {
+            offset: 10,
+            length: 5,
+        },
+        DeltaCommand::Data(Vec::new()),
+        DeltaCommand::Copy {
+            offset: 15,
+            length: 7,
+        },
+        DeltaCommand::Copy {
+            offset: 0,
+            length: 3,
+        },
+    ];
+    let optimized = optimize_delta(&split);
+    assert_eq!(
+        optimized,
+        [
+            DeltaCommand::Data(b"hello".to_vec()),
+            DeltaCommand::Copy {
+                offset: 10,
+                length: 12,
+            },
+            DeltaCommand::Copy {
+                offset: 0,
+                length: 3,
+            },
+        ]
+    );
+    assert_eq!(delta_content_hash(&split), delta_content_hash(&optimized));
+
+    let mut encoded = Vec::new();
+    write_delta(&optimized, &mut encoded).unwrap();
+    assert_eq!(delta_content_hash(&optimized), xxh3_128(&encoded));
+
+    let mut other = optimized.clone();
+    other[0] = DeltaCommand::Data(b"hellO".to_vec());
+    assert_ne!(delta_content_hash(&other), delta_content_hash(&optimized));
+    assert_ne!(
+        delta_content_hash(Vec::<DeltaCommand>::new()),
+        delta_content_hash(&optimized)
+    );
+}
+
+#[test]
+fn test_generate_delta_hierarchical() {
+    let original = random_data(64 * 1024);
+    let mut modified = original.clone();
+    // Scattered edits in the first half, an untouched second half.
+    for i in (0..32 * 1024).step_by(300) {
+        modified[i] ^= 0xFF;
+    }
+    modified.splice(40_000..40_000, [0xAA; 7]);
+    modified.truncate(modified.len() - 100);
+    modified.extend_from_slice(&original[original.len() - 10..]);
+
+    let coarse = generate_signatures_with_block_size(&original[..], 4096).unwrap();
+    let medium = generate_signatures_with_block_size(&original[..], 512).unwrap();
+    let fine = generate_signatures_with_block_size(&original[..], 64).unwrap();
+
+    let literal_bytes = |delta: &[DeltaCommand]| -> usize {
+        delta
+            .iter()
+            .filter(|command| matches!(command, DeltaCommand::Data(_)))
+            .map(DeltaCommand::output_len)
+            .sum()
+    };
+
+    let delta = generate_delta_hierarchical(&[&fine, &coarse, &medium], &modified).unwrap();
+    assert_eq!(apply_patch(&original, &delta), modified);
+    let hierarchical = literal_bytes(&delta);
+    for single in [&coarse, &medium, &fine] {
+        let single_delta = generate_delta(single, &modified[..]).unwrap();
+        assert!(hierarchical <= literal_bytes(&single_delta));
+    }
+    assert!(hierarchical < literal_bytes(&generate_delta(&medium, &modified[..]).unwrap()));
+    assert!(delta.len() <= generate_delta(&fine, &modified[..]).unwrap().len());
+
+    let short = generate_signatures_with_block_size(&original[..1000], 64).unwrap();
+    assert!(generate_delta_hierarchical(&[&fine, &short], &modified).is_err());
+    assert!(generate_delta_hierarchical(&[], &modified).is_err());
+    assert_eq!(
+        generate_delta_hierarchical(&[&fine], &original[..10]).unwrap(),
+        [DeltaCommand::Data(original[..10].to_vec())]
+    );
+    assert!(
+        generate_delta_hierarchical(&[&fine], &[])
+            .unwrap()
+            .is_empty()
+    );
+}
+
+#[test]
+fn test_text_signatures_do_not_split_characters() {
+    let text = "héllo wörld — ünïcödé テキスト 🦀 ".repeat(200);
+    let bytes = text.as_bytes();
+
+    for target in [1, 2, 3, 4, 5, 7, 16, 64] {
+        let signatures = generate_text_signatures(bytes, target).unwrap();
+        assert_eq!(signatures.source_size(), bytes.len() as u64);
+        let mut expected_offset = 0;
+        for &(offset, length) in signatures.blocks() {
+            assert_eq!(offset, expected_offset);
+            assert!(length > 0 && length <= target + 3);
+            expected_offset += length as u64;
+            let end = usize::try_from(expected_offset).unwrap();
+            assert!(text.is_char_boundary(end), "target {target} cuts at {end}");
+        }
+        assert_eq!(expected_offset, bytes.len() as u64);
+    }
+
+    let signatures = generate_text_signatures(bytes, 64).unwrap();
+    let modified = text
+        .replacen("wörld", "wéreld", 3)
+        .replacen("🦀", "🦀🦀", 2);
+    let delta = generate_text_delta(&signatures, modified.as_bytes());
+    assert_eq!(apply_patch(bytes, &delta), modified.as_bytes());
+    let copied: usize = delta
+        .iter()
+        .filter(|command| matches!(command, DeltaCommand::Copy { .. }))
+        .map(DeltaCommand::output_len)
+        .sum();
+    assert!(copied > bytes.len() / 2);
+}
+
+#[test]
+fn test_text_signatures_invalid_utf8() {
+    // Continuation bytes only: no boundary to move back to, blocks keep their target size.
+    let data = [0x80u8; 100];
+    let signatures = generate_text_signatures(&data[..], 16).unwrap();
+    assert!(
+        signatures.blocks()[..6]
+            .iter()
+            .all(|&(_, length)| length == 16)
+    );
+    assert_eq!(signatures.len(), 7);
+
+    let delta = generate_text_delta(&signatures, &data);
+    assert_eq!(apply_patch(&data, &delta), data);
+    assert!(generate_text_signatures(&data[..], 0).is_err());
+    assert!(generate_text_signatures(&[][..], 16).unwrap().is_empty());
+}
+
+/// A byte prepended to data with no repeating pattern, so every block is only found at its
+/// one unaligned offset.
+#[test]
+fn test_prepended_byte_unaligned_matches() {
+    let base = random_data(100_003);
+    let mut new = vec![0xFF];
+    new.extend_from_slice(&base);
+    let expected = [
+        DeltaCommand::Data(vec![0xFF]),
+        DeltaCommand::Copy {
+            offset: 0,
+            length: base.len(),
+        },
+    ];
+
+    let rolling = RollingEngine { block_size: 1024 };
+    let delta = rolling
+        .delta(&rolling.signature(&base[..]).unwrap(), &new[..])
+        .unwrap();
+    assert_eq!(delta, expected);
+
+    let hierarchical = HierarchicalEngine {
+        block_sizes: vec![8192, 512],
+    };
+    let delta = hierarchical
+        .delta(&hierarchical.signature(&base[..]).unwrap(), &new[..])
+        .unwrap();
+    assert_eq!(delta, expected);
+}
+
+#[test]
+fn test_prepended_byte_multi_megabyte() {
+    let base = random_data(4 * 1024 * 1024 + 17);
+    for (block_size, prepended) in [(Some(512), 0x00), (Some(8192), 0x5A), (None, 0xFF)] {
+        let mut new = vec![prepended];
+        new.extend_from_slice(&base);
+        let delta = assert_roundtrip(&base, &new, block_size);
+        assert_eq!(
+            delta,
+            [
+                DeltaCommand::Data(vec![prepended]),
+                DeltaCommand::Copy {
+                    offset: 0,
+                    length: base.len(),
+                },
+            ],
+            "block size {block_size:?}"
+        );
+    }
+}
+
+/// Same scenarios through any engine, using nothing but the trait.
+fn check_engine<E: SyncEngine>(engine: &E) {
+    let base = random_data(20_000);
+    let mut edited = base.clone();
+    edited.splice(5000..5000, *b"inserted text");
+    edited.drain(12_000..12_500);
+    edited[17_000..17_100].fill(0);
+    let mut reordered = base[10_000..].to_vec();
+    reordered.extend_from_slice(&base[..10_000]);
+
+    let scenarios: [(&[u8], &[u8]); 6] = [
+        (&base, &base),
+        (&base, &edited),
+        (&base, &reordered),
+        (&base, &[]),
+        (&[], &base),
+        (b"short", b"also short"),
+    ];
+    for (old, new) in scenarios {
+        let signature = engine.signature(old).unwrap();
+        let delta = engine.delta(&signature, new).unwrap();
+        let mut reconstructed = Vec::new();
+        engine
+            .apply(Cursor::new(old), &delta, &mut reconstructed)
+            .unwrap();
+        assert_eq!(reconstructed, new);
+    }
+
+    let delta = engine
+        .delta(&engine.signature(&base[..]).unwrap(), &base[..])
+        .unwrap();
+    assert!(
+        delta
+            .iter()
+            .all(|command| matches!(command, DeltaCommand::Copy { .. }))
+    );
+}
+
+#[test]
+fn test_sync_engines() {
+    check_engine(&RollingEngine::default());
+    check_engine(&RollingEngine { block_size: 64 });
+    check_engine(&HierarchicalEngine::default());
+    check_engine(&HierarchicalEngine {
+        block_sizes: vec![1024, 64],
+    });
+    check_engine(&TextEngine::default());
+    check_engine(&TextEngine {
+        target_block_size: 64,
+    });
+}
+
+#[test]
+fn test_dyn_engines() {
+    let base = random_data(20_000);
+    let mut edited = base.clone();
+    edited.splice(5000..5000, *b"inserted text");
+    edited.drain(12_000..12_500);
+
+    let engines: Vec<(Box<dyn DynEngine>, usize)> = vec![
+        (Box::new(RollingEngine { block_size: 64 }), 1),
+        (
+            Box::new(HierarchicalEngine {
+                block_sizes: vec![1024, 64],
+            }),
+            2,
+        ),
+    ];
+    for (engine, levels) in engines {
+        for (old, new) in [(&base, &edited), (&base, &base), (&edited, &base)] {
+            let signature = engine.encoded_signature(&mut &old[..]).unwrap();
+            let mut encoded = &signature[..];
+            for _ in 0..levels {
+                Signatures::read_from(&mut encoded).unwrap();
+            }
+            assert!(encoded.is_empty());
+
+            let delta = engine.encoded_delta(&signature, &mut &new[..]).unwrap();
+            assert_eq!(delta[..4], *b"LS3D");
+            let mut reconstructed = Vec::new();
+            engine
+                .apply_encoded(old, &delta, &mut reconstructed)
+                .unwrap();
+            assert_eq!(reconstructed, *new);
+        }
+        let err = engine
+            .encoded_delta(b"not a signature", &mut &base[..])
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_cdc_boundary_stability() {
+    use libsync3::cdc::{CdcParams, boundary_stability, chunk_boundaries};
+
+    let params = CdcParams {
+        min_size: 256,
+        mask: (1 << 10) - 1,
+        max_size: 8192,
+    };
+    let old = random_data(1 << 20);
+    let boundaries = chunk_boundaries(&old[..], params).unwrap();
+    assert_eq!(*boundaries.last().unwrap(), old.len() as u64);
+    assert!(boundaries.windows(2).all(|pair| {
+        let len = pair[1] - pair[0];
+        (256..=8192).contains(&len)
+    }));
+
+    let report = boundary_stability(&old[..], &old[..], params).unwrap();
+    assert_eq!(report.surviving_boundaries, report.old_chunks);
+    assert_eq!(report.surviving_bytes, old.len() as u64);
+    assert_eq!(report.new_chunks, boundaries.len() as u64);
+    assert_eq!(report.size_histogram.iter().sum::<u64>(), report.new_chunks);
+    assert_eq!(
+        report.average_chunk_size(),
+        old.len() as u64 / report.new_chunks
+    );
+
+    // An insertion in the middle of a long chunk, far from both its boundaries, only changes
+    // that chunk: every boundary after it is found again, shifted.
+    let (start, end) = boundaries
+        .windows(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(start, end)| start > 100_000 && end - start > 1024)
+        .unwrap();
+    let at = usize::try_from(start + (end - start) / 2).unwrap();
+    let mut new = old.clone();
+    new.splice(at..at, *b"inserted");
+    let report = boundary_stability(&old[..], &new[..], params).unwrap();
+    assert_eq!(report.new_chunks, report.old_chunks);
+    assert_eq!(report.surviving_boundaries, report.old_chunks - 1);
+    assert_eq!(report.surviving_bytes, old.len() as u64 - (end - start));
+
+    // Unrelated data shares nothing.
+    let unrelated: Vec<u8> = old.iter().map(|byte| byte ^ 0x55).collect();
+    let report = boundary_stability(&old[..], &unrelated[..], params).unwrap();
+    assert_eq!(report.surviving_boundaries, 0);
+
+    let invalid = CdcParams {
+        min_size: 10,
+        mask: 0,
+        max_size: 5,
+    };
+    assert!(boundary_stability(&old[..], &old[..], invalid).is_err());
+}
+
+#[test]
+fn test_signatures_quick_differs() {
+    let original = random_data(10_000);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    assert_eq!(
+        signatures.file_adler(),
+        Some(RollingChecksum::compute(&original))
+    );
+    let same = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    assert!(!signatures.quick_differs(&same));
+
+    let mut modified = original.clone();
+    modified[5000] ^= 1;
+    let changed = generate_signatures_with_block_size(&modified[..], 1024).unwrap();
+    assert!(signatures.quick_differs(&changed));
+    assert!(changed.quick_differs(&signatures));
+
+    // Without an Adler, only the shape is compared.
+    let shape_only =
+        Signatures::from_precomputed(1024, 10_000, (0..10).map(|i| (i, u128::from(i)))).unwrap();
+    assert_eq!(shape_only.file_adler(), None);
+    assert!(!shape_only.quick_differs(&changed));
+    let shorter = generate_signatures_with_block_size(&original[..9000], 1024).unwrap();
+    assert!(signatures.quick_differs(&shorter));
+}
+
+/// Whether `signatures` holds exactly the blocks of `data`, each under its own index.
+fn assert_signs(signatures: &Signatures, data: &[u8]) {
+    let fresh = generate_signatures_with_block_size(data, signatures.block_size()).unwrap();
+    assert_eq!(signatures.source_size(), fresh.source_size());
+    assert_eq!(signatures.len(), fresh.len());
+    for (block_index, offset, length) in fresh.chunk_offsets() {
+        let offset = usize::try_from(offset).unwrap();
+        let block = &data[offset..offset + length];
+        assert_eq!(signatures.from(block), Some(block_index));
+    }
+}
+
+#[test]
+fn test_resign_dirty() {
+    let mut data = random_data(100_000);
+    let mut signatures = generate_signatures_with_block_size(&data[..], 1024).unwrap();
+    assert_eq!(signatures.generation(), 0);
+
+    let noise: Vec<u8> = random_data(3000).iter().map(|byte| byte ^ 0xAA).collect();
+    let writes: [(usize, &[u8]); 4] = [
+        (5000, &noise[..10]),
+        (1020, &noise[10..20]),
+        (99_990, &noise[20..50]),
+        (50_000, &noise),
+    ];
+    for (cycle, (at, bytes)) in writes.into_iter().enumerate() {
+        let stale = data[at.min(data.len() - 1024)..][..1024].to_vec();
+        let end = at + bytes.len();
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+        data[at..end].copy_from_slice(bytes);
+        signatures.mark_dirty(at as u64..end as u64);
+        assert!(!signatures.dirty_blocks().is_empty());
+
+        signatures.resign_dirty(Cursor::new(&data)).unwrap();
+        let generation = u32::try_from(cycle).unwrap() + 1;
+        assert_eq!(signatures.generation(), generation);
+        assert!(signatures.dirty_blocks().is_empty());
+        assert_eq!(signatures.block_generation(at / 1024), generation);
+        assert_eq!(signatures.file_adler(), None);
+        assert_signs(&signatures, &data);
+        if stale[..] != data[at.min(data.len() - 1024)..][..1024] {
+            assert!(signatures.from(&stale).is_none());
+        }
+    }
+    assert_eq!(signatures.block_generation(0), 2);
+    assert_eq!(signatures.block_generation(10), 0);
+
+    // Shrinking re-hashes the new last block and forgets the ones past it.
+    data.truncate(60_500);
+    signatures.resign_dirty(Cursor::new(&data)).unwrap();
+    assert_eq!(signatures.block_generation(59), 5);
+    assert_signs(&signatures, &data);
+
+    let delta = generate_delta(&signatures, &data[..]).unwrap();
+    assert!(
+        delta
+            .iter()
+            .all(|command| matches!(command, DeltaCommand::Copy { .. }))
+    );
+}
+
+#[test]
+fn test_delta_bounded_memory() {
+    let original = random_data(200_000);
+    // Mostly new data, with a few stretches of the base.
+    let mut modified: Vec<u8> = original.iter().map(|byte| byte ^ 0x5A).collect();
+    modified[50_000..60_240].copy_from_slice(&original[10_240..20_480]);
+    modified.extend_from_slice(&original[..777]);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+
+    let max_mem = 2 * 1024 + 5000;
+    let mut encoded = Vec::new();
+    delta_bounded_memory(&modified[..], &signatures, &mut encoded, max_mem).unwrap();
+    let delta = read_delta(&encoded[..]).unwrap();
+    assert_eq!(apply_patch(&original, &delta), modified);
+
+    // Unmatched data is emitted whole whenever it is flushed, so the largest data command is
+    // the most that was ever held back.
+    let largest = delta
+        .iter()
+        .filter_map(|command| match command {
+            DeltaCommand::Data(data) => Some(data.len()),
+            _ => None,
+        })
+        .max()
+        .unwrap();
+    assert_eq!(largest, 5000);
+    assert!(
+        delta
+            .iter()
+            .any(|command| matches!(command, DeltaCommand::Copy { length: 10_240, .. }))
+    );
+
+    // Without a cap the same input gives the same output once merged.
+    let unbounded = make_delta(&original, &modified, Some(1024));
+    assert_eq!(optimize_delta(&delta), optimize_delta(&unbounded));
+
+    assert!(delta_bounded_memory(&modified[..], &signatures, Vec::new(), 2048).is_err());
+    let empty = Signatures::new(0);
+    let mut encoded = Vec::new();
+    delta_bounded_memory(&modified[..], &empty, &mut encoded, 100).unwrap();
+    assert_eq!(
+        apply_patch(&[], &read_delta(&encoded[..]).unwrap()),
+        modified
+    );
+}
+
+#[test]
+fn test_generate_delta_parallel_matches_sequential() {
+    let original = random_data(300_000);
+    let mut cases = Vec::new();
+    // Mostly similar, with edits of every kind.
+    let mut similar = original.clone();
+    for (i, at) in (1000..similar.len() - 100).step_by(10_007).enumerate() {
+        similar[at] ^= 0xFF;
+        if i % 3 == 0 {
+            similar.splice(at..at, random_data(i % 97 + 1));
+        }
+    }
+    // Long enough for several rounds of scanning, with zeros between the copies.
+    let mut long = similar.repeat(4);
+    long.splice(500_000..500_000, vec![0; 200_000]);
+    cases.push(long);
+    cases.push(similar);
+    let mut shifted = vec![7u8; 3];
+    shifted.extend_from_slice(&original);
+    cases.push(shifted);
+    cases.push(original.clone());
+    cases.push(original.iter().rev().copied().collect());
+    cases.push(original[..100].to_vec());
+    cases.push(Vec::new());
+
+    for block_size in [64, 1000] {
+        let signatures = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+        for new in &cases {
+            let sequential = generate_delta(&signatures, &new[..]).unwrap();
+            for threads in [0, 1, 2, 5, 16] {
+                let parallel = generate_delta_parallel(&signatures, new, threads).unwrap();
+                assert_eq!(
+                    parallel,
+                    sequential,
+                    "block size {block_size}, {threads} threads, {} bytes",
+                    new.len()
+                );
+            }
+        }
+    }
+
+    let empty = Signatures::new(0);
+    assert_eq!(
+        generate_delta_parallel(&empty, b"abc", 4).unwrap(),
+        generate_delta(&empty, &b"abc"[..]).unwrap()
+    );
+}
+
+#[test]
+fn test_generate_signatures_parallel_matches_sequential() {
+    let original = random_data(3 * 1024 * 1024 + 1234);
+    let mut modified = original.clone();
+    modified.splice(1000..1000, *b"inserted");
+    modified[2_000_000] ^= 0xFF;
+
+    for block_size in [64, 4096, 100_000] {
+        let sequential = generate_signatures_with_block_size(&original[..], block_size).unwrap();
+        for threads in [0, 1, 2, 5, 16] {
+            let parallel = generate_signatures_parallel(&original, block_size, threads).unwrap();
+            assert_eq!(parallel.block_size(), block_size);
+            assert_eq!(parallel.source_size(), sequential.source_size());
+            for block in original.chunks(block_size) {
+                let weak = RollingChecksum::compute(block);
+                assert_eq!(
+                    format!("{:?}", parallel.weak(weak)),
+                    format!("{:?}", sequential.weak(weak))
+                );
+            }
+            assert_eq!(
+                generate_delta(&parallel, &modified[..]).unwrap(),
+                generate_delta(&sequential, &modified[..]).unwrap(),
+                "block size {block_size}, {threads} threads"
+            );
+        }
+    }
+
+    let empty = generate_signatures_parallel(&[], 64, 4).unwrap();
+    assert_eq!(empty.source_size(), 0);
+    assert_eq!(
+        generate_delta(&empty, &b"abc"[..]).unwrap(),
+        [DeltaCommand::Data(b"abc".to_vec())]
+    );
+}
+
+#[test]
+fn test_matcher_hybrid() {
+    let original = random_data(200_000);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    let matcher = Matcher::new(&signatures);
+    assert_eq!(matcher.aligned(&original[2048..3072]), Some(2));
+    assert_eq!(matcher.aligned(&original[2047..3071]), None);
+
+    let copied = |delta: &[DeltaCommand]| -> usize {
+        delta
+            .iter()
+            .filter(|command| matches!(command, DeltaCommand::Copy { .. }))
+            .map(DeltaCommand::output_len)
+            .sum()
+    };
+
+    // Aligned data is copied whole.
+    let delta = matcher.generate_delta(&original);
+    assert_eq!(
+        delta,
+        [DeltaCommand::Copy {
+            offset: 0,
+            length: original.len()
+        }]
+    );
+
+    // Shifts are recovered by the rolling search, as well as by `generate_delta`.
+    let mut shifted = b"abc".to_vec();
+    shifted.extend_from_slice(&original[..100_000]);
+    shifted.extend_from_slice(b"inserted");
+    shifted.extend_from_slice(&original[100_000..150_500]);
+    shifted.extend_from_slice(&original[151_000..]);
+    let delta = matcher.generate_delta(&shifted);
+    assert_eq!(apply_patch(&original, &delta), shifted);
+    let reference = make_delta(&original, &shifted, Some(1024));
+    assert_eq!(copied(&delta), copied(&reference));
+
+    for new in [&b""[..], b"short", &original[..1500]] {
+        assert_eq!(apply_patch(&original, &matcher.generate_delta(new)), new);
+    }
+    let empty = Signatures::new(0);
+    assert_eq!(
+        Matcher::new(&empty).generate_delta(b"abc"),
+        [DeltaCommand::Data(b"abc".to_vec())]
+    );
+}
+
+#[test]
+fn test_matcher_bounds_data() {
+    let original = random_data(100_000);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    let matcher = Matcher::new(&signatures);
+    let mut unrelated: Vec<u8> = random_data(DEFAULT_MAX_DATA_LEN * 2 + 100)
+        .iter()
+        .map(|byte| byte ^ 0x5A)
+        .collect();
+    unrelated.extend_from_slice(&original[..4096]);
+    unrelated.extend_from_within(..DEFAULT_MAX_DATA_LEN + 7);
+
+    for delta in [
+        matcher.generate_delta(&unrelated),
+        matcher.generate_delta_with_self_copies(&unrelated),
+        Matcher::new(&Signatures::new(0)).generate_delta(&unrelated),
+    ] {
+        assert_eq!(apply_patch(&original, &delta), unrelated);
+        let data: Vec<_> = delta
+            .iter()
+            .filter_map(|command| match command {
+                DeltaCommand::Data(data) => Some(data.len()),
+                _ => None,
+            })
+            .collect();
+        assert!(data.len() >= 3, "{data:?}");
+        assert!(
+            data.iter().all(|&len| len <= DEFAULT_MAX_DATA_LEN),
+            "{data:?}"
+        );
+    }
+}
+
+#[test]
+fn test_matcher_skips_rolling_search_on_aligned_data() {
+    let original = random_data(100_000);
+    // Weak hashes that match no block, so only lookups by strong hash can find one.
+    let hashes = original.chunks(1000).map(|chunk| {
+        (
+            RollingChecksum::compute(chunk).wrapping_add(1),
+            xxh3_128(chunk),
+        )
+    });
+    let signatures = Signatures::from_precomputed(1000, original.len() as u64, hashes).unwrap();
+    let matcher = Matcher::new(&signatures);
+
+    assert_eq!(
+        matcher.generate_delta(&original),
+        [DeltaCommand::Copy {
+            offset: 0,
+            length: original.len()
+        }]
+    );
+    assert_eq!(
+        generate_delta(&signatures, &original[..]).unwrap(),
+        [DeltaCommand::Data(original.clone())]
+    );
+}
+
+#[test]
+fn test_matcher_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let original = random_data(100_000);
+    let signatures = generate_signatures_with_block_size(&original[..], 512).unwrap();
+    let matcher = Matcher::new(&signatures);
+    assert_send_sync(&matcher);
+
+    std::thread::scope(|scope| {
+        for thread in 0..16u8 {
+            let (matcher, original) = (&matcher, &original);
+            scope.spawn(move || {
+                for round in 0..20 {
+                    let mut new = original.clone();
+                    let at = (usize::from(thread) * 997 + round * 4099) % new.len();
+                    new.splice(at..at, [thread; 100]);
+                    assert_eq!(apply_patch(original, &matcher.generate_delta(&new)), new);
+                }
+            });
+        }
+    });
+}
+
+#[test]
+fn test_self_referential_copies() {
+    let original = random_data(65_536);
+    let signatures = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    let matcher = Matcher::new(&signatures);
+
+    // A prefix of the base, then a chunk absent from it repeated many times.
+    let chunk = random_data(65_536 + 8192).split_off(65_536);
+    let mut new = original[..16_384].to_vec();
+    for _ in 0..16 {
+        new.extend_from_slice(&chunk);
+    }
+    new.extend_from_slice(b"tail");
+
+    let delta = matcher.generate_delta_with_self_copies(&new);
+    assert_eq!(
+        delta[..3],
+        [
+            DeltaCommand::Copy {
+                offset: 0,
+                length: 16_384
+            },
+            DeltaCommand::Data(chunk.clone()),
+            DeltaCommand::SelfCopy {
+                output_offset: 16_384,
+                length: 15 * 8192
+            },
+        ]
+    );
+    let base_only = matcher.generate_delta(&new);
+    assert!(encoded_delta_size(&delta) * 10 < encoded_delta_size(&base_only));
+    let mut encoded = Vec::new();
+    write_delta(&delta, &mut encoded).unwrap();
+    assert_eq!(read_delta(&encoded[..]).unwrap(), delta);
+
+    let plan = plan_apply(&delta, original.len() as u64).unwrap();
+    let mut out = vec![0u8; usize::try_from(plan.final_size).unwrap()];
+    assert_eq!(
+        apply_to_slice(Cursor::new(&original), &delta, &mut out).unwrap(),
+        new.len()
+    );
+    assert_eq!(out, new);
+    let mut parallel = vec![0u8; new.len()];
+    apply_parallel_to_slice(|| Ok(Cursor::new(&original)), &delta, &mut parallel, 2).unwrap();
+    assert_eq!(parallel, new);
+    assert_eq!(
+        apply_dry_run(Cursor::new(&original), &delta).unwrap(),
+        new.len() as u64
+    );
+
+    // Streaming apply functions read back the output they keep.
+    let mut streamed = Vec::new();
+    apply_delta(Cursor::new(&original), &delta, &mut streamed).unwrap();
+    assert_eq!(streamed, new);
+    for already_written in [50_000, new.len() - 2] {
+        let mut resumed = new[..already_written].to_vec();
+        apply_delta_resume(
+            Cursor::new(&original),
+            &delta,
+            &mut resumed,
+            already_written as u64,
+        )
+        .unwrap();
+        assert_eq!(resumed, new);
+    }
+    let mut forward = Vec::new();
+    apply_delta_forward_only(&original[..], &delta, &mut forward).unwrap();
+    assert_eq!(forward, new);
+
+    // Copies reaching further back than the window they keep are rejected.
+    let far = [
+        DeltaCommand::Data(vec![0; SELF_COPY_WINDOW + 1]),
+        DeltaCommand::SelfCopy {
+            output_offset: 0,
+            length: 1,
+        },
+    ];
+    let err = apply_delta(Cursor::new(&original), &far, std::io::sink()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // A copy running into its own output repeats the bytes before it.
+    let run = [
+        DeltaCommand::Data(b"ab".to_vec()),
+        DeltaCommand::SelfCopy {
+            output_offset: 0,
+            length: 5,
+        },
+    ];
+    let mut out = [0u8; 7];
+    apply_to_slice(Cursor::new(&original), &run, &mut out).unwrap();
+    assert_eq!(&out, b"abababa");
+
+    // Copies starting at or after their own output are rejected.
+    let forward = [
+        DeltaCommand::Data(b"ab".to_vec()),
+        DeltaCommand::SelfCopy {
+            output_offset: 2,
+            length: 2,
+        },
+    ];
+    let err = apply_to_slice(Cursor::new(&original), &forward, &mut [0u8; 4]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+    // Without repeats the result is a regular delta.
+    assert_eq!(
+        matcher.generate_delta_with_self_copies(&original),
+        matcher.generate_delta(&original)
+    );
+}
+
+#[test]
+fn test_redaction_policy() {
+    const SENTINEL: &[u8] = b"SENTINEL-PAYLOAD-do-not-log";
+    let leaks = |output: &str| {
+        let list = format!("{:?}", &SENTINEL[..4]);
+        output.contains("SENTINEL")
+            || output.contains("53454e54")
+            || output.contains(&list[1..list.len() - 1])
+    };
+
+    let delta = vec![
+        DeltaCommand::Data(SENTINEL.to_vec()),
+        DeltaCommand::Copy {
+            offset: 1000,
+            length: 10,
+        },
+    ];
+    let mut outputs = vec![
+        format!("{delta:?}"),
+        format!("{delta:#?}"),
+        format!("{}", delta[0]),
+        format!(
+            "{:?}",
+            delta_spans(&delta).map(Result::unwrap).collect::<Vec<_>>()
+        ),
+        apply_delta_at(&b"short base"[..], &delta, Vec::new())
+            .unwrap_err()
+            .to_string(),
+    ];
+    let mut encoded = Vec::new();
+    write_delta(&delta, &mut encoded).unwrap();
+    encoded.truncate(encoded.len() - 3);
+    outputs.push(read_delta(&encoded[..]).unwrap_err().to_string());
+    for output in &outputs {
+        assert!(!leaks(output), "{output}");
+    }
+    assert_eq!(
+        format!("{:?}", delta[1]),
+        "Copy { offset: 1000, length: 10 }"
+    );
+    assert!(outputs[2].starts_with("data <27 bytes, xxh3 "));
+
+    // Previews are opt-in and limited to the requested length.
+    RedactionPolicy::HexPreview { max_bytes: 4 }.set_global();
+    let preview = format!("{:?}", delta[0]);
+    RedactionPolicy::default().set_global();
+    assert!(preview.ends_with(", 53454e54..>)"), "{preview}");
+    assert_eq!(RedactionPolicy::global(), RedactionPolicy::Redact);
+    let full = RedactionPolicy::HexPreview { max_bytes: 64 }
+        .apply(b"ab")
+        .to_string();
+    assert!(full.ends_with(", 6162>"), "{full}");
+}
+
+#[test]
+fn test_final_chunk_modes() {
+    let original = random_data(10_000);
+    let tail = &original[9 * 1024..];
+    let padded_tail = [tail, &[0; 1024 - 784]].concat();
+
+    let exact =
+        generate_signatures_with_final_chunk_mode(&original[..], 1024, FinalChunkMode::Exact)
+            .unwrap();
+    let padded =
+        generate_signatures_with_final_chunk_mode(&original[..], 1024, FinalChunkMode::PadZero)
+            .unwrap();
+    // Exact is the default, and hashes the last block as-is like librsync does.
+    assert_eq!(
+        generate_signatures_with_block_size(&original[..], 1024)
+            .unwrap()
+            .final_chunk_mode(),
+        FinalChunkMode::Exact
+    );
+    let tail_entries = |signatures: &Signatures, block: &[u8]| {
+        signatures
+            .weak(RollingChecksum::compute(block))
+            .is_some_and(|entries| entries.iter().any(|entry| entry.block_index == 9))
+    };
+    assert!(tail_entries(&exact, tail) && !tail_entries(&exact, &padded_tail));
+    assert!(tail_entries(&padded, &padded_tail) && !tail_entries(&padded, tail));
+
+    let mut modified = original.clone();
+    modified.splice(100..100, *b"xyz");
+    let news = [
+        original.clone(),
+        modified,
+        original[..9500].to_vec(),
+        // The padding is never copied from the base, whether it fills a block or not.
+        [&original[..], &[0; 240]].concat(),
+        [&original[..], &[0; 100]].concat(),
+    ];
+    for signatures in [exact, padded] {
+        assert_eq!(signatures.from(tail), Some(9));
+        for new in &news {
+            let deltas = [
+                generate_delta(&signatures, &new[..]).unwrap(),
+                generate_delta_parallel(&signatures, new, 2).unwrap(),
+                generate_delta_hierarchical(&[&signatures], new).unwrap(),
+                Matcher::new(&signatures).generate_delta(new),
+            ];
+            for delta in deltas {
+                assert_eq!(&apply_patch(&original, &delta), new);
+            }
+        }
+
+        let mut resigned = signatures.clone();
+        resigned.mark_dirty(9000..10_000);
+        resigned.resign_dirty(Cursor::new(&original)).unwrap();
+        assert_eq!(resigned.from(tail), Some(9));
+        assert_eq!(
+            tail_entries(&resigned, &padded_tail),
+            signatures.final_chunk_mode() == FinalChunkMode::PadZero
+        );
+    }
+}
+
+#[test]
+fn test_first_difference() {
+    let data = random_data(200_000);
+    assert_eq!(first_difference(&data[..], &data[..]).unwrap(), None);
+    assert_eq!(first_difference(&b""[..], &b""[..]).unwrap(), None);
+
+    let mut changed = data.clone();
+    changed[150_001] ^= 1;
+    assert_eq!(
+        first_difference(&data[..], &changed[..]).unwrap(),
+        Some(150_001)
+    );
+    changed[10] ^= 1;
+    assert_eq!(first_difference(&changed[..], &data[..]).unwrap(), Some(10));
+
+    // Where the shorter input ends, whichever side it is on.
+    assert_eq!(
+        first_difference(&data[..], &data[..70_000]).unwrap(),
+        Some(70_000)
+    );
+    assert_eq!(first_difference(&b""[..], &data[..]).unwrap(), Some(0));
+
+    // Reads of different sizes on each side are compared in lockstep.
+    let chunked = (&data[..1]).chain(&data[1..65_537]).chain(&data[65_537..]);
+    assert_eq!(first_difference(chunked, &data[..]).unwrap(), None);
+}
+
+#[test]
+fn test_downsample() {
+    let factor = |n: usize| std::num::NonZeroUsize::new(n).unwrap();
+    let weak_hashes_match = |signatures: &Signatures, data: &[u8]| -> bool {
+        signatures.chunk_offsets().all(|(index, offset, length)| {
+            let weak =
+                RollingChecksum::compute(&data[usize::try_from(offset).unwrap()..][..length]);
+            signatures
+                .weak(weak)
+                .is_some_and(|entries| entries.iter().any(|e| e.block_index == index))
+        })
+    };
+
+    // 10 blocks: the last group of four holds the last two, the last one partial.
+    let original = random_data(9 * 1024 + 500);
+    let fine = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    let coarse = fine.downsample(factor(4));
+    assert_eq!(coarse.block_size(), 4096);
+    assert_eq!(coarse.source_size(), fine.source_size());
+    assert_eq!(coarse.len(), 3);
+    assert!(coarse.has_partial_tail());
+    // Coarse weak hashes are the Adler-32 of the bytes each block covers.
+    assert!(weak_hashes_match(&coarse, &original));
+
+    // Derived hashes compose and do not depend on the order of downsampling.
+    for (a, b) in [(2, 3), (3, 2), (4, 4), (1, 7)] {
+        let twice = fine.downsample(factor(a)).downsample(factor(b));
+        let once = fine.downsample(factor(a * b));
+        assert_eq!(twice.block_size(), once.block_size());
+        assert_eq!(twice.len(), once.len());
+        assert!(twice.changed_blocks(&once).is_empty(), "{a} then {b}");
+    }
+    assert!(fine.downsample(factor(1)).changed_blocks(&fine).is_empty());
+    assert_eq!(fine.changed_blocks(&coarse), (0..10).collect::<Vec<_>>());
+
+    // The coarse level finds the changed regions, the fine level builds the delta for them.
+    let original = random_data(64 * 1024);
+    let mut modified = original.clone();
+    modified[40_000] ^= 1;
+    modified.extend_from_slice(b"appended");
+    let old = generate_signatures_with_block_size(&original[..], 1024).unwrap();
+    let new = generate_signatures_with_block_size(&modified[..], 1024).unwrap();
+    let changed = old
+        .downsample(factor(16))
+        .changed_blocks(&new.downsample(factor(16)));
+    assert_eq!(changed, [2, 4]);
+
+    let coarse_size = 16 * 1024;
+    let mut delta = Vec::new();
+    for (index, region) in modified.chunks(coarse_size).enumerate() {
+        if changed.contains(&index) {
+            delta.extend(generate_delta_hierarchical(&[&old], region).unwrap());
+        } else {
+            delta.push(DeltaCommand::Copy {
+                offset: (index * coarse_size) as u64,
+                length: region.len(),
+            });
+        }
+    }
+    assert_eq!(apply_patch(&original, &delta), modified);
+}
+
+#[test]
+fn test_chunk_strategies_roundtrip() {
+    let original = random_data(200_000);
+    let mut modified = original.clone();
+    modified.splice(50_000..50_000, b"inserted".iter().copied());
+    modified.drain(120_000..121_000);
+    modified[180_000..180_010].fill(0xAB);
+
+    let strategies = [
+        ChunkStrategy::Fixed(1024),
+        ChunkStrategy::Cdc(CdcParams {
+            min_size: 256,
+            mask: (1 << 10) - 1,
+            max_size: 8192,
+        }),
+    ];
+    for strategy in strategies {
+        let signatures = generate_signatures_with_strategy(&original[..], strategy).unwrap();
+        assert_eq!(signatures.strategy(), strategy);
+        assert_eq!(signatures.source_size(), original.len() as u64);
+        let mut offset = 0;
+        for &(chunk_offset, length) in signatures.chunks() {
+            assert_eq!(chunk_offset, offset);
+            offset += length as u64;
+        }
+        assert_eq!(offset, original.len() as u64);
+
+        let delta = generate_delta_with_strategy(&signatures, &modified[..]).unwrap();
+        assert_eq!(apply_patch(&original, &delta), modified, "{strategy:?}");
+        let copied: usize = delta
+            .iter()
+            .filter(|command| matches!(command, DeltaCommand::Copy { .. }))
+            .map(DeltaCommand::output_len)
+            .sum();
+        assert!(
+            copied * 10 > modified.len() * 9,
+            "{strategy:?} copied {copied}"
+        );
+
+        let unchanged = generate_delta_with_strategy(&signatures, &original[..]).unwrap();
+        assert_eq!(
+            unchanged,
+            [DeltaCommand::Copy {
+                offset: 0,
+                length: original.len()
+            }]
+        );
+    }
+
+    assert!(generate_signatures_with_strategy(&original[..], ChunkStrategy::Fixed(0)).is_err());
+}
+
+#[test]
+fn test_cdc_params_from_sizes() {
+    assert_eq!(
+        CdcParams::new(2048, 10 * 1024, 64 * 1024),
+        CdcParams::default()
+    );
+    assert_eq!(CdcParams::new(256, 1000, 8192).mask, (1 << 10) - 1);
+    assert_eq!(CdcParams::new(4096, 1024, 8192).mask, 0);
+
+    // Chunks survive a shift: only the ones around the insertion are sent again.
+    let original = random_data(500_000);
+    let mut modified = vec![0x42; 7];
+    modified.extend_from_slice(&original);
+    for (min, avg, max) in [
+        (256, 1024, 8192),
+        (1024, 4096, 16_384),
+        (2048, 8192, 65_536),
+    ] {
+        let strategy = ChunkStrategy::Cdc(CdcParams::new(min, avg, max));
+        let signatures = generate_signatures_with_strategy(&original[..], strategy).unwrap();
+        assert!(signatures.chunks().iter().all(|&(_, len)| len <= max));
+        let average = original.len() / signatures.len();
+        assert!(
+            average > avg / 2 && average < avg * 2,
+            "{average} for {avg}"
+        );
+
+        let delta = generate_delta_with_strategy(&signatures, &modified[..]).unwrap();
+        assert_eq!(apply_patch(&original, &delta), modified);
+        let sent: usize = delta
+            .iter()
+            .filter(|command| matches!(command, DeltaCommand::Data(_)))
+            .map(DeltaCommand::output_len)
+            .sum();
+        assert!(sent <= 7 + max, "{sent} bytes sent for chunks up to {max}");
+    }
+}
+
+#[test]
+fn test_cdc_huge_max_size() {
+    use libsync3::cdc::chunk_boundaries;
+
+    let data = random_data(100_000);
+    let params = CdcParams {
+        min_size: usize::MAX / 2,
+        mask: 0,
+        max_size: usize::MAX,
+    };
+    assert_eq!(chunk_boundaries(&data[..], params).unwrap(), [100_000]);
+}
+
+/// Identical data is copied whole by every generator, whether or not its length is a multiple
+/// of the block size.
+#[test]
+fn test_identical_data_with_partial_tail() {
+    for block_size in [1, 4, 7, 64] {
+        for len in 1..=3 * block_size + 1 {
+            let data = random_data(len);
+            let whole = [DeltaCommand::Copy {
+                offset: 0,
+                length: len,
+            }];
+            for final_chunk_mode in [FinalChunkMode::Exact, FinalChunkMode::PadZero] {
+                let signatures = generate_signatures_with_final_chunk_mode(
+                    &data[..],
+                    block_size,
+                    final_chunk_mode,
+                )
+                .unwrap();
+                let context =
+                    format!("block size {block_size}, length {len}, {final_chunk_mode:?}");
+                let deltas = [
+                    (
+                        "generate_delta",
+                        generate_delta(&signatures, &data[..]).unwrap(),
+                    ),
+                    (
+                        "generate_delta_with_alignment",
+                        generate_delta_with_alignment(&signatures, &data[..], 0).unwrap(),
+                    ),
+                    (
+                        "generate_delta_parallel",
+                        generate_delta_parallel(&signatures, &data, 2).unwrap(),
+                    ),
+                    (
+                        "generate_delta_hierarchical",
+                        generate_delta_hierarchical(&[&signatures], &data).unwrap(),
+                    ),
+                    ("Matcher", Matcher::new(&signatures).generate_delta(&data)),
+                    (
+                        "DeltaBuilder",
+                        optimize_delta({
+                            let mut builder = DeltaBuilder::new(&signatures).unwrap();
+                            let mut delta = builder.push(&data);
+                            delta.extend(builder.finish());
+                            delta
+                        }),
+                    ),
+                ];
+                for (name, delta) in deltas {
+                    assert_eq!(delta, whole, "{name}, {context}");
+                    assert_eq!(apply_patch(&data, &delta), data, "{name}, {context}");
+                }
+            }
+
+            let strategy = ChunkStrategy::Fixed(block_size);
+            let signatures = generate_signatures_with_strategy(&data[..], strategy).unwrap();
+            let delta = generate_delta_with_strategy(&signatures, &data[..]).unwrap();
+            assert_eq!(
+                delta, whole,
+                "strategy, block size {block_size}, length {len}"
+            );
+        }
+    }
+}
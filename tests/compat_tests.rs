@@ -0,0 +1,158 @@
+#![cfg(feature = "compat")]
+
+use libsync3::{
+    DeltaCommand, FinalChunkMode, Signatures, apply_delta, delta_from_legacy_json, generate_delta,
+    generate_signatures_with_block_size,
+};
+use std::collections::BTreeSet;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+fn fixtures(kind: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/legacy")
+        .join(kind);
+    let mut paths: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures in {}", dir.display());
+    paths
+}
+
+fn keys(value: &serde_json::Value) -> BTreeSet<String> {
+    value.as_object().unwrap().keys().cloned().collect()
+}
+
+#[test]
+fn test_legacy_signature_fixtures() {
+    for path in fixtures("signatures") {
+        let json = std::fs::read_to_string(&path).unwrap();
+        let signatures = Signatures::from_legacy_json(&json)
+            .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+
+        let chunks: Vec<_> = signatures.chunk_offsets().collect();
+        assert_eq!(chunks.len(), signatures.len(), "{}", path.display());
+        let covered: u64 = chunks.iter().map(|&(_, _, length)| length as u64).sum();
+        assert_eq!(covered, signatures.source_size(), "{}", path.display());
+    }
+}
+
+#[test]
+fn test_legacy_signature_defaults() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/legacy/signatures");
+    let read = |name: &str| {
+        Signatures::from_legacy_json(&std::fs::read_to_string(dir.join(name)).unwrap()).unwrap()
+    };
+
+    let legacy = read("v0.1.0-no-source-size.json");
+    assert_eq!(legacy.block_size(), 4);
+    assert_eq!(legacy.source_size(), 12);
+    assert!(!legacy.has_partial_tail());
+
+    let current = read("v0.1.1-source-size.json");
+    assert_eq!(current.source_size(), 10);
+    assert!(current.has_partial_tail());
+
+    assert_eq!(read("v0.1.0-empty.json").source_size(), 0);
+    assert_eq!(current.generation(), 0);
+    assert!(current.dirty_blocks().is_empty());
+
+    let generations = read("v0.1.4-generations.json");
+    assert_eq!(generations.generation(), 2);
+    assert_eq!(generations.block_generation(1), 2);
+    assert_eq!(generations.dirty_blocks(), [1]);
+    assert_eq!(generations.final_chunk_mode(), FinalChunkMode::Exact);
+    assert_eq!(
+        read("v0.1.5-final-chunk-mode.json").final_chunk_mode(),
+        FinalChunkMode::PadZero
+    );
+    assert!(
+        Signatures::from_legacy_json(
+            r#"{"block_size":0,"weak_to_strong":{"1":[{"strong":1,"block_index":0}]}}"#
+        )
+        .is_err()
+    );
+    assert!(Signatures::from_legacy_json("{").is_err());
+}
+
+#[test]
+fn test_legacy_delta_fixtures() {
+    let base = b"0123456789";
+    for path in fixtures("delta") {
+        let json = std::fs::read_to_string(&path).unwrap();
+        let delta =
+            delta_from_legacy_json(&json).unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+
+        let mut reconstructed = Vec::new();
+        apply_delta(Cursor::new(base), &delta, &mut reconstructed).unwrap();
+        let expected_len: usize = delta.iter().map(DeltaCommand::output_len).sum();
+        assert_eq!(reconstructed.len(), expected_len, "{}", path.display());
+    }
+
+    let delta = delta_from_legacy_json(
+        &std::fs::read_to_string(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/legacy/delta/v0.1.0.json"),
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    let mut reconstructed = Vec::new();
+    apply_delta(Cursor::new(base), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, b"hi!234501");
+}
+
+/// Fails when a serialized type gains or loses a field until a fixture of the new shape is
+/// committed.
+#[test]
+fn test_fixtures_cover_current_shapes() {
+    let original: Vec<u8> = (0..=255).cycle().take(1000).collect();
+    let mut modified = original.clone();
+    modified.splice(10..10, [0xAA; 100]);
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let delta = generate_delta(&signatures, &modified[..]).unwrap();
+
+    // `Value` cannot hold the u128 strong hashes when serializing, but parsing JSON text falls
+    // back to floats for them, which is enough to compare keys.
+    let to_value = |json: String| -> serde_json::Value { serde_json::from_str(&json).unwrap() };
+    let fixture_values = |kind: &str| -> Vec<serde_json::Value> {
+        fixtures(kind)
+            .iter()
+            .map(|path| serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap())
+            .collect()
+    };
+
+    let current = keys(&to_value(serde_json::to_string(&signatures).unwrap()));
+    assert!(
+        fixture_values("signatures")
+            .iter()
+            .any(|fixture| keys(fixture) == current),
+        "no signature fixture with fields {current:?}"
+    );
+
+    let command_shapes = |value: &serde_json::Value| -> BTreeSet<String> {
+        value
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|command| {
+                let (variant, fields) = command.as_object().unwrap().iter().next().unwrap();
+                match fields.as_object() {
+                    Some(fields) => format!("{variant}{:?}", fields.keys().collect::<Vec<_>>()),
+                    None => variant.clone(),
+                }
+            })
+            .collect()
+    };
+    let current = command_shapes(&to_value(serde_json::to_string(&delta).unwrap()));
+    let covered: BTreeSet<String> = fixture_values("delta")
+        .iter()
+        .flat_map(command_shapes)
+        .collect();
+    assert!(
+        current.is_subset(&covered),
+        "no delta fixture with commands {current:?}"
+    );
+}
@@ -0,0 +1,39 @@
+//! Syncing gzip-compressed files through streaming decompressors, without temporary files.
+
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use libsync3::{apply_delta_from_stream, generate_delta, generate_signatures_with_block_size};
+use std::io::Write;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn test_sync_gzip_compressed_inputs() {
+    let original: Vec<u8> = (0..200_000u32)
+        .map(|i| i.wrapping_mul(2_654_435_761).to_le_bytes()[2])
+        .collect();
+    let mut modified = original.clone();
+    modified.splice(50_000..50_000, *b"inserted in the middle");
+    modified.drain(120_000..121_000);
+    let original_gz = gzip(&original);
+    let modified_gz = gzip(&modified);
+
+    let signatures =
+        generate_signatures_with_block_size(GzDecoder::new(&original_gz[..]), 1024).unwrap();
+    assert_eq!(signatures.source_size(), original.len() as u64);
+    let delta = generate_delta(&signatures, GzDecoder::new(&modified_gz[..])).unwrap();
+
+    let mut reconstructed = Vec::new();
+    apply_delta_from_stream(GzDecoder::new(&original_gz[..]), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    // A corrupt base fails instead of producing wrong output.
+    let mut corrupt = original_gz.clone();
+    corrupt.truncate(corrupt.len() / 2);
+    assert!(apply_delta_from_stream(GzDecoder::new(&corrupt[..]), &delta, Vec::new()).is_err());
+}
@@ -0,0 +1,43 @@
+#![cfg(feature = "test-util")]
+
+//! Delta quality guards: reconstruction tests pass with any delta, these fail when a change
+//! makes deltas larger than the envelope defined next to each engine.
+
+use libsync3::apply_delta;
+use libsync3::encoding::encoded_delta_size;
+use libsync3::test_util::{EditProfile, Engine, fixture};
+use std::io::Cursor;
+
+/// Small enough that one copy per block instead of merged runs breaks every envelope.
+const BLOCK_SIZE: usize = 64;
+
+fn check_envelopes(size: usize) {
+    for profile in EditProfile::ALL {
+        let fixture = fixture(profile, size);
+        for engine in Engine::ALL {
+            let delta = engine
+                .generate_delta(&fixture.old, &fixture.new, BLOCK_SIZE)
+                .unwrap();
+            let mut reconstructed = Vec::new();
+            apply_delta(Cursor::new(&fixture.old), &delta, &mut reconstructed).unwrap();
+            assert_eq!(reconstructed, fixture.new, "{engine:?} on {profile:?}");
+
+            let encoded = encoded_delta_size(&delta) as u64;
+            let bound = engine.delta_size_envelope().bound(&fixture, BLOCK_SIZE);
+            assert!(
+                encoded <= bound,
+                "{engine:?} on {profile:?} at {size} bytes: {encoded} encoded bytes, envelope {bound}"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_delta_size_envelope_1mb() {
+    check_envelopes(1024 * 1024);
+}
+
+#[test]
+fn test_delta_size_envelope_10mb() {
+    check_envelopes(10 * 1024 * 1024);
+}
@@ -0,0 +1,71 @@
+//! Touches the API behind every feature so each feature is checked on its own and combined.
+
+use libsync3::{DeltaCommand, Signatures, apply_delta, generate_delta, generate_signatures};
+use std::io::Cursor;
+
+#[test]
+fn test_core_api_without_features() {
+    let original = b"Hello, world! This is the original content.";
+    let modified = b"Hello, Rust! This is the modified content.";
+
+    let signatures = generate_signatures(&original[..]).unwrap();
+    let delta = generate_delta(&signatures, &modified[..]).unwrap();
+    let mut reconstructed = Vec::new();
+    apply_delta(Cursor::new(original), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde_feature() {
+    fn assert_serde<T: serde::Serialize + serde::de::DeserializeOwned>() {}
+
+    assert_serde::<Signatures>();
+    assert_serde::<libsync3::SignatureStrong>();
+    assert_serde::<DeltaCommand>();
+}
+
+#[cfg(feature = "rkyv")]
+#[test]
+fn test_rkyv_feature() {
+    fn assert_rkyv<T: rkyv::Archive>() {}
+
+    assert_rkyv::<Signatures>();
+    assert_rkyv::<libsync3::SignatureStrong>();
+}
+
+#[cfg(feature = "compat")]
+#[test]
+fn test_compat_feature() {
+    let signatures = Signatures::from_legacy_json(r#"{"block_size":16,"weak_to_strong":{}}"#);
+    assert_eq!(signatures.unwrap().block_size(), 16);
+    assert!(libsync3::delta_from_legacy_json("[]").unwrap().is_empty());
+}
+
+#[cfg(feature = "test-util")]
+#[test]
+fn test_test_util_feature() {
+    assert!(libsync3::test_util::verify_roundtrip(
+        &b"old data"[..],
+        &b"new data"[..]
+    ));
+}
+
+#[cfg(feature = "verify-simd")]
+#[test]
+fn test_verify_simd_feature() {
+    let data: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+    let (a, b) = libsync3::rolling::update_scalar(1, 0, &data);
+    assert_eq!(
+        libsync3::rolling::RollingChecksum::compute(&data),
+        b << 16 | a
+    );
+}
+
+#[test]
+fn test_core_types_are_send_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    assert_send_sync::<Signatures>();
+    assert_send_sync::<DeltaCommand>();
+}
@@ -0,0 +1,75 @@
+#![cfg(feature = "rkyv")]
+
+use libsync3::{
+    ArchivedSignatures, DeltaBuilder, FinalChunkMode, Signatures, apply_delta, generate_delta,
+    generate_delta_archived, generate_signatures_with_block_size, generate_signatures_with_crc32,
+    generate_signatures_with_final_chunk_mode,
+};
+use std::io::Cursor;
+
+#[test]
+fn test_archived_signatures_roundtrip() {
+    let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 13]);
+
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&signatures).unwrap();
+
+    let archived = rkyv::access::<ArchivedSignatures, rkyv::rancor::Error>(&bytes).unwrap();
+    let restored = rkyv::deserialize::<Signatures, rkyv::rancor::Error>(archived).unwrap();
+    assert_eq!(restored.len(), signatures.len());
+    assert_eq!(restored.source_size(), signatures.source_size());
+
+    let delta = generate_delta(&restored, &modified[..]).unwrap();
+    let mut reconstructed = Vec::new();
+    apply_delta(Cursor::new(&original), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+}
+
+#[test]
+fn test_generate_delta_archived() {
+    let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+    let mut modified = original.clone();
+    modified.splice(100..100, [0xAA; 13]);
+    modified[5000] ^= 0xFF;
+    let truncated = &modified[..modified.len() - 10];
+
+    for signatures in [
+        generate_signatures_with_block_size(&original[..], 64).unwrap(),
+        generate_signatures_with_crc32(&original[..], 64).unwrap(),
+        generate_signatures_with_final_chunk_mode(&original[..], 48, FinalChunkMode::PadZero)
+            .unwrap(),
+        generate_signatures_with_block_size(&[][..], 64).unwrap(),
+    ] {
+        let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&signatures).unwrap();
+        let archived = rkyv::access::<ArchivedSignatures, rkyv::rancor::Error>(&bytes).unwrap();
+        assert_eq!(archived.block_size(), signatures.block_size());
+        assert_eq!(archived.source_size(), signatures.source_size());
+        assert_eq!(archived.final_chunk_mode(), signatures.final_chunk_mode());
+
+        // The archived matcher finds the same blocks as the deserialized one.
+        for new in [&modified[..], truncated] {
+            let delta = generate_delta_archived(archived, new).unwrap();
+            assert_eq!(delta, generate_delta(&signatures, new).unwrap());
+            let mut builder = DeltaBuilder::from_archived(archived).unwrap();
+            let mut pushed = builder.push(&new[..5000]);
+            pushed.extend(builder.push(&new[5000..]));
+            pushed.extend(builder.finish());
+            assert_eq!(pushed, delta);
+
+            let mut reconstructed = Vec::new();
+            apply_delta(Cursor::new(&original), &delta, &mut reconstructed).unwrap();
+            assert_eq!(reconstructed, new);
+        }
+    }
+}
+
+#[test]
+fn test_archived_block_size_is_64_bit() {
+    let block_size = 1 << 33;
+    let signatures = Signatures::new(block_size);
+    let bytes = rkyv::to_bytes::<rkyv::rancor::Error>(&signatures).unwrap();
+    let archived = rkyv::access::<ArchivedSignatures, rkyv::rancor::Error>(&bytes).unwrap();
+    assert_eq!(archived.block_size(), block_size);
+}
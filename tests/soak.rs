@@ -0,0 +1,113 @@
+//! Long-running checks that repeated sync cycles do not leak memory or file descriptors.
+//!
+//! Ignored by default, run with `cargo test --release --test soak -- --ignored soak`.
+
+use libsync3::{
+    DeltaBuilder, SectorAlignedReader, apply_delta, apply_delta_with_watchdog, generate_delta,
+    generate_signatures_with_block_size, read_delta, write_delta,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Cursor;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Allocator keeping track of the number of live heap bytes.
+struct CountingAllocator;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const CYCLES: usize = 20_000;
+/// Heap growth tolerated over the whole run, for lazily initialized runtime state.
+const SLACK_BYTES: usize = 64 * 1024;
+
+fn open_fds() -> Option<usize> {
+    std::fs::read_dir("/proc/self/fd").ok().map(Iterator::count)
+}
+
+fn sync_cycle(cycle: usize, base_path: &std::path::Path) {
+    let mut seed = cycle as u64 + 1;
+    let len = 1000 + cycle % 5000;
+    let original: Vec<u8> = (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            seed.to_be_bytes()[0]
+        })
+        .collect();
+    let mut modified = original.clone();
+    modified.splice(cycle % len..cycle % len, [0xAA; 100]);
+
+    let signatures = generate_signatures_with_block_size(&original[..], 64).unwrap();
+    let mut builder = DeltaBuilder::new(&signatures).unwrap();
+    let mut delta = Vec::new();
+    for chunk in modified.chunks(333) {
+        delta.extend(builder.push(chunk));
+    }
+    delta.extend(builder.finish());
+    assert_eq!(delta, generate_delta(&signatures, &modified[..]).unwrap());
+
+    let mut encoded = Vec::new();
+    write_delta(&delta, &mut encoded).unwrap();
+    let delta = read_delta(&encoded[..]).unwrap();
+
+    let mut reconstructed = Vec::new();
+    apply_delta(Cursor::new(&original), &delta, &mut reconstructed).unwrap();
+    assert_eq!(reconstructed, modified);
+
+    if cycle.is_multiple_of(100) {
+        let reconstructed = apply_delta_with_watchdog(
+            Cursor::new(original.clone()),
+            delta.clone(),
+            Vec::new(),
+            Duration::from_secs(10),
+        )
+        .unwrap();
+        assert_eq!(reconstructed, modified);
+
+        std::fs::write(base_path, &original).unwrap();
+        let base = SectorAlignedReader::new(std::fs::File::open(base_path).unwrap(), 512, 4);
+        let mut reconstructed = Vec::new();
+        apply_delta(base, &delta, &mut reconstructed).unwrap();
+        assert_eq!(reconstructed, modified);
+    }
+}
+
+#[test]
+#[ignore = "long running, run with --ignored"]
+fn soak_sync_cycles_do_not_leak() {
+    let base_path = std::env::temp_dir().join(format!("libsync3-soak-{}", std::process::id()));
+    for cycle in 0..100 {
+        sync_cycle(cycle, &base_path);
+    }
+
+    let baseline_bytes = LIVE_BYTES.load(Ordering::Relaxed);
+    let baseline_fds = open_fds();
+    for cycle in 100..CYCLES {
+        sync_cycle(cycle, &base_path);
+    }
+    std::fs::remove_file(&base_path).unwrap();
+
+    let live_bytes = LIVE_BYTES.load(Ordering::Relaxed);
+    assert!(
+        live_bytes <= baseline_bytes + SLACK_BYTES,
+        "heap grew from {baseline_bytes} to {live_bytes} bytes"
+    );
+    assert_eq!(open_fds(), baseline_fds);
+}
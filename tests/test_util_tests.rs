@@ -0,0 +1,124 @@
+#![cfg(feature = "test-util")]
+
+use libsync3::rolling::RollingChecksum;
+use libsync3::test_util::{EditProfile, Engine, fixture, verify_roundtrip};
+use libsync3::{DeltaCommand, SyncOptions, SyncPreset, apply_delta, generate_delta};
+use std::io::{Cursor, Read};
+
+/// Reader that fails after yielding `fail_after` bytes.
+struct BrokenReader<'a> {
+    data: &'a [u8],
+    fail_after: usize,
+}
+
+impl Read for BrokenReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.fail_after == 0 {
+            return Err(std::io::Error::other("broken reader"));
+        }
+        let n = buf.len().min(self.fail_after).min(self.data.len());
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        self.fail_after -= n;
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_verify_roundtrip() {
+    let original: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+    let mut modified = original.clone();
+    modified.splice(500..500, [0xAA; 42]);
+
+    assert!(verify_roundtrip(&original[..], &modified[..]));
+    assert!(verify_roundtrip(&original[..], &original[..]));
+    assert!(verify_roundtrip(&[][..], &modified[..]));
+}
+
+#[test]
+fn test_verify_roundtrip_broken_reader() {
+    let original: Vec<u8> = (0..=255).cycle().take(100_000).collect();
+
+    let broken = BrokenReader {
+        data: &original,
+        fail_after: 50_000,
+    };
+    assert!(!verify_roundtrip(&original[..], broken));
+
+    let broken = BrokenReader {
+        data: &original,
+        fail_after: 100,
+    };
+    assert!(!verify_roundtrip(broken, &original[..]));
+}
+
+#[test]
+fn test_weak_collisions_never_copied() {
+    // Every block of `modified` has the Adler-32 of a block of `original`, but none matches.
+    let original = [0u8, 2, 0, 0].repeat(256);
+    let modified = [1u8, 0, 1, 0].repeat(256);
+    assert_eq!(
+        RollingChecksum::compute(&original[..4]),
+        RollingChecksum::compute(&modified[..4])
+    );
+
+    for engine in Engine::ALL {
+        let delta = engine.generate_delta(&original, &modified, 4).unwrap();
+        assert!(
+            delta
+                .iter()
+                .all(|command| matches!(command, DeltaCommand::Data(_))),
+            "{engine:?}"
+        );
+        let mut reconstructed = Vec::new();
+        apply_delta(Cursor::new(&original), &delta, &mut reconstructed).unwrap();
+        assert_eq!(reconstructed, modified, "{engine:?}");
+    }
+}
+
+#[test]
+fn test_sync_presets_roundtrip() {
+    for preset in SyncPreset::ALL {
+        let options = SyncOptions::from_preset(preset);
+        for profile in EditProfile::ALL {
+            let fixture = fixture(profile, 256 * 1024);
+            let signatures = options.signatures(Cursor::new(&fixture.old)).unwrap();
+            let block_size = signatures.block_size();
+            assert!(
+                (options.chunk_size.min_block_size..=options.chunk_size.max_block_size)
+                    .contains(&block_size),
+                "{preset:?}"
+            );
+
+            let mut rolling = RollingChecksum::new();
+            rolling.update(&fixture.old[..block_size]);
+            let first = &signatures.weak(rolling.value()).unwrap()[0];
+            assert_eq!(first.crc32.is_some(), options.crc32, "{preset:?}");
+
+            let delta = generate_delta(&signatures, &fixture.new[..]).unwrap();
+            let mut reconstructed = Vec::new();
+            apply_delta(Cursor::new(&fixture.old), &delta, &mut reconstructed).unwrap();
+            assert!(reconstructed == fixture.new, "{preset:?} {profile:?}");
+        }
+    }
+
+    let options = SyncOptions {
+        crc32: true,
+        ..SyncOptions::from_preset(SyncPreset::LogAppend)
+    };
+    let mut log = fixture(EditProfile::Insertion, 256 * 1024).old;
+    let signatures = options.signatures(Cursor::new(&log)).unwrap();
+    let old_len = log.len();
+    log.extend_from_slice(b"appended line\n");
+    let delta = generate_delta(&signatures, &log[..]).unwrap();
+    assert_eq!(
+        delta,
+        [
+            DeltaCommand::Copy {
+                offset: 0,
+                length: old_len
+            },
+            DeltaCommand::Data(b"appended line\n".to_vec()),
+        ]
+    );
+}